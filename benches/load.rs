@@ -15,6 +15,7 @@ struct BenchDef<'a> {
 
 const IMAGE_DIR: [&'static str; 3] = [".", "tests", "images"];
 const BMP: BenchDef<'static> = BenchDef {dir: &["bmp", "images"], format: ImageFormat::BMP};
+const WEBP: BenchDef<'static> = BenchDef {dir: &["webp", "lossless"], format: ImageFormat::WEBP};
 
 fn bench_load(b: &mut test::Bencher, def: &BenchDef, filename: &str, ) {
     let mut path: path::PathBuf = IMAGE_DIR.iter().collect();
@@ -79,3 +80,13 @@ fn bench_load_bmp_16bf(b: &mut test::Bencher) {
 fn bench_load_bmp_32bf(b: &mut test::Bencher) {
     bench_load(b, &BMP, "rgb32bf.bmp");
 }
+
+#[bench]
+fn bench_load_webp_lossy(b: &mut test::Bencher) {
+    bench_load(b, &WEBP, "1_webp_a.webp");
+}
+
+#[bench]
+fn bench_load_webp_lossless(b: &mut test::Bencher) {
+    bench_load(b, &WEBP, "1_webp_ll.webp");
+}
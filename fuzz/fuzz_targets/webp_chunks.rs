@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate image;
+
+fuzz_target!(|data: &[u8]| {
+    image::webp::fuzz_chunk_parsers(data);
+});
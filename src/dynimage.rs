@@ -10,7 +10,7 @@ use num_iter;
 use ppm;
 #[cfg(feature = "gif_codec")]
 use gif;
-#[cfg(feature = "webp")]
+#[cfg(any(feature = "webp", feature = "no-nom"))]
 use webp;
 #[cfg(feature = "jpeg")]
 use jpeg;
@@ -387,6 +387,14 @@ impl DynamicImage {
                 Ok(())
             }
 
+            #[cfg(any(feature = "webp", feature = "no-nom"))]
+            image::ImageFormat::WEBP => {
+                let mut e = webp::WebpEncoder::new(w);
+
+                try!(e.encode(&bytes, width, height, color));
+                Ok(())
+            }
+
             #[cfg(feature = "gif_codec")]
             image::ImageFormat::GIF => {
                 let g = gif::Encoder::new(w);
@@ -598,6 +606,8 @@ fn save_buffer_impl(path: &Path, buf: &[u8], width: u32, height: u32, color: col
         "png"  => png::PNGEncoder::new(fout).encode(buf, width, height, color),
         #[cfg(feature = "ppm")]
         "ppm"  => ppm::PPMEncoder::new(fout).encode(buf, width, height, color),
+        #[cfg(any(feature = "webp", feature = "no-nom"))]
+        "webp" => webp::WebpEncoder::new(fout).encode(buf, width, height, color),
         format => Err(io::Error::new(
             io::ErrorKind::InvalidInput,
             &format!("Unsupported image format image/{:?}", format)[..],
@@ -614,7 +624,7 @@ pub fn load<R: BufRead+Seek>(r: R, format: ImageFormat) -> ImageResult<DynamicIm
         image::ImageFormat::GIF  => decoder_to_image(gif::Decoder::new(r)),
         #[cfg(feature = "jpeg")]
         image::ImageFormat::JPEG => decoder_to_image(jpeg::JPEGDecoder::new(r)),
-        #[cfg(feature = "webp")]
+        #[cfg(any(feature = "webp", feature = "no-nom"))]
         image::ImageFormat::WEBP => decoder_to_image(webp::WebpDecoder::new(r)),
         #[cfg(feature = "tiff")]
         image::ImageFormat::TIFF => decoder_to_image(try!(tiff::TIFFDecoder::new(r))),
@@ -655,6 +665,13 @@ pub fn load_from_memory(buffer: &[u8]) -> ImageResult<DynamicImage> {
 /// Create a new image from a byte slice
 #[inline(always)]
 pub fn load_from_memory_with_format(buf: &[u8], format: ImageFormat) -> ImageResult<DynamicImage> {
+    #[cfg(any(feature = "webp", feature = "no-nom"))]
+    {
+        if format == image::ImageFormat::WEBP {
+            return decoder_to_image(webp::WebpDecoder::from_slice(buf))
+        }
+    }
+
     let b = io::Cursor::new(buf);
     load(b, format)
 }
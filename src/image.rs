@@ -174,7 +174,7 @@ pub trait ImageDecoder: Sized {
     fn load_rect(&mut self, x: u32, y: u32, length: u32, width: u32) -> ImageResult<Vec<u8>> {
         let (w, h) = try!(self.dimensions());
 
-        if length > h || width > w || x > w || y > h {
+        if x as u64 + width as u64 > w as u64 || y as u64 + length as u64 > h as u64 {
             return Err(ImageError::DimensionError)
         }
 
@@ -187,6 +187,10 @@ pub trait ImageDecoder: Sized {
         let mut buf = vec![0u8; length as usize * width as usize * bpp];
         let mut tmp = vec![0u8; rowlen];
 
+        if buf.is_empty() {
+            return Ok(buf)
+        }
+
         loop {
             let row = try!(self.read_scanline(&mut tmp));
 
@@ -195,16 +199,18 @@ pub trait ImageDecoder: Sized {
             }
         }
 
-        for i in 0..length as usize {
-            {
-                let from = &tmp[x as usize * bpp..width as usize * bpp];
+        let rect_row = width as usize * bpp;
 
-                let to   = &mut buf[i * width as usize * bpp..width as usize * bpp];
-
-                ::copy_memory(from, to);
+        for (i, to) in buf.chunks_mut(rect_row).enumerate() {
+            let start = x as usize * bpp;
+            match tmp.get(start..start + rect_row) {
+                Some(from) => to.copy_from_slice(from),
+                None => return Err(ImageError::DimensionError),
             }
 
-            let _ = try!(self.read_scanline(&mut tmp));
+            if i + 1 < length as usize {
+                let _ = try!(self.read_scanline(&mut tmp));
+            }
         }
 
         Ok(buf)
@@ -525,9 +531,45 @@ impl<'a, I: GenericImage + 'static> GenericImage for SubImage<'a, I>
 #[cfg(test)]
 mod tests {
 
-    use super::GenericImage;
+    use super::{DecodingResult, GenericImage, ImageDecoder, ImageError, ImageResult};
     use buffer::ImageBuffer;
-    use color::{Rgba};
+    use color::{ColorType, Rgba};
+
+    // A 4x3 gray image whose pixels hold 16 * y + x, read one row at a time
+    struct RowDecoder {
+        rows_read: u32,
+    }
+
+    impl ImageDecoder for RowDecoder {
+        fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+            Ok((4, 3))
+        }
+
+        fn colortype(&mut self) -> ImageResult<ColorType> {
+            Ok(ColorType::Gray(8))
+        }
+
+        fn row_len(&mut self) -> ImageResult<usize> {
+            Ok(4)
+        }
+
+        fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+            if self.rows_read == 3 {
+                return Err(ImageError::ImageEnd)
+            }
+
+            for (x, b) in buf[..4].iter_mut().enumerate() {
+                *b = (16 * self.rows_read + x as u32) as u8;
+            }
+            self.rows_read += 1;
+
+            Ok(self.rows_read)
+        }
+
+        fn read_image(&mut self) -> ImageResult<DecodingResult> {
+            Err(ImageError::UnsupportedError(String::from("only read by rows")))
+        }
+    }
 
     #[test]
     /// Test that alpha blending works as expected
@@ -548,6 +590,32 @@ mod tests {
         assert!(*target.get_pixel(0, 0) == Rgba([169, 85, 0, 190]));
     }
 
+    #[test]
+    fn test_load_rect() {
+        // Two rows of three pixels from the second row and column on
+        let mut decoder = RowDecoder { rows_read: 0 };
+        assert_eq!(decoder.load_rect(1, 1, 2, 3).unwrap(), vec![17, 18, 19, 33, 34, 35]);
+
+        // The whole image, without reading past its last row
+        let mut decoder = RowDecoder { rows_read: 0 };
+        assert_eq!(decoder.load_rect(0, 0, 3, 4).unwrap(), vec![0, 1, 2, 3, 16, 17, 18, 19, 32, 33, 34, 35]);
+        assert_eq!(decoder.rows_read, 3);
+
+        // An empty rectangle reads nothing
+        let mut decoder = RowDecoder { rows_read: 0 };
+        assert_eq!(decoder.load_rect(2, 1, 0, 2).unwrap(), Vec::<u8>::new());
+        assert_eq!(decoder.rows_read, 0);
+
+        // Rectangles reaching past the right or bottom edge
+        for &(x, y, length, width) in [(2, 0, 1, 3), (0, 2, 2, 1), (4, 0, 1, 1), (0, 0, 1, u32::max_value())].iter() {
+            let mut decoder = RowDecoder { rows_read: 0 };
+            match decoder.load_rect(x, y, length, width) {
+                Err(ImageError::DimensionError) => (),
+                _ => panic!("expected a dimension error for {:?}", (x, y, length, width)),
+            }
+        }
+    }
+
     #[test]
     fn test_in_bounds() {
         let mut target = ImageBuffer::new(2, 2);
@@ -15,11 +15,12 @@ extern crate num_traits;
 extern crate enum_primitive;
 #[cfg(all(test, benchmarks))]
 extern crate test;
-#[cfg(feature = "webp")]
+#[cfg(all(feature = "webp", not(feature = "no-nom")))]
 #[macro_use]
 extern crate nom;
-
-use std::io::Write;
+#[cfg(feature = "log")]
+#[macro_use]
+extern crate log;
 
 pub use color::ColorType::{
     self,
@@ -113,7 +114,7 @@ pub mod math;
 pub mod imageops;
 
 // Image codecs
-#[cfg(feature = "webp")]
+#[cfg(any(feature = "webp", feature = "no-nom"))]
 pub mod webp;
 #[cfg(feature = "ppm")]
 pub mod ppm;
@@ -141,13 +142,3 @@ mod color;
 mod buffer;
 mod traits;
 mod animation;
-
-// Copies data from `src` to `dst`
-//
-// Panics if the length of `dst` is less than the length of `src`.
-#[inline]
-fn copy_memory(src: &[u8], mut dst: &mut [u8]) {
-    let len_src = src.len();
-    assert!(dst.len() >= len_src);
-    dst.write(src).unwrap();
-}
@@ -0,0 +1,366 @@
+//! Decoding of the alpha plane of lossy WebP images
+//!
+//! The alpha plane is stored in an ALPH chunk next to the VP8 image data.
+//! It is either stored raw or compressed as the green channel of a VP8L
+//! image stream, and may be filtered with a spatial predictor beforehand.
+//! The encoder may also have reduced the number of distinct alpha levels,
+//! the steps between them can be smoothed out again after decoding.
+//!
+//! # Related Links
+//! * [webp-container-spec](https://developers.google.com/speed/webp/docs/riff_container#alpha) - The WebP Container Specification
+
+use std::cmp;
+use std::io;
+
+use image::{ImageError, ImageResult};
+
+use super::vp8l::VP8LDecoder;
+
+/// Filter applied to the alpha values before compression
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlphaFilter {
+    /// The values are stored unfiltered
+    None,
+
+    /// Each value is predicted from the value to its left
+    Horizontal,
+
+    /// Each value is predicted from the value above it
+    Vertical,
+
+    /// Each value is predicted from the gradient of its left, top and top left neighbours
+    Gradient,
+}
+
+fn format_error(msg: &str) -> ImageError {
+    ImageError::FormatError(format!("ALPH: {}", msg))
+}
+
+/// Decodes the contents of an ALPH chunk into one alpha value per pixel of a
+/// ```width``` by ```height``` image. With ```smooth``` the steps between the
+/// levels of a level reduced plane are smoothed, otherwise the levels are kept
+/// like libwebp keeps them by default.
+pub fn decode_alpha(data: &[u8], width: u16, height: u16, smooth: bool) -> ImageResult<Vec<u8>> {
+    if data.is_empty() {
+        return Err(format_error("missing header"))
+    }
+
+    let header = data[0];
+    let compression = header & 3;
+    let filter = match (header >> 2) & 3 {
+        0 => AlphaFilter::None,
+        1 => AlphaFilter::Horizontal,
+        2 => AlphaFilter::Vertical,
+        _ => AlphaFilter::Gradient,
+    };
+
+    // Bits 4 and 5, only level reduction is defined
+    let level_reduction = (header >> 4) & 3 == 1;
+
+    let width = width as usize;
+    let height = height as usize;
+    let data = &data[1..];
+
+    let mut alpha = match compression {
+        0 => {
+            if data.len() < width * height {
+                return Err(format_error("not enough alpha data"))
+            }
+
+            data[..width * height].to_vec()
+        }
+
+        1 => {
+            let mut v = VP8LDecoder::new(io::Cursor::new(data));
+            let frame = try!(v.decode_headerless_frame(width as u16, height as u16));
+
+            // The alpha values are stored in the green channel
+            frame.buf.iter().map(|&argb| (argb >> 8) as u8).collect()
+        }
+
+        _ => return Err(format_error("unknown compression method")),
+    };
+
+    unfilter(&mut alpha, width, height, filter);
+
+    if level_reduction && smooth {
+        dequantize(&mut alpha, width, height);
+    }
+
+    Ok(alpha)
+}
+
+// Reverses the filter on the width by height plane in place
+fn unfilter(alpha: &mut [u8], width: usize, height: usize, filter: AlphaFilter) {
+    if filter == AlphaFilter::None {
+        return
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+
+            // The top left value is not predicted, the first row is always predicted
+            // from the left and the first column always from above.
+            let pred = if x == 0 && y == 0 {
+                0
+            } else if y == 0 {
+                alpha[i - 1]
+            } else if x == 0 {
+                alpha[i - width]
+            } else {
+                let left = alpha[i - 1];
+                let top = alpha[i - width];
+                let top_left = alpha[i - width - 1];
+
+                match filter {
+                    AlphaFilter::Horizontal => left,
+                    AlphaFilter::Vertical => top,
+                    _ => gradient(left, top, top_left),
+                }
+            };
+
+            alpha[i] = alpha[i].wrapping_add(pred);
+        }
+    }
+}
+
+fn gradient(a: u8, b: u8, c: u8) -> u8 {
+    let g = a as i32 + b as i32 - c as i32;
+
+    if g < 0 { 0 } else if g > 255 { 255 } else { g as u8 }
+}
+
+// The radius of the box filter used to smooth reduced alpha levels, the
+// largest one libwebp uses
+const SMOOTHING_RADIUS: usize = 4;
+
+// Smooths the steps between the alpha levels of a level reduced plane the same
+// way as libwebp's alpha dithering at full strength.
+//
+// Each value is moved towards the average of the surrounding box, averages are
+// kept with 2 fractional bits. Differences of up to 3/4 of the distance between
+// two levels are corrected fully, larger ones less and less, so that real edges
+// stay sharp. The smallest and largest level are left as they are, and so are the
+// last rows, which libwebp never reaches.
+fn dequantize(alpha: &mut [u8], width: usize, height: usize) {
+    let radius = cmp::min(SMOOTHING_RADIUS, cmp::min(width.saturating_sub(1) / 2, height.saturating_sub(1) / 2));
+    if radius == 0 {
+        return
+    }
+
+    let mut used = [false; 256];
+    for &a in alpha.iter() {
+        used[a as usize] = true;
+    }
+
+    let levels: Vec<i32> = (0..256).filter(|&v| used[v as usize]).collect();
+    if levels.len() <= 2 {
+        return
+    }
+
+    let (min, max) = (levels[0], levels[levels.len() - 1]);
+    let min_dist = levels.windows(2).map(|l| l[1] - l[0]).min().unwrap();
+
+    let threshold1 = min_dist << 2;
+    let threshold2 = (3 * threshold1) >> 2;
+    let correction = |diff: i32| {
+        let d = diff.abs();
+        let c = if d <= threshold2 {
+            d
+        } else if d < threshold1 {
+            threshold2 * (threshold1 - d) / (threshold1 - threshold2)
+        } else {
+            0
+        };
+
+        (c >> 2) * diff.signum()
+    };
+
+    // The sums of each row up to and including each column
+    let mut prefix = vec![0u32; width * height];
+    for y in 0..height {
+        let mut sum = 0;
+        for x in 0..width {
+            sum += alpha[y * width + x] as u32;
+            prefix[y * width + x] = sum;
+        }
+    }
+
+    let size = 2 * radius + 1;
+    let scale = (1 << 18) / (size * size) as u32;
+    let mut sums = vec![0u32; width];
+
+    for y in 0..height - radius {
+        // Rows above the image repeat the first one
+        for (x, s) in sums.iter_mut().enumerate() {
+            *s = (y as isize - radius as isize..(y + radius + 1) as isize)
+                .map(|row| prefix[cmp::max(row, 0) as usize * width + x])
+                .sum();
+        }
+
+        for x in 0..width {
+            // Columns beyond the sides are mirrored
+            let sum = if x <= radius {
+                sums[x + radius - 1] + sums[radius - x]
+            } else if x < width - radius {
+                sums[x + radius] - sums[x - radius - 1]
+            } else {
+                2 * sums[width - 1] - sums[2 * width - 2 - radius - x] - sums[x - radius - 1]
+            };
+
+            let average = ((sum * scale) >> 16) as i32;
+            let v = alpha[y * width + x] as i32;
+
+            if v > min && v < max {
+                let c = v + correction(average - (v << 2));
+                alpha[y * width + x] = if c < 0 { 0 } else if c > 255 { 255 } else { c as u8 };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cmp;
+
+    use super::{AlphaFilter, decode_alpha, unfilter};
+
+    #[test]
+    fn raw_alpha() {
+        let data = [0x00, 1, 2, 3, 4, 5, 6];
+        assert_eq!(decode_alpha(&data, 3, 2, false).unwrap(), vec![1, 2, 3, 4, 5, 6]);
+
+        assert!(decode_alpha(&data[..6], 3, 2, false).is_err());
+        assert!(decode_alpha(&[0x02, 0], 1, 1, false).is_err());
+    }
+
+    // An ALPH chunk compressed with a headerless VP8L stream of a single color,
+    // each prefix code has one 8 bit symbol
+    fn compressed_alpha(header: u8, argb: u32) -> Vec<u8> {
+        let mut bits = vec![0, 0, 0];
+        for &symbol in [(argb >> 8) & 0xff, (argb >> 16) & 0xff, argb & 0xff, argb >> 24, 0].iter() {
+            bits.extend_from_slice(&[1, 0, 1]);
+            bits.extend((0..8).map(|i| (symbol >> i) & 1));
+        }
+
+        let mut data = vec![header];
+        for byte in bits.chunks(8) {
+            data.push(byte.iter().enumerate().fold(0, |b, (i, &bit)| b | (bit as u8) << i));
+        }
+
+        data
+    }
+
+    #[test]
+    fn lossless_compression() {
+        // The alpha values are the green channel, whatever the other channels hold
+        let data = compressed_alpha(0x01, 0x33118022);
+        assert_eq!(decode_alpha(&data, 3, 2, false).unwrap(), vec![0x80; 6]);
+
+        // The filter is reversed after decompression
+        let data = compressed_alpha(0x05, 0x33118022);
+        assert_eq!(decode_alpha(&data, 3, 2, false).unwrap(), vec![0x80, 0x00, 0x80, 0x00, 0x80, 0x00]);
+
+        assert!(decode_alpha(&data[..1], 3, 2, false).is_err());
+    }
+
+    #[test]
+    fn lossless_compression_images() {
+        // The ALPH chunks written by cwebp, unfiltered and with the gradient filter,
+        // against the alpha planes decoded by libwebp
+        let images: [(&[u8], u16, u16, u8, u64); 2] = [
+            (include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"), 400, 301, 0x01, 0x3b3dba59dc01a297),
+            (include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp"), 386, 395, 0x0d, 0x6447c22356057474),
+        ];
+
+        for &(data, width, height, header, hash) in images.iter() {
+            let start = data.windows(4).position(|w| w == b"ALPH").unwrap();
+            let len = data[start + 4] as usize | (data[start + 5] as usize) << 8;
+            let alph = &data[start + 8..start + 8 + len];
+            assert_eq!(alph[0], header);
+
+            let alpha = decode_alpha(alph, width, height, false).unwrap();
+            assert_eq!(alpha.len(), width as usize * height as usize);
+
+            // FNV-1a
+            let h = alpha.iter().fold(0xcbf29ce484222325u64, |h, &a| (h ^ a as u64).wrapping_mul(0x100000001b3));
+            assert_eq!(h, hash);
+        }
+    }
+
+    #[test]
+    fn level_reduction() {
+        // Bands of three levels, 40 apart, the middle one is turned into a ramp
+        let bands: Vec<u8> = (0..12 * 12).map(|i| (i % 12 / 4) as u8 * 40 + 100).collect();
+
+        let mut data = vec![0x00];
+        data.extend_from_slice(&bands);
+        assert_eq!(decode_alpha(&data, 12, 12, false).unwrap(), bands);
+
+        // The reduced levels are kept unless they are asked to be smoothed
+        data[0] = 0x10;
+        assert_eq!(decode_alpha(&data, 12, 12, false).unwrap(), bands);
+        let smoothed = decode_alpha(&data, 12, 12, true).unwrap();
+        assert_eq!(&smoothed[..12], &[100, 100, 100, 100, 118, 136, 144, 153, 180, 180, 180, 180]);
+
+        // The last rows are not smoothed
+        assert_eq!(&smoothed[8 * 12..], &bands[8 * 12..]);
+    }
+
+    #[test]
+    fn filters() {
+        let deltas = [10, 1, 1, 2, 3, 250];
+
+        let mut horizontal = deltas;
+        unfilter(&mut horizontal, 3, 2, AlphaFilter::Horizontal);
+        assert_eq!(horizontal, [10, 11, 12, 12, 15, 9]);
+
+        let mut vertical = deltas;
+        unfilter(&mut vertical, 3, 2, AlphaFilter::Vertical);
+        assert_eq!(vertical, [10, 11, 12, 12, 14, 6]);
+
+        let mut gradient = deltas;
+        unfilter(&mut gradient, 3, 2, AlphaFilter::Gradient);
+        // (1, 1): 12 + 11 - 10 = 13, (2, 1): 16 + 12 - 11 = 17
+        assert_eq!(gradient, [10, 11, 12, 12, 16, 11]);
+    }
+
+    #[test]
+    fn gradient_filter_edges() {
+        // A value in the top left corner and nothing else spreads over the whole
+        // plane, leaving no seam along the top row or the left column
+        let mut data = vec![0; 1 + 5 * 4];
+        data[0] = 0x0c;
+        data[1] = 7;
+        assert_eq!(decode_alpha(&data, 5, 4, false).unwrap(), vec![7; 5 * 4]);
+
+        // Soft edges rising and falling along the diagonal, whose gradients
+        // are clamped to 255 and to 0
+        let (width, height) = (6, 5);
+        let ramps: [[u8; 6]; 2] = [[0, 40, 120, 200, 255, 255], [255, 215, 135, 55, 0, 0]];
+        for ramp in ramps.iter() {
+            let mask: Vec<u8> = (0..width * height).map(|i| ramp[cmp::min(i % width + i / width, 5)]).collect();
+
+            // Filtered the way the container specification describes it
+            let mut data = vec![0x0c];
+            for (i, &a) in mask.iter().enumerate() {
+                let (x, y) = (i % width, i / width);
+                let pred = match (x, y) {
+                    (0, 0) => 0,
+                    (_, 0) => mask[i - 1],
+                    (0, _) => mask[i - width],
+                    _ => cmp::max(0, cmp::min(255, mask[i - 1] as i32 + mask[i - width] as i32 - mask[i - width - 1] as i32)) as u8,
+                };
+                data.push(a.wrapping_sub(pred));
+            }
+
+            let alpha = decode_alpha(&data, width as u16, height as u16, false).unwrap();
+            assert_eq!(&alpha[..width], &mask[..width]);
+            for y in 0..height {
+                assert_eq!(alpha[y * width], mask[y * width]);
+            }
+            assert_eq!(alpha, mask);
+        }
+    }
+}
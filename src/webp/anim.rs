@@ -0,0 +1,305 @@
+//! Compositing of animated WebP images
+//!
+//! An animation consists of an ANIM chunk holding the global parameters,
+//! followed by one ANMF chunk per frame. Each frame covers a rectangle of the
+//! canvas and is either alpha-blended onto or copied over the previous contents,
+//! after which its rectangle may be cleared to the background color.
+//!
+//! # Related Links
+//! * [webp-container-spec](https://developers.google.com/speed/webp/docs/riff_container#animation) - The WebP Container Specification
+
+use std::io;
+use byteorder::{ReadBytesExt, LittleEndian};
+
+use image::{ImageError, ImageResult};
+
+/// How a frame is combined with the canvas
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMethod {
+    /// The frame is alpha-blended onto the canvas
+    AlphaBlend,
+
+    /// The frame replaces the pixels of the canvas it covers
+    NoBlend,
+}
+
+/// What happens to the area of a frame once it has been displayed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisposeMethod {
+    /// The canvas is left as it is
+    None,
+
+    /// The area of the frame is cleared to the background color
+    Background,
+}
+
+/// The placement and timing of a single animation frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationFrame {
+    /// The horizontal offset of the frame on the canvas
+    pub x_offset: u32,
+
+    /// The vertical offset of the frame on the canvas
+    pub y_offset: u32,
+
+    /// The width of the frame
+    pub width: u32,
+
+    /// The height of the frame
+    pub height: u32,
+
+    /// How long the frame is displayed, in milliseconds
+    pub duration: u32,
+
+    /// How the frame is combined with the canvas
+    pub blend: BlendMethod,
+
+    /// What happens to the area of the frame after it has been displayed
+    pub dispose: DisposeMethod,
+}
+
+/// The length of an animation, available without decoding any frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationInfo {
+    /// The number of frames
+    pub frame_count: u32,
+
+    /// How long the animation plays once through, in milliseconds
+    pub duration: u64,
+
+    /// The color of the canvas before the first frame and of the areas of
+    /// disposed frames, as RGBA
+    pub background_color: [u8; 4],
+
+    /// The number of times the animation is played, 0 means forever
+    pub loop_count: u16,
+}
+
+/// The global parameters of an animation from the ANIM chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnimationParams {
+    /// The color of the canvas before the first frame, as RGBA
+    pub background_color: [u8; 4],
+
+    /// The number of times the animation is played, 0 means forever
+    pub loop_count: u16,
+}
+
+fn read_u24(r: &mut io::Cursor<&[u8]>) -> ImageResult<u32> {
+    Ok(try!(r.read_uint::<LittleEndian>(3)) as u32)
+}
+
+/// Parses the payload of an ANIM chunk
+pub fn read_anim(data: &[u8]) -> ImageResult<AnimationParams> {
+    if data.len() < 6 {
+        return Err(ImageError::FormatError(String::from("ANIM chunk is too short")))
+    }
+
+    // The background color is stored in BGRA order
+    let background_color = [data[2], data[1], data[0], data[3]];
+    let loop_count = data[4] as u16 | (data[5] as u16) << 8;
+
+    Ok(AnimationParams {
+        background_color: background_color,
+        loop_count: loop_count,
+    })
+}
+
+/// Parses the 16 byte header at the start of an ANMF chunk
+pub fn read_anmf_header(data: &[u8]) -> ImageResult<AnimationFrame> {
+    if data.len() < 16 {
+        return Err(ImageError::FormatError(String::from("ANMF chunk is too short")))
+    }
+
+    let mut r = io::Cursor::new(data);
+
+    // The offsets are stored divided by two
+    let x_offset = 2 * try!(read_u24(&mut r));
+    let y_offset = 2 * try!(read_u24(&mut r));
+    let width = try!(read_u24(&mut r)) + 1;
+    let height = try!(read_u24(&mut r)) + 1;
+    let duration = try!(read_u24(&mut r));
+    let flags = try!(r.read_u8());
+
+    Ok(AnimationFrame {
+        x_offset: x_offset,
+        y_offset: y_offset,
+        width: width,
+        height: height,
+        duration: duration,
+        blend: if flags & 2 == 0 { BlendMethod::AlphaBlend } else { BlendMethod::NoBlend },
+        dispose: if flags & 1 == 0 { DisposeMethod::None } else { DisposeMethod::Background },
+    })
+}
+
+/// The RGBA canvas frames are drawn onto
+pub struct Canvas {
+    width: u32,
+    height: u32,
+    background_color: [u8; 4],
+
+    /// The current contents of the canvas
+    pub buf: Vec<u8>,
+}
+
+impl Canvas {
+    /// Creates a canvas filled with ```background_color```
+    pub fn new(width: u32, height: u32, background_color: [u8; 4]) -> Canvas {
+        let mut buf = Vec::with_capacity(width as usize * height as usize * 4);
+        for _ in 0..width as usize * height as usize {
+            buf.extend_from_slice(&background_color);
+        }
+
+        Canvas {
+            width: width,
+            height: height,
+            background_color: background_color,
+            buf: buf,
+        }
+    }
+
+    fn check_bounds(&self, frame: &AnimationFrame) -> ImageResult<()> {
+        if frame.x_offset + frame.width > self.width || frame.y_offset + frame.height > self.height {
+            Err(ImageError::FormatError(String::from("Animation frame outside of the canvas")))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Draws the RGBA pixels ```rgba``` of ```frame``` onto the canvas
+    pub fn draw(&mut self, frame: &AnimationFrame, rgba: &[u8]) -> ImageResult<()> {
+        try!(self.check_bounds(frame));
+
+        let fw = frame.width as usize;
+
+        for y in 0..frame.height as usize {
+            let start = ((frame.y_offset as usize + y) * self.width as usize + frame.x_offset as usize) * 4;
+            let dst = &mut self.buf[start..start + fw * 4];
+            let src = &rgba[y * fw * 4..(y + 1) * fw * 4];
+
+            match frame.blend {
+                BlendMethod::NoBlend => dst.copy_from_slice(src),
+                BlendMethod::AlphaBlend => {
+                    for (d, s) in dst.chunks_mut(4).zip(src.chunks(4)) {
+                        blend(d, s);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Applies the dispose method of ```frame``` after it has been displayed
+    pub fn dispose(&mut self, frame: &AnimationFrame) -> ImageResult<()> {
+        try!(self.check_bounds(frame));
+
+        if frame.dispose == DisposeMethod::None {
+            return Ok(())
+        }
+
+        for y in 0..frame.height as usize {
+            let start = ((frame.y_offset as usize + y) * self.width as usize + frame.x_offset as usize) * 4;
+
+            for pixel in self.buf[start..start + frame.width as usize * 4].chunks_mut(4) {
+                pixel.copy_from_slice(&self.background_color);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Blends the non-premultiplied RGBA pixel src over dst, rounding to the nearest
+// value. The weights are kept in 255ths of 255 so that nothing is rounded before
+// the division, which keeps repeated blending from drifting away from src.
+fn blend(dst: &mut [u8], src: &[u8]) {
+    let src_a = src[3] as u32;
+    if src_a == 0 {
+        return
+    } else if src_a == 255 {
+        dst.copy_from_slice(src);
+        return
+    }
+
+    let src_factor = src_a * 255;
+    let dst_factor = dst[3] as u32 * (255 - src_a);
+    let blend_a = src_factor + dst_factor;
+
+    for i in 0..3 {
+        let c = src[i] as u32 * src_factor + dst[i] as u32 * dst_factor;
+        dst[i] = ((c + blend_a / 2) / blend_a) as u8;
+    }
+
+    dst[3] = ((blend_a + 127) / 255) as u8;
+}
+
+#[cfg(test)]
+mod test {
+    use super::blend;
+
+    #[test]
+    fn blend_pixels() {
+        let mut dst = [10, 20, 30, 255];
+        blend(&mut dst, &[200, 100, 0, 255]);
+        assert_eq!(dst, [200, 100, 0, 255]);
+
+        let mut dst = [10, 20, 30, 255];
+        blend(&mut dst, &[200, 100, 0, 0]);
+        assert_eq!(dst, [10, 20, 30, 255]);
+
+        let mut dst = [0, 0, 0, 0];
+        blend(&mut dst, &[200, 100, 50, 128]);
+        assert_eq!(dst, [200, 100, 50, 128]);
+    }
+
+    #[test]
+    fn blend_rounding() {
+        // Within half a step of src over dst in real numbers
+        for &dst_a in [0u8, 1, 77, 128, 254, 255].iter() {
+            for &src_a in [1u8, 2, 64, 127, 200, 254].iter() {
+                for &(d, s) in [(0u8, 255u8), (255, 0), (13, 200), (199, 200), (100, 101)].iter() {
+                    let mut dst = [d, s, d, dst_a];
+                    blend(&mut dst, &[s, d, 255 - s, src_a]);
+
+                    let (sa, da) = (src_a as f64 / 255.0, dst_a as f64 / 255.0);
+                    let a = sa + da * (1.0 - sa);
+                    let over = |s: u8, d: u8| (s as f64 * sa + d as f64 * da * (1.0 - sa)) / a;
+                    for (&c, exact) in dst.iter().zip([over(s, d), over(d, s), over(255 - s, d), a * 255.0].iter()) {
+                        assert!((c as f64 - exact).abs() <= 0.5 + 1e-9, "{} for {}", c, exact);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn repeated_blending() {
+        // Half transparent over opaque reaches the color of the frame exactly
+        let mut dst = [10, 250, 199, 255];
+        for _ in 0..1000 {
+            blend(&mut dst, &[200, 100, 0, 128]);
+        }
+        assert_eq!(dst, [200, 100, 0, 255]);
+
+        // Nearly transparent frames stop where a step is less than half a value,
+        // which is within 255 / (2 * alpha) of the frame
+        let mut dst = [0, 255, 128, 255];
+        for _ in 0..1000 {
+            blend(&mut dst, &[200, 100, 50, 10]);
+        }
+        for (&c, &s) in dst[..3].iter().zip([200, 100, 50].iter()) {
+            assert!((c as i32 - s).abs() <= 255 / 20, "{} for {}", c, s);
+        }
+        assert_eq!(dst[3], 255);
+
+        // Alpha builds up on a transparent canvas without the color moving, up
+        // to where a step is less than half a value as well
+        let mut dst = [0, 0, 0, 0];
+        for _ in 0..100 {
+            blend(&mut dst, &[60, 120, 180, 64]);
+            assert_eq!(&dst[..3], &[60, 120, 180]);
+        }
+        assert_eq!(dst[3], 254);
+    }
+}
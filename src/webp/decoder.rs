@@ -1,203 +1,5842 @@
 use std::io;
-use std::io::Read;
+use std::io::{Read, Seek, SeekFrom};
+use std::cmp;
+use std::fmt;
+use std::mem;
+use std::ops::Range;
 use std::default::Default;
 use std::error::Error;
 use byteorder::{ReadBytesExt, LittleEndian};
+use num_rational::Ratio;
 
 use image;
 use image::ImageResult;
 use image::ImageDecoder;
 
+use animation;
+use buffer::ImageBuffer;
 use color;
+use dynimage::DynamicImage;
 
-use nom::{le_u32, IResult};
-use super::vp8::Frame;
+#[cfg(not(feature = "no-nom"))]
+use nom::{IResult, Needed};
+#[cfg(feature = "no-nom")]
+use super::riff::{IResult, Needed};
+use self::parsers::{chunk_header, image_chunks, riff_header};
+use super::alpha;
+use super::anim;
+use super::anim::{AnimationFrame, AnimationInfo, AnimationParams, Canvas};
+use super::dither;
+use super::dither::Dither;
+use super::orientation;
+use super::stats::{DecodeStats, Timer};
+use super::vp8::{Frame, FrameHeaderInfo, QuantizerInfo, ScaleHint};
 use super::vp8::VP8Decoder;
+use super::vp8l::LosslessFrame;
+use super::vp8l::VP8LDecoder;
+use super::yuv;
+use super::yuv::{ColorConversion, PixelOrder, Sample, Upsampling};
 
-// The "chunk size" item in a RIFF chunk specifies that "If Chunk Size is odd, a single padding byte -- that SHOULD be 0 -- is added." We need to parse the size, take (and return) that many bytes, and if the length was odd, drop one extra byte.
-named!(chunk_size, do_parse!(
-    len : le_u32 >>
-    result : take!(len) >>
-    cond!(len % 2 != 0, take!(1)) >>
-    ( result )
-));
-
-named!(vp8_chunk, preceded!(
-    tag!("VP8 "),
-    chunk_size
-));
-
-named!(vp8l_chunk, preceded!(
-    tag!("VP8L"),
-    chunk_size
-));
-
-named!(vp8x_chunk, preceded!(
-    tag!("VP8X"),
-    chunk_size
-));
-
-named!(iccp_chunk, preceded!(
-    tag!("ICCP"),
-    chunk_size
-));
-
-named!(alph_chunk, preceded!(
-    tag!("ALPH"),
-    chunk_size
-));
-
-named!(exif_chunk, preceded!(
-    tag!("EXIF"),
-    chunk_size
-));
-
-named!(xmp_chunk, preceded!(
-    tag!("XMP "),
-    chunk_size
-));
-
-named!(extended<&[u8], ImageData>, chain!(
-    vp8x_chunk ~
-    opt!(iccp_chunk) ~
-    // opt!(anim_chunk) ~ // don't support animations
-    image_data : alt!(
-        chain!(a : alph_chunk ~ rgb: vp8_chunk, || {ImageData::LossyWithAlpha(rgb, a)}) |
-        map!(vp8_chunk, ImageData::Lossy) |
-        map!(vp8l_chunk, ImageData::Lossless)
-    ) ~
-    // without the complete!, opt! will reach the end of the file and complain it can't decide whether the thing was there or not
-    opt!(complete!(exif_chunk)) ~
-    opt!(complete!(xmp_chunk)),
-    || {image_data}
-));
-
-named!(webp_body<&[u8], ImageData>,
-    alt!(
-        map!(vp8_chunk, ImageData::Lossy) |
-        map!(vp8l_chunk, ImageData::Lossless) |
-        extended
-    )
-);
+// The parsers of the RIFF container, written with nom or by hand
+#[cfg(not(feature = "no-nom"))]
+mod parsers {
+    use nom::{le_u32, IResult};
+    use super::{ImageData, Payload};
+
+    // The "chunk size" item in a RIFF chunk specifies that "If Chunk Size is odd, a single padding byte -- that SHOULD be 0 -- is added." We need to parse the size, take (and return) that many bytes, and if the length was odd, drop one extra byte.
+    named!(pub chunk_size, do_parse!(
+        len : le_u32 >>
+        result : take!(len) >>
+        cond!(len % 2 != 0, padding) >>
+        ( result )
+    ));
+
+    // Some encoders leave out the padding byte of the last chunk, so it is only taken if present.
+    fn padding(input: &[u8]) -> IResult<&[u8], ()> {
+        IResult::Done(if input.is_empty() { input } else { &input[1..] }, ())
+    }
+
+    named!(pub vp8_chunk, preceded!(
+        tag!("VP8 "),
+        chunk_size
+    ));
+
+    named!(pub vp8l_chunk, preceded!(
+        tag!("VP8L"),
+        chunk_size
+    ));
+
+    named!(pub alph_chunk, preceded!(
+        tag!("ALPH"),
+        chunk_size
+    ));
+
+    // The chunks holding a single image inside an ANMF chunk. An ALPH chunk
+    // before VP8L image data is ignored, as VP8L has alpha of its own.
+    named!(pub image_chunks<&[u8], ImageData>, alt!(
+        do_parse!(
+            a : alph_chunk >>
+            rgb : vp8_chunk >>
+            ( ImageData::LossyWithAlpha(Payload::Owned(rgb.to_vec()), Payload::Owned(a.to_vec())) )
+        ) |
+        do_parse!(
+            alph_chunk >>
+            argb : vp8l_chunk >>
+            ( ImageData::Lossless(Payload::Owned(argb.to_vec())) )
+        ) |
+        map!(vp8_chunk, |d: &[u8]| ImageData::Lossy(Payload::Owned(d.to_vec()))) |
+        map!(vp8l_chunk, |d: &[u8]| ImageData::Lossless(Payload::Owned(d.to_vec())))
+    ));
+
+    // The RIFF header, returns the length of the file following the length field
+    named!(pub riff_header<&[u8], u32>, do_parse!(
+        tag!("RIFF") >>
+        len : le_u32 >>
+        tag!("WEBP") >>
+        ( len )
+    ));
+
+    // The header of any chunk, returns its name and the length of its payload
+    named!(pub chunk_header<&[u8], ([u8; 4], u32)>, do_parse!(
+        name : take!(4) >>
+        len : le_u32 >>
+        ( ([name[0], name[1], name[2], name[3]], len) )
+    ));
+}
+
+#[cfg(feature = "no-nom")]
+mod parsers {
+    use super::super::riff::{le_u32, tag, take, ErrorKind, IResult, Needed};
+    use super::{ImageData, Payload};
+
+    // Takes a little endian length and that many bytes, followed by a padding byte
+    // if the length is odd. The padding byte of the last chunk may be left out.
+    pub fn chunk_size(input: &[u8]) -> IResult<&[u8], &[u8]> {
+        let (rest, len) = parse!(0, le_u32(input));
+        let (rest, result) = parse!(4, take(rest, len as usize));
+        let rest = if len % 2 != 0 && !rest.is_empty() { &rest[1..] } else { rest };
+
+        IResult::Done(rest, result)
+    }
+
+    fn named_chunk<'a>(input: &'a [u8], name: &[u8]) -> IResult<&'a [u8], &'a [u8]> {
+        let (rest, _) = parse!(0, tag(input, name));
+        let (rest, result) = parse!(4, chunk_size(rest));
+
+        IResult::Done(rest, result)
+    }
+
+    pub fn vp8_chunk(input: &[u8]) -> IResult<&[u8], &[u8]> {
+        named_chunk(input, b"VP8 ")
+    }
+
+    pub fn vp8l_chunk(input: &[u8]) -> IResult<&[u8], &[u8]> {
+        named_chunk(input, b"VP8L")
+    }
+
+    pub fn alph_chunk(input: &[u8]) -> IResult<&[u8], &[u8]> {
+        named_chunk(input, b"ALPH")
+    }
+
+    fn lossy_with_alpha(input: &[u8]) -> IResult<&[u8], ImageData> {
+        let (rest, a) = parse!(0, alph_chunk(input));
+        let (rest, rgb) = parse!(input.len() - rest.len(), vp8_chunk(rest));
+
+        IResult::Done(rest, ImageData::LossyWithAlpha(Payload::Owned(rgb.to_vec()), Payload::Owned(a.to_vec())))
+    }
+
+    fn lossless_with_alpha(input: &[u8]) -> IResult<&[u8], ImageData> {
+        let (rest, _) = parse!(0, alph_chunk(input));
+        let (rest, argb) = parse!(input.len() - rest.len(), vp8l_chunk(rest));
+
+        IResult::Done(rest, ImageData::Lossless(Payload::Owned(argb.to_vec())))
+    }
+
+    // The chunks holding a single image inside an ANMF chunk, the first of the
+    // alternatives that does not fail. An ALPH chunk before VP8L image data is
+    // ignored, as VP8L has alpha of its own.
+    pub fn image_chunks(input: &[u8]) -> IResult<&[u8], ImageData> {
+        match lossy_with_alpha(input) {
+            IResult::Error(_) => (),
+            result => return result,
+        }
 
-named!(webp_file<&[u8], ImageData>, preceded!(
-    tag!("RIFF"),
-    flat_map!(length_bytes!(le_u32), preceded!(
-        tag!("WEBP"),
-        webp_body
-    ))
-));
+        match lossless_with_alpha(input) {
+            IResult::Error(_) => (),
+            result => return result,
+        }
+
+        match vp8_chunk(input) {
+            IResult::Done(rest, d) => return IResult::Done(rest, ImageData::Lossy(Payload::Owned(d.to_vec()))),
+            IResult::Incomplete(n) => return IResult::Incomplete(n),
+            IResult::Error(_) => (),
+        }
+
+        match vp8l_chunk(input) {
+            IResult::Done(rest, d) => IResult::Done(rest, ImageData::Lossless(Payload::Owned(d.to_vec()))),
+            IResult::Incomplete(n) => IResult::Incomplete(n),
+            IResult::Error(_) => IResult::Error(ErrorKind::Alt),
+        }
+    }
+
+    // The RIFF header, returns the length of the file following the length field
+    pub fn riff_header(input: &[u8]) -> IResult<&[u8], u32> {
+        let (rest, _) = parse!(0, tag(input, b"RIFF"));
+        let (rest, len) = parse!(4, le_u32(rest));
+        let (rest, _) = parse!(8, tag(rest, b"WEBP"));
+
+        IResult::Done(rest, len)
+    }
+
+    // The header of any chunk, returns its name and the length of its payload
+    pub fn chunk_header(input: &[u8]) -> IResult<&[u8], ([u8; 4], u32)> {
+        let (rest, name) = parse!(0, take(input, 4));
+        let (rest, len) = parse!(4, le_u32(rest));
+
+        IResult::Done(rest, ([name[0], name[1], name[2], name[3]], len))
+    }
+}
 
 
 /// A Representation of a Webp Image format decoder.
+///
+/// The decoder is ```Send``` if its reader is, so it can be moved to another
+/// thread to decode the image there.
 pub struct WebpDecoder<R> {
     r: R,
+
+    // Returns the whole input when r is a borrowed slice, chunks are then
+    // referred to by their position in it instead of being copied
+    slice: Option<fn(&R) -> &[u8]>,
+
+    // Moves r forward or back by a number of bytes when it can seek, the
+    // payloads of metadata chunks are then skipped until they are asked for
+    seek: Option<fn(&mut R, i64) -> io::Result<u64>>,
+
+    // Input that has been read from r but not parsed yet
+    buf: Vec<u8>,
+
+    // The number of bytes parsed so far, and where the RIFF header says the file ends
+    position: u64,
+    riff_end: Option<u64>,
+
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+
+    // The largest image that will be decoded
+    max_width: u32,
+    max_height: u32,
+    max_pixels: u64,
+
+    // The most chunks and animation frames that will be read
+    max_chunks: u32,
+    max_frames: u32,
+
+    // Whether unknown chunks and chunks out of order fail decoding
+    strict: bool,
+
+    info: WebpInfo,
+
+    // The flags of the VP8X chunk, and whether the image data has alpha
+    // regardless of what they say
+    vp8x_flags: Option<u32>,
+    image_alpha: bool,
+
+    // Every chunk read so far, in the order of the file
+    chunks: Vec<ChunkInfo>,
+
+    // Problems that did not stop decoding, until they are taken
+    warnings: Vec<String>,
+
+    // The undecoded image, taken when the frame is decoded
+    image_data: Option<ImageData>,
+
+    // Read from the input when it is first asked for if it was skipped, and
+    // kept from then on
+    icc_profile: Option<Payload>,
+    exif: Option<Payload>,
+    xmp: Option<Payload>,
+
     frame: Frame,
+    upsampling: Upsampling,
+    color_conversion: ColorConversion,
+    pixel_order: PixelOrder,
+    sixteen_bit: bool,
+    premultiplied: bool,
+
+    // How the output is dithered, the error spread onto the next row by
+    // Floyd-Steinberg dithering and the row it is for
+    dither: Dither,
+    dither_errors: Vec<i32>,
+    dither_row: usize,
+    alpha_dithering: bool,
+
+    lossless_frame: Option<LosslessFrame>,
+    alpha: Option<Vec<u8>>,
+    animation: Option<Animation>,
+
+    // The first frame of an animation drawn onto the canvas as RGBA, which is
+    // what an animation decodes to as a still image
+    first_frame: Option<Vec<u8>>,
+
+    // Whether the EXIF orientation is applied, the orientation once it is known
+    // and the upright image when it differs from the decoded one
+    apply_orientation: bool,
+    orientation: Option<u16>,
+    oriented: Option<Vec<u8>>,
+
+    // The scale hints of the frame header of a lossy still image
+    scale_hints: Option<(ScaleHint, ScaleHint)>,
+
+    // The RGBA output, kept once a single pixel has been asked for
+    pixels: Option<Vec<u8>>,
+
+    have_metadata: bool,
+    have_trailer: bool,
     have_frame: bool,
     decoded_rows: u32,
+
+    // The first error of reading the container and of decoding the frame,
+    // returned again instead of reading on from wherever the input was left
+    metadata_error: Option<image::ImageError>,
+    frame_error: Option<image::ImageError>,
+
+    // The length of input needed to read further, once the input has ended
+    bytes_needed: Option<u64>,
+
+    // How long the stages of decoding took, with the stats feature
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    stats: DecodeStats,
+
+    // Called with the number of rows done and the total as the image is read
+    progress: Option<Box<FnMut(u32, u32) + Send>>,
+
+    // Called with the ICC profile and each row of the output, and the profile
+    // it is called with, read once the frame is decoded
+    profile_callback: Option<Box<FnMut(&[u8], &mut [u8]) + Send>>,
 }
 
-enum ImageData<'a> {
-    Lossy(&'a[u8]),
-    Lossless(&'a[u8]),
-    LossyWithAlpha(&'a[u8], &'a[u8])
+enum ImageData {
+    Lossy(Payload),
+    Lossless(Payload),
+    LossyWithAlpha(Payload, Payload),
 }
 
-impl<R: Read> WebpDecoder<R> {
-    /// Create a new WebpDecoder from the Reader ```r```.
-    /// This function takes ownership of the Reader.
-    pub fn new(r: R) -> WebpDecoder<R> {
-        let f: Frame = Default::default();
+// The payload of a chunk, either read from the input, the range of the
+// input slice holding it, or the position and length of a skipped payload
+enum Payload {
+    Owned(Vec<u8>),
+    Borrowed(usize, usize),
+    Skipped(u64, usize),
+}
 
-        WebpDecoder {
-            r: r,
-            have_frame: false,
-            frame: f,
-            decoded_rows: 0
-        }
-    }
+fn borrow_slice<'a, 'b>(r: &'b &'a [u8]) -> &'b [u8] {
+    r
+}
+
+fn seek_by<R: Seek>(r: &mut R, offset: i64) -> io::Result<u64> {
+    r.seek(SeekFrom::Current(offset))
+}
+
+// An animation along with the undecoded image data of each of its frames,
+// which is only read from the input when the frame is decoded
+struct Animation {
+    canvas_width: u32,
+    canvas_height: u32,
+    params: AnimationParams,
+    frames: Vec<(AnimationFrame, Payload)>,
 
-    fn read_vp8_frame(&mut self, framedata: &[u8]) -> ImageResult<()> {
-        let m = io::Cursor::new(framedata);
+    // Whether the image data of any frame has alpha
+    alpha: bool,
+}
+
+// The ANMF header and the start of the frame data that frame_alpha_hint looks at
+const ANMF_HEAD_LEN: usize = 16 + 8 + 5;
+
+fn unexpected_chunk(name: &[u8]) -> image::ImageError {
+    image::ImageError::FormatError(
+        format!("Unexpected {} chunk", String::from_utf8_lossy(name))
+    )
+}
+
+// The place of a chunk in the order of the WebP container, or None if it is
+// not a chunk of the container
+fn chunk_rank(name: &[u8; 4]) -> Option<u8> {
+    match name {
+        b"VP8X" => Some(0),
+        b"ICCP" => Some(1),
+        b"ANIM" | b"ALPH" => Some(2),
+        b"ANMF" | b"VP8 " | b"VP8L" => Some(3),
+        b"EXIF" => Some(4),
+        b"XMP " => Some(5),
+        _ => None,
+    }
+}
 
-        let mut v = VP8Decoder::new(m);
-        let frame = try!(v.decode_frame());
+fn no_image_data() -> image::ImageError {
+    image::ImageError::FormatError(String::from("WebP container has no image data"))
+}
 
-        self.frame = frame.clone();
+// A copy of an error that is returned more than once
+fn copy_error(err: &image::ImageError) -> image::ImageError {
+    match *err {
+        image::ImageError::FormatError(ref msg) => image::ImageError::FormatError(msg.clone()),
+        image::ImageError::DimensionError => image::ImageError::DimensionError,
+        image::ImageError::UnsupportedError(ref msg) => image::ImageError::UnsupportedError(msg.clone()),
+        image::ImageError::UnsupportedColor(c) => image::ImageError::UnsupportedColor(c),
+        image::ImageError::NotEnoughData => image::ImageError::NotEnoughData,
+        image::ImageError::IoError(ref e) => image::ImageError::IoError(io::Error::new(e.kind(), format!("{}", e))),
+        image::ImageError::ImageEnd => image::ImageError::ImageEnd,
+    }
+}
 
+// A WebP file can only hold VP8 keyframes, which have the first bit of the frame tag cleared
+fn check_keyframe(vp8: &[u8]) -> ImageResult<()> {
+    if vp8.first().map_or(false, |tag| tag & 1 != 0) {
+        Err(image::ImageError::FormatError(String::from("WebP VP8 payload is not a keyframe")))
+    } else {
         Ok(())
     }
+}
 
-    fn read_metadata(&mut self) -> ImageResult<()> {
-        if !self.have_frame {
-            let mut everything = Vec::new();
-            try!(self.r.read_to_end(&mut everything.as_mut()));
-            match webp_file(everything.as_slice()) {
-                IResult::Done(_, image) => {
-                    match image {
-                        ImageData::Lossy(vp8) | ImageData::LossyWithAlpha(vp8, _) => {
-                            try!(self.read_vp8_frame(vp8));
-                            self.have_frame = true;
-                            Ok(())
-                        },
-                        ImageData::Lossless(_) =>
-                            Err(image::ImageError::UnsupportedError(
-                                String::from("Lossless WebP")
-                            ))
-                    }
+/// Returns whether ```data``` starts with the header of a WebP file
+///
+/// Only the first 12 bytes are looked at, so this is a cheap way to decide
+/// whether to hand a buffered stream to ```WebpDecoder```.
+pub fn can_decode(data: &[u8]) -> bool {
+    match riff_header(data) {
+        IResult::Done(..) => true,
+        _ => false,
+    }
+}
+
+/// Decodes the WebP image in ```data``` in one call.
+///
+/// Returns the 8 bit pixels along with the width, height and color type of the
+/// image, the same as a ```WebpDecoder``` borrowing ```data``` would.
+pub fn decode_webp(data: &[u8]) -> ImageResult<(Vec<u8>, u32, u32, color::ColorType)> {
+    let mut decoder = WebpDecoder::from_slice(data);
+    let (width, height) = try!(decoder.dimensions());
+    let colortype = try!(decoder.colortype());
+
+    let mut buf = vec![0u8; try!(decoder.row_len()) * height as usize];
+    let _ = try!(decoder.read_image_into(&mut buf));
+
+    Ok((buf, width, height, colortype))
+}
+
+/// Runs the RIFF parsers and the decoder over arbitrary ```data```, for fuzzing.
+///
+/// Panics if a parser consumes a different number of bytes than the lengths
+/// it read say it should, or if decoding panics. Decoding errors are ignored.
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub fn fuzz_chunk_parsers(data: &[u8]) {
+    if let IResult::Done(rest, _) = riff_header(data) {
+        assert_eq!(rest.len(), data.len() - 12);
+    }
+
+    // Every chunk header and payload found at the start of the input or of
+    // the rest of it, as the chunks inside of an ANMF chunk are parsed
+    for start in 0..cmp::min(data.len(), 64) {
+        let input = &data[start..];
+
+        if let IResult::Done(rest, (_, len)) = chunk_header(input) {
+            assert_eq!(rest.len(), input.len() - 8);
+
+            // The length field, the payload and its padding byte if there is one
+            let sized = &input[4..];
+            match parsers::chunk_size(sized) {
+                IResult::Done(rest, payload) => {
+                    assert_eq!(payload.len(), len as usize);
+
+                    let end = 4 + len as usize;
+                    let padded = if len % 2 != 0 && sized.len() > end { end + 1 } else { end };
+                    assert_eq!(sized.len() - rest.len(), padded);
                 },
-                IResult::Error(e) => Err(image::ImageError::FormatError(
-                    format!("{}", e)
-                )),
-                IResult::Incomplete(needed) => {
-                    Err(image::ImageError::NotEnoughData)
-                }
+                IResult::Incomplete(Needed::Size(n)) => assert!(n > sized.len()),
+                _ => (),
             }
-        } else {
-            Ok(())
+        }
+
+        if let IResult::Done(rest, image) = image_chunks(input) {
+            let len = |payload: &Payload| match *payload {
+                Payload::Owned(ref d) => d.len(),
+                _ => unreachable!(),
+            };
+            let payloads = match image {
+                ImageData::Lossy(ref vp8) | ImageData::Lossless(ref vp8) => 8 + len(vp8),
+                ImageData::LossyWithAlpha(ref vp8, ref alph) => 16 + len(vp8) + len(alph),
+            };
+
+            // The padding of the last chunk may be missing
+            let consumed = input.len() - rest.len();
+            assert!(consumed >= payloads && consumed <= payloads + 2);
         }
     }
+
+    let mut decoder = WebpDecoder::from_slice(data);
+    decoder.set_limits(1024, 1024, 1 << 18);
+    let _ = decoder.read_image();
+    let _ = decoder.verify_structure();
+
+    let mut decoder = WebpDecoder::new(io::Cursor::new(data));
+    decoder.set_limits(1024, 1024, 1 << 18);
+    let _ = decoder.read_image();
+    let _ = decoder.chunks();
 }
 
-impl<R: Read> ImageDecoder for WebpDecoder<R> {
-    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
-        let _ = try!(self.read_metadata());
+/// A feature of WebP that is recognized, but can not be decoded
+///
+/// The WebP decoder reports these as an ```UnsupportedError``` with a message
+/// for display, ```from_error``` tells them apart from each other.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsupportedFeature {
+    /// A VP8 frame that is not a keyframe, given to ```VP8Decoder``` directly.
+    /// WebP files can not hold them, so the WebP decoder reports a ```FormatError```.
+    InterFrame,
+
+    /// The luma plane of a lossless image, which has none
+    LosslessLuma,
+
+    /// The YUV planes of a lossless image, which is stored as RGB
+    LosslessYuv,
+
+    /// The alpha mask of an image without an alpha channel
+    MissingAlpha,
 
-        Ok((self.frame.width as u32, self.frame.height as u32))
+    /// 16 bit output from anything other than ```read_image```
+    SixteenBitOutput,
+}
+
+const UNSUPPORTED_FEATURES: [UnsupportedFeature; 5] = [
+    UnsupportedFeature::InterFrame,
+    UnsupportedFeature::LosslessLuma,
+    UnsupportedFeature::LosslessYuv,
+    UnsupportedFeature::MissingAlpha,
+    UnsupportedFeature::SixteenBitOutput,
+];
+
+impl UnsupportedFeature {
+    /// Returns the feature an error of the WebP decoder is about, or ```None```
+    /// if it is not an ```UnsupportedError``` of the decoder.
+    pub fn from_error(err: &image::ImageError) -> Option<UnsupportedFeature> {
+        match *err {
+            image::ImageError::UnsupportedError(ref msg) => {
+                UNSUPPORTED_FEATURES.iter().cloned().find(|f| f.message() == msg)
+            }
+            _ => None,
+        }
     }
 
-    fn colortype(&mut self) -> ImageResult<color::ColorType> {
-        Ok(color::ColorType::Gray(8))
+    fn message(&self) -> &'static str {
+        match *self {
+            UnsupportedFeature::InterFrame => "VP8: only keyframes are supported",
+            UnsupportedFeature::LosslessLuma => "Luma plane of lossless WebP",
+            UnsupportedFeature::LosslessYuv => "YUV planes of lossless WebP",
+            UnsupportedFeature::MissingAlpha => "Alpha mask of WebP without alpha",
+            UnsupportedFeature::SixteenBitOutput => "16 bit output is only available from read_image",
+        }
     }
 
-    fn row_len(&mut self) -> ImageResult<usize> {
-        let _ = try!(self.read_metadata());
+    /// The ```UnsupportedError``` reporting the feature
+    pub fn error(self) -> image::ImageError {
+        image::ImageError::UnsupportedError(String::from(self.message()))
+    }
+}
 
-        Ok(self.frame.width as usize)
+impl fmt::Display for UnsupportedFeature {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        write!(fmt, "{}", self.message())
     }
+}
 
-    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
-        let _ = try!(self.read_metadata());
+// VP8X feature flags, every other bit is reserved
+const ICCP_FLAG: u32 = 0x20;
+const ALPHA_FLAG: u32 = 0x10;
+const EXIF_FLAG: u32 = 0x08;
+const XMP_FLAG: u32 = 0x04;
+const ANIMATION_FLAG: u32 = 0x02;
 
-        if self.decoded_rows > self.frame.height as u32 {
-            return Err(image::ImageError::ImageEnd)
+/// A feature announced by a flag of the VP8X chunk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VP8XFlag {
+    /// An ICC profile, stored in an ICCP chunk
+    IccProfile,
+
+    /// An alpha channel, stored in an ALPH chunk or as part of lossless image data
+    Alpha,
+
+    /// EXIF metadata, stored in an EXIF chunk
+    Exif,
+
+    /// XMP metadata, stored in an XMP chunk
+    Xmp,
+
+    /// An animation, stored in an ANIM chunk and ANMF chunks
+    Animation,
+}
+
+const VP8X_FLAGS: [VP8XFlag; 5] = [
+    VP8XFlag::IccProfile,
+    VP8XFlag::Alpha,
+    VP8XFlag::Exif,
+    VP8XFlag::Xmp,
+    VP8XFlag::Animation,
+];
+
+impl VP8XFlag {
+    /// The bit of the VP8X flags holding the flag
+    pub fn bit(&self) -> u32 {
+        match *self {
+            VP8XFlag::IccProfile => ICCP_FLAG,
+            VP8XFlag::Alpha => ALPHA_FLAG,
+            VP8XFlag::Exif => EXIF_FLAG,
+            VP8XFlag::Xmp => XMP_FLAG,
+            VP8XFlag::Animation => ANIMATION_FLAG,
         }
+    }
 
-        let rlen  = buf.len();
-        let slice = &self.frame.ybuf[
-            self.decoded_rows as usize * rlen..
-            self.decoded_rows as usize * rlen + rlen
-        ];
+    fn name(&self) -> &'static str {
+        match *self {
+            VP8XFlag::IccProfile => "ICC profile",
+            VP8XFlag::Alpha => "alpha",
+            VP8XFlag::Exif => "EXIF",
+            VP8XFlag::Xmp => "XMP",
+            VP8XFlag::Animation => "animation",
+        }
+    }
+}
 
-        ::copy_memory(slice, buf);
-        self.decoded_rows += 1;
+/// A disagreement between the VP8X flags of an image and its contents,
+/// as found by ```WebpDecoder::validate```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagDiscrepancy {
+    /// The flag is set, but the image does not have the feature
+    MissingFeature(VP8XFlag),
 
-        Ok(self.decoded_rows)
+    /// The image has the feature, but the flag is not set
+    UnflaggedFeature(VP8XFlag),
+
+    /// Reserved bits of the flags are set, such as the fragment flag of
+    /// early versions of the format
+    ReservedBits(u32),
+}
+
+impl fmt::Display for FlagDiscrepancy {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        match *self {
+            FlagDiscrepancy::MissingFeature(flag) => write!(
+                fmt, "VP8X {} flag is set, but the image has none", flag.name()
+            ),
+            FlagDiscrepancy::UnflaggedFeature(flag) => write!(
+                fmt, "VP8X {} flag is not set, but the image has it", flag.name()
+            ),
+            FlagDiscrepancy::ReservedBits(bits) => write!(
+                fmt, "reserved VP8X flags 0x{:08x} are set", bits
+            ),
+        }
     }
+}
 
-    fn read_image(&mut self) -> ImageResult<image::DecodingResult> {
-        let _ = try!(self.read_metadata());
+/// Basic properties of a WebP image, available without decoding its pixels
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct WebpInfo {
+    /// The width of the image, or of the canvas of an animation
+    pub width: u32,
+
+    /// The height of the image, or of the canvas of an animation
+    pub height: u32,
+
+    /// Whether the image has an alpha channel
+    pub has_alpha: bool,
+
+    /// Whether the image is an animation
+    pub has_animation: bool,
+
+    /// Whether the image, or every frame of an animation, is compressed losslessly
+    pub is_lossless: bool,
+
+    /// Whether the image carries an ICC color profile
+    pub icc_present: bool,
+}
+
+/// The position of a chunk in the RIFF container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkInfo {
+    /// The four character code of the chunk, such as ```VP8X``` or ```EXIF```
+    pub name: [u8; 4],
+
+    /// The offset of the chunk header from the start of the file
+    pub offset: u64,
+
+    /// The length of the payload, as given in the chunk header
+    pub len: u32,
+}
+
+impl ChunkInfo {
+    /// The range of the file holding the payload of the chunk
+    pub fn payload(&self) -> Range<u64> {
+        self.offset + 8..self.offset + 8 + self.len as u64
+    }
+
+    /// The range of the file taken up by the whole chunk, from its header
+    /// up to and including the padding byte that follows a payload of odd length
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + 8 + self.len as u64 + self.len as u64 % 2
+    }
+}
+
+/// An iterator over the remaining rows of an image, returned by ```WebpDecoder::scanlines```
+pub struct Scanlines<'a, R: 'a> {
+    decoder: &'a mut WebpDecoder<R>,
+    done: bool,
+}
+
+impl<'a, R: Read> Iterator for Scanlines<'a, R> {
+    type Item = ImageResult<Vec<u8>>;
+
+    fn next(&mut self) -> Option<ImageResult<Vec<u8>>> {
+        if self.done {
+            return None
+        }
+
+        let row = self.decoder.row_len().and_then(|len| {
+            let mut row = vec![0u8; len];
+            self.decoder.read_scanline(&mut row).map(|_| row)
+        });
+
+        match row {
+            Ok(row) => Some(Ok(row)),
+            Err(image::ImageError::ImageEnd) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+// Reads the flags and the canvas size from the payload of a VP8X chunk
+fn read_vp8x(data: &[u8]) -> ImageResult<(u32, u32, u32)> {
+    // The canvas size is stored after the flags and three reserved bytes
+    if data.len() < 10 {
+        return Err(image::ImageError::FormatError(
+            String::from("VP8X chunk is too short")
+        ))
+    }
+
+    let mut r = io::Cursor::new(data);
+    let flags = try!(r.read_u32::<LittleEndian>());
+    let canvas_width = try!(r.read_uint::<LittleEndian>(3)) as u32 + 1;
+    let canvas_height = try!(r.read_uint::<LittleEndian>(3)) as u32 + 1;
+
+    Ok((flags, canvas_width, canvas_height))
+}
+
+// Reads the dimensions from the frame header at the start of a VP8 chunk
+fn vp8_dimensions(data: &[u8]) -> ImageResult<(u32, u32)> {
+    if data.len() < 10 {
+        return Err(image::ImageError::FormatError(
+            String::from("VP8 chunk is too short")
+        ))
+    }
+
+    try!(check_keyframe(data));
+
+    // The top two bits hold the scale hints, leaving 14 bits for the size
+    let width = (data[6] as u32 | (data[7] as u32) << 8) & 0x3fff;
+    let height = (data[8] as u32 | (data[9] as u32) << 8) & 0x3fff;
+
+    if width == 0 || height == 0 {
+        return Err(image::ImageError::FormatError(
+            String::from("VP8 frame is empty")
+        ))
+    }
+
+    Ok((width, height))
+}
+
+// The scale hints in the top bits of the dimensions of a VP8 frame header,
+// which vp8_dimensions has checked
+fn vp8_scale_hints(data: &[u8]) -> (ScaleHint, ScaleHint) {
+    (ScaleHint::from_field(data[6] as u16 | (data[7] as u16) << 8),
+     ScaleHint::from_field(data[8] as u16 | (data[9] as u16) << 8))
+}
+
+// Reads the dimensions from the header at the start of a VP8L chunk, each is
+// stored in 14 bits as one less than its value
+fn vp8l_dimensions(data: &[u8]) -> ImageResult<(u32, u32)> {
+    if data.len() < 5 {
+        return Err(image::ImageError::FormatError(
+            String::from("VP8L chunk is too short")
+        ))
+    }
+
+    if data[0] != 0x2f {
+        return Err(image::ImageError::FormatError(
+            String::from("VP8L signature is invalid")
+        ))
+    }
+
+    let bits = data[1] as u32 | (data[2] as u32) << 8 | (data[3] as u32) << 16 | (data[4] as u32) << 24;
+
+    Ok(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1))
+}
+
+// The alpha_is_used bit of the VP8L header, follows the dimensions
+fn vp8l_alpha_hint(data: &[u8]) -> bool {
+    data.len() >= 5 && (data[4] >> 4) & 1 != 0
+}
+
+// Wraps the 8 bit output of an image in the buffer of its color type
+fn dynamic_image(color: color::ColorType, width: u32, height: u32, buf: image::DecodingResult)
+    -> ImageResult<DynamicImage> {
+    let image = match (color, buf) {
+        (color::ColorType::RGB(8), image::DecodingResult::U8(buf)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgb8)
+        },
+        (color::ColorType::RGBA(8), image::DecodingResult::U8(buf)) => {
+            ImageBuffer::from_raw(width, height, buf).map(DynamicImage::ImageRgba8)
+        },
+        (color, _) => return Err(image::ImageError::UnsupportedColor(color)),
+    };
+
+    image.ok_or(image::ImageError::DimensionError)
+}
+
+fn verify_error(msg: &str) -> image::ImageError {
+    image::ImageError::FormatError(String::from(msg))
+}
+
+// Checks the frame tag and header at the start of a VP8 payload, Section 9.1
+fn verify_vp8(data: &[u8]) -> ImageResult<(u32, u32)> {
+    let (width, height) = try!(vp8_dimensions(data));
+
+    if data[3..6] != [0x9d, 0x01, 0x2a] {
+        return Err(verify_error("VP8 start code is invalid"))
+    }
+
+    let first_partition_size = (data[0] as usize | (data[1] as usize) << 8 | (data[2] as usize) << 16) >> 5;
+    if first_partition_size > data.len() - 10 {
+        return Err(verify_error("VP8 first partition extends past the end of the chunk"))
+    }
+
+    Ok((width, height))
+}
+
+// Checks the version of a VP8L payload, its signature is checked with its dimensions
+fn verify_vp8l(data: &[u8]) -> ImageResult<(u32, u32)> {
+    let (width, height) = try!(vp8l_dimensions(data));
+
+    if data[4] >> 5 != 0 {
+        return Err(verify_error("VP8L version is not 0"))
+    }
+
+    Ok((width, height))
+}
+
+// Checks the header of an ALPH payload for a width by height image
+fn verify_alph(data: &[u8], width: u32, height: u32) -> ImageResult<()> {
+    match data.first().map(|header| header & 3) {
+        None => Err(verify_error("ALPH chunk is empty")),
+        Some(0) if data.len() - 1 < width as usize * height as usize => {
+            Err(verify_error("ALPH chunk is too short for the image"))
+        },
+        Some(0) | Some(1) => Ok(()),
+        Some(_) => Err(verify_error("ALPH compression method is unknown")),
+    }
+}
+
+// Whether the image data of an animation frame has alpha
+fn frame_alpha_hint(data: &[u8]) -> bool {
+    data.starts_with(b"ALPH") || data.starts_with(b"VP8L") && data.len() > 8 && vp8l_alpha_hint(&data[8..])
+}
+
+// Decodes into the planes of frame, keeping their allocations, and adds the
+// time it took to stats
+#[cfg_attr(not(feature = "stats"), allow(unused_variables))]
+fn decode_vp8(framedata: &[u8], frame: Frame, stats: &mut DecodeStats) -> ImageResult<Frame> {
+    try!(check_keyframe(framedata));
+
+    let mut v = VP8Decoder::with_frame(io::Cursor::new(framedata), frame);
+    let _ = try!(v.decode_frame());
+
+    #[cfg(feature = "stats")]
+    stats.add(&v.stats());
+
+    Ok(v.into_frame())
+}
+
+fn decode_vp8l(framedata: &[u8]) -> ImageResult<LosslessFrame> {
+    let mut v = VP8LDecoder::new(io::Cursor::new(framedata));
+    let _ = try!(v.decode_frame());
+
+    Ok(v.into_frame())
+}
+
+// Appends a row of RGB or RGBA pixels to rgba, making RGB pixels opaque
+fn extend_rgba(rgba: &mut Vec<u8>, row: &[u8], bpp: usize) {
+    if bpp == 4 {
+        rgba.extend_from_slice(row);
+    } else {
+        for rgb in row.chunks(3) {
+            rgba.extend_from_slice(rgb);
+            rgba.push(255);
+        }
+    }
+}
+
+impl<'a> WebpDecoder<&'a [u8]> {
+    /// Create a new WebpDecoder for the WebP file held in ```data```.
+    ///
+    /// Unlike ```new```, the image data of a still image is decoded straight
+    /// from ```data``` instead of being copied out of it first.
+    pub fn from_slice(data: &'a [u8]) -> WebpDecoder<&'a [u8]> {
+        let mut decoder = WebpDecoder::new(data);
+        decoder.slice = Some(borrow_slice);
+
+        decoder
+    }
+
+    /// Create a new WebpDecoder for the WebP file held in ```data```, failing
+    /// right away if it is not one.
+    ///
+    /// The container is read up to and including the headers of the image data,
+    /// as ```dimensions``` reads it, so this fails with the error that
+    /// ```dimensions``` would fail with. No pixels are decoded, and the chunks
+    /// following the image data are left for later.
+    pub fn try_from_slice(data: &'a [u8]) -> ImageResult<WebpDecoder<&'a [u8]>> {
+        let mut decoder = WebpDecoder::from_slice(data);
+        try!(decoder.read_metadata());
+
+        Ok(decoder)
+    }
+}
+
+impl<R: Read + Seek> WebpDecoder<R> {
+    /// Create a new WebpDecoder from the seekable Reader ```r```.
+    ///
+    /// Unlike ```new```, the payloads of ICCP, EXIF and XMP chunks are skipped
+    /// instead of being read into memory. They are read from ```r``` each time
+    /// they are asked for, such as by ```icc_profile``` and ```exif_metadata```.
+    pub fn new_seekable(r: R) -> WebpDecoder<R> {
+        let mut decoder = WebpDecoder::new(r);
+        decoder.seek = Some(seek_by);
+
+        decoder
+    }
+}
+
+impl<R: Read> WebpDecoder<R> {
+    /// Create a new WebpDecoder from the Reader ```r```.
+    /// This function takes ownership of the Reader.
+    pub fn new(r: R) -> WebpDecoder<R> {
+        let f: Frame = Default::default();
+
+        WebpDecoder {
+            r: r,
+            slice: None,
+            seek: None,
+            buf: Vec::new(),
+            position: 0,
+            riff_end: None,
+            width: 0,
+            height: 0,
+            has_alpha: false,
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+            max_chunks: u32::MAX,
+            max_frames: u32::MAX,
+            strict: false,
+            info: Default::default(),
+            vp8x_flags: None,
+            image_alpha: false,
+            chunks: Vec::new(),
+            warnings: Vec::new(),
+            image_data: None,
+            icc_profile: None,
+            exif: None,
+            xmp: None,
+            have_metadata: false,
+            have_trailer: false,
+            have_frame: false,
+            metadata_error: None,
+            frame_error: None,
+            bytes_needed: None,
+            stats: Default::default(),
+            frame: f,
+            upsampling: Default::default(),
+            color_conversion: Default::default(),
+            pixel_order: Default::default(),
+            sixteen_bit: false,
+            premultiplied: false,
+            dither: Dither::None,
+            dither_errors: Vec::new(),
+            dither_row: 0,
+            alpha_dithering: false,
+            lossless_frame: None,
+            alpha: None,
+            animation: None,
+            first_frame: None,
+            apply_orientation: false,
+            orientation: None,
+            oriented: None,
+            scale_hints: None,
+            pixels: None,
+            decoded_rows: 0,
+            progress: None,
+            profile_callback: None,
+        }
+    }
+
+    /// Replaces the input with ```r``` to decode another image with this decoder.
+    ///
+    /// Everything read from the previous input is dropped. The settings and
+    /// callbacks are kept, and so are the allocated planes of the last lossy
+    /// frame, which the next lossy frame is decoded into.
+    pub fn reset(&mut self, r: R) {
+        let old = mem::replace(self, WebpDecoder::new(r));
+
+        self.slice = old.slice;
+        self.seek = old.seek;
+        self.buf = old.buf;
+        self.buf.clear();
+        self.max_width = old.max_width;
+        self.max_height = old.max_height;
+        self.max_pixels = old.max_pixels;
+        self.max_chunks = old.max_chunks;
+        self.max_frames = old.max_frames;
+        self.strict = old.strict;
+        self.frame = old.frame;
+        self.upsampling = old.upsampling;
+        self.color_conversion = old.color_conversion;
+        self.pixel_order = old.pixel_order;
+        self.sixteen_bit = old.sixteen_bit;
+        self.premultiplied = old.premultiplied;
+        self.dither = old.dither;
+        self.alpha_dithering = old.alpha_dithering;
+        self.apply_orientation = old.apply_orientation;
+        self.progress = old.progress;
+        self.profile_callback = old.profile_callback;
+    }
+
+    /// Limits the size of the images that are decoded.
+    ///
+    /// Images wider than ```max_width```, higher than ```max_height``` or with
+    /// more than ```max_pixels``` pixels fail with ```DimensionError``` as soon as
+    /// their header has been read, before any memory is allocated for their pixels.
+    /// This applies to the canvas as well as to each frame of an animation.
+    pub fn set_limits(&mut self, max_width: u32, max_height: u32, max_pixels: u64) {
+        self.max_width = max_width;
+        self.max_height = max_height;
+        self.max_pixels = max_pixels;
+    }
+
+    /// Limits the number of chunks that are read from the file and the number
+    /// of frames of animations.
+    ///
+    /// Files with more than ```max_chunks``` chunks, counting every chunk of the
+    /// file including unknown ones and the frames of animations, or with more than
+    /// ```max_frames``` frames fail with ```FormatError``` as soon as the chunk
+    /// over the limit is reached.
+    pub fn set_chunk_limits(&mut self, max_chunks: u32, max_frames: u32) {
+        self.max_chunks = max_chunks;
+        self.max_frames = max_frames;
+    }
+
+    /// Makes the decoder fail with ```FormatError``` on chunks that are tolerated
+    /// by default. This is off by default.
+    ///
+    /// In strict mode a chunk whose four-CC is not one of those of the WebP
+    /// container fails wherever it is, and so do chunks that are not in the order
+    /// of the specification: the VP8X chunk, an ICCP chunk, the image data, then
+    /// EXIF and XMP chunks, with no chunks after the image data of simple files.
+    /// The input is read up to its end before the image is decoded, so that
+    /// ```read_image``` fails as well as ```validate```. Each file of the stream
+    /// given to ```decode_all``` is checked the same way.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    /// Sets how the chroma planes of lossy images are upsampled when they are
+    /// converted to RGB. This defaults to ```Upsampling::Fancy```.
+    pub fn set_upsampling(&mut self, upsampling: Upsampling) {
+        self.upsampling = upsampling;
+    }
+
+    /// Sets how the YUV samples of lossy images are converted to RGB. This defaults
+    /// to the BT.601 conversion of studio range samples that WebP is specified with.
+    pub fn set_color_conversion(&mut self, conversion: ColorConversion) {
+        self.color_conversion = conversion;
+    }
+
+    /// Sets the order of the color channels of the output. This defaults to
+    /// ```PixelOrder::Rgba```.
+    ///
+    /// With ```PixelOrder::Bgra``` the pixels are written blue first as they are
+    /// converted, so they can be copied to a framebuffer as they are. ```colortype```
+    /// still reports ```RGB``` or ```RGBA```, and the callback of
+    /// ```set_color_profile_callback``` gets the pixels in this order. This applies
+    /// to the still image returned by ```read_image``` and the other methods that
+    /// read it, including ```read_image_rgba```, but not to ```get_pixel```,
+    /// ```into_image```, ```decode_all``` and the frames of animations.
+    pub fn set_pixel_order(&mut self, order: PixelOrder) {
+        self.pixel_order = order;
+    }
+
+    /// Makes ```read_image``` return 16 bits per channel, keeping the extra
+    /// precision of the conversion of lossy images from YUV to RGB.
+    ///
+    /// ```colortype``` then reports ```RGB(16)``` or ```RGBA(16)```. The other
+    /// methods that return pixels in that color type, such as ```read_scanline```,
+    /// fail with ```UnsupportedError```.
+    pub fn set_16_bit_output(&mut self, enable: bool) {
+        self.sixteen_bit = enable;
+    }
+
+    /// Makes the color channels of images with alpha come out multiplied by
+    /// their alpha value, rounded to the nearest value. This defaults to straight
+    /// alpha.
+    ///
+    /// This applies to the still image returned by ```read_image``` and the other
+    /// methods that read it, the frames of animations are left straight.
+    pub fn set_premultiplied_alpha(&mut self, enable: bool) {
+        self.premultiplied = enable;
+    }
+
+    /// Dithers the color channels of the output down to fewer bits per channel,
+    /// for displays and palettes that cannot show all 8 bits. This defaults to
+    /// ```Dither::None```.
+    ///
+    /// The reduced values are scaled back up to 8 bits, so the color type stays
+    /// the same and alpha is left as it is. This applies to the same output as
+    /// ```set_color_profile_callback```, after the callback. Floyd-Steinberg
+    /// dithering spreads the error of each row onto the next one read, so a read
+    /// that starts below the top of the image, such as ```read_region```, starts
+    /// without the error of the rows above it.
+    pub fn set_output_dither(&mut self, dither: Dither) {
+        self.dither = dither;
+    }
+
+    /// Smooths the steps between the alpha levels of lossy images whose encoder
+    /// reduced the number of levels, like the alpha dithering of libwebp at full
+    /// strength. This defaults to off, which keeps the reduced levels like
+    /// libwebp and ```dwebp``` do by default.
+    pub fn set_alpha_dithering(&mut self, enable: bool) {
+        self.alpha_dithering = enable;
+    }
+
+    /// Rotates and flips the decoded image upright according to the Orientation
+    /// tag of its EXIF metadata.
+    ///
+    /// This is off by default. When it is on, ```dimensions``` reports the size of
+    /// the upright image, which means reading the input up to its end as the EXIF
+    /// metadata is stored after the image data. Animations are left as they are.
+    /// This has to be called before anything is read from the image.
+    pub fn apply_orientation(&mut self, apply: bool) {
+        self.apply_orientation = apply;
+    }
+
+    /// Sets a function that is called with the number of rows done and the
+    /// total number of rows each time a row of the image has been read.
+    ///
+    /// This applies to ```read_image```, ```read_image_into``` and ```read_scanline```.
+    pub fn set_progress_callback(&mut self, callback: Box<FnMut(u32, u32) + Send>) {
+        self.progress = Some(callback);
+    }
+
+    /// Sets a function that is called with the ICC profile of the image and the
+    /// pixels of each row of the output, which it may change, for example to
+    /// convert them from the profile to another color space.
+    ///
+    /// It is only called if the image has an ICC profile. The pixels are in the
+    /// 8 bit color type reported by ```colortype```, also for ```read_image_rgba```,
+    /// which adds the alpha of images without it after the callback. They are
+    /// only passed to it once. This applies to the still image output of
+    /// ```read_image```, ```read_scanline``` and the other methods that read it,
+    /// but not to 16 bit output and the frames returned by ```into_frames```.
+    pub fn set_color_profile_callback(&mut self, callback: Box<FnMut(&[u8], &mut [u8]) + Send>) {
+        self.profile_callback = Some(callback);
+    }
+
+    // Reports that rows_done of the total rows of the output have been read
+    fn report_progress(&mut self, rows_done: u32, total: u32) {
+        if let Some(ref mut f) = self.progress {
+            f(rows_done, total);
+        }
+    }
+
+    // The 16 bit RGBA output of the image, at 8 bytes a pixel, also has to fit
+    // in the address space, which a VP8X canvas can exceed on 32 bit targets
+    fn check_limits(&self, width: u32, height: u32) -> ImageResult<()> {
+        let pixels = width as u64 * height as u64;
+        if width > self.max_width || height > self.max_height
+            || pixels > self.max_pixels || pixels.saturating_mul(8) > usize::MAX as u64 {
+            Err(image::ImageError::DimensionError)
+        } else {
+            Ok(())
+        }
+    }
+
+    // Applies parser to the start of the buffered input, reading more input
+    // for as long as the parser reports that it is incomplete.
+    fn parse_input<F, O>(&mut self, parser: F) -> ImageResult<O>
+        where F: Fn(&[u8]) -> IResult<&[u8], O> {
+        if let Some(slice) = self.slice {
+            let input = &slice(&self.r)[self.position as usize..];
+            let result = match parser(input) {
+                IResult::Done(rest, o) => Ok((rest.len(), o)),
+                IResult::Error(e) => return Err(image::ImageError::FormatError(format!("{}", e))),
+                IResult::Incomplete(Needed::Size(n)) => Err(n as u64),
+                IResult::Incomplete(Needed::Unknown) => Err(input.len() as u64 + 1),
+            };
+
+            let (rest, o) = match result {
+                Ok(done) => done,
+                Err(needed) => {
+                    let end = self.position + needed;
+                    return Err(self.end_of_input(end))
+                }
+            };
+            self.position = (slice(&self.r).len() - rest) as u64;
+
+            return Ok(o)
+        }
+
+        loop {
+            let needed = match parser(&self.buf) {
+                IResult::Done(rest, o) => {
+                    let consumed = self.buf.len() - rest.len();
+                    self.buf.drain(..consumed);
+                    self.position += consumed as u64;
+
+                    return Ok(o)
+                },
+                IResult::Error(e) => return Err(image::ImageError::FormatError(
+                    format!("{}", e)
+                )),
+                IResult::Incomplete(Needed::Size(n)) => n,
+                IResult::Incomplete(Needed::Unknown) => self.buf.len() + 1,
+            };
+
+            let missing = cmp::max(needed.saturating_sub(self.buf.len()), 1);
+            let n = try!((&mut self.r).take(missing as u64).read_to_end(&mut self.buf));
+            if n == 0 {
+                let end = self.position + (self.buf.len() + missing) as u64;
+                return Err(self.end_of_input(end))
+            }
+        }
+    }
+
+    // The error for input that ends in the middle of a chunk, which needs to be
+    // at least needed bytes long to be read further
+    fn end_of_input(&mut self, needed: u64) -> image::ImageError {
+        self.bytes_needed = Some(needed);
+
+        let available = match self.slice {
+            Some(slice) => slice(&self.r).len() as u64,
+            None => self.position + self.buf.len() as u64,
+        };
+
+        match self.riff_end {
+            Some(end) if end > available => image::ImageError::FormatError(format!(
+                "RIFF length of {} bytes exceeds the {} bytes of data", end - 8, available - 8
+            )),
+            _ => image::ImageError::NotEnoughData,
+        }
+    }
+
+    // Reads the next chunk, or returns None at the end of the input.
+    // Anything following the length given in the RIFF header is ignored, but the
+    // input may end before it, as long as it does not end inside of a chunk.
+    fn read_chunk(&mut self) -> ImageResult<Option<([u8; 4], Payload)>> {
+        if self.riff_end.map_or(false, |end| self.position >= end) {
+            return Ok(None)
+        }
+
+        let at_end = match self.slice {
+            Some(slice) => self.position as usize >= slice(&self.r).len(),
+            None => self.buf.is_empty() && try!((&mut self.r).take(1).read_to_end(&mut self.buf)) == 0,
+        };
+
+        if at_end {
+            return Ok(None)
+        }
+
+        if self.chunks.len() as u64 >= self.max_chunks as u64 {
+            return Err(image::ImageError::FormatError(
+                format!("File has more than {} chunks", self.max_chunks)
+            ))
+        }
+
+        let offset = self.position;
+        let (name, len) = try!(self.parse_input(chunk_header));
+        self.chunks.push(ChunkInfo { name: name, offset: offset, len: len });
+        if self.strict && chunk_rank(&name).is_none() {
+            return Err(image::ImageError::FormatError(
+                format!("Unknown {} chunk", String::from_utf8_lossy(&name))
+            ))
+        }
+        let len = len as usize;
+
+        let skip = match &name {
+            b"ICCP" | b"EXIF" | b"XMP " | b"ANMF" => self.seek.is_some(),
+            _ => false,
+        };
+
+        let payload = match self.slice {
+            None if skip => try!(self.skip_payload(len)),
+            Some(slice) => {
+                let start = self.position as usize;
+                if slice(&self.r).len() - start < len {
+                    return Err(self.end_of_input((start + len) as u64))
+                }
+
+                self.position += len as u64;
+                Payload::Borrowed(start, start + len)
+            },
+            None => Payload::Owned(try!(self.parse_input(|i: &[u8]| {
+                if i.len() < len {
+                    IResult::Incomplete(Needed::Size(len))
+                } else {
+                    IResult::Done(&i[len..], i[..len].to_vec())
+                }
+            }))),
+        };
+
+        // Chunks of odd length are followed by a padding byte, which some
+        // encoders leave out when the chunk is the last one of the file
+        let mut padding = "none";
+        if len % 2 != 0 {
+            let have_padding = match self.slice {
+                Some(slice) => (self.position as usize) < slice(&self.r).len(),
+                None => !self.buf.is_empty() || try!((&mut self.r).take(1).read_to_end(&mut self.buf)) == 1,
+            };
+
+            if have_padding {
+                if self.slice.is_none() {
+                    self.buf.remove(0);
+                }
+
+                self.position += 1;
+                padding = "read";
+            } else {
+                self.warnings.push(format!("{} chunk is missing its padding byte", String::from_utf8_lossy(&name)));
+                padding = "missing";
+            }
+        }
+
+        webp_debug!("{} chunk at offset {}: {} bytes{}, padding {}", String::from_utf8_lossy(&name), offset, len,
+                    if skip { " skipped" } else { "" }, padding);
+
+        Ok(Some((name, payload)))
+    }
+
+    // Seeks over a payload of len bytes. Its last byte is read to find out
+    // whether the input ends before it.
+    fn skip_payload(&mut self, len: usize) -> ImageResult<Payload> {
+        let start = self.position;
+        let buffered = cmp::min(len, self.buf.len());
+        self.buf.drain(..buffered);
+
+        if len > buffered {
+            let seek = self.seek.unwrap();
+            let _ = try!(seek(&mut self.r, (len - buffered - 1) as i64));
+
+            let mut last = Vec::new();
+            if try!((&mut self.r).take(1).read_to_end(&mut last)) == 0 {
+                self.position += (len - 1) as u64;
+                return Err(self.end_of_input(start + len as u64))
+            }
+        }
+
+        self.position += len as u64;
+
+        Ok(Payload::Skipped(start, len))
+    }
+
+    // Reads a skipped payload, going back to where the input was afterwards
+    fn read_skipped(&mut self, offset: u64, len: usize) -> ImageResult<Vec<u8>> {
+        let seek = self.seek.unwrap();
+        let distance = offset as i64 - (self.position + self.buf.len() as u64) as i64;
+        let _ = try!(seek(&mut self.r, distance));
+
+        let mut data = Vec::with_capacity(len);
+        let n = try!((&mut self.r).take(len as u64).read_to_end(&mut data));
+        let _ = try!(seek(&mut self.r, -distance - n as i64));
+
+        if n < len {
+            self.bytes_needed = Some(offset + len as u64);
+            return Err(image::ImageError::NotEnoughData)
+        }
+
+        Ok(data)
+    }
+
+    // A copy of the payload of a metadata chunk, read from the input if it was skipped
+    fn metadata_chunk(&mut self, payload: fn(&Self) -> &Option<Payload>) -> ImageResult<Option<Vec<u8>>> {
+        let skipped = match *payload(self) {
+            None => return Ok(None),
+            Some(Payload::Skipped(offset, len)) => (offset, len),
+            Some(ref p) => return Ok(Some(self.bytes(p).to_vec())),
+        };
+
+        self.read_skipped(skipped.0, skipped.1).map(Some)
+    }
+
+    // The bytes of a chunk payload
+    fn bytes<'b>(&'b self, payload: &'b Payload) -> &'b [u8] {
+        match *payload {
+            Payload::Owned(ref data) => data,
+            Payload::Borrowed(start, end) => &self.slice.unwrap()(&self.r)[start..end],
+            Payload::Skipped(..) => panic!("skipped payloads have to be read first"),
+        }
+    }
+
+    // Takes the payload of a chunk that is kept by the decoder
+    fn into_vec(&self, payload: Payload) -> Vec<u8> {
+        match payload {
+            Payload::Owned(data) => data,
+            ref borrowed => self.bytes(borrowed).to_vec(),
+        }
+    }
+
+    fn read_required_chunk(&mut self) -> ImageResult<([u8; 4], Payload)> {
+        match try!(self.read_chunk()) {
+            Some(chunk) => Ok(chunk),
+            None if self.riff_end.map_or(false, |end| self.position >= end) => Err(
+                image::ImageError::FormatError(String::from("RIFF length ends before the image data"))
+            ),
+            None => {
+                // The next chunk needs at least its header
+                self.bytes_needed = Some(self.position + 8);
+                Err(image::ImageError::NotEnoughData)
+            },
+        }
+    }
+
+
+    // Reads the container up to and including the chunks holding the image,
+    // leaving any chunks after it, such as EXIF and XMP metadata, unread.
+    // The image itself is decoded by read_frame.
+    fn read_metadata(&mut self) -> ImageResult<()> {
+        if let Some(ref err) = self.metadata_error {
+            return Err(copy_error(err))
+        }
+
+        if !self.have_metadata {
+            let timer = Timer::start();
+            let result = self.read_container();
+            timer.add_to(&mut self.stats.header);
+            if let Err(ref err) = result {
+                self.metadata_error = Some(copy_error(err));
+            }
+
+            try!(result);
+            self.have_metadata = true;
+        }
+
+        Ok(())
+    }
+
+    fn read_container(&mut self) -> ImageResult<()> {
+        let riff_len = try!(self.parse_input(riff_header));
+        webp_debug!("RIFF container: {} bytes", riff_len);
+
+        // The length counts the bytes following the length field
+        self.riff_end = Some(8 + riff_len as u64);
+
+        let (name, data) = try!(self.read_required_chunk());
+        match &name {
+            b"VP8 " => try!(self.set_image_data(ImageData::Lossy(data))),
+            b"VP8L" => try!(self.set_image_data(ImageData::Lossless(data))),
+            b"VP8X" => {
+                let vp8x = self.into_vec(data);
+                try!(self.read_extended(vp8x))
+            },
+            _ => return Err(unexpected_chunk(&name)),
+        }
+
+        let (width, height) = (self.width, self.height);
+        try!(self.check_limits(width, height));
+
+        if self.strict {
+            try!(self.read_trailer_chunks());
+        }
+
+        Ok(())
+    }
+
+    fn set_image_data(&mut self, image: ImageData) -> ImageResult<()> {
+        let (width, height) = match image {
+            ImageData::Lossy(ref vp8) | ImageData::LossyWithAlpha(ref vp8, _) => try!(vp8_dimensions(self.bytes(vp8))),
+            ImageData::Lossless(ref vp8l) => try!(vp8l_dimensions(self.bytes(vp8l))),
+        };
+
+        try!(self.check_limits(width, height));
+
+        self.width = width;
+        self.height = height;
+        self.scale_hints = match image {
+            ImageData::Lossy(ref vp8) | ImageData::LossyWithAlpha(ref vp8, _) => Some(vp8_scale_hints(self.bytes(vp8))),
+            ImageData::Lossless(_) => None,
+        };
+        self.has_alpha = match image {
+            ImageData::Lossy(_) => false,
+            _ => true,
+        };
+
+        self.info = WebpInfo {
+            width: width,
+            height: height,
+            has_alpha: match image {
+                ImageData::Lossy(_) => false,
+                ImageData::LossyWithAlpha(..) => true,
+                ImageData::Lossless(ref vp8l) => vp8l_alpha_hint(self.bytes(vp8l)),
+            },
+            has_animation: false,
+            is_lossless: match image {
+                ImageData::Lossless(_) => true,
+                _ => false,
+            },
+            icc_present: false,
+        };
+        self.image_alpha = self.info.has_alpha;
+
+        self.image_data = Some(image);
+
+        Ok(())
+    }
+
+    // Reads a chunk following the VP8X chunk, which must not end before the image data
+    fn read_extended_chunk(&mut self) -> ImageResult<([u8; 4], Payload)> {
+        match try!(self.read_chunk()) {
+            Some(chunk) => Ok(chunk),
+            // The input ends before the RIFF container does
+            None if self.riff_end.map_or(false, |end| self.position < end) => {
+                let end = self.position + 8;
+                Err(self.end_of_input(end))
+            },
+            None => Err(no_image_data()),
+        }
+    }
+
+    // Reads the chunks following a VP8X chunk up to the image data
+    fn read_extended(&mut self, vp8x: Vec<u8>) -> ImageResult<()> {
+        let (flags, canvas_width, canvas_height) = try!(read_vp8x(&vp8x));
+
+        // The metadata chunks may come in any order, before or after the image data
+        let (mut name, mut data) = try!(self.read_extended_chunk());
+        while &name == b"ICCP" || &name == b"EXIF" || &name == b"XMP " {
+            let (next_name, next_data) = try!(self.read_extended_chunk());
+            self.keep_metadata_chunk(name, data);
+            name = next_name;
+            data = next_data;
+        }
+
+        match &name {
+            b"ANIM" => {
+                let anim = self.into_vec(data);
+                try!(self.read_animation(canvas_width, canvas_height, &anim))
+            },
+            b"ALPH" => {
+                let (next_name, image) = try!(self.read_required_chunk());
+                match &next_name {
+                    b"VP8 " => try!(self.set_image_data(ImageData::LossyWithAlpha(image, data))),
+                    // VP8L image data has alpha of its own, so the ALPH chunk is
+                    // ignored like libwebp does
+                    b"VP8L" => {
+                        self.warnings.push(String::from("ALPH chunk is ignored for VP8L image data"));
+                        try!(self.set_image_data(ImageData::Lossless(image)))
+                    },
+                    _ => return Err(unexpected_chunk(&next_name)),
+                }
+            },
+            b"VP8 " => try!(self.set_image_data(ImageData::Lossy(data))),
+            b"VP8L" => try!(self.set_image_data(ImageData::Lossless(data))),
+            _ => return Err(unexpected_chunk(&name)),
+        }
+
+        // A frame smaller than the canvas is placed in its corner, a larger one
+        // contradicts it
+        if self.animation.is_none() && (self.width > canvas_width || self.height > canvas_height) {
+            return Err(image::ImageError::FormatError(format!(
+                "VP8X canvas of {}x{} is smaller than the {}x{} image", canvas_width, canvas_height, self.width, self.height
+            )))
+        }
+
+        // The canvas is the size of the output, even if the frame is smaller
+        self.width = canvas_width;
+        self.height = canvas_height;
+
+        self.vp8x_flags = Some(flags);
+        self.image_alpha = match self.animation {
+            Some(ref anim) => anim.alpha,
+            None => self.info.has_alpha,
+        };
+
+        // The extended header describes the whole file
+        self.info.width = canvas_width;
+        self.info.height = canvas_height;
+        self.info.has_alpha = flags & ALPHA_FLAG != 0;
+        self.info.has_animation = flags & ANIMATION_FLAG != 0;
+        self.info.icc_present = flags & ICCP_FLAG != 0;
+
+        Ok(())
+    }
+
+    // Reads the ANMF chunks following the ANIM chunk
+    fn read_animation(&mut self, canvas_width: u32, canvas_height: u32, anim: &[u8]) -> ImageResult<()> {
+        let params = try!(anim::read_anim(anim));
+
+        let mut frames = Vec::new();
+        let (mut alpha, mut lossless) = (false, true);
+        loop {
+            match try!(self.read_chunk()) {
+                Some((name, data)) if &name == b"ANMF" => {
+                    if frames.len() as u64 >= self.max_frames as u64 {
+                        return Err(image::ImageError::FormatError(
+                            format!("Animation has more than {} frames", self.max_frames)
+                        ))
+                    }
+
+                    let head = match data {
+                        Payload::Skipped(offset, len) => try!(self.read_skipped(offset, cmp::min(len, ANMF_HEAD_LEN))),
+                        ref data => {
+                            let data = self.bytes(data);
+                            data[..cmp::min(data.len(), ANMF_HEAD_LEN)].to_vec()
+                        },
+                    };
+                    let info = try!(anim::read_anmf_header(&head));
+                    try!(self.check_limits(info.width, info.height));
+                    alpha = alpha || frame_alpha_hint(&head[16..]);
+                    lossless = lossless && head[16..].starts_with(b"VP8L");
+
+                    // The image data follows the header
+                    let image = match data {
+                        Payload::Owned(mut data) => {
+                            data.drain(..16);
+                            Payload::Owned(data)
+                        },
+                        Payload::Borrowed(start, end) => Payload::Borrowed(start + 16, end),
+                        Payload::Skipped(offset, len) => Payload::Skipped(offset + 16, len - 16),
+                    };
+                    frames.push((info, image));
+                },
+                Some((name, data)) => {
+                    self.keep_metadata_chunk(name, data);
+                    break
+                },
+                None => break,
+            }
+        }
+
+        if frames.is_empty() {
+            return Err(image::ImageError::FormatError(
+                String::from("Animation without frames")
+            ))
+        }
+
+        self.width = canvas_width;
+        self.height = canvas_height;
+        self.has_alpha = true;
+        self.info.is_lossless = lossless;
+        self.animation = Some(Animation {
+            canvas_width: canvas_width,
+            canvas_height: canvas_height,
+            params: params,
+            frames: frames,
+            alpha: alpha,
+        });
+
+        Ok(())
+    }
+
+    // Reads the container up to the end of the input
+    fn read_trailer(&mut self) -> ImageResult<()> {
+        try!(self.read_metadata());
+
+        self.read_trailer_chunks()
+    }
+
+    // Reads the chunks following the image data up to the end of the input
+    fn read_trailer_chunks(&mut self) -> ImageResult<()> {
+        while !self.have_trailer {
+            match try!(self.read_chunk()) {
+                Some((name, data)) => self.keep_metadata_chunk(name, data),
+                None => {
+                    self.have_trailer = true;
+                    try!(self.check_trailer());
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    // Records the problems with the end of the file and the VP8X flags as warnings
+    fn check_trailer(&mut self) -> ImageResult<()> {
+        if self.strict {
+            try!(self.check_chunk_order());
+        }
+
+        let end = self.riff_end.unwrap();
+        if self.position > end {
+            self.warnings.push(String::from("The last chunk extends past the end of the RIFF container"));
+        } else if self.position < end {
+            self.warnings.push(format!("RIFF length exceeds the data by {} bytes", end - self.position));
+        } else if try!(self.trailing_data()) {
+            self.warnings.push(String::from("Data follows the end of the RIFF container"));
+        }
+
+        for d in self.flag_discrepancies() {
+            self.warnings.push(format!("{}", d));
+        }
+
+        Ok(())
+    }
+
+    // Fails on the first chunk that is out of the order of the specification
+    fn check_chunk_order(&self) -> ImageResult<()> {
+        let mut previous: Option<&[u8; 4]> = None;
+        for chunk in &self.chunks {
+            let in_order = match previous {
+                None => true,
+                // Simple files hold nothing but the image data
+                Some(p) if p != b"VP8X" && self.vp8x_flags.is_none() => false,
+                // Each chunk but the frames of an animation comes once
+                Some(p) => chunk_rank(&chunk.name) > chunk_rank(p) || (p == b"ANMF" && &chunk.name == b"ANMF"),
+            };
+
+            if !in_order {
+                return Err(image::ImageError::FormatError(format!(
+                    "{} chunk is out of order after the {} chunk",
+                    String::from_utf8_lossy(&chunk.name), String::from_utf8_lossy(previous.unwrap())
+                )))
+            }
+
+            previous = Some(&chunk.name);
+        }
+
+        Ok(())
+    }
+
+    // Whether there is more input after the current position
+    fn trailing_data(&mut self) -> ImageResult<bool> {
+        Ok(match self.slice {
+            Some(slice) => slice(&self.r).len() as u64 > self.position,
+            None => !self.buf.is_empty() || try!((&mut self.r).take(1).read_to_end(&mut self.buf)) > 0,
+        })
+    }
+
+    fn keep_metadata_chunk(&mut self, name: [u8; 4], data: Payload) {
+        match &name {
+            b"ICCP" => self.icc_profile = Some(data),
+            b"EXIF" => self.exif = Some(data),
+            b"XMP " => self.xmp = Some(data),
+            _ => (),
+        }
+    }
+
+    // Decodes the pixels of a still image, or the first frame of an animation
+    fn read_frame(&mut self) -> ImageResult<()> {
+        try!(self.read_metadata());
+
+        if let Some(ref err) = self.frame_error {
+            return Err(copy_error(err))
+        }
+
+        if !self.have_frame {
+            let result = self.decode_frame();
+            if let Err(ref err) = result {
+                self.frame_error = Some(copy_error(err));
+            }
+
+            try!(result);
+            self.have_frame = true;
+        }
+
+        Ok(())
+    }
+
+    fn decode_frame(&mut self) -> ImageResult<()> {
+        if let Some(image) = self.image_data.take() {
+            try!(self.read_image_data(image));
+        } else if self.animation.is_some() {
+            try!(self.read_first_frame());
+        }
+
+        try!(self.read_orientation());
+        let timer = Timer::start();
+        self.oriented = match self.orientation {
+            Some(o) if o != 1 => Some(self.orient(o)),
+            _ => None,
+        };
+        timer.add_to(&mut self.stats.conversion);
+
+        if self.profile_callback.is_some() {
+            let _ = try!(self.load_icc_profile());
+        }
+
+        Ok(())
+    }
+
+    // Finds the orientation of the output, reading the EXIF metadata if it is applied
+    fn read_orientation(&mut self) -> ImageResult<()> {
+        if self.orientation.is_some() {
+            return Ok(())
+        }
+
+        if !self.apply_orientation {
+            try!(self.read_metadata());
+            self.orientation = Some(1);
+            return Ok(())
+        }
+
+        try!(self.read_trailer());
+        let exif = match self.animation {
+            Some(_) => None,
+            None => try!(self.metadata_chunk(|d| &d.exif)),
+        };
+
+        self.orientation = match exif {
+            Some(ref exif) => Some(orientation::read_orientation(exif).unwrap_or(1)),
+            None => Some(1),
+        };
+
+        Ok(())
+    }
+
+    // Returns the decoded frame rotated and flipped according to orientation
+    fn orient(&self, orientation: u16) -> Vec<u8> {
+        let rlen = self.channels() * self.width as usize;
+        let mut buf = vec![0u8; rlen * self.height as usize];
+
+        for (y, row) in buf.chunks_mut(rlen).enumerate() {
+            self.fill_canvas_row(y, row);
+        }
+
+        orientation::orient(orientation, &buf, self.width as usize, self.height as usize, self.channels())
+    }
+
+    // The size of the output, which is turned on its side by some orientations
+    fn output_dimensions(&self) -> (u32, u32) {
+        match self.orientation {
+            Some(o) if orientation::transposes(o) => (self.height, self.width),
+            _ => (self.width, self.height),
+        }
+    }
+
+    fn read_image_data(&mut self, image: ImageData) -> ImageResult<()> {
+        self.lossless_frame = None;
+        self.alpha = None;
+
+        match image {
+            ImageData::Lossy(vp8) => {
+                let frame = mem::replace(&mut self.frame, Default::default());
+                let mut stats = self.stats;
+                self.frame = try!(decode_vp8(self.bytes(&vp8), frame, &mut stats));
+                self.stats = stats;
+            },
+            ImageData::LossyWithAlpha(vp8, alph) => {
+                let frame = mem::replace(&mut self.frame, Default::default());
+                let mut stats = self.stats;
+                self.frame = try!(decode_vp8(self.bytes(&vp8), frame, &mut stats));
+                self.stats = stats;
+
+                let timer = Timer::start();
+                self.alpha = Some(try!(alpha::decode_alpha(
+                    self.bytes(&alph), self.frame.width, self.frame.height, self.alpha_dithering
+                )));
+                timer.add_to(&mut self.stats.entropy);
+            },
+            ImageData::Lossless(vp8l) => {
+                // Checked again for the frames of animations, which are read
+                // after the container
+                let _ = try!(vp8l_dimensions(self.bytes(&vp8l)));
+
+                let timer = Timer::start();
+                self.lossless_frame = Some(try!(decode_vp8l(self.bytes(&vp8l))));
+                timer.add_to(&mut self.stats.entropy);
+            },
+        }
+
+        Ok(())
+    }
+
+    // Draws the first frame of the animation onto the canvas, the same way as
+    // the first of into_frames
+    fn read_first_frame(&mut self) -> ImageResult<()> {
+        // The animation is put back before returning any error
+        let anim = self.animation.take().unwrap();
+        let info = anim.frames[0].0;
+        let rgba = self.read_frame_rgba(&info, &anim.frames[0].1);
+        let mut canvas = Canvas::new(anim.canvas_width, anim.canvas_height, anim.params.background_color);
+        self.animation = Some(anim);
+
+        let rgba = try!(rgba);
+        let timer = Timer::start();
+        try!(canvas.draw(&info, &rgba));
+        timer.add_to(&mut self.stats.conversion);
+        self.first_frame = Some(canvas.buf);
+
+        Ok(())
+    }
+
+    // Parses the image data of an animation frame, reading it from the input
+    // first if it was skipped
+    fn frame_image_data(&mut self, data: &Payload) -> ImageResult<ImageData> {
+        let skipped;
+        let data = match *data {
+            Payload::Skipped(offset, len) => {
+                skipped = Payload::Owned(try!(self.read_skipped(offset, len)));
+                &skipped
+            },
+            ref data => data,
+        };
+
+        match image_chunks(self.bytes(data)) {
+            IResult::Done(_, image) => Ok(image),
+            IResult::Error(e) => Err(image::ImageError::FormatError(
+                format!("{}", e)
+            )),
+            IResult::Incomplete(_) => Err(image::ImageError::NotEnoughData),
+        }
+    }
+
+    // Decodes the image data of an animation frame to RGBA
+    fn read_frame_rgba(&mut self, info: &AnimationFrame, data: &Payload) -> ImageResult<Vec<u8>> {
+        let image = try!(self.frame_image_data(data));
+        try!(self.read_image_data(image));
+
+        if self.frame_dimensions() != (info.width, info.height) {
+            return Err(image::ImageError::FormatError(
+                String::from("Animation frame size does not match its image data")
+            ))
+        }
+
+        let timer = Timer::start();
+        let width = info.width as usize;
+        let bpp = if self.frame_has_alpha() { 4 } else { 3 };
+        let mut row = vec![0u8; bpp * width];
+        let mut rgba = Vec::with_capacity(4 * width * info.height as usize);
+
+        for y in 0..info.height as usize {
+            self.fill_row(y, &mut row, PixelOrder::Rgba);
+            extend_rgba(&mut rgba, &row, bpp);
+        }
+        timer.add_to(&mut self.stats.conversion);
+
+        Ok(rgba)
+    }
+
+    /// Returns the basic properties of the image.
+    ///
+    /// This reads the container up to the image data, but does not decode any pixels.
+    pub fn info(&mut self) -> ImageResult<WebpInfo> {
+        let _ = try!(self.read_metadata());
+
+        Ok(self.info)
+    }
+
+    /// Returns the flags of the VP8X chunk as a little endian 32 bit value,
+    /// including the reserved bits.
+    ///
+    /// Returns ```None``` for simple images, which have no VP8X chunk.
+    pub fn vp8x_flags(&mut self) -> ImageResult<Option<u32>> {
+        let _ = try!(self.read_metadata());
+
+        Ok(self.vp8x_flags)
+    }
+
+    /// Checks the VP8X flags of the image against what the image contains.
+    ///
+    /// Returns a discrepancy for each flag that is set without the image having
+    /// the feature or the other way round, and one for any reserved bits that are
+    /// set. The list is empty for simple images, which have no flags. This reads
+    /// the input up to its end but does not decode any pixels.
+    pub fn validate(&mut self) -> ImageResult<Vec<FlagDiscrepancy>> {
+        let _ = try!(self.read_trailer());
+
+        Ok(self.flag_discrepancies())
+    }
+
+    fn flag_discrepancies(&self) -> Vec<FlagDiscrepancy> {
+        let flags = match self.vp8x_flags {
+            Some(flags) => flags,
+            None => return Vec::new(),
+        };
+
+        let mut discrepancies = Vec::new();
+
+        let reserved = flags & !VP8X_FLAGS.iter().fold(0, |bits, f| bits | f.bit());
+        if reserved != 0 {
+            discrepancies.push(FlagDiscrepancy::ReservedBits(reserved));
+        }
+
+        for &flag in VP8X_FLAGS.iter() {
+            let has_chunk = |name: &[u8; 4]| self.chunks.iter().any(|c| &c.name == name);
+            let present = match flag {
+                VP8XFlag::IccProfile => has_chunk(b"ICCP"),
+                VP8XFlag::Alpha => self.image_alpha,
+                VP8XFlag::Exif => has_chunk(b"EXIF"),
+                VP8XFlag::Xmp => has_chunk(b"XMP "),
+                VP8XFlag::Animation => has_chunk(b"ANIM"),
+            };
+
+            match (flags & flag.bit() != 0, present) {
+                (true, false) => discrepancies.push(FlagDiscrepancy::MissingFeature(flag)),
+                (false, true) => discrepancies.push(FlagDiscrepancy::UnflaggedFeature(flag)),
+                _ => (),
+            }
+        }
+
+        discrepancies
+    }
+
+    /// Returns the length in bytes that the input needs to have, counted from the
+    /// start of the file, for the decoder to get further than it did, after a
+    /// read failed because the input ended.
+    ///
+    /// A caller streaming the file in can fetch up to that length and decode it
+    /// again with a new decoder, as the error of the read is returned again by
+    /// this one. The length is only the next step of the container: the rest of
+    /// a chunk, or the header of the next one. Returns ```None``` if the input has
+    /// not ended.
+    pub fn bytes_needed(&self) -> Option<u64> {
+        self.bytes_needed
+    }
+
+    /// Returns the number of bytes of the input following the end of the RIFF
+    /// container, such as another file of a concatenated stream or a payload
+    /// appended to the file.
+    ///
+    /// This reads the input up to its end. Unless the decoder was created with
+    /// ```from_slice```, the bytes following the container are read into memory
+    /// to count them, which does not stop the image from being read afterwards.
+    /// Returns 0 if the input ends with the container, or before it.
+    pub fn remaining_bytes(&mut self) -> ImageResult<u64> {
+        let _ = try!(self.read_trailer());
+
+        Ok(match self.slice {
+            Some(slice) => slice(&self.r).len() as u64 - self.position,
+            None => {
+                let _ = try!(self.r.read_to_end(&mut self.buf));
+                self.buf.len() as u64
+            },
+        })
+    }
+
+    /// Returns the problems found so far that were tolerated instead of failing
+    /// decoding, such as a missing padding byte, data following the RIFF container
+    /// or VP8X flags that disagree with the chunks, and clears them.
+    ///
+    /// The problems with the end of the file and the flags are found once the
+    /// input has been read up to its end, for example by ```chunks``` or
+    /// ```validate```.
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        mem::replace(&mut self.warnings, Vec::new())
+    }
+
+    /// Checks that the file is structurally sound, without decoding any pixels.
+    ///
+    /// This reads the input up to its end and checks that the chunks fill the
+    /// RIFF container exactly, that the image data is present, that the VP8, VP8L
+    /// and ALPH headers of the image, or of each animation frame, are valid, and
+    /// that their sizes agree with the canvas. Returns a ```FormatError``` describing
+    /// the first problem found.
+    pub fn verify_structure(&mut self) -> ImageResult<()> {
+        let _ = try!(self.read_trailer());
+
+        let end = self.riff_end.unwrap();
+        if self.position > end {
+            return Err(verify_error("The last chunk extends past the end of the RIFF container"))
+        } else if self.position < end {
+            return Err(self.end_of_input(end))
+        }
+
+        if try!(self.trailing_data()) {
+            return Err(verify_error("Data follows the end of the RIFF container"))
+        }
+
+        if let Some(anim) = self.animation.take() {
+            let verified = self.verify_frames(&anim);
+            self.animation = Some(anim);
+            try!(verified);
+        } else if let Some(ref image) = self.image_data {
+            if try!(self.verify_image_data(image)) != (self.width, self.height) {
+                return Err(verify_error("VP8X canvas size does not match the image"))
+            }
+        } else if let Some(ref err) = self.frame_error {
+            return Err(copy_error(err))
+        }
+
+        Ok(())
+    }
+
+    // Checks the image data of each frame of an animation and its placement
+    fn verify_frames(&mut self, anim: &Animation) -> ImageResult<()> {
+        for &(ref info, ref data) in anim.frames.iter() {
+            let image = match self.frame_image_data(data) {
+                Ok(image) => image,
+                Err(_) => return Err(verify_error("Animation frame has no image data")),
+            };
+
+            if try!(self.verify_image_data(&image)) != (info.width, info.height) {
+                return Err(verify_error("Animation frame size does not match its image data"))
+            }
+
+            if info.x_offset + info.width > anim.canvas_width || info.y_offset + info.height > anim.canvas_height {
+                return Err(verify_error("Animation frame extends past the canvas"))
+            }
+        }
+
+        Ok(())
+    }
+
+    // Checks the headers of the chunks holding an image, returns its size
+    fn verify_image_data(&self, image: &ImageData) -> ImageResult<(u32, u32)> {
+        match *image {
+            ImageData::Lossy(ref vp8) => verify_vp8(self.bytes(vp8)),
+            ImageData::LossyWithAlpha(ref vp8, ref alph) => {
+                let (width, height) = try!(verify_vp8(self.bytes(vp8)));
+                try!(verify_alph(self.bytes(alph), width, height));
+
+                Ok((width, height))
+            },
+            ImageData::Lossless(ref vp8l) => verify_vp8l(self.bytes(vp8l)),
+        }
+    }
+
+    /// Returns the number of channels of the color type reported by ```colortype```,
+    /// 3 for RGB and 4 for RGBA.
+    pub fn channel_count(&mut self) -> ImageResult<u8> {
+        let colortype = try!(self.colortype());
+
+        Ok(color::num_components(colortype) as u8)
+    }
+
+    /// Returns the number of bytes of a single pixel of the color type reported
+    /// by ```colortype```, which is twice the channel count for 16 bit output.
+    pub fn bytes_per_pixel(&mut self) -> ImageResult<usize> {
+        let colortype = try!(self.colortype());
+
+        Ok(color::bits_per_pixel(colortype) / 8)
+    }
+
+    /// Returns the ICC color profile embedded in the image.
+    ///
+    /// Returns ```None``` if the image does not contain a profile. A profile that
+    /// the VP8X flags announce, but that is not before the image data, is looked
+    /// for up to the end of the input.
+    pub fn icc_profile(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(try!(self.icc_profile_bytes()).map(|p| p.to_vec()))
+    }
+
+    /// Returns the ICC color profile embedded in the image without copying it,
+    /// like ```icc_profile```.
+    ///
+    /// The profile is borrowed from the input of a decoder made with ```from_slice```,
+    /// otherwise from the copy the decoder keeps. A profile skipped by a decoder
+    /// made with ```new_seekable``` is read the first time it is asked for.
+    pub fn icc_profile_bytes(&mut self) -> ImageResult<Option<&[u8]>> {
+        if !try!(self.load_icc_profile()) {
+            return Ok(None)
+        }
+
+        Ok(match self.icc_profile {
+            Some(ref p) => Some(self.bytes(p)),
+            None => None,
+        })
+    }
+
+    // Finds the ICC profile, which is looked for after the image data as well if
+    // the VP8X flags have one that is not before it, and reads it if it was skipped.
+    // Returns whether there is one.
+    fn load_icc_profile(&mut self) -> ImageResult<bool> {
+        let _ = try!(self.read_metadata());
+
+        let flagged = self.vp8x_flags.map_or(false, |flags| flags & ICCP_FLAG != 0);
+        if self.icc_profile.is_none() && flagged {
+            let _ = try!(self.read_trailer());
+        }
+
+        if let Some(Payload::Skipped(offset, len)) = self.icc_profile {
+            let data = try!(self.read_skipped(offset, len));
+            self.icc_profile = Some(Payload::Owned(data));
+        }
+
+        Ok(self.icc_profile.is_some())
+    }
+
+    /// Returns the raw EXIF metadata of the image, starting with the TIFF header.
+    ///
+    /// The metadata is stored after the image data, so this reads the input
+    /// up to its end. Returns ```None``` if the image has no EXIF chunk.
+    pub fn exif_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        let _ = try!(self.read_trailer());
+
+        self.metadata_chunk(|d| &d.exif)
+    }
+
+    /// Returns the raw XMP metadata of the image.
+    ///
+    /// Like EXIF, the metadata is stored after the image data and this reads
+    /// the input up to its end. Returns ```None``` if the image has no XMP chunk.
+    pub fn xmp_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        let _ = try!(self.read_trailer());
+
+        self.metadata_chunk(|d| &d.xmp)
+    }
+
+    /// Returns the top level chunks of the RIFF container in the order they are stored.
+    ///
+    /// The chunks of an animation frame are part of its ANMF chunk and are not
+    /// listed. This reads the input up to its end.
+    pub fn chunks(&mut self) -> ImageResult<Vec<ChunkInfo>> {
+        let _ = try!(self.read_trailer());
+
+        Ok(self.chunks.clone())
+    }
+
+    /// Returns the placement and timing of each frame of an animated image.
+    ///
+    /// Returns an empty list if the image is not animated.
+    pub fn animation_frames(&mut self) -> ImageResult<Vec<AnimationFrame>> {
+        let _ = try!(self.read_metadata());
+
+        Ok(match self.animation {
+            Some(ref a) => a.frames.iter().map(|f| f.0).collect(),
+            None => Vec::new(),
+        })
+    }
+
+    /// Returns the number of frames of an animated image, how long it plays and
+    /// the background color and loop count from its ANIM chunk, without decoding
+    /// any frames.
+    ///
+    /// Returns ```None``` if the image is not animated.
+    pub fn animation_info(&mut self) -> ImageResult<Option<AnimationInfo>> {
+        let _ = try!(self.read_metadata());
+
+        Ok(self.animation.as_ref().map(|a| AnimationInfo {
+            frame_count: a.frames.len() as u32,
+            duration: a.frames.iter().map(|f| f.0.duration as u64).sum(),
+            background_color: a.params.background_color,
+            loop_count: a.params.loop_count,
+        }))
+    }
+
+    /// Returns the coding parameters from the VP8 frame header of the image, or
+    /// of the first frame of an animation. Only the header is read, no
+    /// macroblocks are decoded.
+    ///
+    /// Returns ```None``` for lossless images, which have no VP8 frame.
+    pub fn frame_header_info(&mut self) -> ImageResult<Option<FrameHeaderInfo>> {
+        let _ = try!(self.read_metadata());
+
+        if self.have_frame {
+            return Ok(match self.lossless_frame {
+                Some(_) => None,
+                None => Some(self.frame.header_info()),
+            })
+        }
+
+        self.read_vp8_header(|vp8| vp8.header_info())
+    }
+
+    /// Returns the quantizer indices of the VP8 frame of the image, or of the
+    /// first frame of an animation, and the segment of each of its macroblocks,
+    /// which show how the encoder spent bits on the parts of the image. Only the
+    /// frame header and the macroblock headers are read, no coefficients.
+    ///
+    /// Returns ```None``` for lossless images, which have no VP8 frame.
+    pub fn quantizer_info(&mut self) -> ImageResult<Option<QuantizerInfo>> {
+        let _ = try!(self.read_metadata());
+
+        if self.have_frame {
+            return Ok(match self.lossless_frame {
+                Some(_) => None,
+                None => Some(self.frame.quantizer_info()),
+            })
+        }
+
+        self.read_vp8_header(|vp8| vp8.quantizer_info())
+    }
+
+    // Reads the headers of the VP8 frame of the still image, or of the first frame
+    // of an animation, with read. Returns None for lossless image data. The data
+    // of a still image is only gone once decoding it has failed.
+    fn read_vp8_header<T, F>(&mut self, read: F) -> ImageResult<Option<T>>
+        where F: FnOnce(&mut VP8Decoder<io::Cursor<&[u8]>>) -> ImageResult<T>
+    {
+        let first_frame = match self.animation.take() {
+            Some(anim) => {
+                let image = self.frame_image_data(&anim.frames[0].1);
+                self.animation = Some(anim);
+                Some(try!(image))
+            },
+            None => None,
+        };
+
+        let image = match (&first_frame, &self.image_data) {
+            (&Some(ref image), _) | (&None, &Some(ref image)) => image,
+            (&None, &None) => return Err(self.frame_error.as_ref().map_or_else(no_image_data, copy_error)),
+        };
+
+        let data = match *image {
+            ImageData::Lossy(ref vp8) | ImageData::LossyWithAlpha(ref vp8, _) => self.bytes(vp8),
+            ImageData::Lossless(_) => return Ok(None),
+        };
+        try!(check_keyframe(data));
+
+        read(&mut VP8Decoder::new(io::Cursor::new(data))).map(Some)
+    }
+
+    /// Returns the horizontal and vertical upscaling that the VP8 frame header of
+    /// a lossy still image asks it to be displayed with.
+    ///
+    /// Returns ```None``` for lossless images and animations. Only the header is
+    /// read, no pixels are decoded.
+    pub fn scale_hints(&mut self) -> ImageResult<Option<(ScaleHint, ScaleHint)>> {
+        let _ = try!(self.read_metadata());
+
+        Ok(self.scale_hints)
+    }
+
+    /// Returns the size the image is meant to be displayed at, which is the
+    /// size from ```dimensions``` upscaled according to ```scale_hints```.
+    ///
+    /// The decoded image is not resized, ```imageops::resize``` can scale it to
+    /// this size.
+    pub fn display_dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        let _ = try!(self.read_orientation());
+
+        let (horizontal, vertical) = self.scale_hints.unwrap_or_default();
+        let (width, height) = (horizontal.apply(self.width), vertical.apply(self.height));
+
+        Ok(match self.orientation {
+            Some(o) if orientation::transposes(o) => (height, width),
+            _ => (width, height),
+        })
+    }
+
+    /// Decodes the image and returns its luma plane only.
+    ///
+    /// This is the raw ```Gray(8)``` luma as stored in the VP8 bitstream,
+    /// without any chroma or color conversion applied. When the EXIF orientation
+    /// is applied, the plane is turned upright the same way as the image.
+    pub fn read_luma(&mut self) -> ImageResult<Vec<u8>> {
+        let _ = try!(self.read_frame());
+
+        if self.lossless_frame.is_some() {
+            return Err(UnsupportedFeature::LosslessLuma.error())
+        }
+
+        let (width, height) = (self.frame.width as usize, self.frame.height as usize);
+        Ok(match self.orientation {
+            Some(o) if o != 1 => orientation::orient(o, &self.frame.ybuf, width, height, 1),
+            _ => self.frame.ybuf.clone(),
+        })
+    }
+
+    /// Decodes the image and returns its planes in the I420 layout of the VP8
+    /// bitstream, without any upsampling or color conversion applied.
+    ///
+    /// Returns the Y, U and V planes followed by the stride of the luma plane and
+    /// the stride of the two chroma planes, which are subsampled by 2 in both
+    /// directions, rounding up. The planes are not padded, so the strides are
+    /// their widths. The alpha plane, if any, is not included, and the EXIF
+    /// orientation is never applied.
+    pub fn read_yuv420(&mut self) -> ImageResult<(Vec<u8>, Vec<u8>, Vec<u8>, usize, usize)> {
+        let _ = try!(self.read_frame());
+
+        if self.lossless_frame.is_some() {
+            return Err(UnsupportedFeature::LosslessYuv.error())
+        }
+
+        Ok((
+            self.frame.ybuf.clone(),
+            self.frame.ubuf.clone(),
+            self.frame.vbuf.clone(),
+            self.frame.width as usize,
+            self.frame.chroma_width() as usize,
+        ))
+    }
+
+    /// Returns the alpha channel of the image as a ```Gray(8)``` mask with the
+    /// size from ```dimensions```.
+    ///
+    /// Of a lossy image only the ALPH chunk is decoded, the VP8 frame is skipped
+    /// unless the image has been decoded already. The alpha of a lossless image
+    /// is stored along with its color, so the whole image is decoded, and an
+    /// animation gives the alpha of its first frame drawn onto the canvas. The
+    /// canvas outside of a smaller frame is transparent, and the EXIF orientation
+    /// is applied the same way as to the image. Fails with an ```UnsupportedError```
+    /// if the image has no alpha channel, which includes a lossless image whose
+    /// header says its alpha is unused.
+    pub fn read_alpha_mask(&mut self) -> ImageResult<Vec<u8>> {
+        let _ = try!(self.read_orientation());
+
+        // A lossless image is decoded with alpha even if its header says it
+        // does not use it
+        if !self.has_alpha || self.animation.is_none() && !self.image_alpha {
+            return Err(UnsupportedFeature::MissingAlpha.error())
+        }
+
+        if let Some(ImageData::LossyWithAlpha(ref vp8, ref alph)) = self.image_data {
+            let (width, height) = try!(vp8_dimensions(self.bytes(vp8)));
+            let alpha = try!(alpha::decode_alpha(self.bytes(alph), width as u16, height as u16,
+                                                 self.alpha_dithering));
+
+            return Ok(self.alpha_mask(&alpha, width as usize))
+        }
+
+        let _ = try!(self.read_frame());
+
+        let (width, _) = self.frame_dimensions();
+        let alpha = match (&self.first_frame, &self.lossless_frame, &self.alpha) {
+            (&Some(ref rgba), _, _) => rgba.chunks(4).map(|px| px[3]).collect(),
+            (_, &Some(ref f), _) => f.buf.iter().map(|argb| (argb >> 24) as u8).collect(),
+            (_, _, &Some(ref a)) => a.clone(),
+            _ => return Err(UnsupportedFeature::MissingAlpha.error()),
+        };
+
+        Ok(self.alpha_mask(&alpha, width as usize))
+    }
+
+    // Places the alpha plane of the frame in the top left corner of the canvas
+    // and orients it like the image
+    fn alpha_mask(&self, alpha: &[u8], frame_width: usize) -> Vec<u8> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut mask = vec![0u8; width * height];
+
+        for (row, src) in mask.chunks_mut(width).zip(alpha.chunks(frame_width)) {
+            row[..frame_width].copy_from_slice(src);
+        }
+
+        match self.orientation {
+            Some(o) if o != 1 => orientation::orient(o, &mask, width, height, 1),
+            _ => mask,
+        }
+    }
+
+    /// Returns an iterator over the rows of the image that have not been read
+    /// with ```read_scanline``` yet, each row is ```row_len``` bytes long.
+    ///
+    /// The iterator ends after the last row, or after the first error.
+    pub fn scanlines<'a>(&'a mut self) -> Scanlines<'a, R> {
+        Scanlines {
+            decoder: self,
+            done: false,
+        }
+    }
+
+    /// Decodes the image into ```buf``` instead of allocating a new buffer.
+    ///
+    /// The pixels are written in the color type reported by ```colortype```,
+    /// returns the number of bytes written. Fails with ```DimensionError```
+    /// if ```buf``` is too small to hold the image.
+    pub fn read_image_into(&mut self, buf: &mut [u8]) -> ImageResult<usize> {
+        let _ = try!(self.check_8_bit());
+        let _ = try!(self.read_frame());
+
+        let (width, height) = self.output_dimensions();
+        let rlen = self.channels() * width as usize;
+        let len = rlen * height as usize;
+
+        if buf.len() < len {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        for (y, row) in buf[..len].chunks_mut(rlen).enumerate() {
+            self.fill_output_row(y, row);
+            self.report_progress(y as u32 + 1, height);
+        }
+
+        Ok(len)
+    }
+
+    /// Decodes the image and returns the pixels of the ```width``` by ```height```
+    /// rectangle with its top left corner at ```x```, ```y```.
+    ///
+    /// The pixels are in the color type reported by ```colortype```. The whole
+    /// frame is still decoded, but only the rows of the rectangle are converted
+    /// from YUV and only the rectangle is kept. Fails with ```DimensionError```
+    /// if the rectangle does not lie within the image.
+    pub fn read_region(&mut self, x: u32, y: u32, width: u32, height: u32) -> ImageResult<Vec<u8>> {
+        let _ = try!(self.check_8_bit());
+        let _ = try!(self.read_frame());
+
+        let (image_width, image_height) = self.output_dimensions();
+        if x as u64 + width as u64 > image_width as u64 || y as u64 + height as u64 > image_height as u64 {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        let bpp = self.channels();
+        let start = bpp * x as usize;
+        let end = start + bpp * width as usize;
+
+        let mut row = vec![0u8; bpp * image_width as usize];
+        let mut region = Vec::with_capacity(bpp * width as usize * height as usize);
+
+        for row_y in y..y + height {
+            self.fill_output_row(row_y as usize, &mut row);
+            region.extend_from_slice(&row[start..end]);
+        }
+
+        Ok(region)
+    }
+
+    /// Decodes the top ```max_rows``` rows of the image, as a preview that is
+    /// quicker to get than the whole image.
+    ///
+    /// Returns the image in the layout of ```read_image``` along with the number of
+    /// rows at its top that are decoded, the rows below them are zero. Only the
+    /// macroblock rows of a lossy image that those rows need are decoded, lossless
+    /// images and animations are decoded whole. The rows are those of the image as
+    /// it is stored, which is not turned by its EXIF orientation, and are always
+    /// 8 bit. The image is left undecoded for the other read functions.
+    pub fn read_image_partial(&mut self, max_rows: u32) -> ImageResult<(Vec<u8>, u32)> {
+        let _ = try!(self.read_metadata());
+
+        let rows = cmp::min(max_rows, self.height) as usize;
+        let rlen = self.channels() * self.width as usize;
+        let mut image = vec![0u8; rlen * self.height as usize];
+
+        let lossy = match self.image_data {
+            Some(ImageData::Lossy(_)) | Some(ImageData::LossyWithAlpha(..)) => true,
+            _ => false,
+        };
+
+        let rows = if lossy {
+            let (frame, alpha, decoded) = try!(self.decode_vp8_rows(rows));
+            let rows = cmp::min(rows, decoded);
+
+            // The rows are converted from the partial planes in place of those
+            // of the frame, which is still undecoded
+            let frame = mem::replace(&mut self.frame, frame);
+            let alpha = mem::replace(&mut self.alpha, alpha);
+            for (y, row) in image.chunks_mut(rlen).take(rows).enumerate() {
+                self.fill_canvas_row(y, row);
+            }
+            self.frame = frame;
+            self.alpha = alpha;
+
+            rows
+        } else {
+            try!(self.read_frame());
+            for (y, row) in image.chunks_mut(rlen).take(rows).enumerate() {
+                self.fill_canvas_row(y, row);
+            }
+
+            rows
+        };
+
+        if self.profile_callback.is_some() {
+            let _ = try!(self.load_icc_profile());
+        }
+
+        for (y, row) in image.chunks_mut(rlen).take(rows).enumerate() {
+            self.finish_output_row(y, row);
+        }
+
+        Ok((image, rows as u32))
+    }
+
+    // Decodes the macroblock rows of the lossy image data covering the top rows
+    // of the frame. Returns the frame with the planes zero below the decoded rows,
+    // its alpha plane and the number of rows that can be converted to RGB.
+    fn decode_vp8_rows(&self, rows: usize) -> ImageResult<(Frame, Option<Vec<u8>>, usize)> {
+        let (vp8, alph) = match self.image_data {
+            Some(ImageData::Lossy(ref vp8)) => (self.bytes(vp8), None),
+            Some(ImageData::LossyWithAlpha(ref vp8, ref alph)) => (self.bytes(vp8), Some(self.bytes(alph))),
+            _ => unreachable!(),
+        };
+
+        try!(check_keyframe(vp8));
+
+        // A row past the last one is asked for, as the chroma row below the last
+        // luma row of a band is needed to convert it
+        let (mut ys, mut us, mut vs) = (Vec::new(), Vec::new(), Vec::new());
+        let mut decoder = VP8Decoder::new(io::Cursor::new(vp8));
+        try!(decoder.decode_frame_rows_up_to(rows + 1, |_, y, u, v| {
+            ys.extend_from_slice(y);
+            us.extend_from_slice(u);
+            vs.extend_from_slice(v);
+        }));
+
+        let mut frame = decoder.into_frame();
+        let (width, height) = (frame.width as usize, frame.height as usize);
+        let (cw, ch) = (frame.chroma_width() as usize, frame.chroma_height() as usize);
+
+        // Fancy upsampling of an odd row uses the chroma row below it
+        let (luma_rows, chroma_rows) = (ys.len() / width, us.len() / cw);
+        let decoded = if luma_rows == height {
+            height
+        } else {
+            cmp::min(luma_rows, 2 * chroma_rows - 1)
+        };
+
+        ys.resize(width * height, 0);
+        us.resize(cw * ch, 0);
+        vs.resize(cw * ch, 0);
+        frame.ybuf = ys;
+        frame.ubuf = us;
+        frame.vbuf = vs;
+
+        let alpha = match alph {
+            Some(alph) => Some(try!(alpha::decode_alpha(alph, frame.width, frame.height, self.alpha_dithering))),
+            None => None,
+        };
+
+        Ok((frame, alpha, decoded))
+    }
+
+    /// Decodes the image to RGBA, whatever color type ```colortype``` reports.
+    ///
+    /// Images without an alpha channel are returned as fully opaque.
+    pub fn read_image_rgba(&mut self) -> ImageResult<Vec<u8>> {
+        let _ = try!(self.read_frame());
+
+        let (width, height) = self.output_dimensions();
+        let width = width as usize;
+        let bpp = self.channels();
+        let mut row = vec![0u8; bpp * width];
+        let mut rgba = Vec::with_capacity(4 * width * height as usize);
+
+        for y in 0..height as usize {
+            self.fill_output_row(y, &mut row);
+            extend_rgba(&mut rgba, &row, bpp);
+        }
+
+        Ok(rgba)
+    }
+
+    /// Decodes the image with each channel as a ```f32``` from 0 to 1, in the
+    /// color type reported by ```colortype``` and the pixel order set with
+    /// ```set_pixel_order```.
+    ///
+    /// The values are normalized as they are converted, so the conversion of
+    /// lossy images from YUV keeps its fractional bits like 16 bit output does.
+    /// The output is neither dithered nor passed to the color profile callback.
+    pub fn read_image_f32(&mut self) -> ImageResult<Vec<f32>> {
+        self.read_samples::<f32>()
+    }
+
+    /// Decodes the image like ```read_image``` and returns how long the stages
+    /// of decoding it took.
+    ///
+    /// The stages read before, for example the container read by ```dimensions```,
+    /// are included. The times are measured with ```std::time::Instant``` around
+    /// each stage, down to single macroblocks of lossy images, so they add some
+    /// time of their own.
+    #[cfg(feature = "stats")]
+    pub fn decode_with_stats(&mut self) -> ImageResult<(image::DecodingResult, DecodeStats)> {
+        let _ = try!(self.read_frame());
+
+        let timer = Timer::start();
+        let image = try!(self.read_image());
+        timer.add_to(&mut self.stats.conversion);
+
+        Ok((image, self.stats))
+    }
+
+    /// Decodes the image into a ```DynamicImage```.
+    ///
+    /// Images with alpha are returned as ```ImageRgba8```, those without as
+    /// ```ImageRgb8```, an animation as its first frame drawn onto the canvas
+    /// like ```read_image``` does. The image is always 8 bit RGB or RGBA,
+    /// ```set_16_bit_output``` and ```set_pixel_order``` do not apply.
+    pub fn into_image(mut self) -> ImageResult<DynamicImage> {
+        self.sixteen_bit = false;
+        self.pixel_order = PixelOrder::Rgba;
+
+        let color = try!(self.colortype());
+        let (width, height) = try!(self.dimensions());
+        let buf = try!(self.read_image());
+
+        dynamic_image(color, width, height, buf)
+    }
+
+    /// Decodes every WebP file of a stream of files following one another.
+    ///
+    /// Each RIFF container ends where its length says it does, the next one starts
+    /// after it. Zero bytes and ASCII whitespace between containers are skipped.
+    /// The images are decoded with the settings and callbacks of this decoder, and
+    /// are always 8 bit. Unless the decoder was created with ```from_slice```, this
+    /// has to be called before anything else reads from the input.
+    pub fn decode_all(mut self) -> ImageResult<Vec<DynamicImage>> {
+        let read;
+        let data = match self.slice {
+            Some(slice) => slice(&self.r),
+            None if self.position == 0 => {
+                let mut data = mem::replace(&mut self.buf, Vec::new());
+                let _ = try!(self.r.read_to_end(&mut data));
+                read = data;
+                &read[..]
+            },
+            None => return Err(image::ImageError::FormatError(
+                String::from("Input has been read before decode_all")
+            )),
+        };
+
+        let mut progress = self.progress.take();
+        let mut profile_callback = self.profile_callback.take();
+
+        let mut images = Vec::new();
+        let mut rest = data;
+
+        loop {
+            match rest.iter().position(|&b| b != 0 && !b.is_ascii_whitespace()) {
+                Some(n) => rest = &rest[n..],
+                None if images.is_empty() => return Err(image::ImageError::NotEnoughData),
+                None => return Ok(images),
+            }
+
+            let len = match riff_header(rest) {
+                IResult::Done(_, len) => len,
+                IResult::Error(_) => return Err(image::ImageError::FormatError(
+                    format!("Image {} of the stream is not a WebP file", images.len())
+                )),
+                IResult::Incomplete(_) => return Err(image::ImageError::NotEnoughData),
+            };
+
+            // The length counts the bytes following the length field
+            let end = cmp::min(8 + len as u64, rest.len() as u64) as usize;
+            images.push(try!(self.decode_container(&rest[..end], &mut progress, &mut profile_callback)));
+            rest = &rest[end..];
+        }
+    }
+
+    // Decodes a single file of the stream given to decode_all with the settings
+    // of this decoder, lending it the callbacks taken from this one
+    fn decode_container(&self,
+                        data: &[u8],
+                        progress: &mut Option<Box<FnMut(u32, u32) + Send>>,
+                        profile_callback: &mut Option<Box<FnMut(&[u8], &mut [u8]) + Send>>)
+                        -> ImageResult<DynamicImage> {
+        let mut decoder = WebpDecoder::from_slice(data);
+        decoder.set_limits(self.max_width, self.max_height, self.max_pixels);
+        decoder.set_chunk_limits(self.max_chunks, self.max_frames);
+        decoder.strict = self.strict;
+        decoder.upsampling = self.upsampling;
+        decoder.color_conversion = self.color_conversion;
+        decoder.premultiplied = self.premultiplied;
+        decoder.dither = self.dither;
+        decoder.alpha_dithering = self.alpha_dithering;
+        decoder.apply_orientation = self.apply_orientation;
+        decoder.progress = progress.take();
+        decoder.profile_callback = profile_callback.take();
+
+        let result = decoder.colortype().and_then(|color| {
+            let (width, height) = try!(decoder.dimensions());
+            Ok((color, width, height, try!(decoder.read_image())))
+        });
+
+        *progress = decoder.progress.take();
+        *profile_callback = decoder.profile_callback.take();
+
+        let (color, width, height, buf) = try!(result);
+        dynamic_image(color, width, height, buf)
+    }
+
+    /// Returns the pixel at ```x```, ```y``` of the image.
+    ///
+    /// The image is decoded to RGBA on the first call and kept for the calls that
+    /// follow, pixels of images without alpha are fully opaque. Fails with
+    /// ```DimensionError``` if the pixel lies outside of the image.
+    pub fn get_pixel(&mut self, x: u32, y: u32) -> ImageResult<color::Rgba<u8>> {
+        if self.pixels.is_none() {
+            self.pixels = Some(try!(self.read_image_rgba()));
+        }
+
+        let (width, height) = self.output_dimensions();
+        if x >= width || y >= height {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        let i = 4 * (y as usize * width as usize + x as usize);
+        let p = &self.pixels.as_ref().unwrap()[i..i + 4];
+        let (r, b) = self.pixel_order.red_blue();
+
+        Ok(color::Rgba([p[r], p[1], p[b], p[3]]))
+    }
+
+    fn check_8_bit(&self) -> ImageResult<()> {
+        if self.sixteen_bit {
+            Err(UnsupportedFeature::SixteenBitOutput.error())
+        } else {
+            Ok(())
+        }
+    }
+
+    // Decodes the image with 16 bit or floating point channels
+    fn read_samples<T: Sample + Default>(&mut self) -> ImageResult<Vec<T>> {
+        let _ = try!(self.read_frame());
+
+        let bpp = self.channels();
+        let rlen = bpp * self.width as usize;
+        let mut buf = vec![T::default(); rlen * self.height as usize];
+
+        for (y, row) in buf.chunks_mut(rlen).enumerate() {
+            self.fill_canvas_row(y, row);
+
+            let height = self.height;
+            self.report_progress(y as u32 + 1, height);
+        }
+
+        Ok(match self.orientation {
+            Some(o) if o != 1 => orientation::orient(o, &buf, self.width as usize, self.height as usize, bpp),
+            _ => buf,
+        })
+    }
+
+    // The size of the most recently decoded frame
+    fn frame_dimensions(&self) -> (u32, u32) {
+        if self.first_frame.is_some() {
+            return (self.width, self.height)
+        }
+
+        match self.lossless_frame {
+            Some(ref f) => (f.width as u32, f.height as u32),
+            None => (self.frame.width as u32, self.frame.height as u32),
+        }
+    }
+
+    // Lossy frames without an alpha plane are decoded to RGB, everything else to RGBA
+    fn frame_has_alpha(&self) -> bool {
+        self.first_frame.is_some() || self.lossless_frame.is_some() || self.alpha.is_some()
+    }
+
+    // The number of channels, which is the number of bytes of an 8 bit pixel
+    fn channels(&self) -> usize {
+        if self.has_alpha { 4 } else { 3 }
+    }
+
+    // Fills row y of the output, which is the upright image if the orientation is applied
+    fn fill_output_row(&mut self, y: usize, buf: &mut [u8]) {
+        let rlen = self.channels() * self.output_dimensions().0 as usize;
+
+        match self.oriented {
+            Some(ref o) => buf[..rlen].copy_from_slice(&o[y * rlen..y * rlen + rlen]),
+            None => self.fill_canvas_row(y, buf),
+        }
+
+        self.finish_output_row(y, &mut buf[..rlen]);
+    }
+
+    // Passes row y of the 8 bit output to the profile callback and dithers it
+    fn finish_output_row(&mut self, y: usize, row: &mut [u8]) {
+        self.call_profile_callback(row);
+
+        let bpp = self.channels();
+        match self.dither {
+            Dither::None => (),
+            Dither::Ordered(bits) => dither::ordered(row, bpp, y, bits),
+            Dither::FloydSteinberg(bits) => {
+                if y == 0 || y != self.dither_row {
+                    self.dither_errors.clear();
+                }
+
+                dither::floyd_steinberg(row, bpp, &mut self.dither_errors, bits);
+                self.dither_row = y + 1;
+            }
+        }
+    }
+
+    // Passes the row to the callback along with the profile, once it has been
+    // loaded. The profile is borrowed field by field, next to the callback.
+    fn call_profile_callback(&mut self, row: &mut [u8]) {
+        let profile = match self.icc_profile {
+            Some(Payload::Owned(ref data)) => &data[..],
+            Some(Payload::Borrowed(start, end)) => &self.slice.unwrap()(&self.r)[start..end],
+            _ => return,
+        };
+
+        if let Some(ref mut callback) = self.profile_callback {
+            callback(profile, row);
+        }
+    }
+
+    // Fills a row of the canvas. A frame that differs from the VP8X canvas in size
+    // is placed in its top left corner, the rest of the canvas is left empty.
+    fn fill_canvas_row<T: Sample>(&self, y: usize, buf: &mut [T]) {
+        let (frame_width, frame_height) = self.frame_dimensions();
+        let bpp = self.channels();
+
+        if (frame_width, frame_height) == (self.width, self.height) {
+            self.fill_row(y, buf, self.pixel_order);
+        } else {
+            for b in buf.iter_mut() {
+                *b = T::from_u8(0);
+            }
+
+            if y < frame_height as usize {
+                let mut row = vec![T::from_u8(0); bpp * frame_width as usize];
+                self.fill_row(y, &mut row, self.pixel_order);
+
+                let n = bpp * cmp::min(frame_width, self.width) as usize;
+                buf[..n].copy_from_slice(&row[..n]);
+            }
+        }
+
+        if self.premultiplied && bpp == 4 {
+            for px in buf.chunks_mut(4) {
+                let a = px[3];
+                for c in px[..3].iter_mut() {
+                    *c = c.premultiply(a);
+                }
+            }
+        }
+    }
+
+    // Fills row y of the decoded frame with pixels in order
+    fn fill_row<T: Sample>(&self, y: usize, buf: &mut [T], order: PixelOrder) {
+        let (r, b) = order.red_blue();
+
+        if let Some(ref rgba) = self.first_frame {
+            let rlen = 4 * self.width as usize;
+
+            for (v, px) in rgba[y * rlen..y * rlen + rlen].chunks(4).zip(buf.chunks_mut(4)) {
+                px[r] = T::from_u8(v[0]);
+                px[1] = T::from_u8(v[1]);
+                px[b] = T::from_u8(v[2]);
+                px[3] = T::from_u8(v[3]);
+            }
+
+            return
+        }
+
+        match self.lossless_frame {
+            Some(ref f) => {
+                let width = f.width as usize;
+                let row = &f.buf[y * width..y * width + width];
+
+                for (argb, rgba) in row.iter().zip(buf.chunks_mut(4)) {
+                    rgba[r] = T::from_u8((argb >> 16) as u8);
+                    rgba[1] = T::from_u8((argb >> 8) as u8);
+                    rgba[b] = T::from_u8(*argb as u8);
+                    rgba[3] = T::from_u8((argb >> 24) as u8);
+                }
+            }
+            None => match self.alpha {
+                Some(ref a) => yuv::fill_rgba_row(&self.frame, self.upsampling, self.color_conversion, order, a, y, buf),
+                None => yuv::fill_rgb_row(&self.frame, self.upsampling, self.color_conversion, order, y, buf),
+            },
+        }
+    }
+}
+
+impl<R: Read> ImageDecoder for WebpDecoder<R> {
+    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        let _ = try!(self.read_orientation());
+
+        Ok(self.output_dimensions())
+    }
+
+    fn colortype(&mut self) -> ImageResult<color::ColorType> {
+        let _ = try!(self.read_metadata());
+
+        let bits = if self.sixteen_bit { 16 } else { 8 };
+
+        if self.has_alpha {
+            Ok(color::ColorType::RGBA(bits))
+        } else {
+            Ok(color::ColorType::RGB(bits))
+        }
+    }
+
+    fn row_len(&mut self) -> ImageResult<usize> {
+        let _ = try!(self.read_orientation());
+
+        let bytes = if self.sixteen_bit { 2 } else { 1 };
+
+        Ok(bytes * self.channels() * self.output_dimensions().0 as usize)
+    }
+
+    /// Reads the next row of the image into ```buf```, which must be exactly
+    /// ```row_len``` bytes long. Fails with ```DimensionError``` otherwise.
+    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+        let _ = try!(self.check_8_bit());
+        let _ = try!(self.read_frame());
+
+        if buf.len() != self.channels() * self.output_dimensions().0 as usize {
+            return Err(image::ImageError::DimensionError)
+        }
+
+        if self.decoded_rows >= self.output_dimensions().1 {
+            return Err(image::ImageError::ImageEnd)
+        }
+
+        self.fill_output_row(self.decoded_rows as usize, buf);
+        self.decoded_rows += 1;
+
+        let (rows_done, total) = (self.decoded_rows, self.output_dimensions().1);
+        self.report_progress(rows_done, total);
+
+        Ok(self.decoded_rows)
+    }
+
+    fn read_image(&mut self) -> ImageResult<image::DecodingResult> {
+        let _ = try!(self.read_orientation());
+
+        if self.sixteen_bit {
+            return Ok(image::DecodingResult::U16(try!(self.read_samples::<u16>())))
+        }
+
+        let (width, height) = self.output_dimensions();
+        let mut buf = vec![0u8; self.channels() * width as usize * height as usize];
+        let _ = try!(self.read_image_into(&mut buf));
+
+        Ok(image::DecodingResult::U8(buf))
+    }
+
+    fn is_animated(&mut self) -> ImageResult<bool> {
+        let _ = try!(self.read_metadata());
+
+        Ok(self.animation.is_some())
+    }
+
+    /// Returns the frames of an animated image composited onto the canvas,
+    /// or the image as a single frame if it is not animated.
+    fn into_frames(self) -> ImageResult<animation::Frames> {
+        let mut decoder = self;
+        let _ = try!(decoder.read_metadata());
+
+        let anim = match decoder.animation.take() {
+            Some(a) => a,
+            None => return Ok(animation::Frames::new(vec![
+                animation::Frame::new(try!(decoder.into_image()).to_rgba())
+            ])),
+        };
+
+        let mut canvas = Canvas::new(anim.canvas_width, anim.canvas_height, anim.params.background_color);
+        let mut frames = Vec::with_capacity(anim.frames.len());
+        let mut previous: Option<AnimationFrame> = None;
+
+        for &(ref info, ref data) in anim.frames.iter() {
+            if let Some(ref p) = previous {
+                try!(canvas.dispose(p));
+            }
+
+            let rgba = try!(decoder.read_frame_rgba(info, data));
+            try!(canvas.draw(info, &rgba));
+
+            let buffer = ImageBuffer::from_raw(anim.canvas_width, anim.canvas_height, canvas.buf.clone()).unwrap();
+            let delay = Ratio::new(cmp::min(info.duration, 0xffff) as u16, 1000);
+            frames.push(animation::Frame::from_parts(buffer, 0, 0, delay));
+
+            previous = Some(*info);
+        }
+
+        Ok(animation::Frames::new(frames))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::io::{Cursor, Read, Seek, SeekFrom};
+    use std::sync::{Arc, Mutex};
+
+    use color;
+    use dynimage::DynamicImage;
+    use image::{DecodingResult, GenericImage, ImageDecoder, ImageError};
+    use super::{can_decode, decode_webp, ChunkInfo, FlagDiscrepancy, UnsupportedFeature, VP8XFlag, WebpDecoder, WebpInfo};
+    use super::{ImageData, IResult, Needed, Payload};
+    use super::parsers::{chunk_header, chunk_size, image_chunks, riff_header, vp8l_chunk};
+    use super::super::vp8::{Frame, VP8Decoder};
+    use super::super::vp8l::VP8LDecoder;
+    use super::super::{AnimationInfo, BlendMethod, Dither, DisposeMethod, FilterType, FrameHeaderInfo, PixelOrder,
+                       ScaleHint, Upsampling, WebpEncoder};
+
+    // Writes bits least significant bit first, as VP8L reads them
+    struct BitWriter {
+        buf: Vec<u8>,
+        nbits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, n: usize) {
+            for i in 0..n {
+                if self.nbits % 8 == 0 {
+                    self.buf.push(0);
+                }
+
+                let last = self.buf.len() - 1;
+                self.buf[last] |= (((value >> i) & 1) as u8) << (self.nbits % 8);
+                self.nbits += 1;
+            }
+        }
+    }
+
+    // A VP8L image of a single ARGB color, every prefix code has one symbol
+    fn solid_vp8l(width: u32, height: u32, argb: u32) -> Vec<u8> {
+        let mut w = BitWriter { buf: Vec::new(), nbits: 0 };
+
+        w.write(0x2f, 8);
+        w.write(width - 1, 14);
+        w.write(height - 1, 14);
+        w.write(1, 1);
+        w.write(0, 3);
+
+        // No transforms, color cache or entropy image
+        w.write(0, 3);
+
+        for &symbol in [(argb >> 8) & 0xff, (argb >> 16) & 0xff, argb & 0xff, argb >> 24, 0].iter() {
+            // A simple code of one 8 bit symbol
+            w.write(1, 1);
+            w.write(0, 1);
+            w.write(1, 1);
+            w.write(symbol, 8);
+        }
+
+        w.buf
+    }
+
+    fn chunk(name: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut c = name.to_vec();
+        let len = payload.len() as u32;
+        c.extend_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+        c.extend_from_slice(payload);
+        if payload.len() % 2 != 0 {
+            c.push(0);
+        }
+
+        c
+    }
+
+    fn u24(v: u32) -> [u8; 3] {
+        [v as u8, (v >> 8) as u8, (v >> 16) as u8]
+    }
+
+    fn riff(chunks: &[Vec<u8>]) -> Vec<u8> {
+        let mut body = b"WEBP".to_vec();
+        for c in chunks {
+            body.extend_from_slice(c);
+        }
+
+        chunk(b"RIFF", &body)
+    }
+
+    // Offsets must be even
+    fn anmf(x: u32, y: u32, width: u32, height: u32, duration: u32, flags: u8, argb: u32) -> Vec<u8> {
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&u24(x / 2));
+        payload.extend_from_slice(&u24(y / 2));
+        payload.extend_from_slice(&u24(width - 1));
+        payload.extend_from_slice(&u24(height - 1));
+        payload.extend_from_slice(&u24(duration));
+        payload.push(flags);
+        payload.extend_from_slice(&chunk(b"VP8L", &solid_vp8l(width, height, argb)));
+
+        chunk(b"ANMF", &payload)
+    }
+
+    fn animated_file() -> Vec<u8> {
+        let mut vp8x = vec![0x12, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(3));
+
+        // Opaque blue background, loop forever
+        let anim = [255, 0, 0, 255, 0, 0];
+
+        riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ANIM", &anim),
+            // Opaque red over the whole canvas
+            anmf(0, 0, 4, 4, 100, 0, 0xffff0000),
+            // Half transparent green in the bottom right, blended and then disposed
+            anmf(2, 2, 2, 2, 50, 1, 0x8000ff00),
+            // Fully transparent black in the top left, not blended
+            anmf(0, 0, 2, 2, 70000, 2, 0x00000000),
+        ])
+    }
+
+    // Hands out at most one byte per read and keeps count of the bytes read
+    struct TrickleReader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl<'a> Read for TrickleReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos == self.data.len() || buf.is_empty() {
+                return Ok(0)
+            }
+
+            buf[0] = self.data[self.pos];
+            self.pos += 1;
+
+            Ok(1)
+        }
+    }
+
+    // A seekable reader counting the bytes read from it
+    struct CountingReader<'a> {
+        inner: Cursor<&'a [u8]>,
+        read: usize,
+    }
+
+    impl<'a> Read for CountingReader<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = try!(self.inner.read(buf));
+            self.read += n;
+
+            Ok(n)
+        }
+    }
+
+    impl<'a> Seek for CountingReader<'a> {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            self.inner.seek(pos)
+        }
+    }
+
+    fn pixel(buf: &[u8], x: usize, y: usize) -> &[u8] {
+        &buf[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4]
+    }
+
+    fn decode(data: &[u8]) -> (color::ColorType, Vec<u8>) {
+        let mut decoder = WebpDecoder::new(Cursor::new(data));
+        let colortype = decoder.colortype().unwrap();
+
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => (colortype, buf),
+            _ => panic!("unexpected 16 bit output"),
+        }
+    }
+
+    fn assert_same_alpha(lossy: &[u8], lossless: &[u8]) {
+        let (lossy_type, lossy) = decode(lossy);
+        let (lossless_type, lossless) = decode(lossless);
+
+        assert_eq!(lossy_type, color::ColorType::RGBA(8));
+        assert_eq!(lossless_type, color::ColorType::RGBA(8));
+        assert_eq!(lossy.len(), lossless.len());
+
+        // The alpha plane is stored losslessly, only the color is approximated
+        for (a, b) in lossy.chunks(4).zip(lossless.chunks(4)) {
+            assert_eq!(a[3], b[3]);
+        }
+    }
+
+    #[test]
+    fn entropy_image_groups() {
+        let mut w = BitWriter { buf: Vec::new(), nbits: 0 };
+
+        // An 8x4 image without transforms or color cache
+        w.write(0x2f, 8);
+        w.write(7, 14);
+        w.write(3, 14);
+        w.write(1, 1);
+        w.write(0, 3);
+        w.write(0, 1);
+        w.write(0, 1);
+
+        // An entropy image of 4x4 blocks, the left one uses group 0 and the right one group 1
+        w.write(1, 1);
+        w.write(0, 3);
+        w.write(0, 1);
+
+        // A simple code of the symbols 0 and 1 for green, the group index
+        w.write(1, 1);
+        w.write(1, 1);
+        w.write(0, 1);
+        w.write(0, 1);
+        w.write(1, 8);
+
+        // Red, blue, alpha and distance are always 0
+        for _ in 0..4 {
+            w.write(1, 1);
+            w.write(0, 1);
+            w.write(0, 1);
+            w.write(0, 1);
+        }
+
+        // The two pixels of the entropy image
+        w.write(0, 1);
+        w.write(1, 1);
+
+        // Each group codes a single color, so the pixels take no bits
+        for &argb in [0xff336699u32, 0x80ccaa00].iter() {
+            for &symbol in [(argb >> 8) & 0xff, (argb >> 16) & 0xff, argb & 0xff, argb >> 24, 0].iter() {
+                w.write(1, 1);
+                w.write(0, 1);
+                w.write(1, 1);
+                w.write(symbol, 8);
+            }
+        }
+
+        let data = riff(&[chunk(b"VP8L", &w.buf)]);
+        let rgba = WebpDecoder::new(Cursor::new(&data[..])).read_image_rgba().unwrap();
+
+        for y in 0..4 {
+            for x in 0..8 {
+                let expected = if x < 4 { [0x33, 0x66, 0x99, 0xff] } else { [0xcc, 0xaa, 0x00, 0x80] };
+                assert_eq!(&rgba[4 * (y * 8 + x)..][..4], &expected);
+            }
+        }
+    }
+
+    #[test]
+    fn lossy_with_alpha_matches_lossless_alpha() {
+        assert_same_alpha(include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+                          include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp"));
+    }
+
+    #[test]
+    fn decoders_are_send() {
+        fn assert_send<T: Send>() {}
+
+        assert_send::<WebpDecoder<Cursor<Vec<u8>>>>();
+        assert_send::<WebpDecoder<&[u8]>>();
+        assert_send::<VP8Decoder<Cursor<Vec<u8>>>>();
+        assert_send::<VP8LDecoder<Cursor<Vec<u8>>>>();
+        assert_send::<Frame>();
+    }
+
+    #[test]
+    fn unsupported_features() {
+        fn unsupported<T>(result: Result<T, ImageError>) -> Option<UnsupportedFeature> {
+            result.err().and_then(|e| UnsupportedFeature::from_error(&e))
+        }
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(unsupported(decoder.read_luma()), Some(UnsupportedFeature::LosslessLuma));
+        assert_eq!(unsupported(decoder.read_yuv420()), Some(UnsupportedFeature::LosslessYuv));
+
+        // A VP8 frame with the keyframe bit cleared is only unsupported outside of WebP
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let mut frame = data[vp8 + 8..].to_vec();
+        frame[0] |= 1;
+
+        let result = VP8Decoder::new(Cursor::new(&frame[..])).decode_frame().map(|_| ());
+        assert_eq!(unsupported(result), Some(UnsupportedFeature::InterFrame));
+
+        // Corrupt files are not unsupported
+        let result = WebpDecoder::new(Cursor::new(&b"RIFF\x0c\0\0\0WEBPVP8L\0\0\0\0"[..])).read_image();
+        assert!(result.is_err());
+        assert_eq!(unsupported(result), None);
+        assert_eq!(format!("{}", UnsupportedFeature::InterFrame), "VP8: only keyframes are supported");
+    }
+
+    #[test]
+    fn interframe() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let mut image = riff(&[data[vp8..].to_vec()]);
+        image[20] |= 1;
+
+        match WebpDecoder::new(Cursor::new(&image[..])).dimensions() {
+            Err(ImageError::FormatError(ref msg)) if msg == "WebP VP8 payload is not a keyframe" => (),
+            _ => panic!("expected a FormatError about the interframe"),
+        }
+    }
+
+    #[test]
+    fn vp8l_header_dimensions() {
+        let dimensions = |vp8l: &[u8]| {
+            let data = riff(&[chunk(b"VP8L", vp8l)]);
+            WebpDecoder::new(Cursor::new(&data[..])).dimensions()
+        };
+
+        // Both fields hold one less than the size in 14 bits
+        assert_eq!(dimensions(&solid_vp8l(1, 1, 0xff000000)).unwrap(), (1, 1));
+        assert_eq!(dimensions(&solid_vp8l(16384, 16384, 0xff000000)).unwrap(), (16384, 16384));
+        assert_eq!(dimensions(&solid_vp8l(16384, 1, 0xff000000)).unwrap(), (16384, 1));
+        assert_eq!(dimensions(&solid_vp8l(1, 16384, 0xff000000)).unwrap(), (1, 16384));
+        assert_eq!(dimensions(&[0x2f, 0xff, 0x3f, 0, 0]).unwrap(), (16384, 1));
+
+        let data = riff(&[chunk(b"VP8L", &solid_vp8l(1, 1, 0xff102030))]);
+        let (_, image) = decode(&data);
+        assert_eq!(image, [0x10, 0x20, 0x30, 0xff]);
+
+        let mut vp8l = solid_vp8l(1, 1, 0xff000000);
+        vp8l[0] = 0x2e;
+        match dimensions(&vp8l) {
+            Err(ImageError::FormatError(msg)) => assert_eq!(msg, "VP8L signature is invalid"),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn vp8_header_dimensions() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+
+        let dimensions = |width: u16, height: u16| {
+            let mut vp8 = data[start..].to_vec();
+            vp8[6..10].copy_from_slice(&[width as u8, (width >> 8) as u8, height as u8, (height >> 8) as u8]);
+            WebpDecoder::new(Cursor::new(riff(&[chunk(b"VP8 ", &vp8)]))).dimensions()
+        };
+
+        // The sizes are 14 bits, the scale hints above them are not part of it
+        assert_eq!(dimensions(421, 163).unwrap(), (421, 163));
+        assert_eq!(dimensions(0x3fff, 0x3fff).unwrap(), (16383, 16383));
+        assert_eq!(dimensions(0xffff, 0x4001).unwrap(), (16383, 1));
+
+        for &(width, height) in [(0, 163), (421, 0), (0xc000, 163), (421, 0x4000)].iter() {
+            match dimensions(width, height) {
+                Err(ImageError::FormatError(msg)) => assert_eq!(msg, "VP8 frame is empty"),
+                _ => panic!("expected a format error"),
+            }
+        }
+    }
+
+    #[test]
+    fn vp8l_signature() {
+        let signature_error = |result: Result<(), ImageError>| match result {
+            Err(ImageError::FormatError(ref msg)) => {
+                assert_eq!(msg, "VP8L signature is invalid");
+                assert!(UnsupportedFeature::from_error(&result.unwrap_err()).is_none());
+            },
+            _ => panic!("expected a format error"),
+        };
+
+        let mut data = riff(&[chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000))]);
+        data[20] = 0x2e;
+        signature_error(WebpDecoder::new(Cursor::new(&data[..])).dimensions().map(|_| ()));
+        signature_error(WebpDecoder::new(Cursor::new(&data[..])).verify_structure());
+
+        // In the frames of an animation
+        let frames = animated_file();
+        let starts: Vec<_> = frames.windows(4).enumerate().filter(|&(_, w)| w == b"VP8L").map(|(i, _)| i + 8).collect();
+
+        let mut data = frames.clone();
+        data[starts[2]] = 0x2e;
+        assert!(WebpDecoder::new(Cursor::new(&data[..])).read_image().is_ok());
+        signature_error(WebpDecoder::new(Cursor::new(&data[..])).into_frames().map(|_| ()));
+        signature_error(WebpDecoder::new(Cursor::new(&data[..])).verify_structure());
+
+        let mut data = frames.clone();
+        data[starts[0]] = 0x00;
+        signature_error(WebpDecoder::new(Cursor::new(&data[..])).read_image().map(|_| ()));
+    }
+
+    #[test]
+    fn dimensions_without_reading_trailer() {
+        let mut vp8x = vec![0x08, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(6));
+        vp8x.extend_from_slice(&u24(2));
+
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(7, 3, 0xff102030)),
+            chunk(b"EXIF", &[0u8; 1000]),
+        ]);
+
+        let mut decoder = WebpDecoder::new(TrickleReader { data: &image, pos: 0 });
+        assert_eq!(decoder.dimensions().unwrap(), (7, 3));
+        assert_eq!(decoder.colortype().unwrap(), color::ColorType::RGBA(8));
+        assert!(decoder.r.pos < image.len() - 1000);
+
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => assert!(buf.chunks(4).all(|p| p == [0x10, 0x20, 0x30, 0xff])),
+            _ => panic!("unexpected 16 bit output"),
+        }
+        assert!(decoder.r.pos < image.len() - 1000);
+    }
+
+    #[test]
+    fn trickled_input() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+
+        let mut decoder = WebpDecoder::new(TrickleReader { data: data, pos: 0 });
+        assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+        let trickled = match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => buf,
+            _ => panic!("unexpected 16 bit output"),
+        };
+
+        assert!(decode(data).1 == trickled);
+    }
+
+    #[test]
+    fn truncated_input() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+
+        for &len in [0, 11, 12, 30, data.len() - 1].iter() {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..len]));
+            assert!(decoder.dimensions().is_err());
+        }
+    }
+
+    #[test]
+    fn errors_are_kept() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+
+        // The input is not read on from where parsing stopped
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..30]));
+        let err = format!("{}", decoder.dimensions().unwrap_err());
+        assert_eq!(format!("{}", decoder.dimensions().unwrap_err()), err);
+        assert_eq!(format!("{}", decoder.info().unwrap_err()), err);
+
+        // Neither is a frame that failed to decode decoded again, or taken as empty
+        let vp8l = solid_vp8l(2, 2, 0xff000000);
+        let data = riff(&[chunk(b"VP8L", &vp8l[..6])]);
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let err = format!("{}", decoder.read_image().err().unwrap());
+        assert_eq!(format!("{}", decoder.read_image().err().unwrap()), err);
+        assert_eq!(format!("{}", decoder.get_pixel(0, 0).unwrap_err()), err);
+
+        // The container was read, so its properties are still available
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+    }
+
+    #[test]
+    fn read_image_into_buffer() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, expected) = decode(data);
+
+        let mut buf = vec![0u8; expected.len() + 10];
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.read_image_into(&mut buf).unwrap(), expected.len());
+        assert_eq!(&buf[..expected.len()], &expected[..]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        match decoder.read_image_into(&mut buf[..expected.len() - 1]) {
+            Err(ImageError::DimensionError) => (),
+            _ => panic!("expected a dimension error"),
+        }
+    }
+
+    #[test]
+    fn from_slice() {
+        let images: [&[u8]; 2] = [
+            include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp"),
+        ];
+
+        for data in images.iter() {
+            let mut decoder = WebpDecoder::from_slice(data);
+            assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+            match decoder.read_image().unwrap() {
+                DecodingResult::U8(buf) => assert!(buf == decode(data).1),
+                _ => panic!("unexpected 16 bit output"),
+            }
+        }
+
+        let mut data = animated_file();
+        data.extend_from_slice(&chunk(b"EXIF", b"II*\x00"));
+        let len = data.len() as u32 - 8;
+        data[4] = len as u8;
+
+        let mut decoder = WebpDecoder::from_slice(&data);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(b"II*\x00".to_vec()));
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+        assert_eq!(frames.len(), 3);
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        for &len in [0, 11, 12, 30, data.len() - 1].iter() {
+            assert!(WebpDecoder::from_slice(&data[..len]).dimensions().is_err());
+        }
+    }
+
+    #[test]
+    fn try_from_slice() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::try_from_slice(data).ok().unwrap();
+        assert!(decoder.have_metadata && !decoder.have_frame);
+        assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+        assert!(decoder.read_image_rgba().unwrap() == decode(data).1);
+
+        match WebpDecoder::try_from_slice(include_bytes!("../../tests/images/png/transparency/tbbn0g04.png")) {
+            Err(ImageError::FormatError(_)) => (),
+            _ => panic!("expected a format error"),
+        }
+
+        for &len in [0, 11, 12, 30, data.len() - 1].iter() {
+            assert!(WebpDecoder::try_from_slice(&data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn corrupt_coefficients() {
+        // The partitions filled with ones from a few bytes into the first one on
+        // decode to the largest coefficients, which fail decoding instead of
+        // overflowing the inverse transform
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut corrupt = data.to_vec();
+        let vp8 = corrupt.windows(4).position(|w| w == b"VP8 ").unwrap();
+        for b in corrupt[vp8 + 30..].iter_mut() {
+            *b = 0xff;
+        }
+
+        assert!(WebpDecoder::from_slice(&corrupt).read_image().is_err());
+        assert!(WebpDecoder::new(Cursor::new(&corrupt[..])).read_image().is_err());
+    }
+
+    #[test]
+    fn into_image() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let (color, buf) = decode(data);
+        let image = WebpDecoder::new(Cursor::new(&data[..])).into_image().unwrap();
+        assert_eq!(image.color(), color);
+        assert_eq!(image.dimensions(), (421, 163));
+        assert!(image.raw_pixels() == buf);
+
+        let data = riff(&[chunk(b"VP8L", &solid_vp8l(3, 2, 0x80102030))]);
+        match WebpDecoder::from_slice(&data).into_image().unwrap() {
+            DynamicImage::ImageRgba8(image) => assert_eq!(image.get_pixel(2, 1).data, [0x10, 0x20, 0x30, 0x80]),
+            _ => panic!("expected an RGBA image"),
+        }
+
+        // 16 bit output does not apply
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_16_bit_output(true);
+        let image = decoder.into_image().unwrap();
+        let (color, buf) = decode(data);
+        assert_eq!(image.color(), color);
+        assert!(image.raw_pixels() == buf);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(animated_file()));
+        let canvas = decoder.read_image_rgba().unwrap();
+        let image = WebpDecoder::new(Cursor::new(animated_file())).into_image().unwrap();
+        assert!(image.to_rgba().into_raw() == canvas);
+
+        assert!(WebpDecoder::new(Cursor::new(&data[..100])).into_image().is_err());
+    }
+
+    #[test]
+    fn reset() {
+        let large = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let small = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let lossless = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+
+        let premultiplied = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            decoder.set_premultiplied_alpha(true);
+            decoder.read_image_rgba().unwrap()
+        };
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&large[..]));
+        decoder.set_premultiplied_alpha(true);
+        decoder.set_limits(1000, 1000, 200000);
+        let _ = decoder.read_image_rgba().unwrap();
+        let ybuf = decoder.frame.ybuf.as_ptr();
+
+        // The smaller frame is decoded into the planes of the larger one
+        decoder.reset(Cursor::new(&small[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+        let image = decoder.read_image_rgba().unwrap();
+        assert_eq!(decoder.frame.ybuf.as_ptr(), ybuf);
+        assert!(image == premultiplied(small));
+
+        // Errors and the metadata of the previous input are gone
+        decoder.reset(Cursor::new(&small[..100]));
+        assert!(decoder.read_image().is_err());
+        decoder.reset(Cursor::new(&lossless[..]));
+        assert!(decoder.read_image_rgba().unwrap() == premultiplied(lossless));
+        assert_eq!(decoder.chunks().unwrap().len(), 1);
+
+        // The limits are kept
+        decoder.reset(Cursor::new(&large[..]));
+        assert!(decoder.dimensions().is_ok());
+        decoder.set_limits(1000, 1000, 100000);
+        decoder.reset(Cursor::new(&large[..]));
+        assert!(decoder.dimensions().is_err());
+
+        let mut decoder = WebpDecoder::from_slice(&large[..]);
+        assert_eq!(decoder.dimensions().unwrap(), (400, 301));
+        decoder.reset(&small[..]);
+        assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+        assert!(decoder.read_image().is_ok());
+    }
+
+    #[test]
+    fn partial_image() {
+        let images: [&[u8]; 3] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/2_webp_ll.webp"),
+            include_bytes!("../../tests/images/webp/lossless/odd_size.webp"),
+        ];
+
+        for data in images.iter() {
+            let (_, full) = decode(data);
+
+            for &max_rows in [0, 1, 15, 16, 17, 100, 1000].iter() {
+                let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+                let (width, height) = decoder.dimensions().unwrap();
+                let (image, rows) = decoder.read_image_partial(max_rows).unwrap();
+
+                // The decoded rows match the whole image, the others are zero
+                let end = rows as usize * full.len() / height as usize;
+                assert_eq!(rows, max_rows.min(height));
+                assert_eq!(image.len(), full.len());
+                assert!(image[..end] == full[..end]);
+                assert!(image[end..].iter().all(|&b| b == 0));
+
+                // The image is still decoded whole afterwards
+                assert_eq!(decoder.read_image_rgba().unwrap().len(), 4 * (width * height) as usize);
+            }
+        }
+
+        // The preview does not need the coefficients of the macroblock rows below it
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let (_, full) = decode(data);
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let mut cut = data.to_vec();
+        for b in cut[vp8 + 4000..].iter_mut() {
+            *b = 0;
+        }
+
+        let (image, rows) = WebpDecoder::new(Cursor::new(cut)).read_image_partial(20).unwrap();
+        assert_eq!(rows, 20);
+        assert!(image[..20 * 400 * 4] == full[..20 * 400 * 4]);
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn chunk_log() {
+        use std::cell::RefCell;
+        use log::{self, LogLevelFilter, LogMetadata, LogRecord};
+
+        // Tests run on several threads, each keeps the lines it logs
+        thread_local!(static LINES: RefCell<Vec<String>> = RefCell::new(Vec::new()));
+
+        struct Logger;
+        impl log::Log for Logger {
+            fn enabled(&self, _: &LogMetadata) -> bool {
+                true
+            }
+
+            fn log(&self, record: &LogRecord) {
+                LINES.with(|lines| lines.borrow_mut().push(format!("{}", record.args())));
+            }
+        }
+
+        log::set_logger(|max| {
+            max.set(LogLevelFilter::Debug);
+            Box::new(Logger)
+        }).unwrap();
+
+        let vp8l = solid_vp8l(2, 2, 0xff000000);
+        let mut data = riff(&[chunk(b"VP8L", &vp8l), chunk(b"EXIF", b"abc")]);
+        assert!(WebpDecoder::new(Cursor::new(&data[..])).exif_metadata().unwrap().is_some());
+
+        // Without the padding byte of the last chunk
+        let _ = data.pop();
+        data[4] -= 1;
+        assert!(WebpDecoder::new(Cursor::new(&data[..])).exif_metadata().unwrap().is_some());
+
+        assert_eq!(vp8l.len() % 2, 1);
+        let exif = 20 + vp8l.len() + 1;
+        let lines = LINES.with(|lines| lines.borrow().clone());
+        assert_eq!(lines, vec![
+            format!("RIFF container: {} bytes", data.len() - 7),
+            format!("VP8L chunk at offset 12: {} bytes, padding read", vp8l.len()),
+            format!("EXIF chunk at offset {}: 3 bytes, padding read", exif),
+            format!("RIFF container: {} bytes", data.len() - 8),
+            format!("VP8L chunk at offset 12: {} bytes, padding read", vp8l.len()),
+            format!("EXIF chunk at offset {}: 3 bytes, padding missing", exif),
+        ]);
+    }
+
+    #[test]
+    fn bytes_needed() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, image) = decode(data);
+
+        // Fetching the length asked for each time reads the file a step at a time
+        let mut lens = vec![0];
+        loop {
+            let len = *lens.last().unwrap() as usize;
+            let mut decoder = WebpDecoder::from_slice(&data[..len]);
+            let mut streamed = WebpDecoder::new(Cursor::new(&data[..len]));
+            assert_eq!(decoder.bytes_needed(), None);
+
+            match (decoder.read_image(), streamed.read_image()) {
+                (Ok(DecodingResult::U8(a)), Ok(DecodingResult::U8(b))) => {
+                    assert_eq!(a, image);
+                    assert_eq!(b, image);
+                    assert_eq!(decoder.bytes_needed(), None);
+                    break
+                },
+                (Err(_), Err(_)) => (),
+                _ => panic!("{} bytes decode differently", len),
+            }
+
+            let needed = decoder.bytes_needed().unwrap();
+            assert_eq!(streamed.bytes_needed(), Some(needed));
+            assert!(needed > len as u64 && needed <= data.len() as u64, "{} bytes need {}", len, needed);
+            lens.push(needed);
+        }
+
+        // The fields of the RIFF header, then the header and payload of the VP8X,
+        // ALPH and VP8 chunks
+        assert_eq!(lens, vec![0, 4, 8, 12, 20, 30, 38, 6750, 6758, data.len() as u64]);
+    }
+
+    #[test]
+    fn vp8x_lossless_alpha() {
+        let vp8l = solid_vp8l(3, 2, 0x80ff0000);
+        let mut vp8x = vec![0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(2));
+        vp8x.extend_from_slice(&u24(1));
+
+        let expected = [0xff, 0, 0, 0x80].iter().cycle().take(24).cloned().collect::<Vec<u8>>();
+
+        // The alpha of the VP8L image data is kept, with or without an ALPH
+        // chunk in front of it, which is ignored
+        let containers = [
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &vp8l)]),
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"ALPH", &[0, 0xff]), chunk(b"VP8L", &vp8l)]),
+        ];
+
+        for (i, data) in containers.iter().enumerate() {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            assert_eq!(decoder.colortype().unwrap(), color::ColorType::RGBA(8));
+            assert!(decoder.info().unwrap().has_alpha);
+            assert!(decoder.info().unwrap().is_lossless);
+            assert_eq!(decoder.read_image_rgba().unwrap(), expected);
+            assert_eq!(decoder.take_warnings().len(), i);
+        }
+
+        let mut warned = WebpDecoder::new(Cursor::new(&containers[1][..]));
+        let _ = warned.read_image_rgba().unwrap();
+        assert_eq!(warned.take_warnings(), vec![String::from("ALPH chunk is ignored for VP8L image data")]);
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn decode_stats() {
+        use std::time::Duration;
+
+        let zero = Duration::new(0, 0);
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (image, stats) = WebpDecoder::new(Cursor::new(&data[..])).decode_with_stats().unwrap();
+        match image {
+            DecodingResult::U8(image) => assert_eq!(image, decode(data).1),
+            _ => panic!("Expected U8 image"),
+        }
+        assert!(stats.header > zero && stats.entropy > zero);
+        assert!(stats.reconstruction > zero && stats.conversion > zero);
+
+        // Lossless images have no reconstruction stage
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let (_, stats) = WebpDecoder::new(Cursor::new(&data[..])).decode_with_stats().unwrap();
+        assert!(stats.header > zero && stats.entropy > zero && stats.conversion > zero);
+        assert_eq!(stats.reconstruction, zero);
+    }
+
+    #[test]
+    fn concatenated_files() {
+        let lossy = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let solid = riff(&[chunk(b"VP8L", &solid_vp8l(3, 2, 0x80102030))]);
+
+        let stream = [&lossy[..], b"\x00\n ", &solid, &solid, b"\x00"].concat();
+        let from_reader = WebpDecoder::new(Cursor::new(&stream)).decode_all().unwrap();
+        let from_slice = WebpDecoder::from_slice(&stream).decode_all().unwrap();
+
+        for images in [from_reader, from_slice].iter() {
+            assert_eq!(images.len(), 3);
+            assert_eq!(images[0].raw_pixels(), decode(lossy).1);
+            for image in images[1..].iter() {
+                assert_eq!(image.color(), color::ColorType::RGBA(8));
+                assert_eq!(image.raw_pixels(), [0x10, 0x20, 0x30, 0x80].iter().cycle().cloned().take(24).collect::<Vec<_>>());
+            }
+        }
+
+        // The settings apply to every image
+        let mut decoder = WebpDecoder::from_slice(&stream);
+        decoder.set_premultiplied_alpha(true);
+        assert_eq!(&decoder.decode_all().unwrap()[2].raw_pixels()[..4], &[8, 16, 24, 0x80]);
+
+        let mut decoder = WebpDecoder::from_slice(&stream);
+        decoder.set_limits(100, 100, 10000);
+        assert!(decoder.decode_all().is_err());
+
+        let unknown = riff(&[chunk(b"VP8L", &solid_vp8l(3, 2, 0x80102030)), chunk(b"ABCD", b"ab")]);
+        let stream = [&solid[..], &unknown].concat();
+        assert_eq!(WebpDecoder::from_slice(&stream).decode_all().unwrap().len(), 2);
+        let mut decoder = WebpDecoder::from_slice(&stream);
+        decoder.set_strict(true);
+        assert!(decoder.decode_all().is_err());
+
+        // Every container has to be a complete WebP file
+        assert!(WebpDecoder::from_slice(&[&solid[..], b"RIFX"].concat()).decode_all().is_err());
+        assert!(WebpDecoder::from_slice(&[&solid[..], &solid[..solid.len() - 3]].concat()).decode_all().is_err());
+        assert!(WebpDecoder::from_slice(b" \x00").decode_all().is_err());
+
+        // Only ASCII whitespace separates them
+        assert!(WebpDecoder::from_slice(&[&solid[..], b"\t\r\x0c"].concat()).decode_all().is_ok());
+        for separator in [b"\x0b", b"\x85", b"\xa0"].iter() {
+            assert!(WebpDecoder::from_slice(&[&solid[..], &separator[..], &solid].concat()).decode_all().is_err());
+        }
+
+        // Without a slice, nothing may have been read yet
+        let mut decoder = WebpDecoder::new(Cursor::new(&stream));
+        assert!(decoder.dimensions().is_ok());
+        assert!(decoder.decode_all().is_err());
+    }
+
+    #[test]
+    fn remaining_bytes() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, expected) = decode(data);
+        let solid = riff(&[chunk(b"VP8L", &solid_vp8l(3, 2, 0x80102030))]);
+
+        let inputs: [(Vec<u8>, u64); 3] = [
+            (data.to_vec(), 0),
+            ([&data[..], b"\x00\x00\x00"].concat(), 3),
+            ([&data[..], &solid[..]].concat(), solid.len() as u64),
+        ];
+
+        for &(ref input, remaining) in inputs.iter() {
+            let mut decoder = WebpDecoder::from_slice(&input[..]);
+            assert_eq!(decoder.remaining_bytes().unwrap(), remaining);
+            assert!(decoder.read_image_rgba().unwrap() == expected);
+
+            for &seekable in [false, true].iter() {
+                let mut decoder = if seekable {
+                    WebpDecoder::new_seekable(Cursor::new(&input[..]))
+                } else {
+                    WebpDecoder::new(Cursor::new(&input[..]))
+                };
+
+                assert_eq!(decoder.remaining_bytes().unwrap(), remaining);
+                assert_eq!(decoder.remaining_bytes().unwrap(), remaining);
+                assert!(decoder.read_image_rgba().unwrap() == expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rgba_output() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, expected) = decode(data);
+        let rgba = WebpDecoder::new(Cursor::new(&data[..])).read_image_rgba().unwrap();
+        assert!(rgba == expected);
+
+        // The lossy image without its ALPH chunk
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let image = riff(&[data[vp8..].to_vec()]);
+
+        let (colortype, rgb) = decode(&image);
+        assert_eq!(colortype, color::ColorType::RGB(8));
+
+        let rgba = WebpDecoder::new(Cursor::new(&image[..])).read_image_rgba().unwrap();
+        assert_eq!(rgba.len(), rgb.len() / 3 * 4);
+        for (a, b) in rgba.chunks(4).zip(rgb.chunks(3)) {
+            assert_eq!(&a[..3], b);
+            assert_eq!(a[3], 255);
+        }
+    }
+
+    #[test]
+    fn frame_header_info() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let info = WebpDecoder::new(Cursor::new(&data[..])).frame_header_info().unwrap().unwrap();
+        assert_eq!(info.profile, 1);
+        assert_eq!(info.filter_type, FilterType::Simple);
+
+        // The encoder writes version 0 without a loop filter
+        let mut encoded = Vec::new();
+        WebpEncoder::new(&mut encoded).encode(&[128; 3 * 16 * 16], 16, 16, color::ColorType::RGB(8)).unwrap();
+        let info = WebpDecoder::new(Cursor::new(&encoded[..])).frame_header_info().unwrap().unwrap();
+        assert_eq!(info, FrameHeaderInfo {
+            profile: 0,
+            filter_type: FilterType::Normal,
+            filter_level: 0,
+            sharpness: 0,
+            horizontal_scale: ScaleHint::None,
+            vertical_scale: ScaleHint::None,
+        });
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).frame_header_info().unwrap(), None);
+
+        // The macroblocks are not decoded, so a frame cut short still has a header
+        let (data, truncated) = truncated_vp8();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let info = decoder.frame_header_info().unwrap();
+        assert!(decoder.read_image().is_ok());
+        assert_eq!(decoder.frame_header_info().unwrap(), info);
+        assert_eq!(WebpDecoder::new(Cursor::new(&truncated[..])).frame_header_info().unwrap(), info);
+        assert!(WebpDecoder::new(Cursor::new(&truncated[..])).read_image().is_err());
+    }
+
+    #[test]
+    fn quantizer_info() {
+        // The indices libwebp's encoder chose for the four segments, in absolute values
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let info = WebpDecoder::new(Cursor::new(&data[..])).quantizer_info().unwrap().unwrap();
+        assert_eq!(info.base_index, 11);
+        assert_eq!(info.segment_indices, [11, 8, 6, 5]);
+        assert_eq!((info.ydc_delta, info.y2dc_delta, info.y2ac_delta), (0, 0, 0));
+        assert_eq!((info.uvdc_delta, info.uvac_delta), (-2, -3));
+
+        // One segment for each of the 27x11 macroblocks, using all of them
+        assert_eq!(info.segment_map.len(), 27 * 11);
+        for segment in 0..4 {
+            assert!(info.segment_map.contains(&segment));
+        }
+        assert!(info.segment_map.iter().all(|&s| s < 4));
+
+        // The encoder does not use segments
+        let mut encoded = Vec::new();
+        WebpEncoder::new(&mut encoded).encode(&[128; 3 * 16 * 16], 16, 16, color::ColorType::RGB(8)).unwrap();
+        let info = WebpDecoder::new(Cursor::new(&encoded[..])).quantizer_info().unwrap().unwrap();
+        assert_eq!(info.segment_indices, [info.base_index; 4]);
+        assert!(info.segment_map.is_empty());
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).quantizer_info().unwrap(), None);
+
+        // Only the first partition is read, the coefficients are not
+        let (data, truncated) = truncated_vp8();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let info = decoder.quantizer_info().unwrap();
+        assert!(decoder.read_image().is_ok());
+        assert_eq!(decoder.quantizer_info().unwrap(), info);
+        assert_eq!(WebpDecoder::new(Cursor::new(&truncated[..])).quantizer_info().unwrap(), info);
+    }
+
+    // A lossy file and the same file with its token partition cut in half
+    fn truncated_vp8() -> (Vec<u8>, Vec<u8>) {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let len = data[start - 4] as usize | (data[start - 3] as usize) << 8 | (data[start - 2] as usize) << 16;
+        let vp8 = &data[start..start + len];
+        let first_partition_size = (vp8[0] as usize | (vp8[1] as usize) << 8 | (vp8[2] as usize) << 16) >> 5;
+        let cut = 10 + first_partition_size + (vp8.len() - 10 - first_partition_size) / 2;
+
+        (riff(&[chunk(b"VP8 ", vp8)]), riff(&[chunk(b"VP8 ", &vp8[..cut])]))
+    }
+
+    #[test]
+    fn scale_hints() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.scale_hints().unwrap(), Some((ScaleHint::None, ScaleHint::None)));
+        assert_eq!(decoder.display_dimensions().unwrap(), (421, 163));
+
+        // Ask for 5/4 horizontally and 5/3 vertically in the top bits of the dimensions
+        let mut scaled = data.to_vec();
+        let start = scaled.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        scaled[start + 7] |= 1 << 6;
+        scaled[start + 9] |= 2 << 6;
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&scaled[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (421, 163));
+        assert_eq!(decoder.scale_hints().unwrap(), Some((ScaleHint::FiveFourths, ScaleHint::FiveThirds)));
+        assert_eq!(decoder.display_dimensions().unwrap(), (526, 272));
+
+        let info = decoder.frame_header_info().unwrap().unwrap();
+        assert_eq!((info.horizontal_scale, info.vertical_scale), (ScaleHint::FiveFourths, ScaleHint::FiveThirds));
+
+        // The image is decoded at its stored size
+        assert!(decode(&scaled) == decode(data));
+
+        assert_eq!(ScaleHint::Double.apply(163), 326);
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).scale_hints().unwrap(), None);
+    }
+
+    #[test]
+    fn progress_callback() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let calls = Arc::new(Mutex::new(Vec::new()));
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let c = calls.clone();
+        decoder.set_progress_callback(Box::new(move |done, total| c.lock().unwrap().push((done, total))));
+        assert!(decoder.read_image().is_ok());
+
+        let expected: Vec<_> = (1..164).map(|y| (y, 163)).collect();
+        assert_eq!(*calls.lock().unwrap(), expected);
+
+        calls.lock().unwrap().clear();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let c = calls.clone();
+        decoder.set_progress_callback(Box::new(move |done, total| c.lock().unwrap().push((done, total))));
+
+        let mut row = vec![0u8; decoder.row_len().unwrap()];
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 1);
+        assert_eq!(decoder.read_scanline(&mut row).unwrap(), 2);
+        assert_eq!(*calls.lock().unwrap(), [(1, 163), (2, 163)]);
+    }
+
+    #[test]
+    fn chroma_upsampling() {
+        // A red triangle on blue, split along the diagonal
+        let mut image = Vec::new();
+        for y in 0..32 {
+            for x in 0..32 {
+                image.extend_from_slice(if x > y { &[255, 0, 0] } else { &[0, 0, 255] });
+            }
+        }
+
+        let mut encoded = Vec::new();
+        WebpEncoder::new_with_quality(&mut encoded, 100).encode(&image, 32, 32, color::ColorType::RGB(8)).unwrap();
+
+        let error = |upsampling: Option<Upsampling>| {
+            let mut decoder = WebpDecoder::new(Cursor::new(&encoded[..]));
+            if let Some(u) = upsampling {
+                decoder.set_upsampling(u);
+            }
+
+            let decoded = match decoder.read_image().unwrap() {
+                DecodingResult::U8(d) => d,
+                _ => panic!("Expected U8 image"),
+            };
+
+            decoded.iter().zip(image.iter()).map(|(&a, &b)| (a as i32 - b as i32).pow(2)).sum::<i32>()
+        };
+
+        // Repeating the chroma samples leaves steps along the edge that interpolating smooths out
+        let fancy = error(Some(Upsampling::Fancy));
+        assert!(fancy < error(Some(Upsampling::Nearest)));
+        assert_eq!(error(None), fancy);
+    }
+
+    #[test]
+    fn luma_plane() {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let luma = decoder.read_luma().unwrap();
+        assert_eq!(luma.len(), 400 * 301);
+
+        // Mirrored left to right by the EXIF orientation
+        let exif = b"II*\x00\x08\x00\x00\x00\x01\x00\x12\x01\x03\x00\x01\x00\x00\x00\x02\x00\x00\x00";
+        let vp8x = data.windows(4).position(|w| w == b"VP8X").unwrap();
+        let mut mirrored = data[..].to_vec();
+        mirrored[vp8x + 8] |= 0x08;
+        mirrored.extend_from_slice(&chunk(b"EXIF", exif));
+        let len = mirrored.len() as u32 - 8;
+        mirrored[4..8].copy_from_slice(&[len as u8, (len >> 8) as u8, (len >> 16) as u8, (len >> 24) as u8]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&mirrored[..]));
+        assert_eq!(decoder.read_luma().unwrap(), luma);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&mirrored[..]));
+        decoder.apply_orientation(true);
+        let upright = decoder.read_luma().unwrap();
+        for &(x, y) in [(0, 0), (399, 0), (17, 300)].iter() {
+            assert_eq!(upright[y * 400 + x], luma[y * 400 + 399 - x]);
+        }
+    }
+
+    #[test]
+    fn alpha_mask() {
+        fn alpha_of(data: &[u8]) -> Vec<u8> {
+            decode(data).1.chunks(4).map(|px| px[3]).collect()
+        }
+
+        // Only the ALPH chunk of a lossy image is decoded
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.read_alpha_mask().unwrap(), alpha_of(data));
+        assert!(decoder.frame.ybuf.is_empty());
+        let _ = decoder.read_image().unwrap();
+        assert_eq!(decoder.read_alpha_mask().unwrap(), alpha_of(data));
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let mask = WebpDecoder::from_slice(&data[..]).read_alpha_mask().unwrap();
+        assert_eq!(mask.len(), 421 * 163);
+        assert_eq!(mask, alpha_of(data));
+
+        let data = animated_file();
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).read_alpha_mask().unwrap(), vec![255; 16]);
+
+        // The canvas outside of the frame is transparent
+        let mut vp8x = vec![0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(0));
+        let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(2, 1, 0x80ff0000))]);
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).read_alpha_mask().unwrap(), vec![0x80, 0x80, 0, 0]);
+
+        // The lossy image without its ALPH chunk has no alpha
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let image = riff(&[data[vp8..].to_vec()]);
+        match WebpDecoder::new(Cursor::new(&image[..])).read_alpha_mask() {
+            Err(ref e) => assert_eq!(UnsupportedFeature::from_error(e), Some(UnsupportedFeature::MissingAlpha)),
+            Ok(_) => panic!("expected an error"),
+        }
+
+        // Neither has a lossless image whose header says its alpha is unused
+        let mut vp8l = solid_vp8l(2, 2, 0xffff0000);
+        vp8l[4] &= !0x10;
+        let mut vp8x = vec![0, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        for data in [riff(&[chunk(b"VP8L", &vp8l)]), riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &vp8l)])].iter() {
+            match WebpDecoder::new(Cursor::new(&data[..])).read_alpha_mask() {
+                Err(ref e) => assert_eq!(UnsupportedFeature::from_error(e), Some(UnsupportedFeature::MissingAlpha)),
+                Ok(_) => panic!("expected an error"),
+            }
+        }
+    }
+
+    #[test]
+    fn yuv420_planes() {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let (y, u, v, y_stride, uv_stride) = decoder.read_yuv420().unwrap();
+
+        // The chroma planes of the 400x301 image cover the last row by themselves
+        assert_eq!((y_stride, uv_stride), (400, 200));
+        assert_eq!((y.len(), u.len(), v.len()), (400 * 301, 200 * 151, 200 * 151));
+        assert_eq!(y, decoder.read_luma().unwrap());
+
+        // The sums of the planes decoded by libwebp
+        let sum = |plane: &[u8]| plane.iter().map(|&b| b as u64).sum::<u64>();
+        assert_eq!((sum(&y), sum(&u), sum(&v)), (13387153, 3000418, 4568403));
+    }
+
+    #[test]
+    fn partial_macroblocks() {
+        // 17x17, one pixel past a macroblock in each direction
+        let data = include_bytes!("../../tests/images/webp/lossless/odd_size.webp");
+        let fnv = |bytes: &[u8]| bytes.iter().fold(0xcbf29ce484222325u64, |h, &b| (h ^ b as u64).wrapping_mul(0x100000001b3));
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let (y, u, v, y_stride, uv_stride) = decoder.read_yuv420().unwrap();
+        assert_eq!((y_stride, uv_stride), (17, 9));
+        assert_eq!((fnv(&y), fnv(&u), fnv(&v)), (0xaa24b273ce9198c8, 0x8c0325fb361366cc, 0x570099bd0dac4d03));
+
+        // The rows are cropped from the macroblocks, the hash is of the RGB output of libwebp
+        let mut rows = Vec::new();
+        let mut row = vec![0u8; 17 * 3];
+        while decoder.read_scanline(&mut row).is_ok() {
+            rows.extend_from_slice(&row);
+        }
+        assert_eq!(rows.len(), 17 * 17 * 3);
+        assert_eq!(fnv(&rows), 0x869135d5a55bacde);
+
+        assert_eq!(&rows[16 * 3..17 * 3], &[233, 2, 62]);
+        assert_eq!(&rows[17 * 3..18 * 3], &[1, 9, 74]);
+        assert_eq!(decoder.read_region(16, 16, 1, 1).unwrap(), [235, 235, 81]);
+    }
+
+    #[test]
+    fn single_pixels() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, rgba) = decode(data);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        for &(x, y) in [(0, 0), (420, 0), (17, 99), (420, 162)].iter() {
+            let i = 4 * (y * 421 + x) as usize;
+            assert_eq!(decoder.get_pixel(x, y).unwrap(), color::Rgba([rgba[i], rgba[i + 1], rgba[i + 2], rgba[i + 3]]));
+        }
+
+        assert!(decoder.get_pixel(421, 0).is_err());
+        assert!(decoder.get_pixel(0, 163).is_err());
+
+        // The lossy image without its ALPH chunk is opaque
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let image = riff(&[data[vp8..].to_vec()]);
+        let (_, rgb) = decode(&image);
+
+        let i = 3 * (99 * 421 + 17);
+        let mut decoder = WebpDecoder::new(Cursor::new(&image[..]));
+        assert_eq!(decoder.get_pixel(17, 99).unwrap(), color::Rgba([rgb[i], rgb[i + 1], rgb[i + 2], 255]));
+    }
+
+    #[test]
+    fn lossy_rgb_output() {
+        // Lossy images without alpha come out as RGB, also at odd sizes
+        let mut lossy = Vec::new();
+        let rgb: Vec<u8> = (0..3 * 3).flat_map(|i| vec![200, 100 + i as u8 * 10, 50]).collect();
+        WebpEncoder::new(&mut lossy).encode(&rgb, 3, 3, color::ColorType::RGB(8)).unwrap();
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&lossy[..]));
+        assert_eq!(decoder.colortype().unwrap(), color::ColorType::RGB(8));
+        assert_eq!(decoder.row_len().unwrap(), 3 * 3);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => assert_eq!(buf.len(), 3 * 3 * 3),
+            _ => panic!("unexpected 16 bit output"),
+        }
+    }
+
+    #[test]
+    fn tiny_images() {
+        let sizes = [(1, 1), (1, 7), (7, 1), (1, 17), (17, 1), (2, 2), (16, 16), (17, 17)];
+
+        for &(width, height) in sizes.iter() {
+            let mut lossy = Vec::new();
+            let rgb: Vec<u8> = (0..width * height).flat_map(|_| vec![200, 100, 50]).collect();
+            WebpEncoder::new(&mut lossy).encode(&rgb, width, height, color::ColorType::RGB(8)).unwrap();
+            let lossless = riff(&[chunk(b"VP8L", &solid_vp8l(width, height, 0xffc86432))]);
+
+            for (data, bpp) in vec![(lossy, 3), (lossless, 4)].into_iter() {
+                let (w, h) = (width as usize, height as usize);
+                let rlen = bpp * w;
+
+                let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+                assert_eq!(decoder.dimensions().unwrap(), (width, height));
+                assert_eq!(decoder.row_len().unwrap(), rlen);
+
+                let (_, image) = decode(&data);
+                assert_eq!(image.len(), rlen * h, "{}x{}", width, height);
+                for px in image.chunks(bpp) {
+                    for (&a, &b) in px.iter().zip([200u8, 100, 50].iter()) {
+                        assert!((a as i32 - b as i32).abs() <= 4, "{}x{}: {:?}", width, height, px);
+                    }
+                }
+
+                let mut row = vec![0u8; rlen];
+                for y in 0..h {
+                    assert_eq!(decoder.read_scanline(&mut row).unwrap(), y as u32 + 1);
+                    assert_eq!(&row[..], &image[y * rlen..(y + 1) * rlen]);
+                }
+                match decoder.read_scanline(&mut row) {
+                    Err(ImageError::ImageEnd) => (),
+                    _ => panic!("expected ImageEnd after the last row of {}x{}", width, height),
+                }
+
+                let mut decoder = WebpDecoder::from_slice(&data[..]);
+                assert_eq!(decoder.scanlines().count(), h);
+                assert_eq!(decoder.read_image_partial(1).unwrap(), ({
+                    let mut top = image.clone();
+                    for b in top[rlen..].iter_mut() {
+                        *b = 0;
+                    }
+                    top
+                }, 1));
+                assert_eq!(decoder.read_region(width - 1, height - 1, 1, 1).unwrap(), &image[image.len() - bpp..]);
+
+                let last = decoder.get_pixel(width - 1, height - 1).unwrap();
+                assert_eq!(&last.data[..3], &image[image.len() - bpp..image.len() - bpp + 3]);
+                assert!(decoder.get_pixel(width, 0).is_err());
+                assert!(decoder.get_pixel(0, height).is_err());
+
+                if bpp == 3 {
+                    let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+                    assert_eq!(decoder.read_luma().unwrap().len(), w * h);
+
+                    let (cw, ch) = ((w + 1) / 2, (h + 1) / 2);
+                    let (y, u, v, y_stride, uv_stride) = decoder.read_yuv420().unwrap();
+                    assert_eq!((y.len(), u.len(), v.len()), (w * h, cw * ch, cw * ch));
+                    assert_eq!((y_stride, uv_stride), (w, cw));
+                }
+
+                let image = WebpDecoder::new(Cursor::new(&data[..])).into_image().unwrap();
+                assert_eq!(image.dimensions(), (width, height));
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_sizes() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let mut decoder = WebpDecoder::new(Cursor::new(riff(&[data[vp8..].to_vec()])));
+        assert_eq!(decoder.channel_count().unwrap(), 3);
+        assert_eq!(decoder.bytes_per_pixel().unwrap(), 3);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.channel_count().unwrap(), 4);
+        assert_eq!(decoder.bytes_per_pixel().unwrap(), 4);
+
+        decoder.set_16_bit_output(true);
+        assert_eq!(decoder.channel_count().unwrap(), 4);
+        assert_eq!(decoder.bytes_per_pixel().unwrap(), 8);
+    }
+
+    #[test]
+    fn sixteen_bit_output() {
+        let read16 = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            decoder.set_16_bit_output(true);
+            assert_eq!(decoder.colortype().unwrap(), color::ColorType::RGBA(16));
+            assert_eq!(decoder.row_len().unwrap(), 2 * 4 * 421);
+
+            match decoder.read_image().unwrap() {
+                DecodingResult::U16(image) => image,
+                _ => panic!("Expected U16 image"),
+            }
+        };
+
+        // Lossless values are scaled exactly
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let (_, image) = decode(data);
+        let image16 = read16(data);
+        assert!(image.iter().zip(image16.iter()).all(|(&a, &b)| 257 * a as u16 == b));
+
+        // Lossy values keep the fractional bits of the color conversion
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, image) = decode(data);
+        let image16 = read16(data);
+        assert_eq!(image.len(), image16.len());
+        assert!(image.iter().zip(image16.iter()).all(|(&a, &b)| (a as i32 - (b / 257) as i32).abs() <= 1));
+        assert!(image16.iter().any(|&b| b % 257 != 0));
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_16_bit_output(true);
+        let mut row = vec![0u8; decoder.row_len().unwrap()];
+        match decoder.read_scanline(&mut row) {
+            Err(ImageError::UnsupportedError(_)) => (),
+            _ => panic!("expected an unsupported error"),
+        }
+    }
+
+    #[test]
+    fn float_output() {
+        let read16 = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            decoder.set_16_bit_output(true);
+            match decoder.read_image().unwrap() {
+                DecodingResult::U16(image) => image,
+                _ => panic!("Expected U16 image"),
+            }
+        };
+
+        // Lossless values are the 8 bit values scaled down
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let (_, image) = decode(data);
+        let float = WebpDecoder::new(Cursor::new(&data[..])).read_image_f32().unwrap();
+        assert_eq!(float.len(), image.len());
+        assert!(image.iter().zip(float.iter()).all(|(&a, &f)| a as f32 / 255.0 == f));
+
+        // Lossy values keep the fractional bits of the color conversion, like 16 bit output
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, image) = decode(data);
+        let float = WebpDecoder::new(Cursor::new(&data[..])).read_image_f32().unwrap();
+        assert_eq!(float.len(), image.len());
+        assert!(float.iter().all(|&f| f >= 0.0 && f <= 1.0));
+        assert!(image.iter().zip(float.iter()).all(|(&a, &f)| (a as f32 - 255.0 * f).abs() <= 1.0));
+        assert!(read16(data).iter().zip(float.iter()).all(|(&b, &f)| (b as f32 / 65535.0 - f).abs() < 1e-4));
+        assert!(float.iter().any(|&f| (255.0 * f).fract() != 0.0));
+
+        // Without alpha, in BGR order
+        let data = include_bytes!("../../tests/images/webp/lossless/odd_size.webp");
+        let (colortype, image) = decode(data);
+        assert_eq!(colortype, color::ColorType::RGB(8));
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_pixel_order(PixelOrder::Bgra);
+        let float = decoder.read_image_f32().unwrap();
+        assert_eq!(float.len(), image.len());
+        for (rgb, bgr) in image.chunks(3).zip(float.chunks(3)) {
+            for (c, &f) in rgb.iter().rev().zip(bgr.iter()) {
+                assert!((*c as f32 - 255.0 * f).abs() <= 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn pixel_order() {
+        let swapped = |image: &[u8], bpp: usize| {
+            image.chunks(bpp).flat_map(|px| {
+                let mut px = px.to_vec();
+                px.swap(0, 2);
+                px
+            }).collect::<Vec<u8>>()
+        };
+
+        let read = |data: &[u8], sixteen_bit: bool| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            decoder.set_pixel_order(PixelOrder::Bgra);
+            decoder.set_16_bit_output(sixteen_bit);
+            decoder.read_image().unwrap()
+        };
+
+        let lossy = include_bytes!("../../tests/images/webp/lossless/odd_size.webp");
+        let alpha = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let lossless = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let animated = animated_file();
+
+        for data in [&lossy[..], &alpha[..], &lossless[..], &animated[..]].iter() {
+            let (colortype, image) = decode(data);
+            let bpp = color::num_components(colortype);
+            match read(data, false) {
+                DecodingResult::U8(bgra) => assert!(bgra == swapped(&image, bpp)),
+                _ => panic!("Expected U8 image"),
+            }
+
+            // 16 bit values are swapped the same way
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_16_bit_output(true);
+            match (decoder.read_image().unwrap(), read(data, true)) {
+                (DecodingResult::U16(rgba), DecodingResult::U16(bgra)) => {
+                    let mut expected = rgba.clone();
+                    for px in expected.chunks_mut(bpp) {
+                        px.swap(0, 2);
+                    }
+                    assert!(bgra == expected);
+                },
+                _ => panic!("Expected U16 images"),
+            }
+
+            // Single pixels and DynamicImage stay RGB
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_pixel_order(PixelOrder::Bgra);
+            let p = decoder.get_pixel(1, 0).unwrap();
+            assert_eq!(&p.data[..3], &image[bpp..bpp + 3]);
+
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_pixel_order(PixelOrder::Bgra);
+            assert_eq!(decoder.into_image().unwrap().raw_pixels(), image);
+        }
+    }
+
+    #[test]
+    fn premultiplied_alpha() {
+        let data = riff(&[chunk(b"VP8L", &solid_vp8l(2, 2, 0x80ff8040))]);
+
+        let (_, straight) = decode(&data);
+        assert_eq!(&straight[..4], &[0xff, 0x80, 0x40, 0x80]);
+
+        // 255 * 128 / 255 = 128, 128 * 128 / 255 = 64.25 and 64 * 128 / 255 = 32.13
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_premultiplied_alpha(true);
+        assert_eq!(decoder.read_image_rgba().unwrap(), [128, 64, 32, 128].iter().cycle().take(16).cloned().collect::<Vec<u8>>());
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_premultiplied_alpha(true);
+        decoder.set_16_bit_output(true);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U16(image) => assert_eq!(&image[..4], &[32896, 16513, 8256, 32896]),
+            _ => panic!("Expected U16 image"),
+        }
+
+        // Opaque images are unchanged
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let (_, image) = decode(data);
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_premultiplied_alpha(true);
+        let premultiplied = decoder.read_image_rgba().unwrap();
+        assert!(image.chunks(4).zip(premultiplied.chunks(4)).all(|(a, b)| a[3] != 255 || a == b));
+    }
+
+    #[test]
+    fn output_dither() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, image) = decode(data);
+        let levels = [0u8, 36, 73, 109, 146, 182, 219, 255];
+
+        for &dither in [Dither::Ordered(3), Dither::FloydSteinberg(3)].iter() {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_output_dither(dither);
+            let dithered = match decoder.read_image().unwrap() {
+                DecodingResult::U8(image) => image,
+                _ => panic!("Expected U8 image"),
+            };
+
+            assert_eq!(dithered.len(), image.len());
+            for (a, b) in image.chunks(4).zip(dithered.chunks(4)) {
+                assert!(b[..3].iter().all(|v| levels.contains(v)), "{:?} is not dithered", b);
+                assert_eq!(a[3], b[3]);
+            }
+
+            // The average of the dithered image stays close to that of the image
+            let mean = |image: &[u8]| image.iter().map(|&v| v as f64).sum::<f64>() / image.len() as f64;
+            assert!((mean(&image) - mean(&dithered)).abs() < 1.0, "{:?}", dither);
+
+            // Reading the rows one by one dithers them the same way
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_output_dither(dither);
+            let mut row = vec![0u8; decoder.row_len().unwrap()];
+            for expected in dithered.chunks(row.len()) {
+                let _ = decoder.read_scanline(&mut row).unwrap();
+                assert_eq!(&row[..], expected);
+            }
+        }
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_output_dither(Dither::Ordered(8));
+        assert_eq!(decoder.read_image_rgba().unwrap(), image);
+    }
+
+    #[test]
+    #[cfg(feature = "png_codec")]
+    fn lossless_alpha() {
+        use png::PNGDecoder;
+
+        // Converted from the PNG with the alpha_is_used bit set
+        let data = include_bytes!("../../tests/images/webp/lossless/alpha_icon.webp");
+        let png = include_bytes!("../../tests/images/webp/lossless/alpha_icon.png");
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert!(decoder.info().unwrap().has_alpha);
+        let (colortype, image) = decode(data);
+        assert_eq!(colortype, color::ColorType::RGBA(8));
+
+        let mut png = PNGDecoder::new(Cursor::new(&png[..]));
+        assert_eq!(png.colortype().unwrap(), color::ColorType::RGBA(8));
+        let expected = match png.read_image().unwrap() {
+            DecodingResult::U8(buf) => buf,
+            _ => panic!("unexpected 16 bit output"),
+        };
+
+        assert_eq!(image.len(), expected.len());
+        assert!(expected.chunks(4).any(|p| p[3] > 0 && p[3] < 255));
+
+        // The encoder is free to change the color of transparent pixels
+        for (p, e) in image.chunks(4).zip(expected.chunks(4)) {
+            assert_eq!(p[3], e[3]);
+            if e[3] != 0 {
+                assert_eq!(p, e);
+            }
+        }
+    }
+
+    #[test]
+    fn region() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (_, full) = decode(data);
+
+        // Rectangles straddling the edges of macroblocks and the edge of the image
+        for &(x, y, w, h) in [(0, 0, 421, 163), (13, 15, 20, 19), (16, 32, 16, 16), (400, 150, 21, 13), (7, 9, 1, 1)].iter() {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            let region = decoder.read_region(x, y, w, h).unwrap();
+
+            let mut expected = Vec::new();
+            for row in y..y + h {
+                let start = ((row * 421 + x) * 4) as usize;
+                expected.extend_from_slice(&full[start..start + w as usize * 4]);
+            }
+
+            assert!(region == expected);
+
+            // The rectangle read a row at a time by the ImageDecoder default
+            let rect = WebpDecoder::new(Cursor::new(&data[..])).load_rect(x, y, h, w).unwrap();
+            assert!(rect == expected);
+        }
+
+        for &(x, y, w, h) in [(0, 0, 422, 1), (1, 0, 421, 1), (0, 160, 1, 4)].iter() {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            match decoder.read_region(x, y, w, h) {
+                Err(ImageError::DimensionError) => (),
+                _ => panic!("expected a dimension error"),
+            }
+
+            match WebpDecoder::new(Cursor::new(&data[..])).load_rect(x, y, h, w) {
+                Err(ImageError::DimensionError) => (),
+                _ => panic!("expected a dimension error"),
+            }
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+
+        let decode_with_limits = |max_width, max_height, max_pixels| {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_limits(max_width, max_height, max_pixels);
+            decoder.dimensions()
+        };
+
+        assert!(decode_with_limits(421, 163, 421 * 163).is_ok());
+        for &(w, h, n) in [(420, 163, 100000), (421, 162, 100000), (1000, 1000, 421 * 163 - 1)].iter() {
+            match decode_with_limits(w, h, n) {
+                Err(ImageError::DimensionError) => (),
+                _ => panic!("expected a dimension error"),
+            }
+        }
+
+        // A huge canvas fails before its frame is decoded
+        let mut vp8x = vec![0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(16383));
+        vp8x.extend_from_slice(&u24(16383));
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&image[..]));
+        decoder.set_limits(4096, 4096, 1 << 24);
+        assert!(decoder.read_image().is_err());
+
+        let data = animated_file();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_limits(4, 4, 15);
+        assert!(decoder.animation_frames().is_err());
+    }
+
+    #[test]
+    fn chunk_limits() {
+        let mut chunks = vec![chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000))];
+        for _ in 0..50000 {
+            chunks.push(chunk(b"ABCD", &[]));
+        }
+        let data = riff(&chunks);
+
+        let count_chunks = |max_chunks| {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_chunk_limits(max_chunks, 1);
+            decoder.chunks().map(|c| c.len())
+        };
+
+        assert_eq!(count_chunks(50001).unwrap(), 50001);
+        match count_chunks(1000) {
+            Err(ImageError::FormatError(msg)) => assert_eq!(msg, "File has more than 1000 chunks"),
+            _ => panic!("expected a format error"),
+        }
+
+        // The image itself is still read
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        decoder.set_chunk_limits(1, 1);
+        assert!(decoder.read_image().is_ok());
+
+        let data = animated_file();
+        let read_frames = |max_chunks, max_frames| {
+            let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+            decoder.set_chunk_limits(max_chunks, max_frames);
+            decoder.animation_frames().map(|f| f.len())
+        };
+
+        assert_eq!(read_frames(5, 3).unwrap(), 3);
+        assert!(read_frames(4, 3).is_err());
+        match read_frames(5, 2) {
+            Err(ImageError::FormatError(msg)) => assert_eq!(msg, "Animation has more than 2 frames"),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn chunk_parsers() {
+        assert_eq!(riff_header(b"RIFF\x04\x00\x00\x00WEBP"), IResult::Done(&b""[..], 4));
+        assert!(riff_header(b"RIFF\x04\x00\x00\x00WEBQ").is_err());
+        assert_eq!(chunk_header(b"VP8X\x0a\x00\x00\x00"), IResult::Done(&b""[..], (*b"VP8X", 10)));
+
+        // Payloads of odd length are followed by a padding byte, unless they end the input
+        assert_eq!(chunk_size(b"\x02\x00\x00\x00abcd"), IResult::Done(&b"cd"[..], &b"ab"[..]));
+        assert_eq!(chunk_size(b"\x03\x00\x00\x00abc\x00d"), IResult::Done(&b"d"[..], &b"abc"[..]));
+        assert_eq!(chunk_size(b"\x03\x00\x00\x00abc"), IResult::Done(&b""[..], &b"abc"[..]));
+        assert!(chunk_size(b"\x04\x00\x00\x00abc").is_incomplete());
+
+        assert_eq!(vp8l_chunk(b"VP8L\x01\x00\x00\x00a\x00"), IResult::Done(&b""[..], &b"a"[..]));
+        assert!(vp8l_chunk(b"VP8 \x01\x00\x00\x00a\x00").is_err());
+
+        // Incomplete input reports the length needed from the start of the parser
+        assert_eq!(riff_header(b"RI"), IResult::Incomplete(Needed::Size(4)));
+        assert_eq!(riff_header(b"RIFF\x04\x00"), IResult::Incomplete(Needed::Size(8)));
+        assert_eq!(riff_header(b"RIFF\x04\x00\x00\x00WE"), IResult::Incomplete(Needed::Size(12)));
+        assert_eq!(chunk_header(b"VP8"), IResult::Incomplete(Needed::Size(4)));
+        assert_eq!(chunk_header(b"VP8X\x0a"), IResult::Incomplete(Needed::Size(8)));
+        assert_eq!(chunk_size(b"\x04\x00\x00\x00abc"), IResult::Incomplete(Needed::Size(8)));
+        assert_eq!(vp8l_chunk(b"VP8L\x04\x00\x00\x00abc"), IResult::Incomplete(Needed::Size(12)));
+
+        // Errors are displayed the same way by both parsers
+        match riff_header(b"RIFX\x04\x00\x00\x00WEBP") {
+            IResult::Error(e) => assert_eq!(format!("{}", e), "Tag"),
+            _ => panic!("expected an error"),
+        }
+    }
+
+    #[test]
+    fn frame_image_chunks() {
+        fn owned(payload: &Payload) -> &[u8] {
+            match *payload {
+                Payload::Owned(ref data) => data,
+                _ => panic!("expected an owned payload"),
+            }
+        }
+
+        let alph = chunk(b"ALPH", b"a");
+        let vp8 = chunk(b"VP8 ", b"vp8");
+        let vp8l = chunk(b"VP8L", b"vp8l");
+
+        match image_chunks(&[&alph[..], &vp8[..]].concat()) {
+            IResult::Done(rest, ImageData::LossyWithAlpha(ref rgb, ref a)) => {
+                assert!(rest.is_empty());
+                assert_eq!((owned(rgb), owned(a)), (&b"vp8"[..], &b"a"[..]));
+            },
+            _ => panic!("expected lossy image data with alpha"),
+        }
+
+        match image_chunks(&vp8l) {
+            IResult::Done(_, ImageData::Lossless(ref data)) => assert_eq!(owned(data), b"vp8l"),
+            _ => panic!("expected lossless image data"),
+        }
+
+        // The alpha chunk must come first
+        match image_chunks(&[&vp8[..], &alph[..]].concat()) {
+            IResult::Done(rest, ImageData::Lossy(ref data)) => {
+                assert_eq!(rest, &alph[..]);
+                assert_eq!(owned(data), b"vp8");
+            },
+            _ => panic!("expected lossy image data"),
+        }
+
+        // and is skipped before lossless image data
+        match image_chunks(&[&alph[..], &vp8l[..]].concat()) {
+            IResult::Done(rest, ImageData::Lossless(ref data)) => {
+                assert!(rest.is_empty());
+                assert_eq!(owned(data), b"vp8l");
+            },
+            _ => panic!("expected lossless image data"),
+        }
+
+        match image_chunks(b"VP8X") {
+            IResult::Error(e) => assert_eq!(format!("{}", e), "Alternative"),
+            _ => panic!("expected an error"),
+        }
+
+        // An incomplete alternative is not passed over
+        assert!(image_chunks(&alph[..alph.len() - 1]).is_incomplete());
+        match image_chunks(&[&alph[..], &vp8[..5]].concat()) {
+            IResult::Incomplete(Needed::Size(n)) => assert_eq!(n, alph.len() + 8),
+            _ => panic!("expected incomplete input"),
+        }
+        assert!(image_chunks(&vp8l[..6]).is_incomplete());
+    }
+
+    #[test]
+    fn sniff_header() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        assert!(can_decode(&data[..12]));
+        assert!(!can_decode(&data[..11]));
+        assert!(!can_decode(b"RIFF\x04\0\0\0WAVE"));
+        assert!(!can_decode(b"\x89PNG\r\n\x1a\n\0\0\0\0"));
+    }
+
+    #[test]
+    fn one_shot_decoding() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let (colortype, image) = decode(data);
+        assert_eq!(decode_webp(data).unwrap(), (image, 421, 163, colortype));
+
+        assert!(decode_webp(&data[..100]).is_err());
+    }
+
+    #[test]
+    fn vp8x_canvas_dimensions() {
+        let mut vp8x = vec![0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(2));
+
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xffff0000)),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&image[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (4, 3));
+
+        let (colortype, buf) = decode(&image);
+        assert_eq!(colortype, color::ColorType::RGBA(8));
+        assert_eq!(buf.len(), 4 * 3 * 4);
+        assert_eq!(pixel(&buf, 1, 1), &[255, 0, 0, 255]);
+        assert_eq!(pixel(&buf, 2, 1), &[0, 0, 0, 0]);
+        assert_eq!(pixel(&buf, 0, 2), &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn vp8x_canvas_smaller_than_frame() {
+        fn error(data: &[u8]) -> String {
+            let stream = WebpDecoder::new(Cursor::new(data)).dimensions();
+            let slice = WebpDecoder::from_slice(data).read_image();
+
+            match (stream, slice) {
+                (Err(ImageError::FormatError(a)), Err(ImageError::FormatError(b))) => {
+                    assert_eq!(a, b);
+                    a
+                },
+                _ => panic!("expected a format error"),
+            }
+        }
+
+        // Wider than the canvas, and taller than it
+        for &(width, height) in [(3, 4), (4, 3)].iter() {
+            let mut vp8x = vec![0x10, 0, 0, 0];
+            vp8x.extend_from_slice(&u24(width - 1));
+            vp8x.extend_from_slice(&u24(height - 1));
+            let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(4, 4, 0xff000000))]);
+
+            assert_eq!(error(&data), format!("VP8X canvas of {}x{} is smaller than the 4x4 image", width, height));
+        }
+
+        // A lossy frame with alpha one pixel wider than its canvas
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let (width, height) = WebpDecoder::new(Cursor::new(&data[..])).dimensions().unwrap();
+        let mut narrow = data.to_vec();
+        narrow[24] -= 1;
+        assert_eq!(error(&narrow), format!(
+            "VP8X canvas of {}x{} is smaller than the {}x{} image", width - 1, height, width, height
+        ));
+    }
+
+    #[test]
+    fn structure_verification() {
+        fn verify(data: &[u8]) -> Result<(), String> {
+            let stream = WebpDecoder::new(Cursor::new(data)).verify_structure();
+            let slice = WebpDecoder::from_slice(data).verify_structure();
+
+            match (stream, slice) {
+                (Ok(()), Ok(())) => Ok(()),
+                (Err(ImageError::FormatError(a)), Err(ImageError::FormatError(b))) => {
+                    assert_eq!(a, b);
+                    Err(a)
+                },
+                _ => panic!("expected the same result from a stream and a slice"),
+            }
+        }
+
+        let files: [&[u8]; 3] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp"),
+            include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp"),
+        ];
+
+        for data in files.iter() {
+            assert_eq!(verify(data), Ok(()));
+
+            // Data past the end of the container, or a container longer than the data
+            let mut appended = data.to_vec();
+            appended.extend_from_slice(b"EXIF");
+            assert!(verify(&appended).unwrap_err().contains("follows"));
+            let mut longer = data.to_vec();
+            longer[4] += 2;
+            assert!(verify(&longer).unwrap_err().contains("exceeds"));
+        }
+
+        assert_eq!(verify(&animated_file()), Ok(()));
+
+        // A frame header made invalid by a flipped bit
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let mut corrupt = data.to_vec();
+        corrupt[vp8 + 4] ^= 0x10;
+        assert_eq!(verify(&corrupt), Err(String::from("VP8 start code is invalid")));
+
+        let alph = data.windows(4).position(|w| w == b"ALPH").unwrap() + 8;
+        let mut corrupt = data.to_vec();
+        corrupt[alph] |= 0x03;
+        assert_eq!(verify(&corrupt), Err(String::from("ALPH compression method is unknown")));
+
+        let mut vp8l = solid_vp8l(2, 2, 0xff000000);
+        vp8l[4] |= 0x20;
+        assert_eq!(verify(&riff(&[chunk(b"VP8L", &vp8l)])), Err(String::from("VP8L version is not 0")));
+
+        // A canvas larger than the image
+        let mut vp8x = vec![0x10, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(2));
+        let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000))]);
+        assert!(verify(&data).unwrap_err().contains("canvas"));
+
+        // An animation frame placed partly outside of the canvas
+        let mut vp8x = vec![0x12, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(3));
+        let data = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]),
+            anmf(2, 2, 4, 4, 100, 0, 0xffff0000),
+        ]);
+        assert_eq!(verify(&data), Err(String::from("Animation frame extends past the canvas")));
+    }
+
+    #[test]
+    fn no_image_data() {
+        let mut vp8x = vec![0x28, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(0));
+        vp8x.extend_from_slice(&u24(0));
+
+        let containers = [
+            riff(&[chunk(b"VP8X", &vp8x)]),
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile")]),
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), chunk(b"EXIF", b"II*\x00")]),
+        ];
+
+        for image in containers.iter() {
+            match WebpDecoder::new(Cursor::new(&image[..])).dimensions() {
+                Err(ImageError::FormatError(msg)) => assert_eq!(msg, "WebP container has no image data"),
+                _ => panic!("expected a format error"),
+            }
+        }
+    }
+
+    #[test]
+    fn riff_length_mismatch() {
+        let image = riff(&[chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000))]);
+        let set_len = |len: u32| {
+            let mut image = image.clone();
+            image[4] = len as u8;
+            image[5] = (len >> 8) as u8;
+            image
+        };
+
+        // A length past the end of the file is fine if the chunks are complete
+        let longer = set_len(image.len() as u32 + 100);
+        let mut decoder = WebpDecoder::new(Cursor::new(&longer[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert!(decoder.read_image().is_ok());
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+
+        // Data after the end of the RIFF container is ignored
+        let mut trailing = image.clone();
+        trailing.extend_from_slice(b"EXIF\xff\xff\xff\xff");
+        let mut decoder = WebpDecoder::new(Cursor::new(&trailing[..]));
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&longer[..longer.len() - 3]));
+        match decoder.dimensions() {
+            Err(ImageError::FormatError(msg)) => assert!(msg.contains("exceeds"), "{}", msg),
+            _ => panic!("expected a format error"),
+        }
+
+        let shorter = set_len(4);
+        let mut decoder = WebpDecoder::new(Cursor::new(&shorter[..]));
+        match decoder.dimensions() {
+            Err(ImageError::FormatError(_)) => (),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn missing_padding_at_end() {
+        // The VP8L chunk has an odd length and is the last chunk of the file
+        let data = include_bytes!("../../tests/images/webp/lossless/2_webp_ll.webp");
+        let (_, expected) = decode(data);
+        let unpadded = &data[..data.len() - 1];
+
+        // With the padding byte counted in the RIFF length, and without
+        let mut shorter_riff = unpadded.to_vec();
+        shorter_riff[4] -= 1;
+
+        for file in [unpadded.to_vec(), shorter_riff].iter() {
+            assert_eq!(decode(file).1, expected);
+            assert_eq!(WebpDecoder::from_slice(file).read_image_rgba().unwrap(), expected);
+
+            let mut decoder = WebpDecoder::new(Cursor::new(&file[..]));
+            assert_eq!(decoder.chunks().unwrap().len(), 1);
+        }
+
+        // An odd XMP chunk at the end
+        let mut vp8x = vec![0x04, 0, 0, 0];
+        vp8x.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let mut unpadded = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(1, 1, 0xff0000ff)),
+                                chunk(b"XMP ", b"abc")]);
+        unpadded.pop();
+
+        assert_eq!(WebpDecoder::new(Cursor::new(&unpadded[..])).xmp_metadata().unwrap(), Some(b"abc".to_vec()));
+        assert_eq!(WebpDecoder::from_slice(&unpadded).xmp_metadata().unwrap(), Some(b"abc".to_vec()));
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        // 100 pseudo random bytes
+        let mut seed = 12345u32;
+        let garbage: Vec<u8> = (0..100).map(|_| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            (seed >> 16) as u8
+        }).collect();
+
+        let files: [&[u8]; 2] = [
+            include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/2_webp_ll.webp"),
+        ];
+
+        for data in files.iter() {
+            let (_, expected) = decode(data);
+
+            let mut appended = data.to_vec();
+            appended.extend_from_slice(&garbage);
+            assert_eq!(decode(&appended).1, expected);
+
+            let mut decoder = WebpDecoder::from_slice(&appended);
+            assert_eq!(decoder.read_image_rgba().unwrap().len(), 4 * 386 * 395);
+            assert_eq!(decoder.exif_metadata().unwrap(), None);
+            assert_eq!(decoder.chunks().unwrap().last().unwrap().range().end, data.len() as u64);
+        }
+
+        // A second file concatenated to the first
+        let mut twice = animated_file();
+        twice.extend_from_slice(&animated_file());
+        let decoder = WebpDecoder::new(Cursor::new(&twice[..]));
+        assert_eq!(decoder.into_frames().unwrap().count(), 3);
+    }
+
+    #[test]
+    fn icc_profile() {
+        let mut vp8x = vec![0x20, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ICCP", b"profile"),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&image[..]));
+        assert_eq!(decoder.icc_profile().unwrap(), Some(b"profile".to_vec()));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+
+        // Borrowed from the input of a slice decoder
+        let mut decoder = WebpDecoder::from_slice(&image[..]);
+        let profile = decoder.icc_profile_bytes().unwrap().unwrap();
+        assert_eq!(profile, b"profile");
+        let offset = image.windows(7).position(|w| w == b"profile").unwrap();
+        assert_eq!(profile.as_ptr(), image[offset..].as_ptr());
+
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+    }
+
+    #[test]
+    fn color_profile_callback() {
+        let mut vp8x = vec![0x20, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(2));
+        vp8x.extend_from_slice(&u24(1));
+
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ICCP", b"profile"),
+            chunk(b"VP8L", &solid_vp8l(3, 2, 0xff102030)),
+        ]);
+
+        let rows = Arc::new(Mutex::new(Vec::new()));
+        let new_decoder = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new_seekable(Cursor::new(data.to_vec()));
+            let rows = rows.clone();
+            decoder.set_color_profile_callback(Box::new(move |profile, pixels| {
+                rows.lock().unwrap().push((profile.to_vec(), pixels.len()));
+                for p in pixels.chunks_mut(4) {
+                    p[0] = 255 - p[0];
+                }
+            }));
+            decoder
+        };
+
+        // Each row is passed once, with its channels as returned
+        let mut decoder = new_decoder(&image);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => assert_eq!(&buf[..8], &[0xef, 0x20, 0x30, 0xff, 0xef, 0x20, 0x30, 0xff]),
+            _ => panic!("unexpected 16 bit output"),
+        }
+        assert_eq!(*rows.lock().unwrap(), vec![(b"profile".to_vec(), 12), (b"profile".to_vec(), 12)]);
+
+        let mut row = vec![0u8; 12];
+        let mut decoder = new_decoder(&image);
+        assert!(decoder.read_scanline(&mut row).is_ok());
+        assert_eq!(row[0], 0xef);
+        assert_eq!(rows.lock().unwrap().len(), 3);
+
+        // Images without a profile are left alone
+        let data = riff(&[chunk(b"VP8L", &solid_vp8l(3, 2, 0xff102030))]);
+        let (_, expected) = decode(&data);
+        assert_eq!(new_decoder(&data).read_image_rgba().unwrap(), expected);
+        assert_eq!(rows.lock().unwrap().len(), 3);
+
+        // The rows of a lossy image without alpha are RGB, even for RGBA output
+        let lossy = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let vp8 = lossy.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let (width, height) = WebpDecoder::new(Cursor::new(&lossy[..])).dimensions().unwrap();
+        let mut vp8x = vec![0x20, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+        let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), lossy[vp8..].to_vec()]);
+
+        rows.lock().unwrap().clear();
+        assert_eq!(new_decoder(&data).read_image_rgba().unwrap().len(), 4 * (width * height) as usize);
+        let rows = rows.lock().unwrap();
+        assert_eq!(rows.len(), height as usize);
+        assert!(rows.iter().all(|row| row.1 == 3 * width as usize));
+    }
+
+    #[test]
+    fn seekable_input() {
+        let mut vp8x = vec![0x2c, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(6));
+        vp8x.extend_from_slice(&u24(2));
+
+        // A large profile and metadata, the EXIF rotates the image 90 degrees clockwise
+        let profile = vec![7u8; 100001];
+        let mut exif = b"II*\x00\x08\x00\x00\x00\x01\x00\x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00".to_vec();
+        exif.extend_from_slice(&[0; 50000]);
+        let xmp = vec![b'x'; 20000];
+
+        let image = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ICCP", &profile),
+            chunk(b"VP8L", &solid_vp8l(7, 3, 0xff102030)),
+            chunk(b"EXIF", &exif),
+            chunk(b"XMP ", &xmp),
+        ]);
+
+        let mut decoder = WebpDecoder::new_seekable(CountingReader { inner: Cursor::new(&image), read: 0 });
+        assert_eq!(decoder.dimensions().unwrap(), (7, 3));
+        assert_eq!(decoder.chunks().unwrap().len(), 5);
+        assert!(decoder.r.read < 1000);
+
+        // Applying the orientation only reads the EXIF payload
+        let mut oriented = WebpDecoder::new_seekable(CountingReader { inner: Cursor::new(&image), read: 0 });
+        oriented.apply_orientation(true);
+        assert_eq!(oriented.dimensions().unwrap(), (3, 7));
+        assert!(oriented.r.read < exif.len() + 1000);
+
+        let (_, expected) = decode(&image);
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => assert_eq!(buf, expected),
+            _ => panic!("unexpected 16 bit output"),
+        }
+
+        // The payloads are read when they are asked for, the profile only once
+        assert_eq!(decoder.icc_profile().unwrap(), Some(profile.clone()));
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(exif.clone()));
+        assert_eq!(decoder.xmp_metadata().unwrap(), Some(xmp.clone()));
+        assert_eq!(decoder.icc_profile().unwrap(), Some(profile.clone()));
+        assert_eq!(decoder.icc_profile_bytes().unwrap(), Some(&profile[..]));
+        assert!(decoder.r.read > profile.len() && decoder.r.read < 2 * profile.len());
+        assert!(decoder.verify_structure().is_ok());
+
+        // Input that ends inside of a skipped payload is found out as it is skipped
+        for &missing in [1, xmp.len()].iter() {
+            let mut decoder = WebpDecoder::new_seekable(Cursor::new(&image[..image.len() - missing]));
+            assert!(decoder.read_image().is_ok());
+            assert!(decoder.chunks().is_err());
+        }
+    }
+
+    #[test]
+    fn exif_metadata() {
+        let mut vp8x = vec![0x28, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let exif = b"II*\x00\x08\x00\x00\x00";
+
+        let with_profile = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ICCP", b"profile"),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+            chunk(b"EXIF", exif),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&with_profile[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(exif.to_vec()));
+        assert!(decoder.read_image().is_ok());
+
+        vp8x[0] = 0x08;
+        let without_profile = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+            chunk(b"EXIF", exif),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&without_profile[..]));
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(exif.to_vec()));
+        assert_eq!(decoder.icc_profile().unwrap(), None);
+
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+    }
+
+    #[test]
+    fn metadata_chunk_order() {
+        let mut vp8x = vec![0x3c, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let exif = b"II*\x00\x08\x00\x00\x00";
+        let image = chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000));
+
+        let orders = [
+            // The profile last, and the metadata ahead of the image data
+            riff(&[chunk(b"VP8X", &vp8x), image.clone(), chunk(b"EXIF", exif), chunk(b"XMP ", b"xmp"), chunk(b"ICCP", b"profile")]),
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"XMP ", b"xmp"), chunk(b"EXIF", exif), chunk(b"ICCP", b"profile"), image.clone()]),
+            riff(&[chunk(b"VP8X", &vp8x), chunk(b"EXIF", exif), image.clone(), chunk(b"ICCP", b"profile"), chunk(b"XMP ", b"xmp")]),
+        ];
+
+        for data in orders.iter() {
+            for &seekable in [false, true].iter() {
+                let mut decoder = if seekable {
+                    WebpDecoder::new_seekable(Cursor::new(&data[..]))
+                } else {
+                    WebpDecoder::new(Cursor::new(&data[..]))
+                };
+
+                assert_eq!(decoder.icc_profile().unwrap(), Some(b"profile".to_vec()));
+                assert_eq!(decoder.exif_metadata().unwrap(), Some(exif.to_vec()));
+                assert_eq!(decoder.xmp_metadata().unwrap(), Some(b"xmp".to_vec()));
+                assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+                assert!(decoder.take_warnings().is_empty());
+                assert!(decoder.verify_structure().is_ok());
+            }
+
+            let mut decoder = WebpDecoder::from_slice(&data[..]);
+            assert!(decoder.read_image().is_ok());
+            assert_eq!(decoder.icc_profile().unwrap(), Some(b"profile".to_vec()));
+        }
+
+        // The image data is still required, and VP8X still comes first
+        let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"EXIF", exif), chunk(b"ICCP", b"profile")]);
+        match WebpDecoder::new(Cursor::new(&data[..])).dimensions() {
+            Err(ImageError::FormatError(msg)) => assert_eq!(msg, "WebP container has no image data"),
+            _ => panic!("expected a format error"),
+        }
+
+        let data = riff(&[chunk(b"ICCP", b"profile"), chunk(b"VP8X", &vp8x), image.clone()]);
+        assert!(WebpDecoder::new(Cursor::new(&data[..])).dimensions().is_err());
+    }
+
+    #[test]
+    fn strict_chunks() {
+        let mut vp8x = vec![0x3c, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let exif = b"II*\x00\x08\x00\x00\x00";
+        let image = chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000));
+
+        let strict = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            decoder.set_strict(true);
+            match decoder.read_image() {
+                Ok(_) => Ok(()),
+                Err(ImageError::FormatError(msg)) => Err(msg),
+                Err(err) => panic!("unexpected error {:?}", err),
+            }
+        };
+
+        let data = riff(&[
+            chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), image.clone(), chunk(b"EXIF", exif), chunk(b"XMP ", b"xmp"),
+        ]);
+        assert_eq!(strict(&data), Ok(()));
+        assert_eq!(strict(include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp")), Ok(()));
+        assert_eq!(strict(&animated_file()), Ok(()));
+
+        let failing: [(Vec<u8>, &str); 5] = [
+            (riff(&[chunk(b"VP8X", &vp8x), image.clone(), chunk(b"ICCP", b"profile"), chunk(b"EXIF", exif), chunk(b"XMP ", b"xmp")]),
+             "ICCP chunk is out of order after the VP8L chunk"),
+            (riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), chunk(b"XMP ", b"xmp"), chunk(b"EXIF", exif), image.clone()]),
+             "EXIF chunk is out of order after the XMP  chunk"),
+            (riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), image.clone(), chunk(b"EXIF", exif), chunk(b"EXIF", exif)]),
+             "EXIF chunk is out of order after the EXIF chunk"),
+            (riff(&[image.clone(), chunk(b"EXIF", exif)]),
+             "EXIF chunk is out of order after the VP8L chunk"),
+            (riff(&[image.clone(), chunk(b"junk", b"")]),
+             "Unknown junk chunk"),
+        ];
+
+        for &(ref data, msg) in failing.iter() {
+            assert_eq!(strict(data), Err(String::from(msg)));
+
+            // All of them are tolerated by default
+            assert!(WebpDecoder::new(Cursor::new(&data[..])).read_image().is_ok());
+        }
+
+        // Unknown chunks ahead of the image data always fail, only the message differs
+        let data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"ICCP", b"profile"), chunk(b"junk", b""), image.clone()]);
+        assert_eq!(strict(&data), Err(String::from("Unknown junk chunk")));
+        match WebpDecoder::new(Cursor::new(&data[..])).read_image() {
+            Err(ImageError::FormatError(msg)) => assert_eq!(msg, "Unexpected junk chunk"),
+            _ => panic!("expected a format error"),
+        }
+    }
+
+    #[test]
+    fn consistent_output() {
+        let mut lossy = Vec::new();
+        WebpEncoder::new(&mut lossy).encode(&[128; 3 * 16 * 8], 16, 8, color::ColorType::RGB(8)).unwrap();
+
+        // 7x3 with the EXIF orientation rotating it 90 degrees clockwise
+        let mut vp8x = vec![0x18, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(6));
+        vp8x.extend_from_slice(&u24(2));
+        let exif = b"II*\x00\x08\x00\x00\x00\x01\x00\x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00";
+        let rotated = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(7, 3, 0xff102030)), chunk(b"EXIF", exif)]);
+
+        let files: Vec<&[u8]> = vec![
+            &lossy,
+            include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp"),
+            &rotated,
+        ];
+        let animated = animated_file();
+
+        for data in files.iter().cloned().chain(Some(&animated[..])) {
+            for &(orient, sixteen_bit) in [(false, false), (true, false), (false, true), (true, true)].iter() {
+                let new_decoder = || {
+                    let mut decoder = WebpDecoder::new(Cursor::new(data));
+                    decoder.apply_orientation(orient);
+                    decoder.set_16_bit_output(sixteen_bit);
+                    decoder
+                };
+
+                // The image is read before asking for its layout
+                let mut decoder = new_decoder();
+                let bytes = match decoder.read_image().unwrap() {
+                    DecodingResult::U8(image) => image.len(),
+                    DecodingResult::U16(image) => 2 * image.len(),
+                };
+
+                let (width, height) = decoder.dimensions().unwrap();
+                let colortype = decoder.colortype().unwrap();
+                let row_len = decoder.row_len().unwrap();
+                assert_eq!(color::bits_per_pixel(colortype) / 8 * width as usize, row_len);
+                assert_eq!(row_len * height as usize, bytes);
+
+                let mut decoder = new_decoder();
+                assert_eq!(decoder.row_len().unwrap(), row_len);
+                assert_eq!(decoder.colortype().unwrap(), colortype);
+                assert_eq!(decoder.dimensions().unwrap(), (width, height));
+
+                if !sixteen_bit {
+                    let mut row = vec![0u8; row_len];
+                    let mut rows = 0;
+                    while decoder.read_scanline(&mut row).is_ok() {
+                        rows += 1;
+                    }
+                    assert_eq!(rows, height);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exif_orientation() {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let upright = decoder.read_image_rgba().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (400, 301));
+
+        let mut vp8x = vec![0x08, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(399));
+        vp8x.extend_from_slice(&u24(300));
+
+        // An IFD with only the Orientation tag, rotate 90 degrees clockwise
+        let exif = b"II*\x00\x08\x00\x00\x00\x01\x00\x12\x01\x03\x00\x01\x00\x00\x00\x06\x00\x00\x00";
+        let rotated = riff(&[
+            chunk(b"VP8X", &vp8x),
+            data[12..].to_vec(),
+            chunk(b"EXIF", exif),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&rotated[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (400, 301));
+        assert_eq!(decoder.read_image_rgba().unwrap(), upright);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&rotated[..]));
+        decoder.apply_orientation(true);
+        assert_eq!(decoder.dimensions().unwrap(), (301, 400));
+        assert_eq!(decoder.row_len().unwrap(), 4 * 301);
+
+        let image = match decoder.read_image().unwrap() {
+            DecodingResult::U8(image) => image,
+            _ => panic!("Expected U8 image"),
+        };
+        assert_eq!(image.len(), upright.len());
+
+        // The bottom left corner of the stored image is shown at the top left
+        for &(x, y) in [(0, 0), (300, 0), (0, 399), (120, 7)].iter() {
+            assert_eq!(&image[4 * (y * 301 + x)..][..4], &upright[4 * ((300 - x) * 400 + y)..][..4]);
+        }
+    }
+
+    #[test]
+    fn chunk_list() {
+        let mut vp8x = vec![0x08, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let exif = b"II*\x00\x08";
+
+        let data = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+            chunk(b"EXIF", exif),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let chunks = decoder.chunks().unwrap();
+        let names: Vec<_> = chunks.iter().map(|c| c.name).collect();
+        assert_eq!(names, [*b"VP8X", *b"VP8L", *b"EXIF"]);
+        assert_eq!(chunks[0], ChunkInfo { name: *b"VP8X", offset: 12, len: 10 });
+
+        // The chunks follow each other, the EXIF payload is padded to an even length
+        assert_eq!(chunks[1].range().start, chunks[0].range().end);
+        assert_eq!(chunks[2].range().start, chunks[1].range().end);
+        assert_eq!(chunks[2].range().end, data.len() as u64);
+
+        let payload = chunks[2].payload();
+        assert_eq!(&data[payload.start as usize..payload.end as usize], exif);
+
+        // Borrowing the input makes no difference
+        let mut decoder = WebpDecoder::from_slice(&data);
+        assert_eq!(decoder.chunks().unwrap(), chunks);
+    }
+
+    #[test]
+    fn xmp_metadata() {
+        let mut vp8x = vec![0x04, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let xmp = b"<x:xmpmeta xmlns:x='adobe:ns:meta/'/>";
+
+        let without_exif = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+            chunk(b"XMP ", xmp),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&without_exif[..]));
+        assert_eq!(decoder.xmp_metadata().unwrap(), Some(xmp.to_vec()));
+        assert_eq!(decoder.exif_metadata().unwrap(), None);
+
+        vp8x[0] = 0x0c;
+        let with_exif = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)),
+            chunk(b"EXIF", b"II*\x00"),
+            chunk(b"XMP ", xmp),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&with_exif[..]));
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(b"II*\x00".to_vec()));
+        assert_eq!(decoder.xmp_metadata().unwrap(), Some(xmp.to_vec()));
+    }
+
+    #[test]
+    fn animation_exif_metadata() {
+        let mut data = animated_file();
+        let exif = chunk(b"EXIF", b"II*\x00");
+        data.extend_from_slice(&exif);
+
+        // Fix up the RIFF length
+        let len = data.len() as u32 - 8;
+        data[4] = len as u8;
+        data[5] = (len >> 8) as u8;
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.animation_frames().unwrap().len(), 3);
+        assert_eq!(decoder.exif_metadata().unwrap(), Some(b"II*\x00".to_vec()));
+    }
+
+    #[test]
+    fn scanline_length() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let len = decoder.row_len().unwrap();
+
+        for &n in [0, len - 1, len + 1].iter() {
+            match decoder.read_scanline(&mut vec![0u8; n]) {
+                Err(ImageError::DimensionError) => (),
+                _ => panic!("expected a dimension error for a row of {} bytes", n),
+            }
+        }
+
+        // No row was read by the failed calls
+        assert_eq!(decoder.read_scanline(&mut vec![0u8; len]).unwrap(), 1);
+    }
+
+    #[test]
+    fn read_past_last_scanline() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+
+        let (_, height) = decoder.dimensions().unwrap();
+        let mut row = vec![0u8; decoder.row_len().unwrap()];
+
+        for y in 0..height {
+            assert_eq!(decoder.read_scanline(&mut row).unwrap(), y + 1);
+        }
+
+        match decoder.read_scanline(&mut row) {
+            Err(::image::ImageError::ImageEnd) => (),
+            _ => panic!("expected ImageEnd after the last row"),
+        }
+    }
+
+    #[test]
+    fn info() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let info = WebpDecoder::new(Cursor::new(&data[..])).info().unwrap();
+        assert_eq!(info, WebpInfo {
+            width: 421,
+            height: 163,
+            has_alpha: true,
+            has_animation: false,
+            is_lossless: false,
+            icc_present: false,
+        });
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let info = WebpDecoder::new(Cursor::new(&data[..])).info().unwrap();
+        assert_eq!((info.width, info.height), (421, 163));
+        assert!(info.has_alpha && info.is_lossless && !info.has_animation);
+
+        let data = animated_file();
+        let info = WebpDecoder::new(Cursor::new(&data[..])).info().unwrap();
+        assert_eq!((info.width, info.height), (4, 4));
+        assert!(info.has_alpha && info.is_lossless && info.has_animation && !info.icc_present);
+    }
+
+    #[test]
+    fn vp8x_validation() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.vp8x_flags().unwrap(), Some(0x10));
+        assert_eq!(decoder.validate().unwrap(), []);
+
+        // Simple images have no flags to check
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.vp8x_flags().unwrap(), None);
+        assert_eq!(decoder.validate().unwrap(), []);
+
+        let data = animated_file();
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).validate().unwrap(), []);
+
+        // The fragment and ICC profile flags without a profile, an EXIF chunk and
+        // an image with alpha that are not flagged
+        let mut vp8x = vec![0x21, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+
+        let data = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"VP8L", &solid_vp8l(2, 2, 0x80000000)),
+            chunk(b"EXIF", b"II*\x00"),
+        ]);
+
+        let discrepancies = WebpDecoder::new(Cursor::new(&data[..])).validate().unwrap();
+        assert_eq!(discrepancies, [
+            FlagDiscrepancy::ReservedBits(0x01),
+            FlagDiscrepancy::MissingFeature(VP8XFlag::IccProfile),
+            FlagDiscrepancy::UnflaggedFeature(VP8XFlag::Alpha),
+            FlagDiscrepancy::UnflaggedFeature(VP8XFlag::Exif),
+        ]);
+        assert_eq!(format!("{}", discrepancies[1]), "VP8X ICC profile flag is set, but the image has none");
+    }
+
+    #[test]
+    fn warnings() {
+        let warnings = |data: &[u8]| {
+            let mut decoder = WebpDecoder::new(Cursor::new(data));
+            assert!(decoder.read_image().is_ok());
+            let _ = decoder.chunks().unwrap();
+            decoder.take_warnings()
+        };
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        assert_eq!(warnings(data), Vec::<String>::new());
+        assert_eq!(warnings(&animated_file()), Vec::<String>::new());
+
+        // An odd length EXIF chunk without its padding byte ends the file
+        let mut vp8x = vec![0x18, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(1));
+        vp8x.extend_from_slice(&u24(1));
+        let mut data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)), chunk(b"EXIF", b"abc")]);
+        data.pop();
+        assert_eq!(warnings(&data), ["EXIF chunk is missing its padding byte", "RIFF length exceeds the data by 1 bytes"]);
+
+        // Trailing data and an unflagged feature
+        vp8x[0] = 0x10;
+        let mut data = riff(&[chunk(b"VP8X", &vp8x), chunk(b"VP8L", &solid_vp8l(2, 2, 0xff000000)), chunk(b"EXIF", b"abcd")]);
+        data.extend_from_slice(b"garbage");
+        assert_eq!(warnings(&data), [
+            "Data follows the end of the RIFF container",
+            "VP8X EXIF flag is not set, but the image has it",
+        ]);
+
+        // Streaming the input finds the same, and taking the warnings clears them
+        let mut decoder = WebpDecoder::new(TrickleReader { data: &data, pos: 0 });
+        let _ = decoder.chunks().unwrap();
+        assert_eq!(decoder.take_warnings().len(), 2);
+        assert!(decoder.take_warnings().is_empty());
+        assert!(decoder.validate().is_ok());
+        assert!(decoder.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn animation_frames() {
+        let data = animated_file();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+
+        assert!(decoder.is_animated().unwrap());
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+
+        let info = decoder.animation_frames().unwrap();
+        assert_eq!(info.len(), 3);
+        assert_eq!((info[1].x_offset, info[1].y_offset, info[1].width, info[1].height), (2, 2, 2, 2));
+        assert_eq!(info[1].duration, 50);
+        assert_eq!(info[1].blend, BlendMethod::AlphaBlend);
+        assert_eq!(info[1].dispose, DisposeMethod::Background);
+        assert_eq!(info[2].blend, BlendMethod::NoBlend);
+        assert_eq!(info[2].dispose, DisposeMethod::None);
+    }
+
+    #[test]
+    fn animation_length() {
+        let data = animated_file();
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.animation_info().unwrap(), Some(AnimationInfo {
+            frame_count: 3,
+            duration: 100 + 50 + 70000,
+            background_color: [0, 0, 255, 255],
+            loop_count: 0,
+        }));
+
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        assert_eq!(WebpDecoder::new(Cursor::new(&data[..])).animation_info().unwrap(), None);
+
+        // The image data of the frames is only read when they are decoded
+        let vp8 = data.windows(4).position(|w| w == b"VP8 ").unwrap();
+        let (width, height) = WebpDecoder::new(Cursor::new(&data[..])).dimensions().unwrap();
+        let mut vp8x = vec![0x02, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+        let mut frame = [u24(0), u24(0), u24(width - 1), u24(height - 1), u24(100)].concat();
+        frame.push(0);
+        frame.extend_from_slice(&data[vp8..]);
+        let image = riff(&[chunk(b"VP8X", &vp8x), chunk(b"ANIM", &[0; 6]), chunk(b"ANMF", &frame), chunk(b"ANMF", &frame)]);
+
+        let mut decoder = WebpDecoder::new_seekable(CountingReader { inner: Cursor::new(&image), read: 0 });
+        assert_eq!(decoder.animation_info().unwrap().unwrap().frame_count, 2);
+        assert!(decoder.r.read < 1000);
+
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+        let opaque = riff(&[data[vp8..].to_vec()]);
+        assert!(frames[1].buffer().clone().into_raw() == WebpDecoder::new(Cursor::new(&opaque[..])).read_image_rgba().unwrap());
+
+        let mut decoder = WebpDecoder::from_slice(&image);
+        assert_eq!(decoder.animation_info().unwrap().unwrap().frame_count, 2);
+        for f in decoder.animation.as_ref().unwrap().frames.iter() {
+            match f.1 {
+                Payload::Borrowed(start, end) => assert_eq!(&image[start..end], &data[vp8..]),
+                _ => panic!("frame data was copied"),
+            }
+        }
+        assert!(decoder.verify_structure().is_ok());
+    }
+
+    #[test]
+    fn animation_compositing() {
+        let data = animated_file();
+        let decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].delay(), ::num_rational::Ratio::new(1, 10));
+
+        let first = frames[0].buffer().clone().into_raw();
+        assert!(first.chunks(4).all(|p| p == [255, 0, 0, 255]));
+
+        let second = frames[1].buffer().clone().into_raw();
+        assert_eq!(pixel(&second, 1, 1), [255, 0, 0, 255]);
+        // 128 / 255 of green over red, rounded
+        assert_eq!(pixel(&second, 3, 3), [127, 128, 0, 255]);
+
+        // The second frame was disposed to the background before the third was drawn
+        let third = frames[2].buffer().clone().into_raw();
+        assert_eq!(pixel(&third, 0, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(&third, 2, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&third, 3, 3), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    fn animation_background() {
+        let mut vp8x = vec![0x12, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(3));
+        vp8x.extend_from_slice(&u24(3));
+
+        // Half transparent purple background in BGRA order, played three times
+        let anim = [0x40, 0x20, 0x80, 0x80, 3, 0];
+        let data = riff(&[
+            chunk(b"VP8X", &vp8x),
+            chunk(b"ANIM", &anim),
+            // Opaque red over the whole canvas, disposed to the background
+            anmf(0, 0, 4, 4, 100, 1, 0xffff0000),
+            // Opaque green in the bottom right
+            anmf(2, 2, 2, 2, 100, 0, 0xff00ff00),
+        ]);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let info = decoder.animation_info().unwrap().unwrap();
+        assert_eq!(info.background_color, [0x80, 0x20, 0x40, 0x80]);
+        assert_eq!(info.loop_count, 3);
+
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].buffer().clone().into_raw().chunks(4).all(|p| p == [255, 0, 0, 255]));
+
+        let second = frames[1].buffer().clone().into_raw();
+        assert_eq!(pixel(&second, 0, 0), [0x80, 0x20, 0x40, 0x80]);
+        assert_eq!(pixel(&second, 3, 1), [0x80, 0x20, 0x40, 0x80]);
+        assert_eq!(pixel(&second, 2, 2), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn animation_as_still_image() {
+        let data = animated_file();
+        let (colortype, image) = decode(&data);
+        assert_eq!(colortype, color::ColorType::RGBA(8));
+
+        let decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+        assert_eq!(image, frames[0].buffer().clone().into_raw());
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.read_region(2, 2, 2, 1).unwrap(), vec![255, 0, 0, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn still_image_as_single_frame() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_ll.webp");
+        let decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let frames: Vec<_> = decoder.into_frames().unwrap().collect();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].buffer().dimensions(), (421, 163));
+    }
+
+    #[test]
+    fn gradient_filtered_alpha() {
+        assert_same_alpha(include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp"),
+                          include_bytes!("../../tests/images/webp/lossless/2_webp_ll.webp"));
+    }
+
+    #[test]
+    fn dequantized_alpha() {
+        let lossy = include_bytes!("../../tests/images/webp/lossless/shadow_webp_a.webp");
+        let (_, lossless) = decode(include_bytes!("../../tests/images/webp/lossless/shadow_webp_ll.webp"));
+        let (_, reduced) = decode(lossy);
+
+        // The reference is libwebp's output with full alpha dithering
+        let mut decoder = WebpDecoder::new(Cursor::new(&lossy[..]));
+        decoder.set_alpha_dithering(true);
+        let smoothed = match decoder.read_image().unwrap() {
+            DecodingResult::U8(buf) => buf,
+            _ => panic!("unexpected 16 bit output"),
+        };
+        assert_eq!(smoothed.len(), lossless.len());
+        for (a, b) in smoothed.chunks(4).zip(lossless.chunks(4)) {
+            assert_eq!(a[3], b[3]);
+        }
+
+        // Without it the alpha plane keeps its reduced levels
+        assert!(reduced.chunks(4).zip(lossless.chunks(4)).any(|(a, b)| a[3] != b[3]));
+        let mut levels: Vec<u8> = reduced.chunks(4).map(|p| p[3]).collect();
+        levels.sort();
+        levels.dedup();
+        assert!(levels.len() <= 16, "{} alpha levels", levels.len());
+    }
+
+    #[test]
+    fn scanline_iterator() {
+        let data = include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp");
+        let (_, image) = decode(data);
+
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        let (width, height) = decoder.dimensions().unwrap();
+        let rows: Vec<Vec<u8>> = {
+            let mut scanlines = decoder.scanlines();
+            let rows = scanlines.by_ref().map(|row| row.unwrap()).collect();
+            assert!(scanlines.next().is_none());
+            rows
+        };
 
-        Ok(image::DecodingResult::U8(self.frame.ybuf.clone()))
+        assert_eq!(rows.len(), height as usize);
+        assert_eq!(rows.concat(), image);
+        assert_eq!(rows[0].len(), 4 * width as usize);
+        assert!(decoder.scanlines().next().is_none());
     }
 }
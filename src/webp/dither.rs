@@ -0,0 +1,173 @@
+//! Dithering of the 8 bit output down to fewer levels per channel
+//!
+//! The color channels are reduced to the values of ```bits``` bits scaled back
+//! up to the range of 0 to 255, so the output keeps its color type. Alpha is
+//! left as it is.
+
+use std::cmp;
+use std::default::Default;
+
+/// How the color channels of the output are dithered, see
+/// ```WebpDecoder::set_output_dither```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// The output is left as it is decoded
+    None,
+
+    /// Each channel is reduced to the given number of bits with a 4x4 Bayer
+    /// threshold matrix, which does not depend on the other pixels of the image
+    Ordered(u8),
+
+    /// Each channel is reduced to the given number of bits and the error is
+    /// spread onto the pixels to the right and below with Floyd-Steinberg weights
+    FloydSteinberg(u8),
+}
+
+impl Default for Dither {
+    fn default() -> Dither {
+        Dither::None
+    }
+}
+
+const BAYER: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
+];
+
+// The highest level of a channel reduced to bits, which are at least one
+fn max_level(bits: u8) -> i32 {
+    (1 << cmp::max(bits, 1)) - 1
+}
+
+// The 8 bit value of a level
+fn expand(level: i32, max: i32) -> u8 {
+    ((level * 255 + max / 2) / max) as u8
+}
+
+/// Dithers row ```y``` of the output, with ```bpp``` values per pixel, to ```bits```
+/// bits per channel with the Bayer matrix.
+pub fn ordered(row: &mut [u8], bpp: usize, y: usize, bits: u8) {
+    if bits >= 8 {
+        return
+    }
+
+    let max = max_level(bits);
+    let thresholds = &BAYER[y % 4];
+
+    for (x, px) in row.chunks_mut(bpp).enumerate() {
+        // Each threshold is the center of one of the 16 steps between two levels
+        let t = 2 * thresholds[x % 4] as i32 + 1;
+        for c in px[..3].iter_mut() {
+            let level = (*c as i32 * max * 32 + t * 255) / (255 * 32);
+            *c = expand(level, max);
+        }
+    }
+}
+
+/// Dithers a row of the output to ```bits``` bits per channel, spreading the error
+/// of each pixel onto the ones right and below it.
+///
+/// ```errors``` holds the error spread onto the row from the row above it in
+/// sixteenths, three values per pixel with a pixel of room on either side, and
+/// is left holding the error for the row below. It is reset if it has the wrong
+/// length, as it does for the first row.
+pub fn floyd_steinberg(row: &mut [u8], bpp: usize, errors: &mut Vec<i32>, bits: u8) {
+    if bits >= 8 {
+        return
+    }
+
+    let width = row.len() / bpp;
+    if errors.len() != 3 * (width + 2) {
+        *errors = vec![0; 3 * (width + 2)];
+    }
+
+    let max = max_level(bits);
+    let mut current = errors.clone();
+    for e in errors.iter_mut() {
+        *e = 0;
+    }
+
+    for (x, px) in row.chunks_mut(bpp).enumerate() {
+        for (c, v) in px[..3].iter_mut().enumerate() {
+            let i = 3 * (x + 1) + c;
+
+            // The error is rounded to the nearest value, the shift rounds down
+            let value = cmp::max(0, cmp::min(255, *v as i32 + ((current[i] + 8) >> 4)));
+            let level = (value * max + 127) / 255;
+            *v = expand(level, max);
+
+            let error = value - *v as i32;
+            current[i + 3] += 7 * error;
+            errors[i - 3] += 3 * error;
+            errors[i] += 5 * error;
+            errors[i + 3] += error;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{floyd_steinberg, ordered};
+
+    fn levels(row: &[u8]) -> Vec<u8> {
+        let mut levels = row.to_vec();
+        levels.sort();
+        levels.dedup();
+        levels
+    }
+
+    #[test]
+    fn ordered_levels() {
+        let mut rows = vec![vec![128u8; 4 * 3]; 4];
+        for (y, row) in rows.iter_mut().enumerate() {
+            ordered(row, 3, y, 1);
+            assert_eq!(levels(row), vec![0, 255]);
+        }
+
+        // Half of the pixels of a 4x4 block of mid gray are set
+        let set = rows.iter().flat_map(|r| r.iter()).filter(|&&v| v == 255).count();
+        assert_eq!(set, 4 * 4 * 3 / 2);
+
+        let mut row = vec![0, 255, 17, 200, 255, 0, 255, 7];
+        ordered(&mut row, 4, 2, 2);
+        assert_eq!(row, vec![0, 255, 0, 200, 255, 0, 255, 7]);
+
+        let mut row = vec![1, 2, 3];
+        ordered(&mut row, 3, 0, 8);
+        assert_eq!(row, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn floyd_steinberg_mean() {
+        let (width, height) = (16, 16);
+        let mut errors = Vec::new();
+        let mut sum = 0;
+
+        for _ in 0..height {
+            let mut row = vec![64u8; 4 * width];
+            for a in row.chunks_mut(4) {
+                a[3] = 99;
+            }
+
+            floyd_steinberg(&mut row, 4, &mut errors, 1);
+
+            for px in row.chunks(4) {
+                assert!(px[0] == 0 || px[0] == 255);
+                assert_eq!(px[3], 99);
+                sum += px[0] as u32;
+            }
+        }
+
+        // A quarter of the pixels are set, within a row of error at the bottom
+        let set = sum / 255;
+        assert!((set as i32 - (width * height / 4) as i32).abs() <= width as i32 / 4, "{} set", set);
+
+        let mut row = vec![200u8, 100, 50];
+        let mut errors = vec![0; 3];
+        floyd_steinberg(&mut row, 3, &mut errors, 3);
+        assert_eq!(errors.len(), 9);
+        assert_eq!(row, vec![182, 109, 36]);
+    }
+}
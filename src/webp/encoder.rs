@@ -0,0 +1,194 @@
+//! Encoding of Webp Images
+//!
+//! Images are written in the simple lossy format, a RIFF container holding
+//! a single VP8 chunk with one keyframe.
+//!
+//! # Related Links
+//! * [webp-container-spec](https://developers.google.com/speed/webp/docs/riff_container#simple_file_format_lossy) - The WebP Container Specification
+
+use std::io::{self, Write};
+use std::cmp;
+use byteorder::{WriteBytesExt, LittleEndian};
+
+use color;
+
+use super::vp8::VP8Encoder;
+use super::yuv;
+
+/// A representation of a Webp encoder
+pub struct WebpEncoder<'a, W: 'a> {
+    w: &'a mut W,
+    quality: u8,
+}
+
+impl<'a, W: Write> WebpEncoder<'a, W> {
+    /// Create a new encoder that writes its output to ```w```
+    pub fn new(w: &mut W) -> WebpEncoder<W> {
+        WebpEncoder::new_with_quality(w, 75)
+    }
+
+    /// Create a new encoder that writes its output to ```w```, and has
+    /// the quality parameter ```quality``` with a value in the range 0-100
+    /// where 0 is the worst and 100 is the best.
+    pub fn new_with_quality(w: &mut W, quality: u8) -> WebpEncoder<W> {
+        WebpEncoder {
+            w: w,
+            quality: cmp::min(quality, 100),
+        }
+    }
+
+    /// Encodes the image ```data```
+    /// that has dimensions ```width``` and ```height```
+    /// and ```ColorType``` ```color```
+    ///
+    /// The image is stored lossily as YUV 4:2:0, an alpha channel is dropped.
+    pub fn encode(&mut self,
+                  data: &[u8],
+                  width: u32,
+                  height: u32,
+                  color: color::ColorType) -> io::Result<()> {
+
+        let bpp = match color {
+            color::ColorType::RGB(8)   => 3,
+            color::ColorType::RGBA(8)  => 4,
+            color::ColorType::Gray(8)  => 1,
+            color::ColorType::GrayA(8) => 2,
+            _  => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported color type {:?}. Use 8 bit per channel RGB(A) or Gray(A) instead.", color)[..],
+            ))
+        };
+
+        // VP8 stores the dimensions in 14 bits
+        if width == 0 || height == 0 || width > 0x3FFF || height > 0x3FFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Unsupported dimensions {}x{}", width, height)[..],
+            ))
+        }
+
+        if data.len() < width as usize * height as usize * bpp {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Not enough image data",
+            ))
+        }
+
+        let frame = yuv::rgb_to_frame(data, width as u16, height as u16, bpp);
+
+        // Quality 100 uses the finest quantizer index 0, quality 0 the coarsest index 127
+        let quantizer = (100 - self.quality as u32) * 127 / 100;
+
+        let mut vp8 = Vec::new();
+        try!(VP8Encoder::new(&mut vp8).encode_frame(&frame, quantizer as u8));
+
+        // The RIFF length covers the WEBP tag and the padded chunk
+        let riff_len = 4 + 8 + vp8.len() + vp8.len() % 2;
+
+        try!(self.w.write_all(b"RIFF"));
+        try!(self.w.write_u32::<LittleEndian>(riff_len as u32));
+        try!(self.w.write_all(b"WEBP"));
+
+        self.write_chunk(b"VP8 ", &vp8)
+    }
+
+    // The chunk size does not include the padding byte that follows odd sized chunks
+    fn write_chunk(&mut self, name: &[u8], data: &[u8]) -> io::Result<()> {
+        try!(self.w.write_all(name));
+        try!(self.w.write_u32::<LittleEndian>(data.len() as u32));
+        try!(self.w.write_all(data));
+
+        if data.len() % 2 != 0 {
+            try!(self.w.write_all(&[0]));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use color;
+    use image::{DecodingResult, ImageDecoder};
+
+    use super::WebpEncoder;
+    use super::super::WebpDecoder;
+
+    fn encode(data: &[u8], width: u32, height: u32, c: color::ColorType, quality: u8) -> Vec<u8> {
+        let mut out = Vec::new();
+        WebpEncoder::new_with_quality(&mut out, quality).encode(data, width, height, c).unwrap();
+        out
+    }
+
+    fn decode(data: &[u8]) -> Vec<u8> {
+        let mut decoder = WebpDecoder::new(Cursor::new(data));
+        assert_eq!(decoder.colortype().unwrap(), color::ColorType::RGB(8));
+
+        match decoder.read_image().unwrap() {
+            DecodingResult::U8(pixels) => pixels,
+            _ => panic!("unexpected sample type"),
+        }
+    }
+
+    // The mean absolute difference of the RGB channels
+    fn mean_error(a: &[u8], a_bpp: usize, b: &[u8]) -> f64 {
+        let sum: u64 = a.chunks(a_bpp).zip(b.chunks(3)).map(|(p, q)| {
+            (0..3).map(|c| (p[c] as i64 - q[c] as i64).abs() as u64).sum::<u64>()
+        }).sum();
+
+        sum as f64 / (b.len() as f64)
+    }
+
+    #[test]
+    fn round_trip_gradient() {
+        let (width, height) = (37, 23);
+        let mut rgb = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                rgb.extend_from_slice(&[(x * 6) as u8, (y * 10) as u8, 128]);
+            }
+        }
+
+        let data = encode(&rgb, width, height, color::ColorType::RGB(8), 90);
+        let mut decoder = WebpDecoder::new(Cursor::new(&data[..]));
+        assert_eq!(decoder.dimensions().unwrap(), (width, height));
+
+        let decoded = decode(&data);
+        assert_eq!(decoded.len(), rgb.len());
+        assert!(mean_error(&rgb, 3, &decoded) < 3.0);
+
+        let coarse = encode(&rgb, width, height, color::ColorType::RGB(8), 0);
+        let fine = encode(&rgb, width, height, color::ColorType::RGB(8), 100);
+        assert!(coarse.len() < fine.len());
+    }
+
+    #[test]
+    fn round_trip_image() {
+        let file = include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp");
+        let mut decoder = WebpDecoder::new(Cursor::new(&file[..]));
+        let (width, height) = decoder.dimensions().unwrap();
+        let rgba = match decoder.read_image().unwrap() {
+            DecodingResult::U8(pixels) => pixels,
+            _ => panic!("unexpected sample type"),
+        };
+
+        let data = encode(&rgba, width, height, color::ColorType::RGBA(8), 75);
+        assert!(data.len() < rgba.len() / 4);
+
+        let decoded = decode(&data);
+        assert!(mean_error(&rgba, 4, &decoded) < 6.0);
+    }
+
+    #[test]
+    fn gray_and_unsupported_colors() {
+        let gray = vec![100u8; 16 * 16];
+        let decoded = decode(&encode(&gray, 16, 16, color::ColorType::Gray(8), 75));
+        assert!(mean_error(&[100u8, 100, 100].repeat(256), 3, &decoded) < 2.0);
+
+        let mut out = Vec::new();
+        assert!(WebpEncoder::new(&mut out).encode(&gray, 16, 16, color::ColorType::Gray(16)).is_err());
+        assert!(WebpEncoder::new(&mut out).encode(&gray, 32, 16, color::ColorType::Gray(8)).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! The VP8 loop filter, Section 15
+//!
+//! Each filter works on the pixels across a single edge. The pixel
+//! at ```point``` is the first one after the edge, ```step``` is 1
+//! for a vertical edge and the stride of the plane for a horizontal one.
+
+fn c(v: i32) -> i32 {
+    if v < -128 { -128 } else if v > 127 { 127 } else { v }
+}
+
+fn u2s(v: u8) -> i32 {
+    v as i32 - 128
+}
+
+fn s2u(v: i32) -> u8 {
+    (c(v) + 128) as u8
+}
+
+fn diff(a: u8, b: u8) -> i32 {
+    (a as i32 - b as i32).abs()
+}
+
+fn common_adjust(use_outer_taps: bool, buf: &mut [u8], point: usize, step: usize) -> i32 {
+    let p1 = u2s(buf[point - 2 * step]);
+    let p0 = u2s(buf[point - step]);
+    let q0 = u2s(buf[point]);
+    let q1 = u2s(buf[point + step]);
+
+    let a = c(if use_outer_taps { c(p1 - q1) } else { 0 } + 3 * (q0 - p0));
+
+    // b balances the rounding of a / 8 when its fractional part is exactly 1/2
+    let b = c(a + 3) >> 3;
+    let a = c(a + 4) >> 3;
+
+    buf[point] = s2u(q0 - a);
+    buf[point - step] = s2u(p0 + b);
+
+    a
+}
+
+fn simple_threshold(edge_limit: i32, buf: &[u8], point: usize, step: usize) -> bool {
+    diff(buf[point - step], buf[point]) * 2 + (diff(buf[point - 2 * step], buf[point + step]) >> 1) <= edge_limit
+}
+
+fn should_filter(interior_limit: i32, edge_limit: i32, buf: &[u8], point: usize, step: usize) -> bool {
+    let p3 = buf[point - 4 * step];
+    let p2 = buf[point - 3 * step];
+    let p1 = buf[point - 2 * step];
+    let p0 = buf[point - step];
+    let q0 = buf[point];
+    let q1 = buf[point + step];
+    let q2 = buf[point + 2 * step];
+    let q3 = buf[point + 3 * step];
+
+    simple_threshold(edge_limit, buf, point, step)
+        && diff(p3, p2) <= interior_limit && diff(p2, p1) <= interior_limit
+        && diff(p1, p0) <= interior_limit && diff(q3, q2) <= interior_limit
+        && diff(q2, q1) <= interior_limit && diff(q1, q0) <= interior_limit
+}
+
+// Whether the edge has high variance
+fn hev(threshold: i32, buf: &[u8], point: usize, step: usize) -> bool {
+    diff(buf[point - 2 * step], buf[point - step]) > threshold
+        || diff(buf[point + step], buf[point]) > threshold
+}
+
+/// The filter used on every luma edge by the simple loop filter
+pub fn simple_segment(edge_limit: i32, buf: &mut [u8], point: usize, step: usize) {
+    if simple_threshold(edge_limit, buf, point, step) {
+        common_adjust(true, buf, point, step);
+    }
+}
+
+/// The filter of the normal loop filter for edges between subblocks
+pub fn subblock_filter(hev_threshold: i32,
+                       interior_limit: i32,
+                       edge_limit: i32,
+                       buf: &mut [u8],
+                       point: usize,
+                       step: usize) {
+
+    if should_filter(interior_limit, edge_limit, buf, point, step) {
+        let hv = hev(hev_threshold, buf, point, step);
+        let a = (common_adjust(hv, buf, point, step) + 1) >> 1;
+
+        if !hv {
+            buf[point + step] = s2u(u2s(buf[point + step]) - a);
+            buf[point - 2 * step] = s2u(u2s(buf[point - 2 * step]) + a);
+        }
+    }
+}
+
+/// The filter of the normal loop filter for edges between macroblocks
+pub fn macroblock_filter(hev_threshold: i32,
+                         interior_limit: i32,
+                         edge_limit: i32,
+                         buf: &mut [u8],
+                         point: usize,
+                         step: usize) {
+
+    if !should_filter(interior_limit, edge_limit, buf, point, step) {
+        return
+    }
+
+    if hev(hev_threshold, buf, point, step) {
+        common_adjust(true, buf, point, step);
+        return
+    }
+
+    let p2 = u2s(buf[point - 3 * step]);
+    let p1 = u2s(buf[point - 2 * step]);
+    let p0 = u2s(buf[point - step]);
+    let q0 = u2s(buf[point]);
+    let q1 = u2s(buf[point + step]);
+    let q2 = u2s(buf[point + 2 * step]);
+
+    let w = c(c(p1 - q1) + 3 * (q0 - p0));
+
+    // The pixels next to the edge are adjusted by about 3/7 of the edge
+    // difference, the ones after them by 2/7 and 1/7
+    let a = c((27 * w + 63) >> 7);
+    buf[point] = s2u(q0 - a);
+    buf[point - step] = s2u(p0 + a);
+
+    let a = c((18 * w + 63) >> 7);
+    buf[point + step] = s2u(q1 - a);
+    buf[point - 2 * step] = s2u(p1 + a);
+
+    let a = c((9 * w + 63) >> 7);
+    buf[point + 2 * step] = s2u(q2 - a);
+    buf[point - 3 * step] = s2u(p2 + a);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{macroblock_filter, simple_segment, subblock_filter};
+
+    #[test]
+    fn smooths_small_steps_only() {
+        // A step of 8 between two flat areas is smoothed
+        let mut row = [60, 60, 60, 60, 68, 68, 68, 68];
+        simple_segment(40, &mut row, 4, 1);
+        assert_eq!(row, [60, 60, 60, 62, 66, 68, 68, 68]);
+
+        let mut row = [60, 60, 60, 60, 68, 68, 68, 68];
+        macroblock_filter(2, 10, 40, &mut row, 4, 1);
+        assert_eq!(row, [60, 61, 62, 63, 65, 66, 67, 68]);
+
+        let mut row = [60, 60, 60, 60, 68, 68, 68, 68];
+        subblock_filter(2, 10, 40, &mut row, 4, 1);
+        assert_eq!(row, [60, 60, 62, 63, 65, 66, 68, 68]);
+
+        // A real edge is kept as it is
+        let mut row = [20, 20, 20, 20, 200, 200, 200, 200];
+        macroblock_filter(2, 10, 40, &mut row, 4, 1);
+        assert_eq!(row, [20, 20, 20, 20, 200, 200, 200, 200]);
+    }
+}
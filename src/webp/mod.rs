@@ -1,8 +1,47 @@
-//! Decoding of Webp Images
+//! Decoding and Encoding of Webp Images
+
+#[cfg(feature = "rayon")]
+extern crate rayon;
 
 pub use self::decoder::WebpDecoder as WebpDecoder;
+pub use self::decoder::{can_decode, decode_webp, ChunkInfo, FlagDiscrepancy, Scanlines, UnsupportedFeature, VP8XFlag, WebpInfo};
+#[cfg(feature = "fuzzing")]
+#[doc(hidden)]
+pub use self::decoder::fuzz_chunk_parsers;
+pub use self::encoder::WebpEncoder;
+pub use self::anim::{AnimationFrame, AnimationInfo, BlendMethod, DisposeMethod};
+pub use self::dither::Dither;
+pub use self::vp8::{FilterType, FrameHeaderInfo, QuantizerInfo, ScaleHint};
+#[cfg(feature = "stats")]
+pub use self::stats::DecodeStats;
+pub use self::yuv::{ColorConversion, ColorMatrix, PixelOrder, Upsampling};
+
+// Logs a debug line of what the parser saw with the log crate, the line is
+// not even formatted without the log feature
+#[cfg(feature = "log")]
+macro_rules! webp_debug {
+    ($($arg:tt)*) => (debug!($($arg)*))
+}
+
+#[cfg(not(feature = "log"))]
+macro_rules! webp_debug {
+    ($($arg:tt)*) => (if false { let _ = format!($($arg)*); })
+}
+
+#[cfg(feature = "no-nom")]
+#[macro_use]
+mod riff;
 
+mod alpha;
+mod anim;
 mod decoder;
+mod dither;
+mod encoder;
+mod loop_filter;
+mod orientation;
+mod stats;
 mod transform;
+mod yuv;
 
-pub mod vp8;
\ No newline at end of file
+pub mod vp8;
+pub mod vp8l;
\ No newline at end of file
@@ -0,0 +1,153 @@
+//! Reading and applying the EXIF orientation of an image
+//!
+//! The Orientation tag of the first IFD tells how the stored pixels have to
+//! be rotated and flipped to show the image upright. Values 5 to 8 turn the
+//! image on its side, which swaps its width and height.
+//!
+//! # Related Links
+//! * [exif-spec](http://www.cipa.jp/std/documents/e/DC-008-2012_E.pdf) - The Exif 2.3 Specification, Section 4.6.4
+
+use std::io;
+use std::io::{Cursor, Seek, SeekFrom};
+use byteorder::{BigEndian, ByteOrder, LittleEndian, ReadBytesExt};
+
+const ORIENTATION_TAG: u16 = 0x0112;
+const SHORT_TYPE: u16 = 3;
+
+// Looks for the Orientation tag in the first IFD of a TIFF structure
+fn read_first_ifd<B: ByteOrder>(tiff: &[u8]) -> io::Result<Option<u16>> {
+    let mut r = Cursor::new(tiff);
+    let _ = try!(r.seek(SeekFrom::Start(4)));
+    let ifd = try!(r.read_u32::<B>()) as u64;
+
+    let _ = try!(r.seek(SeekFrom::Start(ifd)));
+    let count = try!(r.read_u16::<B>());
+
+    for _ in 0..count {
+        let tag = try!(r.read_u16::<B>());
+        let kind = try!(r.read_u16::<B>());
+        let _ = try!(r.read_u32::<B>());
+        let value = try!(r.read_u16::<B>());
+        let _ = try!(r.read_u16::<B>());
+
+        if tag == ORIENTATION_TAG {
+            return Ok(if kind == SHORT_TYPE { Some(value) } else { None })
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns the value of the Orientation tag of the EXIF metadata ```exif```.
+///
+/// The metadata starts with the TIFF header, optionally preceded by the
+/// ```Exif\0\0``` marker of a JPEG APP1 segment. Returns ```None``` if the tag
+/// is missing or its value is not between 1 and 8.
+pub fn read_orientation(exif: &[u8]) -> Option<u16> {
+    let tiff = if exif.starts_with(b"Exif\0\0") { &exif[6..] } else { exif };
+
+    let orientation = if tiff.starts_with(b"II*\0") {
+        read_first_ifd::<LittleEndian>(tiff)
+    } else if tiff.starts_with(b"MM\0*") {
+        read_first_ifd::<BigEndian>(tiff)
+    } else {
+        return None
+    };
+
+    match orientation {
+        Ok(Some(v)) if v >= 1 && v <= 8 => Some(v),
+        _ => None,
+    }
+}
+
+/// Whether the orientation swaps the width and height of the image
+pub fn transposes(orientation: u16) -> bool {
+    orientation >= 5
+}
+
+/// Returns the ```width``` by ```height``` image ```buf```, with ```bpp``` values
+/// per pixel, rotated and flipped upright according to ```orientation```.
+pub fn orient<T: Copy>(orientation: u16, buf: &[T], width: usize, height: usize, bpp: usize) -> Vec<T> {
+    let (out_width, out_height) = if transposes(orientation) { (height, width) } else { (width, height) };
+    let mut out = Vec::with_capacity(buf.len());
+
+    for y in 0..out_height {
+        for x in 0..out_width {
+            // The pixel of the stored image shown at x, y
+            let (sx, sy) = match orientation {
+                2 => (width - 1 - x, y),
+                3 => (width - 1 - x, height - 1 - y),
+                4 => (x, height - 1 - y),
+                5 => (y, x),
+                6 => (y, height - 1 - x),
+                7 => (width - 1 - y, height - 1 - x),
+                8 => (width - 1 - y, x),
+                _ => (x, y),
+            };
+
+            let i = bpp * (sy * width + sx);
+            out.extend_from_slice(&buf[i..i + bpp]);
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{orient, read_orientation};
+
+    fn exif(orientation: u16, big_endian: bool) -> Vec<u8> {
+        // A single IFD entry after the 8 byte TIFF header
+        let le: Vec<u8> = vec![
+            b'I', b'I', 42, 0, 8, 0, 0, 0,
+            1, 0,
+            0x12, 0x01, 3, 0, 1, 0, 0, 0, orientation as u8, 0, 0, 0,
+        ];
+
+        if !big_endian {
+            return le
+        }
+
+        vec![
+            b'M', b'M', 0, 42, 0, 0, 0, 8,
+            0, 1,
+            0x01, 0x12, 0, 3, 0, 0, 0, 1, 0, orientation as u8, 0, 0,
+        ]
+    }
+
+    #[test]
+    fn orientation_tag() {
+        assert_eq!(read_orientation(&exif(6, false)), Some(6));
+        assert_eq!(read_orientation(&exif(3, true)), Some(3));
+
+        let mut app1 = b"Exif\0\0".to_vec();
+        app1.extend_from_slice(&exif(8, true));
+        assert_eq!(read_orientation(&app1), Some(8));
+
+        assert_eq!(read_orientation(&exif(9, false)), None);
+        assert_eq!(read_orientation(b"II*\x00\x08\x00\x00\x00"), None);
+        assert_eq!(read_orientation(b"II*\x00"), None);
+    }
+
+    #[test]
+    fn all_orientations() {
+        // 1 2 3
+        // 4 5 6
+        let image = [1, 2, 3, 4, 5, 6];
+        let expected: [&[u8]; 8] = [
+            &[1, 2, 3, 4, 5, 6],
+            &[3, 2, 1, 6, 5, 4],
+            &[6, 5, 4, 3, 2, 1],
+            &[4, 5, 6, 1, 2, 3],
+            &[1, 4, 2, 5, 3, 6],
+            &[4, 1, 5, 2, 6, 3],
+            &[6, 3, 5, 2, 4, 1],
+            &[3, 6, 2, 5, 1, 4],
+        ];
+
+        for (i, e) in expected.iter().enumerate() {
+            assert_eq!(&orient(i as u16 + 1, &image, 3, 2, 1)[..], *e);
+        }
+    }
+}
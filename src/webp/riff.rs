@@ -0,0 +1,158 @@
+//! A minimal parser for the RIFF container, used instead of nom with the
+//! ```no-nom``` feature.
+//!
+//! The results mirror those of nom, so the chunk parsers written with it behave
+//! the same: an incomplete result gives the total length of input needed from
+//! the start of the parser, and errors are displayed with the same names.
+
+use std::fmt;
+
+/// The result of a parser
+#[derive(Debug, PartialEq, Eq)]
+pub enum IResult<I, O> {
+    /// The remaining input and the output
+    Done(I, O),
+
+    /// The input does not match
+    Error(ErrorKind),
+
+    /// The input ends before the parser is done
+    Incomplete(Needed),
+}
+
+impl<I, O> IResult<I, O> {
+    /// Whether the parser succeeded
+    pub fn is_done(&self) -> bool {
+        match *self {
+            IResult::Done(..) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the input does not match
+    pub fn is_err(&self) -> bool {
+        match *self {
+            IResult::Error(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether more input is needed
+    pub fn is_incomplete(&self) -> bool {
+        match *self {
+            IResult::Incomplete(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// The amount of input an incomplete parser needs
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Needed {
+    /// The amount is not known
+    Unknown,
+
+    /// The total number of bytes needed
+    Size(usize),
+}
+
+/// The ways a parser fails
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+    /// The input does not start with the expected tag
+    Tag,
+
+    /// None of the alternatives match
+    Alt,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            ErrorKind::Tag => "Tag",
+            ErrorKind::Alt => "Alternative",
+        })
+    }
+}
+
+/// Returns the output of a parser, or returns its error, with an incomplete
+/// result offset by the ```consumed``` bytes of input preceding the parser
+macro_rules! parse {
+    ($consumed:expr, $e:expr) => (
+        match $e {
+            IResult::Done(rest, o) => (rest, o),
+            IResult::Error(e) => return IResult::Error(e),
+            IResult::Incomplete(Needed::Size(n)) => return IResult::Incomplete(
+                ($consumed as usize).checked_add(n).map_or(Needed::Unknown, Needed::Size)
+            ),
+            IResult::Incomplete(Needed::Unknown) => return IResult::Incomplete(Needed::Unknown),
+        }
+    )
+}
+
+/// Parses a little endian 32 bit integer
+pub fn le_u32(input: &[u8]) -> IResult<&[u8], u32> {
+    if input.len() < 4 {
+        IResult::Incomplete(Needed::Size(4))
+    } else {
+        let n = input[0] as u32 | (input[1] as u32) << 8 | (input[2] as u32) << 16 | (input[3] as u32) << 24;
+        IResult::Done(&input[4..], n)
+    }
+}
+
+/// Parses the bytes of ```tag```
+pub fn tag<'a>(input: &'a [u8], tag: &[u8]) -> IResult<&'a [u8], &'a [u8]> {
+    let n = if input.len() < tag.len() { input.len() } else { tag.len() };
+    if input[..n] != tag[..n] {
+        IResult::Error(ErrorKind::Tag)
+    } else if n < tag.len() {
+        IResult::Incomplete(Needed::Size(tag.len()))
+    } else {
+        IResult::Done(&input[n..], &input[..n])
+    }
+}
+
+/// Takes ```len``` bytes
+pub fn take(input: &[u8], len: usize) -> IResult<&[u8], &[u8]> {
+    if input.len() < len {
+        IResult::Incomplete(Needed::Size(len))
+    } else {
+        IResult::Done(&input[len..], &input[..len])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{le_u32, tag, take, ErrorKind, IResult, Needed};
+
+    fn pair(input: &[u8]) -> IResult<&[u8], (u32, &[u8])> {
+        let (rest, len) = parse!(0, le_u32(input));
+        let (rest, data) = parse!(4, take(rest, len as usize));
+        IResult::Done(rest, (len, data))
+    }
+
+    #[test]
+    fn primitives() {
+        assert_eq!(le_u32(b"\x01\x02\x03\x04\x05"), IResult::Done(&b"\x05"[..], 0x04030201));
+        assert_eq!(le_u32(b"\x01\x02\x03"), IResult::Incomplete(Needed::Size(4)));
+
+        assert_eq!(tag(b"RIFFx", b"RIFF"), IResult::Done(&b"x"[..], &b"RIFF"[..]));
+        assert_eq!(tag(b"RI", b"RIFF"), IResult::Incomplete(Needed::Size(4)));
+        assert_eq!(tag(b"RX", b"RIFF"), IResult::Error(ErrorKind::Tag));
+        assert_eq!(tag(b"", b"RIFF"), IResult::Incomplete(Needed::Size(4)));
+
+        assert_eq!(take(b"abc", 2), IResult::Done(&b"c"[..], &b"ab"[..]));
+        assert_eq!(take(b"abc", 4), IResult::Incomplete(Needed::Size(4)));
+
+        assert_eq!(format!("{}", ErrorKind::Tag), "Tag");
+        assert_eq!(format!("{}", ErrorKind::Alt), "Alternative");
+    }
+
+    #[test]
+    fn incomplete_offset() {
+        assert_eq!(pair(b"\x02\x00\x00\x00abc"), IResult::Done(&b"c"[..], (2, &b"ab"[..])));
+        assert_eq!(pair(b"\x02\x00"), IResult::Incomplete(Needed::Size(4)));
+        assert_eq!(pair(b"\x05\x00\x00\x00abc"), IResult::Incomplete(Needed::Size(9)));
+        assert_eq!(pair(b"\xff\xff\xff\x7f"), IResult::Incomplete(Needed::Size(4 + 0x7fffffff)));
+    }
+}
@@ -0,0 +1,69 @@
+//! Timing of the stages of decoding
+//!
+//! The stages are only timed with the ```stats``` feature, without it the
+//! timers do nothing and the durations stay zero.
+
+use std::time::Duration;
+#[cfg(feature = "stats")]
+use std::time::Instant;
+
+/// How long the stages of decoding an image took, see ```WebpDecoder::decode_with_stats```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeStats {
+    /// Reading the RIFF container and the VP8 frame headers
+    pub header: Duration,
+
+    /// Reading the macroblock headers and coefficients of lossy image data, and
+    /// the whole of decoding lossless image data and alpha planes
+    pub entropy: Duration,
+
+    /// Prediction, inverse transforms and loop filtering of lossy image data
+    pub reconstruction: Duration,
+
+    /// Conversion of the decoded frame to the output, including drawing
+    /// animation frames onto the canvas
+    pub conversion: Duration,
+}
+
+#[cfg(feature = "stats")]
+impl DecodeStats {
+    /// Adds the durations of ```other``` to those of this
+    pub fn add(&mut self, other: &DecodeStats) {
+        self.header += other.header;
+        self.entropy += other.entropy;
+        self.reconstruction += other.reconstruction;
+        self.conversion += other.conversion;
+    }
+}
+
+/// Measures the time from when it is started to when it is added to a duration
+#[cfg(feature = "stats")]
+pub struct Timer(Instant);
+
+#[cfg(feature = "stats")]
+impl Timer {
+    #[inline]
+    pub fn start() -> Timer {
+        Timer(Instant::now())
+    }
+
+    #[inline]
+    pub fn add_to(self, duration: &mut Duration) {
+        *duration += self.0.elapsed();
+    }
+}
+
+/// Measures nothing without the ```stats``` feature
+#[cfg(not(feature = "stats"))]
+pub struct Timer;
+
+#[cfg(not(feature = "stats"))]
+impl Timer {
+    #[inline]
+    pub fn start() -> Timer {
+        Timer
+    }
+
+    #[inline]
+    pub fn add_to(self, _: &mut Duration) {}
+}
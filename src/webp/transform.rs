@@ -3,41 +3,54 @@ i32 = 20091;
 static CONST2:
 i32 = 35468;
 
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
 pub fn idct4x4(block: &mut [i32]) {
+    sse2::idct4x4(block)
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+pub fn idct4x4(block: &mut [i32]) {
+    idct4x4_scalar(block)
+}
+
+// The sums and products wrap around like those of the SSE2 transform, which
+// only the coefficients of corrupt data are large enough for
+#[cfg(any(test, not(all(feature = "simd", target_arch = "x86_64"))))]
+fn idct4x4_scalar(block: &mut [i32]) {
     for i in 0usize..4 {
-        let a1 = block[0 + i] + block[8 + i];
-        let b1 = block[0 + i] - block[8 + i];
+        let a1 = block[0 + i].wrapping_add(block[8 + i]);
+        let b1 = block[0 + i].wrapping_sub(block[8 + i]);
 
-        let t1 = (block[4 + i] * CONST2) >> 16;
-        let t2 = block[12 + i] + ((block[12 + i] * CONST1) >> 16);
-        let c1 = t1 - t2;
+        let t1 = block[4 + i].wrapping_mul(CONST2) >> 16;
+        let t2 = block[12 + i].wrapping_add(block[12 + i].wrapping_mul(CONST1) >> 16);
+        let c1 = t1.wrapping_sub(t2);
 
-        let t1 = block[4 + i] + ((block[4 + i] * CONST1) >> 16);
-        let t2 = (block[12 + i] * CONST2) >> 16;
-        let d1 = t1 + t2;
+        let t1 = block[4 + i].wrapping_add(block[4 + i].wrapping_mul(CONST1) >> 16);
+        let t2 = block[12 + i].wrapping_mul(CONST2) >> 16;
+        let d1 = t1.wrapping_add(t2);
 
-        block[4 * 0 + i] = a1 + d1;
-        block[4 * 3 + i] = a1 - d1;
-        block[4 * 1 + i] = b1 + c1;
-        block[4 * 2 + i] = b1 - c1;
+        block[4 * 0 + i] = a1.wrapping_add(d1);
+        block[4 * 3 + i] = a1.wrapping_sub(d1);
+        block[4 * 1 + i] = b1.wrapping_add(c1);
+        block[4 * 2 + i] = b1.wrapping_sub(c1);
     }
 
     for i in 0usize..4 {
-        let a1 = block[4 * i + 0] + block[4 * i + 2];
-        let b1 = block[4 * i + 0] - block[4 * i + 2];
+        let a1 = block[4 * i + 0].wrapping_add(block[4 * i + 2]);
+        let b1 = block[4 * i + 0].wrapping_sub(block[4 * i + 2]);
 
-        let t1 = (block[4 * i + 1] * CONST2) >> 16;
-        let t2 = block[4 * i + 3] + ((block[4 * i + 3] * CONST1) >> 16);
-        let c1 = t1 - t2;
+        let t1 = block[4 * i + 1].wrapping_mul(CONST2) >> 16;
+        let t2 = block[4 * i + 3].wrapping_add(block[4 * i + 3].wrapping_mul(CONST1) >> 16);
+        let c1 = t1.wrapping_sub(t2);
 
-        let t1 = block[4 * i + 1] + ((block[4 * i + 1] * CONST1) >> 16);
-        let t2 = (block[4 * i + 3] * CONST2) >> 16;
-        let d1 = t1 + t2;
+        let t1 = block[4 * i + 1].wrapping_add(block[4 * i + 1].wrapping_mul(CONST1) >> 16);
+        let t2 = block[4 * i + 3].wrapping_mul(CONST2) >> 16;
+        let d1 = t1.wrapping_add(t2);
 
-        block[4 * i + 0] = (a1 + d1 + 4) >> 3;
-        block[4 * i + 3] = (a1 - d1 + 4) >> 3;
-        block[4 * i + 1] = (b1 + c1 + 4) >> 3;
-        block[4 * i + 2] = (b1 - c1 + 4) >> 3;
+        block[4 * i + 0] = a1.wrapping_add(d1).wrapping_add(4) >> 3;
+        block[4 * i + 3] = a1.wrapping_sub(d1).wrapping_add(4) >> 3;
+        block[4 * i + 1] = b1.wrapping_add(c1).wrapping_add(4) >> 3;
+        block[4 * i + 2] = b1.wrapping_sub(c1).wrapping_add(4) >> 3;
     }
 }
 
@@ -72,3 +85,184 @@ pub fn iwht4x4(block: &mut [i32]) {
         block[4 * i + 3] = (d2 + 3) >> 3;
     }
 }
+
+// The forward transforms match the ones used by libvpx, so that the inverse
+// transforms above reconstruct the input up to rounding.
+pub fn fdct4x4(block: &mut [i32]) {
+    for i in 0usize..4 {
+        let a1 = (block[4 * i + 0] + block[4 * i + 3]) * 8;
+        let b1 = (block[4 * i + 1] + block[4 * i + 2]) * 8;
+        let c1 = (block[4 * i + 1] - block[4 * i + 2]) * 8;
+        let d1 = (block[4 * i + 0] - block[4 * i + 3]) * 8;
+
+        block[4 * i + 0] = a1 + b1;
+        block[4 * i + 2] = a1 - b1;
+        block[4 * i + 1] = (c1 * 2217 + d1 * 5352 + 14500) >> 12;
+        block[4 * i + 3] = (d1 * 2217 - c1 * 5352 + 7500) >> 12;
+    }
+
+    for i in 0usize..4 {
+        let a1 = block[0 + i] + block[12 + i];
+        let b1 = block[4 + i] + block[8  + i];
+        let c1 = block[4 + i] - block[8  + i];
+        let d1 = block[0 + i] - block[12 + i];
+
+        block[0  + i] = (a1 + b1 + 7) >> 4;
+        block[8  + i] = (a1 - b1 + 7) >> 4;
+        block[4  + i] = ((c1 * 2217 + d1 * 5352 + 12000) >> 16) + if d1 != 0 { 1 } else { 0 };
+        block[12 + i] = (d1 * 2217 - c1 * 5352 + 51000) >> 16;
+    }
+}
+
+pub fn fwht4x4(block: &mut [i32]) {
+    for i in 0usize..4 {
+        let a1 = (block[4 * i + 0] + block[4 * i + 2]) * 4;
+        let d1 = (block[4 * i + 1] + block[4 * i + 3]) * 4;
+        let c1 = (block[4 * i + 1] - block[4 * i + 3]) * 4;
+        let b1 = (block[4 * i + 0] - block[4 * i + 2]) * 4;
+
+        block[4 * i + 0] = a1 + d1 + if a1 != 0 { 1 } else { 0 };
+        block[4 * i + 1] = b1 + c1;
+        block[4 * i + 2] = b1 - c1;
+        block[4 * i + 3] = a1 - d1;
+    }
+
+    for i in 0usize..4 {
+        let a1 = block[0 + i] + block[8  + i];
+        let d1 = block[4 + i] + block[12 + i];
+        let c1 = block[4 + i] - block[12 + i];
+        let b1 = block[0 + i] - block[8  + i];
+
+        // Round towards zero before the final shift
+        let a2 = a1 + d1;
+        let b2 = b1 + c1;
+        let c2 = b1 - c1;
+        let d2 = a1 - d1;
+
+        block[0  + i] = (a2 + if a2 < 0 { 1 } else { 0 } + 3) >> 3;
+        block[4  + i] = (b2 + if b2 < 0 { 1 } else { 0 } + 3) >> 3;
+        block[8  + i] = (c2 + if c2 < 0 { 1 } else { 0 } + 3) >> 3;
+        block[12 + i] = (d2 + if d2 < 0 { 1 } else { 0 } + 3) >> 3;
+    }
+}
+
+// The inverse DCT on four columns or rows at once. SSE2 is part of every
+// x86_64 processor, so it needs no detection at runtime.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    use super::{CONST1, CONST2};
+
+    // The low 32 bits of the products of each lane, SSE2 only multiplies
+    // the even lanes into 64 bits
+    #[inline]
+    unsafe fn mullo(a: __m128i, b: __m128i) -> __m128i {
+        let even = _mm_mul_epu32(a, b);
+        let odd = _mm_mul_epu32(_mm_srli_si128(a, 4), _mm_srli_si128(b, 4));
+
+        _mm_unpacklo_epi32(_mm_shuffle_epi32(even, 0b00_00_10_00), _mm_shuffle_epi32(odd, 0b00_00_10_00))
+    }
+
+    // (v * c) >> 16 of each lane
+    #[inline]
+    unsafe fn mul_shift(v: __m128i, c: i32) -> __m128i {
+        _mm_srai_epi32(mullo(v, _mm_set1_epi32(c)), 16)
+    }
+
+    #[inline]
+    unsafe fn transpose(r: [__m128i; 4]) -> [__m128i; 4] {
+        let t0 = _mm_unpacklo_epi32(r[0], r[1]);
+        let t1 = _mm_unpacklo_epi32(r[2], r[3]);
+        let t2 = _mm_unpackhi_epi32(r[0], r[1]);
+        let t3 = _mm_unpackhi_epi32(r[2], r[3]);
+
+        [_mm_unpacklo_epi64(t0, t1), _mm_unpackhi_epi64(t0, t1),
+         _mm_unpacklo_epi64(t2, t3), _mm_unpackhi_epi64(t2, t3)]
+    }
+
+    // One pass of the scalar transform, the lanes of i hold its ith inputs
+    #[inline]
+    unsafe fn pass(i: [__m128i; 4]) -> [__m128i; 4] {
+        let a1 = _mm_add_epi32(i[0], i[2]);
+        let b1 = _mm_sub_epi32(i[0], i[2]);
+        let c1 = _mm_sub_epi32(mul_shift(i[1], CONST2), _mm_add_epi32(i[3], mul_shift(i[3], CONST1)));
+        let d1 = _mm_add_epi32(_mm_add_epi32(i[1], mul_shift(i[1], CONST1)), mul_shift(i[3], CONST2));
+
+        [_mm_add_epi32(a1, d1), _mm_add_epi32(b1, c1), _mm_sub_epi32(b1, c1), _mm_sub_epi32(a1, d1)]
+    }
+
+    pub fn idct4x4(block: &mut [i32]) {
+        assert!(block.len() >= 16);
+
+        unsafe {
+            let p = block.as_mut_ptr() as *mut __m128i;
+            let rows = [_mm_loadu_si128(p), _mm_loadu_si128(p.offset(1)),
+                        _mm_loadu_si128(p.offset(2)), _mm_loadu_si128(p.offset(3))];
+
+            // The rows hold the inputs of the vertical pass for all columns,
+            // transposed they hold the inputs of the horizontal pass
+            let columns = pass(transpose(pass(rows)));
+
+            let round = _mm_set1_epi32(4);
+            let mut out = [_mm_setzero_si128(); 4];
+            for (o, c) in out.iter_mut().zip(columns.iter()) {
+                *o = _mm_srai_epi32(_mm_add_epi32(*c, round), 3);
+            }
+
+            let out = transpose(out);
+            for (i, o) in out.iter().enumerate() {
+                _mm_storeu_si128(p.offset(i as isize), *o);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    use super::idct4x4;
+    use super::idct4x4_scalar;
+
+    #[test]
+    fn idct_wraps_around() {
+        // Sums past the range of i32 wrap around instead of overflowing
+        let mut block = [i32::max_value(); 16];
+        idct4x4_scalar(&mut block);
+
+        let mut block = [0i32; 16];
+        block[0] = i32::max_value();
+        block[8] = 1;
+        idct4x4_scalar(&mut block);
+        assert_eq!(block[0], (i32::min_value() + 4) >> 3);
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    #[test]
+    fn simd_idct() {
+        // Coefficients in the range of dequantized tokens, from a simple generator
+        let mut seed = 12345u32;
+        for _ in 0..10000 {
+            let mut block = [0i32; 16];
+            for c in block.iter_mut() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                *c = (seed >> 16) as i32 % 8192 - 4096;
+            }
+
+            let mut expected = block;
+            idct4x4_scalar(&mut expected);
+            idct4x4(&mut block);
+            assert_eq!(block, expected);
+        }
+
+        // The sums and products of the largest coefficients corrupt data can
+        // hold wrap around the same way in both
+        for &c in [663796, -663796, 1327592, i32::max_value(), i32::min_value()].iter() {
+            let mut block = [c; 16];
+            let mut expected = block;
+            idct4x4_scalar(&mut expected);
+            idct4x4(&mut block);
+            assert_eq!(block, expected);
+        }
+    }
+}
@@ -3,7 +3,8 @@
 //! This module contains a partial implementation of the
 //! VP8 video format as defined in RFC-6386.
 //!
-//! It decodes Keyframes only sans Loop Filtering.
+//! It decodes Keyframes only, and encodes Keyframes using
+//! whole macroblock prediction only and without Loop Filtering.
 //! VP8 is the underpinning of the Webp image format
 //!
 //! # Related Links
@@ -13,17 +14,33 @@
 //!
 
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::cmp;
+use std::mem;
+use std::ops::Range;
 use std::default::Default;
-use byteorder::{ReadBytesExt, LittleEndian};
+use byteorder::{ReadBytesExt, WriteBytesExt, LittleEndian};
 
+use super::decoder::UnsupportedFeature;
+use super::loop_filter;
+use super::stats::{DecodeStats, Timer};
 use super::transform;
 
+#[cfg(feature = "rayon")]
+use super::rayon::prelude::*;
+
+use image::{ImageError, ImageResult};
 use math::utils::clamp;
 
 const MAX_SEGMENTS: usize = 4;
 const NUM_DCT_TOKENS: usize = 12;
 
+// The rows of the luma and chroma planes kept from the macroblock row above
+// when decoding by rows. The loop filter of the next row changes the last
+// three of them, and they are handed out in bands of whole chroma rows.
+const KEPT_ROWS: usize = 8;
+const KEPT_CHROMA_ROWS: usize = 4;
+
 // Prediction modes
 const DC_PRED: i8 = 0;
 const V_PRED: i8 = 1;
@@ -628,6 +645,11 @@ static AC_QUANT: [i16; 128] = [
 
 static ZIGZAG: [u8; 16] = [0, 1, 4, 8, 5, 2, 3, 6, 9, 12, 13, 10, 7, 11, 14, 15];
 
+// The number of zero bytes the reader may read past the end of its buffer.
+// The value holds one byte more than the decoding needs, so a complete
+// partition can be read up to two bytes past its end.
+const MAX_OVERREAD: usize = 2;
+
 struct BoolReader {
     buf: Vec<u8>,
     index: usize,
@@ -635,24 +657,140 @@ struct BoolReader {
     range: u32,
     value: u32,
     bit_count: u8,
+
+    // Set once the reader has run out of data
+    eof: bool,
+
+    // Cleared while more of the partition may be appended to buf
+    complete: bool,
+
+    // Set once the first two bytes have been loaded into value
+    primed: bool,
+}
+
+/// The position of a ```BoolReader``` in its partition, to go back to when it
+/// runs out of the part of the partition it has been given so far
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BoolReaderState {
+    index: usize,
+    range: u32,
+    value: u32,
+    bit_count: u8,
+    primed: bool,
 }
 
 impl BoolReader {
     pub fn new() -> BoolReader {
-        BoolReader {buf: Vec::new(), range: 0, value: 0, bit_count: 0, index: 0}
+        BoolReader {buf: Vec::new(), range: 0, value: 0, bit_count: 0, index: 0, eof: false, complete: true, primed: false}
     }
 
+    /// Starts reading a whole partition
     pub fn init(&mut self, buf: Vec<u8>) {
+        self.init_partial(buf);
+        self.finish();
+    }
+
+    /// Starts reading the first part of a partition, the rest of it is given to
+    /// ```extend``` as it arrives.
+    ///
+    /// Reading past the end of the part given so far reads zeros, like reading
+    /// past the end of a whole partition, after which ```starved``` is set. The
+    /// values read since then are wrong, so the caller goes back to a ```state```
+    /// saved before and reads them again once there is more data.
+    pub fn init_partial(&mut self, buf: Vec<u8>) {
         self.buf = buf;
+        self.index = 0;
         self.value = 0;
+        self.range = 255;
+        self.bit_count = 0;
+        self.eof = false;
+        self.complete = false;
+        self.primed = false;
+
+        self.prime();
+    }
+
+    /// Appends the next part of the partition
+    pub fn extend(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+        self.prime();
+    }
+
+    /// Marks the partition as complete, past its end the reader reads zeros
+    /// up to MAX_OVERREAD bytes and then sets eof
+    pub fn finish(&mut self) {
+        self.complete = true;
+        self.prime();
+    }
+
+    /// Whether the reader has read past the end of an incomplete partition, or
+    /// has not been able to start reading it
+    pub fn starved(&self) -> bool {
+        !self.complete && (!self.primed || self.index > self.buf.len())
+    }
+
+    /// The current position of the reader
+    pub fn state(&self) -> BoolReaderState {
+        BoolReaderState {
+            index: self.index,
+            range: self.range,
+            value: self.value,
+            bit_count: self.bit_count,
+            primed: self.primed,
+        }
+    }
+
+    /// Goes back to a position saved with ```state``` before the reader starved
+    pub fn restore(&mut self, state: BoolReaderState) {
+        self.index = state.index;
+        self.range = state.range;
+        self.value = state.value;
+        self.bit_count = state.bit_count;
+        self.primed = state.primed;
+        self.eof = self.index > self.buf.len() + MAX_OVERREAD;
+
+        self.prime();
+    }
+
+    // Starts reading from the beginning with the first two bytes loaded into
+    // value once they are there, or once the partition is complete. Anything
+    // read before is garbage.
+    fn prime(&mut self) {
+        if !self.primed && (self.complete || self.buf.len() >= 2) {
+            self.index = 0;
+            self.value = 0;
+            self.range = 255;
+            self.bit_count = 0;
+            self.primed = true;
+
+            for _ in 0usize..2 {
+                self.value = (self.value << 8) | self.next_byte() as u32;
+            }
+        }
+    }
 
-        for _ in 0usize..2 {
-            self.value = (self.value << 8) | self.buf[self.index] as u32;
+    // Reads zeros after the end of the buffer, instead of failing on every read
+    // the caller checks eof once in a while.
+    fn next_byte(&mut self) -> u8 {
+        if self.index < self.buf.len() {
+            self.index += 1;
+            self.buf[self.index - 1]
+        } else {
             self.index += 1;
+            if self.complete && self.index > self.buf.len() + MAX_OVERREAD {
+                self.eof = true;
+            }
+
+            0
         }
+    }
 
-        self.range = 255;
-        self.bit_count = 0;
+    pub fn check_eof(&self) -> ImageResult<()> {
+        if self.eof {
+            Err(ImageError::FormatError(String::from("VP8: unexpected end of data")))
+        } else {
+            Ok(())
+        }
     }
 
     pub fn read_bool(&mut self, probability: u8) -> u8 {
@@ -675,8 +813,7 @@ impl BoolReader {
 
             if self.bit_count == 8 {
                 self.bit_count = 0;
-                self.value |= self.buf[self.index] as u32;
-                self.index += 1;
+                self.value |= self.next_byte() as u32;
             }
         }
 
@@ -727,6 +864,126 @@ impl BoolReader {
     }
 }
 
+// Section 7.3
+struct BoolWriter {
+    buf: Vec<u8>,
+
+    range: u32,
+    bottom: u32,
+    bit_count: u8,
+}
+
+impl BoolWriter {
+    pub fn new() -> BoolWriter {
+        BoolWriter {buf: Vec::new(), range: 255, bottom: 0, bit_count: 24}
+    }
+
+    // Propagates a carry into the bytes already written
+    fn add_one_to_output(&mut self) {
+        let mut i = self.buf.len();
+
+        while i > 0 && self.buf[i - 1] == 255 {
+            self.buf[i - 1] = 0;
+            i -= 1;
+        }
+
+        if i > 0 {
+            self.buf[i - 1] += 1;
+        }
+    }
+
+    pub fn write_bool(&mut self, value: bool, probability: u8) {
+        let split = 1 + (((self.range - 1) * probability as u32) >> 8);
+
+        if value {
+            self.bottom += split;
+            self.range -= split;
+        } else {
+            self.range = split;
+        }
+
+        while self.range < 128 {
+            self.range <<= 1;
+
+            if self.bottom & (1 << 31) != 0 {
+                self.add_one_to_output();
+            }
+
+            self.bottom <<= 1;
+            self.bit_count -= 1;
+
+            if self.bit_count == 0 {
+                self.buf.push((self.bottom >> 24) as u8);
+                self.bottom &= (1 << 24) - 1;
+                self.bit_count = 8;
+            }
+        }
+    }
+
+    pub fn write_literal(&mut self, n: u8, value: u8) {
+        for i in (0..n).rev() {
+            self.write_bool((value >> i) & 1 != 0, 128u8);
+        }
+    }
+
+    pub fn write_with_tree(&mut self, tree: &[i8], probs: &[Prob], start: isize, value: i8) {
+        let mut path = Vec::new();
+        let found = tree_path(tree, start, value, &mut path);
+        assert!(found);
+
+        for &(index, bit) in path.iter() {
+            self.write_bool(bit, probs[index as usize >> 1]);
+        }
+    }
+
+    pub fn write_flag(&mut self, flag: bool) {
+        self.write_literal(1, flag as u8);
+    }
+
+    // Writes out the remaining bits of bottom
+    pub fn finish(mut self) -> Vec<u8> {
+        let mut c = self.bit_count;
+        let mut v = self.bottom;
+
+        if v & (1 << (32 - c)) != 0 {
+            self.add_one_to_output();
+        }
+
+        v <<= c & 7;
+        c >>= 3;
+
+        while c > 0 {
+            v <<= 8;
+            c -= 1;
+        }
+
+        for _ in 0usize..4 {
+            self.buf.push((v >> 24) as u8);
+            v <<= 8;
+        }
+
+        self.buf
+    }
+}
+
+// Finds the branches leading from index to the leaf value
+fn tree_path(tree: &[i8], index: isize, value: i8, path: &mut Vec<(isize, bool)>) -> bool {
+    for bit in 0isize..2 {
+        let next = tree[(index + bit) as usize] as isize;
+        path.push((index, bit == 1));
+
+        if next <= 0 && -next == value as isize {
+            return true
+        } else if next > 0 && tree_path(tree, next, value, path) {
+            return true
+        }
+
+        path.pop();
+    }
+
+    false
+}
+
 #[derive(Clone, Copy)]
 struct MacroBlock {
     bpred:       [i8; 16],
@@ -749,6 +1006,12 @@ impl MacroBlock {
 }
 
 /// A Representation of the last decoded video frame
+///
+/// The planes are stored in 4:2:0 layout, the chroma planes have half the width
+/// and height of the luma plane, rounding up. Each plane holds its rows one after
+/// the other without padding: the macroblock aligned borders the frame is decoded
+/// with are cropped, so the stride of a plane is its width. Row ```y``` of the luma
+/// plane starts at ```y * width``` and is ```width``` bytes long.
 #[derive(Default, Debug, Clone)]
 pub struct Frame {
     /// The width of the luma plane
@@ -757,9 +1020,17 @@ pub struct Frame {
     /// The height of the luma plane
     pub height: u16,
 
-    /// The luma plane of the frame
+    /// The luma plane of the frame, ```width * height``` bytes
     pub ybuf: Vec<u8>,
 
+    /// The blue-difference chroma plane of the frame,
+    /// ```chroma_width() * chroma_height()``` bytes
+    pub ubuf: Vec<u8>,
+
+    /// The red-difference chroma plane of the frame,
+    /// ```chroma_width() * chroma_height()``` bytes
+    pub vbuf: Vec<u8>,
+
     /// Indicates whether this frame is a keyframe
     pub keyframe: bool,
 
@@ -777,6 +1048,198 @@ pub struct Frame {
     filter: u8,
     filter_level: u8,
     sharpness_level: u8,
+
+    // Section 9.1
+    horizontal_scale: ScaleHint,
+    vertical_scale: ScaleHint,
+
+    // Section 9.6 and 10
+    base_quantizer: u8,
+    segment_quantizers: [u8; MAX_SEGMENTS],
+    quantizer_deltas: [i8; 5],
+    segment_map: Vec<u8>,
+}
+
+/// The upscaling a frame asks to be displayed with along one axis, Section 9.1
+///
+/// The decoder does not apply it, the frame is always decoded at its stored size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScaleHint {
+    /// The frame is displayed at its size
+    None,
+
+    /// The frame is upscaled by 5/4
+    FiveFourths,
+
+    /// The frame is upscaled by 5/3
+    FiveThirds,
+
+    /// The frame is upscaled by 2
+    Double,
+}
+
+impl Default for ScaleHint {
+    fn default() -> ScaleHint {
+        ScaleHint::None
+    }
+}
+
+impl ScaleHint {
+    /// Reads the hint from the top two bits of a 16 bit dimension field of the
+    /// frame header, the lower 14 bits hold the size.
+    pub fn from_field(field: u16) -> ScaleHint {
+        match field >> 14 {
+            0 => ScaleHint::None,
+            1 => ScaleHint::FiveFourths,
+            2 => ScaleHint::FiveThirds,
+            _ => ScaleHint::Double,
+        }
+    }
+
+    /// The size ```size``` is displayed at, rounded to the nearest pixel
+    pub fn apply(self, size: u32) -> u32 {
+        match self {
+            ScaleHint::None => size,
+            ScaleHint::FiveFourths => (size * 5 + 2) / 4,
+            ScaleHint::FiveThirds => (size * 5 + 1) / 3,
+            ScaleHint::Double => size * 2,
+        }
+    }
+}
+
+/// The type of loop filter of a frame, Section 9.6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterType {
+    /// The normal loop filter, applied to luma and chroma
+    Normal,
+
+    /// The simple loop filter, applied to luma only
+    Simple,
+}
+
+/// The coding parameters from the header of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameHeaderInfo {
+    /// The version number of the bitstream, from 0 to 3
+    pub profile: u8,
+
+    /// The type of loop filter
+    pub filter_type: FilterType,
+
+    /// The strength of the loop filter, from 0 to 63
+    pub filter_level: u8,
+
+    /// The sharpness of the loop filter, from 0 to 7
+    pub sharpness: u8,
+
+    /// The upscaling the frame asks to be displayed with horizontally
+    pub horizontal_scale: ScaleHint,
+
+    /// The upscaling the frame asks to be displayed with vertically
+    pub vertical_scale: ScaleHint,
+}
+
+/// The quantizer indices of a frame, which select the step sizes its
+/// coefficients are dequantized with, Section 9.6. Lower indices keep more
+/// detail and take more bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuantizerInfo {
+    /// The luma AC quantizer index from the frame header, from 0 to 127
+    pub base_index: u8,
+
+    /// The luma AC quantizer index each of the four segments uses, from 0 to 127.
+    /// Without segmentation all four are the base index.
+    pub segment_indices: [u8; 4],
+
+    /// The delta from the index of a segment to its luma DC quantizer index
+    pub ydc_delta: i8,
+
+    /// The delta from the index of a segment to its Y2 DC quantizer index
+    pub y2dc_delta: i8,
+
+    /// The delta from the index of a segment to its Y2 AC quantizer index
+    pub y2ac_delta: i8,
+
+    /// The delta from the index of a segment to its chroma DC quantizer index
+    pub uvdc_delta: i8,
+
+    /// The delta from the index of a segment to its chroma AC quantizer index
+    pub uvac_delta: i8,
+
+    /// The segment of each macroblock decoded, in rows from the top left. Empty
+    /// if the frame does not code a segment map, when every macroblock is in
+    /// segment 0.
+    pub segment_map: Vec<u8>,
+}
+
+impl Frame {
+    /// The quantizer indices read from the header of the frame, and the segment
+    /// map read along with its macroblocks
+    pub fn quantizer_info(&self) -> QuantizerInfo {
+        QuantizerInfo {
+            base_index: self.base_quantizer,
+            segment_indices: self.segment_quantizers,
+            ydc_delta: self.quantizer_deltas[0],
+            y2dc_delta: self.quantizer_deltas[1],
+            y2ac_delta: self.quantizer_deltas[2],
+            uvdc_delta: self.quantizer_deltas[3],
+            uvac_delta: self.quantizer_deltas[4],
+            segment_map: self.segment_map.clone(),
+        }
+    }
+
+    /// The coding parameters read from the header of the frame
+    pub fn header_info(&self) -> FrameHeaderInfo {
+        FrameHeaderInfo {
+            profile: self.version,
+            filter_type: if self.filter == 0 { FilterType::Normal } else { FilterType::Simple },
+            filter_level: self.filter_level,
+            sharpness: self.sharpness_level,
+            horizontal_scale: self.horizontal_scale,
+            vertical_scale: self.vertical_scale,
+        }
+    }
+
+    /// The width of the chroma planes.
+    /// Chroma is subsampled by 2 in both directions, rounding up.
+    pub fn chroma_width(&self) -> u16 {
+        (self.width + 1) / 2
+    }
+
+    /// The height of the chroma planes
+    pub fn chroma_height(&self) -> u16 {
+        (self.height + 1) / 2
+    }
+
+    /// The distance in bytes between the starts of two rows of the luma plane
+    pub fn luma_stride(&self) -> usize {
+        self.width as usize
+    }
+
+    /// The distance in bytes between the starts of two rows of the chroma planes
+    pub fn chroma_stride(&self) -> usize {
+        self.chroma_width() as usize
+    }
+
+    /// Row ```y``` of the luma plane
+    pub fn y_row(&self, y: usize) -> &[u8] {
+        let stride = self.luma_stride();
+        &self.ybuf[y * stride..y * stride + stride]
+    }
+
+    /// Row ```y``` of the blue-difference chroma plane, covering
+    /// the luma rows ```2 * y``` and ```2 * y + 1```
+    pub fn u_row(&self, y: usize) -> &[u8] {
+        let stride = self.chroma_stride();
+        &self.ubuf[y * stride..y * stride + stride]
+    }
+
+    /// Row ```y``` of the red-difference chroma plane, covering
+    /// the luma rows ```2 * y``` and ```2 * y + 1```
+    pub fn v_row(&self, y: usize) -> &[u8] {
+        let stride = self.chroma_stride();
+        &self.vbuf[y * stride..y * stride + stride]
+    }
 }
 
 #[derive(Clone, Copy, Default)]
@@ -796,6 +1259,30 @@ struct Segment {
     loopfilter_level: i8,
 }
 
+// The progress of a frame decoded as its partitions arrive
+struct PartialFrame {
+    // The bytes each partition but the last still lacks
+    missing: [usize; 8],
+
+    // The next macroblock row to decode
+    mby: usize,
+}
+
+// What decoding a macroblock row changes besides the planes, to go back to
+// when its partition runs out in the middle of the row
+struct RowState {
+    first_partition: BoolReaderState,
+    partition: BoolReaderState,
+
+    top: Vec<MacroBlock>,
+    top_border: Vec<u8>,
+    top_border_u: Vec<u8>,
+    top_border_v: Vec<u8>,
+
+    macroblock_filters: usize,
+    segment_map: usize,
+}
+
 /// VP8 Decoder
 ///
 /// Only decodes keyframes
@@ -824,14 +1311,53 @@ pub struct VP8Decoder<R> {
     // Section 9.11
     prob_skip_false: Option<Prob>,
 
+    // Section 9.6, the adjustments of the loop filter level for the
+    // reference frame and the prediction mode of a macroblock
+    loop_filter_adjustments: bool,
+    ref_delta: [i32; 4],
+    mode_delta: [i32; 4],
+
+    // The loop filter level of each macroblock and whether the edges
+    // between its subblocks are filtered
+    macroblock_filters: Vec<(u8, bool)>,
+
+    // The planes of the frame, padded to whole macroblocks. When decoding by rows
+    // they only hold the macroblock row being decoded, below the last rows of the
+    // one above it.
+    ybuf: Vec<u8>,
+    ubuf: Vec<u8>,
+    vbuf: Vec<u8>,
+    by_rows: bool,
+
     top: Vec<MacroBlock>,
     left: MacroBlock,
 
     top_border: Vec<u8>,
-    left_border: Vec<u8>,
+    top_border_u: Vec<u8>,
+    top_border_v: Vec<u8>,
+
+    left_border: LeftBorder,
+
+    // Set while a frame is decoded from partial data
+    partial: Option<PartialFrame>,
+
+    // How long the stages of decoding took, with the stats feature
+    #[cfg_attr(not(feature = "stats"), allow(dead_code))]
+    stats: DecodeStats,
 }
 
 impl<R: Read> VP8Decoder<R> {
+    /// Create a new decoder that decodes into the planes of ```frame```,
+    /// reusing their allocations, such as those of a frame decoded before.
+    pub fn with_frame(r: R, frame: Frame) -> VP8Decoder<R> {
+        let mut decoder = VP8Decoder::new(r);
+        decoder.frame.ybuf = frame.ybuf;
+        decoder.frame.ubuf = frame.ubuf;
+        decoder.frame.vbuf = frame.vbuf;
+
+        decoder
+    }
+
     /// Create a new decoder.
     /// The reader must present a raw vp8 bitstream to the decoder
     pub fn new(r: R) -> VP8Decoder<R> {
@@ -849,7 +1375,8 @@ impl<R: Read> VP8Decoder<R> {
             frame: f,
             segments_enabled: false,
             segments_update_map: false,
-            segment: [s; MAX_SEGMENTS],
+            // The segment data defaults to deltas, Section 9.3
+            segment: [Segment { delta_values: true, ..s }; MAX_SEGMENTS],
 
             partitions: [
                 BoolReader::new(), BoolReader::new(),
@@ -868,11 +1395,29 @@ impl<R: Read> VP8Decoder<R> {
             // Section 9.11
             prob_skip_false: None,
 
+            loop_filter_adjustments: false,
+            ref_delta: [0; 4],
+            mode_delta: [0; 4],
+
+            macroblock_filters: Vec::new(),
+
+            ybuf: Vec::new(),
+            ubuf: Vec::new(),
+            vbuf: Vec::new(),
+            by_rows: false,
+
             top: Vec::new(),
             left: m,
 
             top_border: Vec::new(),
-            left_border: Vec::new(),
+            top_border_u: Vec::new(),
+            top_border_v: Vec::new(),
+
+            left_border: LeftBorder::new(),
+
+            partial: None,
+
+            stats: Default::default(),
         }
 }
 
@@ -892,23 +1437,42 @@ impl<R: Read> VP8Decoder<R> {
         }
     }
 
-    fn init_partitions(&mut self, n: usize) -> io::Result<()> {
+    fn init_partitions(&mut self, n: usize) -> ImageResult<()> {
         if n > 1 {
             let mut sizes = Vec::with_capacity(3 * n - 3);
             try!(self.r.by_ref().take(3 * n as u64 - 3).read_to_end(&mut sizes));
+            if sizes.len() < 3 * n - 3 && self.partial.is_some() {
+                return Err(ImageError::NotEnoughData)
+            } else if sizes.len() < 3 * n - 3 {
+                return Err(ImageError::FormatError(String::from("VP8: unexpected end of data")))
+            }
 
             for (i, s) in sizes.chunks(3).enumerate() {
-                let size = s[0] as u32 + ((s[1] as u32) << 8) + ((s[2] as u32) << 8);
+                let size = s[0] as u32 + ((s[1] as u32) << 8) + ((s[2] as u32) << 16);
                 let mut buf = Vec::with_capacity(size as usize);
                 try!(self.r.by_ref().take(size as u64).read_to_end(&mut buf));
 
-                self.partitions[i].init(buf);
+                match self.partial {
+                    // The rest of a partition that is cut short follows later
+                    Some(ref mut partial) => {
+                        partial.missing[i] = size as usize - buf.len();
+                        self.partitions[i].init_partial(buf);
+                        if partial.missing[i] == 0 {
+                            self.partitions[i].finish();
+                        }
+                    }
+                    None => self.partitions[i].init(buf),
+                }
             }
         }
 
         let mut buf = Vec::new();
         try!(self.r.read_to_end(&mut buf));
-        self.partitions[n - 1].init(buf);
+        if self.partial.is_some() {
+            self.partitions[n - 1].init_partial(buf);
+        } else {
+            self.partitions[n - 1].init(buf);
+        }
 
         Ok(())
     }
@@ -930,16 +1494,25 @@ impl<R: Read> VP8Decoder<R> {
         let uvac_delta = if self.b.read_flag() { self.b.read_magnitude_and_sign(4) }
                         else { 0 };
 
+        self.frame.base_quantizer = yac_abs;
+        self.frame.quantizer_deltas = [
+            ydc_delta as i8, y2dc_delta as i8, y2ac_delta as i8, uvdc_delta as i8, uvac_delta as i8,
+        ];
+        self.frame.segment_quantizers = [yac_abs; MAX_SEGMENTS];
+
         let n = if self.segments_enabled { MAX_SEGMENTS } else { 1 };
         for i in 0usize..n {
-            let base = if !self.segment[i].delta_values { self.segment[i].quantizer_level as i16 }
+            let base = if !self.segments_enabled { yac_abs as i16 }
+                    else if !self.segment[i].delta_values { self.segment[i].quantizer_level as i16 }
                     else { self.segment[i].quantizer_level as i16 + yac_abs as i16} as i32;
 
+            self.frame.segment_quantizers[i] = clamp(base, 0, 127) as u8;
+
             self.segment[i].ydc  = DC_QUANT[clamp(base + ydc_delta, 0, 127) as usize];
             self.segment[i].yac  = AC_QUANT[clamp(base, 0, 127) as usize];
 
             self.segment[i].y2dc = DC_QUANT[clamp(base + y2dc_delta, 0, 127) as usize] * 2;
-            self.segment[i].y2ac = AC_QUANT[clamp(base + y2ac_delta, 0, 127) as usize] * 155 / 100;
+            self.segment[i].y2ac = (AC_QUANT[clamp(base + y2ac_delta, 0, 127) as usize] as i32 * 155 / 100) as i16;
 
             self.segment[i].uvdc = DC_QUANT[clamp(base + uvdc_delta, 0, 127) as usize];
             self.segment[i].uvac = AC_QUANT[clamp(base + uvac_delta, 0, 127) as usize];
@@ -956,24 +1529,20 @@ impl<R: Read> VP8Decoder<R> {
 
     fn read_loop_filter_adjustments(&mut self) {
         if self.b.read_flag() {
-            for _i in 0usize..4 {
+            for i in 0usize..4 {
                 let ref_frame_delta_update_flag = self.b.read_flag();
 
-                let _delta = if ref_frame_delta_update_flag {
-                    self.b.read_magnitude_and_sign(6)
-                } else {
-                    0i32
-                };
+                if ref_frame_delta_update_flag {
+                    self.ref_delta[i] = self.b.read_magnitude_and_sign(6);
+                }
             }
 
-            for _i in 0usize..4 {
+            for i in 0usize..4 {
                 let mb_mode_delta_update_flag = self.b.read_flag();
 
-                let _delta = if mb_mode_delta_update_flag {
-                    self.b.read_magnitude_and_sign(6)
-                } else {
-                    0i32
-                };
+                if mb_mode_delta_update_flag {
+                    self.mode_delta[i] = self.b.read_magnitude_and_sign(6);
+                }
             }
         }
     }
@@ -1024,11 +1593,15 @@ impl<R: Read> VP8Decoder<R> {
         }
     }
 
-    fn read_frame_header(&mut self) -> io::Result<()> {
+    fn read_frame_header(&mut self) -> ImageResult<()> {
         let mut tag = [0u8; 3];
         try!(self.r.read_exact(&mut tag));
 
         self.frame.keyframe = tag[0] & 1 == 0;
+        if !self.frame.keyframe {
+            return Err(UnsupportedFeature::InterFrame.error())
+        }
+
         self.frame.version = (tag[0] >> 1) & 7;
         self.frame.for_display = (tag[0] >> 4) & 1 != 0;
 
@@ -1037,7 +1610,9 @@ impl<R: Read> VP8Decoder<R> {
 
         if self.frame.keyframe {
             try!(self.r.read_exact(&mut tag));
-            assert!(tag == [0x9d, 0x01, 0x2a]);
+            if tag != [0x9d, 0x01, 0x2a] {
+                return Err(ImageError::FormatError(String::from("VP8: invalid start code")))
+            }
 
             let w = try!(self.r.read_u16::<LittleEndian>());
             let h = try!(self.r.read_u16::<LittleEndian>());
@@ -1045,41 +1620,57 @@ impl<R: Read> VP8Decoder<R> {
             self.frame.width = w & 0x3FFF;
             self.frame.height = h & 0x3FFF;
 
+            // Checked before the planes and borders of the frame are allocated
+            if self.frame.width == 0 || self.frame.height == 0 {
+                return Err(ImageError::FormatError(String::from("VP8: frame is empty")))
+            }
+            self.frame.horizontal_scale = ScaleHint::from_field(w);
+            self.frame.vertical_scale = ScaleHint::from_field(h);
+
             self.top = init_top_macroblocks(self.frame.width as usize);
             self.left = MacroBlock{..self.top[0]};
 
             self.mbwidth  = (self.frame.width + 15) / 16;
             self.mbheight = (self.frame.height + 15) / 16;
 
-            self.frame.ybuf = vec![0u8; self.frame.width as usize * 
-                                        self.frame.height as usize];
+            self.macroblock_filters = Vec::with_capacity(self.mbwidth as usize * self.mbheight as usize);
 
             self.top_border = vec![127u8; self.frame.width as usize + 4 + 16];
-            self.left_border = vec![129u8; 1 + 16];
+            self.top_border_u = vec![127u8; self.mbwidth as usize * 8];
+            self.top_border_v = vec![127u8; self.mbwidth as usize * 8];
+
+            self.left_border = LeftBorder::new();
         }
 
         let mut buf = Vec::with_capacity(first_partition_size as usize);
         try!(self.r.by_ref().take(first_partition_size as u64).read_to_end(&mut buf));
+        if self.partial.is_some() && buf.len() < first_partition_size as usize {
+            return Err(ImageError::NotEnoughData)
+        }
         // initialise binary decoder
         self.b.init(buf);
 
         if self.frame.keyframe {
             let color_space = self.b.read_literal(1);
             self.frame.pixel_type = self.b.read_literal(1);
-            assert!(color_space == 0);
+
+            if color_space != 0 {
+                return Err(ImageError::FormatError(String::from("VP8: color space is reserved")))
+            }
         }
 
         self.segments_enabled = self.b.read_flag();
         if self.segments_enabled {
             self.read_segment_updates();
         }
+        self.frame.segment_map.clear();
 
         self.frame.filter          = self.b.read_literal(1);
         self.frame.filter_level    = self.b.read_literal(6);
         self.frame.sharpness_level = self.b.read_literal(3);
 
-        let lf_adjust_enable = self.b.read_flag();
-        if lf_adjust_enable {
+        self.loop_filter_adjustments = self.b.read_flag();
+        if self.loop_filter_adjustments {
             self.read_loop_filter_adjustments();
         }
 
@@ -1106,6 +1697,8 @@ impl<R: Read> VP8Decoder<R> {
             None
         };
 
+        try!(self.b.check_eof());
+
         if !self.frame.keyframe {
             // 9.10 remaining frame data
             self.prob_intra = 0;
@@ -1118,11 +1711,38 @@ impl<R: Read> VP8Decoder<R> {
         Ok(())
     }
 
+    // Allocates the padded planes, for the whole frame or for the rows kept
+    // while decoding by rows
+    fn init_planes(&mut self, by_rows: bool) {
+        let padded_width = self.mbwidth as usize * 16;
+        let (rows, chroma_rows) = if by_rows {
+            (KEPT_ROWS + 16, KEPT_CHROMA_ROWS + 8)
+        } else {
+            (self.mbheight as usize * 16, self.mbheight as usize * 8)
+        };
+
+        self.by_rows = by_rows;
+        self.ybuf = vec![0u8; padded_width * rows];
+        self.ubuf = vec![0u8; padded_width / 2 * chroma_rows];
+        self.vbuf = vec![0u8; padded_width / 2 * chroma_rows];
+    }
+
+    // The rows of the padded luma and chroma planes where a macroblock row starts
+    fn plane_rows(&self, mby: usize) -> (usize, usize) {
+        if self.by_rows {
+            (KEPT_ROWS, KEPT_CHROMA_ROWS)
+        } else {
+            (mby * 16, mby * 8)
+        }
+    }
+
     fn read_macroblock_header(&mut self, mbx: usize) -> (bool, MacroBlock) {
         let mut mb = MacroBlock::new();
 
         mb.segmentid = if self.segments_enabled && self.segments_update_map {
-            self.b.read_with_tree(&SEGMENT_ID_TREE, &self.segment_tree_probs, 0) as u8
+            let id = self.b.read_with_tree(&SEGMENT_ID_TREE, &self.segment_tree_probs, 0) as u8;
+            self.frame.segment_map.push(id);
+            id
         } else {
             0
         };
@@ -1187,73 +1807,256 @@ impl<R: Read> VP8Decoder<R> {
         (skip_coeff, mb)
     }
 
-    fn intra_predict(&mut self, mbx: usize, mby: usize, mb: &MacroBlock, resdata: &[i32]) {
-        let stride = 1usize + 16 + 4;
-        let w  = self.frame.width as usize;
-        let mw = self.mbwidth as usize;
-        let mut ws = create_border(
-            mbx, mby, mw, &self.top_border, &self.left_border);
-
-        match mb.luma_mode {
-            V_PRED  => predict_vpred(&mut ws, 16, 1, 1, stride),
-            H_PRED  => predict_hpred(&mut ws, 16, 1, 1, stride),
-            TM_PRED => predict_tmpred(&mut ws, 16, 1, 1, stride),
-            DC_PRED => predict_dcpred(&mut ws, 16, stride, mby != 0, mbx != 0),
-            B_PRED  => predict_4x4(&mut ws, stride, &mb.bpred, resdata),
-            _       => panic!("unknown luma intra prediction mode")
-        }
-
-        if mb.luma_mode != B_PRED {
-            for y in 0usize..4 {
-                for x in 0usize..4 {
-                    let i  = x + y * 4;
-                    let rb = &resdata[i * 16..i * 16 + 16];
-                    let y0 = 1 + y * 4;
-                    let x0 = 1 + x * 4;
-
-                    add_residue(&mut ws, rb, y0, x0, stride);
-                }
-            }
-        }
+    fn intra_predict(&mut self, mbx: usize, mby: usize, mb: &MacroBlock, resdata: [i32; 384]) {
+        let pixels = reconstruct_macroblock(
+            mbx, mby, self.mbwidth as usize, mb, resdata, self.top_borders(), &self.left_border);
+
+        self.left_border.update(&pixels);
+        self.store_macroblock(mbx, mby, &pixels);
+    }
+
+    fn top_borders(&self) -> [&[u8]; 3] {
+        [&self.top_border, &self.top_border_u, &self.top_border_v]
+    }
 
-        self.left_border[0] = ws[16];
+    // Writes the pixels of a reconstructed macroblock to the padded planes, and
+    // keeps its bottom row as the top border of the macroblock below it
+    fn store_macroblock(&mut self, mbx: usize, mby: usize, pixels: &MacroBlockPixels) {
+        let stride = 1usize + 16 + 4;
+        let ws = &pixels.y;
+        let w  = self.mbwidth as usize * 16;
+        let (row0, chroma_row0) = self.plane_rows(mby);
 
         for i in 0usize..16 {
             self.top_border[mbx * 16 + i] = ws[16 * stride + 1 + i];
-            self.left_border[i + 1] = ws[(i + 1) * stride + 16];
         }
 
-        let ylength = if mby < self.mbheight as usize - 1 { 16usize }
-                      else if self.frame.height % 16 == 0 { 16usize }
-                      else { (16 - (self.frame.height as usize & 15)) % 16 };
+        for y in 0usize..16 {
+            let start = (row0 + y) * w + mbx * 16;
+            let row = (1 + y) * stride + 1;
+
+            self.ybuf[start..start + 16].copy_from_slice(&ws[row..row + 16]);
+        }
 
-        let xlength = if mbx < self.mbwidth as usize - 1 { 16usize }
-                      else if self.frame.width % 16 == 0 { 16usize }
-                      else { (16 - (self.frame.width as usize & 15)) % 16 };
+        let stride = 1usize + 8;
+        let (uws, vws) = (&pixels.u, &pixels.v);
+        let cw = self.mbwidth as usize * 8;
 
-        for y in 0usize..ylength {
-            for x in 0usize..xlength {
-                self.frame.ybuf[(mby * 16 + y) * w + mbx * 16 + x] =
-                    ws[(1 + y) * stride + 1 + x];
-            }
+        for i in 0usize..8 {
+            self.top_border_u[mbx * 8 + i] = uws[8 * stride + 1 + i];
+            self.top_border_v[mbx * 8 + i] = vws[8 * stride + 1 + i];
+        }
+
+        for y in 0usize..8 {
+            let start = (chroma_row0 + y) * cw + mbx * 8;
+            let row = (1 + y) * stride + 1;
+
+            self.ubuf[start..start + 8].copy_from_slice(&uws[row..row + 8]);
+            self.vbuf[start..start + 8].copy_from_slice(&vws[row..row + 8]);
         }
     }
 
-    fn read_coefficients(&mut self,
-                         block: &mut [i32],
-                         p: usize,
-                         plane: usize,
-                         complexity: usize,
-                         dcq: i16,
-                         acq: i16) -> bool {
+    // The loop filter level of a macroblock, Section 9.6
+    fn filter_level(&self, mb: &MacroBlock) -> u8 {
+        let mut level = self.frame.filter_level as i32;
 
-        let first = if plane == 0 { 1usize } else { 0usize };
-        let probs = &self.token_probs[plane];
-        let tree  = &DCT_TOKEN_TREE;
+        if self.segments_enabled {
+            let segment = &self.segment[mb.segmentid as usize];
+            level = if segment.delta_values {
+                level + segment.loopfilter_level as i32
+            } else {
+                segment.loopfilter_level as i32
+            };
 
-        let mut complexity = complexity;
-        let mut has_coefficients = false;
-        let mut skip = false;
+            level = clamp(level, 0, 63);
+        }
+
+        if self.loop_filter_adjustments {
+            // Keyframes only hold intra predicted macroblocks, the
+            // first mode delta applies to B_PRED
+            level += self.ref_delta[0];
+            if mb.luma_mode == B_PRED {
+                level += self.mode_delta[0];
+            }
+        }
+
+        clamp(level, 0, 63) as u8
+    }
+
+    // Applies the loop filter to the padded planes, Section 15
+    fn filter_frame(&mut self) {
+        for mby in 0..self.mbheight as usize {
+            self.filter_row(mby);
+        }
+    }
+
+    // Applies the loop filter to a macroblock row. This changes the last
+    // three rows of the macroblock row above.
+    fn filter_row(&mut self, mby: usize) {
+        // The frame level turns the filter on or off, whatever the adjustments
+        if self.frame.filter_level == 0 {
+            return
+        }
+
+        let mbw = self.mbwidth as usize;
+        let stride = mbw * 16;
+        let cstride = mbw * 8;
+        let sharpness = self.frame.sharpness_level as i32;
+        let simple = self.frame.filter == 1;
+        let (row0, chroma_row0) = self.plane_rows(mby);
+
+        for mbx in 0..mbw {
+            let (level, inner) = self.macroblock_filters[mby * mbw + mbx];
+            if level == 0 {
+                continue
+            }
+
+            let level = level as i32;
+            let mut interior_limit = level;
+            if sharpness != 0 {
+                interior_limit >>= if sharpness > 4 { 2 } else { 1 };
+                interior_limit = cmp::min(interior_limit, 9 - sharpness);
+            }
+
+            let interior_limit = cmp::max(interior_limit, 1);
+            let hev_threshold = if level >= 40 { 2 } else if level >= 15 { 1 } else { 0 };
+            let mbedge_limit = (level + 2) * 2 + interior_limit;
+            let subblock_limit = level * 2 + interior_limit;
+
+            let y0 = row0 * stride + mbx * 16;
+            let c0 = chroma_row0 * cstride + mbx * 8;
+
+            // The left edge, the vertical edges inside of the macroblock,
+            // the top edge and the horizontal edges inside of it in that order
+            if simple {
+                let ybuf = &mut self.ybuf;
+
+                if mbx > 0 {
+                    for y in 0..16 {
+                        loop_filter::simple_segment(mbedge_limit, ybuf, y0 + y * stride, 1);
+                    }
+                }
+
+                if inner {
+                    for x in [4, 8, 12].iter() {
+                        for y in 0..16 {
+                            loop_filter::simple_segment(subblock_limit, ybuf, y0 + y * stride + x, 1);
+                        }
+                    }
+                }
+
+                if mby > 0 {
+                    for x in 0..16 {
+                        loop_filter::simple_segment(mbedge_limit, ybuf, y0 + x, stride);
+                    }
+                }
+
+                if inner {
+                    for y in [4, 8, 12].iter() {
+                        for x in 0..16 {
+                            loop_filter::simple_segment(subblock_limit, ybuf, y0 + y * stride + x, stride);
+                        }
+                    }
+                }
+
+                continue
+            }
+
+            let mb_filter = |buf: &mut [u8], point: usize, step: usize| {
+                loop_filter::macroblock_filter(hev_threshold, interior_limit, mbedge_limit, buf, point, step)
+            };
+            let sub_filter = |buf: &mut [u8], point: usize, step: usize| {
+                loop_filter::subblock_filter(hev_threshold, interior_limit, subblock_limit, buf, point, step)
+            };
+
+            if mbx > 0 {
+                for y in 0..16 {
+                    mb_filter(&mut self.ybuf, y0 + y * stride, 1);
+                }
+
+                for y in 0..8 {
+                    mb_filter(&mut self.ubuf, c0 + y * cstride, 1);
+                    mb_filter(&mut self.vbuf, c0 + y * cstride, 1);
+                }
+            }
+
+            if inner {
+                for x in [4, 8, 12].iter() {
+                    for y in 0..16 {
+                        sub_filter(&mut self.ybuf, y0 + y * stride + x, 1);
+                    }
+                }
+
+                for y in 0..8 {
+                    sub_filter(&mut self.ubuf, c0 + y * cstride + 4, 1);
+                    sub_filter(&mut self.vbuf, c0 + y * cstride + 4, 1);
+                }
+            }
+
+            if mby > 0 {
+                for x in 0..16 {
+                    mb_filter(&mut self.ybuf, y0 + x, stride);
+                }
+
+                for x in 0..8 {
+                    mb_filter(&mut self.ubuf, c0 + x, cstride);
+                    mb_filter(&mut self.vbuf, c0 + x, cstride);
+                }
+            }
+
+            if inner {
+                for y in [4, 8, 12].iter() {
+                    for x in 0..16 {
+                        sub_filter(&mut self.ybuf, y0 + y * stride + x, stride);
+                    }
+                }
+
+                for x in 0..8 {
+                    sub_filter(&mut self.ubuf, c0 + 4 * cstride + x, cstride);
+                    sub_filter(&mut self.vbuf, c0 + 4 * cstride + x, cstride);
+                }
+            }
+        }
+    }
+
+    // Filters the padded planes and crops them to the size of the frame
+    fn finish_frame(&mut self) {
+        self.filter_frame();
+
+        // The planes of the frame keep their allocations, which are only
+        // grown when the frame is larger than the one they held before
+        let crop = |plane: &[u8], padded_width: usize, width: usize, height: usize, buf: &mut Vec<u8>| {
+            buf.clear();
+            buf.reserve(width * height);
+            for row in plane.chunks(padded_width).take(height) {
+                buf.extend_from_slice(&row[..width]);
+            }
+        };
+
+        let mbw = self.mbwidth as usize;
+        let (width, height) = (self.frame.width as usize, self.frame.height as usize);
+        let (cw, ch) = (self.frame.chroma_width() as usize, self.frame.chroma_height() as usize);
+
+        crop(&self.ybuf, mbw * 16, width, height, &mut self.frame.ybuf);
+        crop(&self.ubuf, mbw * 8, cw, ch, &mut self.frame.ubuf);
+        crop(&self.vbuf, mbw * 8, cw, ch, &mut self.frame.vbuf);
+    }
+
+    fn read_coefficients(&mut self,
+                         block: &mut [i32],
+                         p: usize,
+                         plane: usize,
+                         complexity: usize,
+                         dcq: i16,
+                         acq: i16) -> bool {
+
+        let first = if plane == 0 { 1usize } else { 0usize };
+        let probs = &self.token_probs[plane];
+        let tree  = &DCT_TOKEN_TREE;
+
+        let mut complexity = complexity;
+        let mut has_coefficients = false;
+        let mut skip = false;
 
         for i in first..16usize {
             let table = &probs[COEFF_BANDS[i] as usize][complexity];
@@ -1267,163 +2070,1163 @@ impl<R: Read> VP8Decoder<R> {
             let mut abs_value = match token {
                 DCT_EOB => break,
 
-                DCT_0   => {
-                    skip = true;
-                    has_coefficients = true;
-                    complexity = 0;
-                    continue
-                }
+                DCT_0   => {
+                    skip = true;
+                    has_coefficients = true;
+                    complexity = 0;
+                    continue
+                }
+
+                literal  @ DCT_1 ... DCT_4 => literal as i16,
+
+                category @ DCT_CAT1 ... DCT_CAT6 => {
+                    let t = PROB_DCT_CAT[(category - DCT_CAT1) as usize];
+
+                    let mut extra = 0i16;
+                    let mut j = 0;
+
+                    while t[j] > 0 {
+                        extra += extra + self.partitions[p].read_bool(t[j]) as i16;
+                        j += 1;
+                    }
+
+                    DCT_CAT_BASE[(category - DCT_CAT1) as usize] as i16 + extra
+                }
+
+                c => panic!(format!("unknown token: {}", c))
+            } as i32;
+
+            skip = false;
+
+            complexity = if abs_value == 0 { 0 }
+                         else if abs_value == 1 { 1 }
+                         else { 2 };
+
+            if self.partitions[p].read_bool(128) == 1 {
+                abs_value = -abs_value;
+            }
+
+            block[ZIGZAG[i] as usize] = abs_value * if ZIGZAG[i] > 0 { acq }
+                                                   else { dcq } as i32;
+
+            has_coefficients = true;
+        }
+
+        has_coefficients
+    }
+
+    // Reads the dequantized coefficients of a macroblock, the inverse DCT is
+    // left to reconstruct_macroblock
+    fn read_residual_data(&mut self, mb: &MacroBlock, mbx: usize, p: usize) -> ([i32; 384], bool) {
+        let sindex     = mb.segmentid as usize;
+        let mut blocks = [0i32; 384];
+        let mut non_zero = false;
+        let mut plane  = if mb.luma_mode == B_PRED { 3 }
+                         else { 1 };
+
+        if plane == 1 {
+            let complexity = self.top[mbx].complexity[0] + self.left.complexity[0];
+            let mut block = [0i32; 16];
+            let dcq = self.segment[sindex].y2dc;
+            let acq = self.segment[sindex].y2ac;
+            let n   = self.read_coefficients(&mut block, p, plane, complexity as usize, dcq, acq);
+            non_zero |= n;
+
+            self.left.complexity[0] = if n { 1 } else { 0 };
+            self.top[mbx].complexity[0] = if n { 1 } else { 0 };
+
+            transform::iwht4x4(&mut block);
+
+            for k in 0usize..16 {
+                blocks[16 * k] = block[k];
+            }
+
+            plane = 0;
+        }
+
+        for y in 0usize..4 {
+            let mut left = self.left.complexity[y + 1];
+            for x in 0usize..4 {
+                let i = x + y * 4;
+                let block = &mut blocks[i * 16..i * 16 + 16];
+
+                let complexity = self.top[mbx].complexity[x + 1] + left;
+                let dcq = self.segment[sindex].ydc;
+                let acq = self.segment[sindex].yac;
+
+                let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
+                non_zero |= n;
+
+                left = if n { 1 } else { 0 };
+                self.top[mbx].complexity[x + 1] = if n { 1 } else { 0 };
+            }
+
+            self.left.complexity[y + 1] = left;
+        }
+
+        plane = 2;
+
+        for &j in [5usize, 7usize].iter() {
+            for y in 0usize..2 {
+                let mut left = self.left.complexity[y + j];
+
+                for x in 0usize..2 {
+                    let i = x + y * 2 + if j == 5 { 16 } else { 20 };
+                    let block = &mut blocks[i * 16..i * 16 + 16];
+
+                    let complexity = self.top[mbx].complexity[x + j] + left;
+                    let dcq   = self.segment[sindex].uvdc;
+                    let acq   = self.segment[sindex].uvac;
+
+                    let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
+                    non_zero |= n;
+
+                    left = if n { 1 } else { 0 };
+                    self.top[mbx].complexity[x + j] = if n { 1 } else { 0 };
+                }
+
+                self.left.complexity[y + j] = left;
+            }
+        }
+
+        (blocks, non_zero)
+    }
+
+    // Reads the header and the coefficients of the next macroblock of a row,
+    // and notes how the loop filter applies to it
+    fn read_macroblock(&mut self, mbx: usize, p: usize) -> (MacroBlock, [i32; 384]) {
+        let (skip, mb) = self.read_macroblock_header(mbx);
+        let mut blocks = [0i32; 384];
+        let mut non_zero = false;
+
+        if !skip {
+            let (b, n) = self.read_residual_data(&mb, mbx, p);
+            blocks = b;
+            non_zero = n;
+        } else {
+            if mb.luma_mode != B_PRED {
+                self.left.complexity[0] = 0;
+                self.top[mbx].complexity[0] = 0;
+            }
+
+            for i in 1usize..9 {
+                self.left.complexity[i] = 0;
+                self.top[mbx].complexity[i] = 0;
+            }
+        }
+
+        // The edges inside of a macroblock without coefficients are left
+        // alone, unless its subblocks are predicted on their own
+        let filter = (self.filter_level(&mb), non_zero || mb.luma_mode == B_PRED);
+        self.macroblock_filters.push(filter);
+
+        (mb, blocks)
+    }
+
+    fn check_row_eof(&self, mby: usize) -> ImageResult<()> {
+        let p = mby % self.num_partitions as usize;
+
+        try!(self.b.check_eof());
+        self.partitions[p].check_eof()
+    }
+
+    /// Decodes the current frame and returns a reference to it
+    pub fn decode_frame(&mut self) -> ImageResult<&Frame> {
+        let timer = Timer::start();
+        let _ = try!(self.read_frame_header());
+        timer.add_to(&mut self.stats.header);
+        self.init_planes(false);
+
+        for mby in 0..self.mbheight as usize {
+            let p = mby % self.num_partitions as usize;
+            self.left = MacroBlock::new();
+
+            for mbx in 0..self.mbwidth as usize {
+                self.decode_macroblock(mbx, mby, p);
+            }
+
+            try!(self.check_row_eof(mby));
+
+            self.left_border = LeftBorder::new();
+        }
+
+        let timer = Timer::start();
+        self.finish_frame();
+        timer.add_to(&mut self.stats.reconstruction);
+
+        Ok(&self.frame)
+    }
+
+    /// Reads the header of the current frame and returns its coding parameters,
+    /// without decoding any macroblocks
+    pub fn header_info(&mut self) -> ImageResult<FrameHeaderInfo> {
+        let _ = try!(self.read_frame_header());
+
+        Ok(self.frame.header_info())
+    }
+
+    /// Reads the header of the current frame and the macroblock headers of its
+    /// first partition, and returns the quantizer indices and the segment map of
+    /// the frame. The coefficients in the other partitions are not read.
+    pub fn quantizer_info(&mut self) -> ImageResult<QuantizerInfo> {
+        let _ = try!(self.read_frame_header());
+
+        if self.segments_enabled && self.segments_update_map {
+            for _ in 0..self.mbheight as usize {
+                self.left = MacroBlock::new();
+
+                for mbx in 0..self.mbwidth as usize {
+                    let _ = self.read_macroblock_header(mbx);
+                }
+
+                try!(self.b.check_eof());
+            }
+        }
+
+        Ok(self.frame.quantizer_info())
+    }
+
+    // Reads and reconstructs a macroblock, timing each
+    fn decode_macroblock(&mut self, mbx: usize, mby: usize, p: usize) {
+        let timer = Timer::start();
+        let (mb, blocks) = self.read_macroblock(mbx, p);
+        timer.add_to(&mut self.stats.entropy);
+
+        let timer = Timer::start();
+        self.intra_predict(mbx, mby, &mb, blocks);
+        timer.add_to(&mut self.stats.reconstruction);
+    }
+
+    /// Returns how long the stages of decoding the frame took so far. The
+    /// conversion of the frame is left zero.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> DecodeStats {
+        self.stats
+    }
+
+    /// Decodes the current frame one macroblock row at a time, handing out the
+    /// finished rows of each plane as it goes instead of keeping the whole frame.
+    ///
+    /// ```rows``` is called with the index of the first luma row of a band and
+    /// the Y, U and V rows of the band, cropped to the width of the planes. A band
+    /// starts at an even row and covers the chroma rows of its luma rows. Only a
+    /// macroblock row and a half of pixels are held in memory, the planes of the
+    /// frame returned by ```into_frame``` stay empty.
+    pub fn decode_frame_rows<F>(&mut self, rows: F) -> ImageResult<()>
+        where F: FnMut(usize, &[u8], &[u8], &[u8])
+    {
+        self.decode_frame_rows_up_to(usize::MAX, rows)
+    }
+
+    /// Decodes the top of the current frame like ```decode_frame_rows```, stopping
+    /// after the band that reaches luma row ```max_rows```. The macroblock rows
+    /// below it are not read.
+    pub fn decode_frame_rows_up_to<F>(&mut self, max_rows: usize, mut rows: F) -> ImageResult<()>
+        where F: FnMut(usize, &[u8], &[u8], &[u8])
+    {
+        let timer = Timer::start();
+        let _ = try!(self.read_frame_header());
+        timer.add_to(&mut self.stats.header);
+        self.init_planes(true);
+
+        let mbw = self.mbwidth as usize;
+        let mbh = self.mbheight as usize;
+        let (width, height) = (self.frame.width as usize, self.frame.height as usize);
+        let (cw, ch) = (self.frame.chroma_width() as usize, self.frame.chroma_height() as usize);
+
+        let (mut ys, mut us, mut vs) = (Vec::new(), Vec::new(), Vec::new());
+        self.frame.ybuf = Vec::new();
+        self.frame.ubuf = Vec::new();
+        self.frame.vbuf = Vec::new();
+
+        for mby in 0..mbh {
+            let p = mby % self.num_partitions as usize;
+            self.left = MacroBlock::new();
+
+            for mbx in 0..mbw {
+                self.decode_macroblock(mbx, mby, p);
+            }
+
+            try!(self.check_row_eof(mby));
+
+            self.left_border = LeftBorder::new();
+            let timer = Timer::start();
+            self.filter_row(mby);
+
+            // The rows kept from the row above are final now, and so are all
+            // but the kept ones of this row
+            let start = if mby == 0 { 0 } else { mby * 16 - KEPT_ROWS };
+            let (end, chroma_end) = if mby + 1 == mbh {
+                (height, ch)
+            } else {
+                (mby * 16 + 16 - KEPT_ROWS, mby * 8 + 8 - KEPT_CHROMA_ROWS)
+            };
+
+            // The planes hold the kept rows followed by this row
+            let luma = start + KEPT_ROWS - mby * 16..end + KEPT_ROWS - mby * 16;
+            let chroma = start / 2 + KEPT_CHROMA_ROWS - mby * 8..chroma_end + KEPT_CHROMA_ROWS - mby * 8;
+            copy_rows(&self.ybuf, mbw * 16, width, luma, &mut ys);
+            copy_rows(&self.ubuf, mbw * 8, cw, chroma.clone(), &mut us);
+            copy_rows(&self.vbuf, mbw * 8, cw, chroma, &mut vs);
+            timer.add_to(&mut self.stats.reconstruction);
+            rows(start, &ys, &us, &vs);
+
+            if end >= max_rows {
+                break
+            }
+
+            keep_last_rows(&mut self.ybuf, mbw * 16, KEPT_ROWS);
+            keep_last_rows(&mut self.ubuf, mbw * 8, KEPT_CHROMA_ROWS);
+            keep_last_rows(&mut self.vbuf, mbw * 8, KEPT_CHROMA_ROWS);
+        }
+
+        Ok(())
+    }
+
+    /// Starts decoding the current frame from the part of it the reader holds,
+    /// the rest of it is given to ```extend_frame``` as it arrives. The frame
+    /// header and the first partition have to be there, ```NotEnoughData``` is
+    /// returned otherwise.
+    ///
+    /// Returns the number of macroblock rows decoded so far. Once all of the
+    /// frame has been given, ```finish_frame_partial``` decodes the rest of it.
+    pub fn start_frame_partial(&mut self) -> ImageResult<usize> {
+        self.partial = Some(PartialFrame { missing: [0; 8], mby: 0 });
+
+        let timer = Timer::start();
+        if let Err(e) = self.read_frame_header() {
+            self.partial = None;
+            return Err(e)
+        }
+        timer.add_to(&mut self.stats.header);
+        self.init_planes(false);
+
+        self.decode_available_rows()
+    }
+
+    /// Appends the next bytes of the frame started by ```start_frame_partial```,
+    /// following the part the reader held, and decodes the macroblock rows they
+    /// complete. Returns the number of macroblock rows decoded so far.
+    pub fn extend_frame(&mut self, data: &[u8]) -> ImageResult<usize> {
+        let n = self.num_partitions as usize;
+        let mut data = data;
+
+        {
+            let partial = match self.partial {
+                Some(ref mut partial) => partial,
+                None => return Err(ImageError::FormatError(String::from("VP8: no frame is started")))
+            };
+
+            // The partitions follow each other, the last one takes the rest
+            for i in 0..n - 1 {
+                let len = cmp::min(partial.missing[i], data.len());
+                if len > 0 {
+                    self.partitions[i].extend(&data[..len]);
+                    partial.missing[i] -= len;
+                    data = &data[len..];
+
+                    if partial.missing[i] == 0 {
+                        self.partitions[i].finish();
+                    }
+                }
+            }
+        }
+
+        if !data.is_empty() {
+            self.partitions[n - 1].extend(data);
+        }
+
+        self.decode_available_rows()
+    }
+
+    /// Decodes the rest of the frame started by ```start_frame_partial``` once
+    /// all of it has been given, and returns a reference to it like
+    /// ```decode_frame```.
+    pub fn finish_frame_partial(&mut self) -> ImageResult<&Frame> {
+        if self.partial.is_none() {
+            return Err(ImageError::FormatError(String::from("VP8: no frame is started")))
+        }
+
+        for partition in self.partitions[..self.num_partitions as usize].iter_mut() {
+            partition.finish();
+        }
+
+        let decoded = self.decode_available_rows();
+        self.partial = None;
+        let _ = try!(decoded);
+
+        let timer = Timer::start();
+        self.finish_frame();
+        timer.add_to(&mut self.stats.reconstruction);
+
+        Ok(&self.frame)
+    }
+
+    // Decodes the macroblock rows the partitions hold so far. A row whose
+    // partition runs out is undone and decoded again once there is more of it.
+    fn decode_available_rows(&mut self) -> ImageResult<usize> {
+        let mut mby = self.partial.as_ref().map_or(0, |partial| partial.mby);
+
+        while mby < self.mbheight as usize {
+            let p = mby % self.num_partitions as usize;
+            let state = self.row_state(p);
+            self.left = MacroBlock::new();
+
+            for mbx in 0..self.mbwidth as usize {
+                self.decode_macroblock(mbx, mby, p);
+            }
+
+            self.left_border = LeftBorder::new();
+            if self.partitions[p].starved() {
+                self.restore_row(p, state);
+                break
+            }
+
+            try!(self.check_row_eof(mby));
+            mby += 1;
+        }
+
+        if let Some(ref mut partial) = self.partial {
+            partial.mby = mby;
+        }
+
+        Ok(mby)
+    }
+
+    fn row_state(&self, p: usize) -> RowState {
+        RowState {
+            first_partition: self.b.state(),
+            partition: self.partitions[p].state(),
+
+            top: self.top.clone(),
+            top_border: self.top_border.clone(),
+            top_border_u: self.top_border_u.clone(),
+            top_border_v: self.top_border_v.clone(),
+
+            macroblock_filters: self.macroblock_filters.len(),
+            segment_map: self.frame.segment_map.len(),
+        }
+    }
+
+    // The pixels of the row in the planes are overwritten when it is decoded again
+    fn restore_row(&mut self, p: usize, state: RowState) {
+        self.b.restore(state.first_partition);
+        self.partitions[p].restore(state.partition);
+
+        self.top = state.top;
+        self.top_border = state.top_border;
+        self.top_border_u = state.top_border_u;
+        self.top_border_v = state.top_border_v;
+
+        self.macroblock_filters.truncate(state.macroblock_filters);
+        self.frame.segment_map.truncate(state.segment_map);
+    }
+
+    /// Decodes the current frame like ```decode_frame```, reconstructing
+    /// the macroblocks on multiple threads.
+    ///
+    /// The coefficients of the whole frame are read first. Each macroblock is
+    /// predicted from its left, top and top right neighbours, so the macroblocks
+    /// are then reconstructed in diagonals running from the top right to the bottom
+    /// left, which only depend on earlier diagonals. The output is identical
+    /// to that of ```decode_frame```.
+    #[cfg(feature = "rayon")]
+    pub fn decode_frame_parallel(&mut self) -> ImageResult<&Frame> {
+        let timer = Timer::start();
+        let _ = try!(self.read_frame_header());
+        timer.add_to(&mut self.stats.header);
+        self.init_planes(false);
+
+        let mbw = self.mbwidth as usize;
+        let mbh = self.mbheight as usize;
+        let mut macroblocks = Vec::with_capacity(mbw * mbh);
+
+        let timer = Timer::start();
+        for mby in 0..mbh {
+            let p = mby % self.num_partitions as usize;
+            self.left = MacroBlock::new();
+
+            for mbx in 0..mbw {
+                macroblocks.push(self.read_macroblock(mbx, p));
+            }
+
+            try!(self.check_row_eof(mby));
+        }
+        timer.add_to(&mut self.stats.entropy);
+
+        let timer = Timer::start();
+        let mut left_borders = vec![LeftBorder::new(); mbh];
+
+        // Macroblock (x, y) is on diagonal x + 2 * y
+        for d in 0..mbw + 2 * (mbh - 1) {
+            let first = if d >= mbw { (d - mbw) / 2 + 1 } else { 0 };
+            let last = cmp::min(d / 2, mbh - 1);
+
+            let pixels: Vec<MacroBlockPixels> = {
+                let top = self.top_borders();
+                let macroblocks = &macroblocks;
+                let left_borders = &left_borders;
+
+                (first..last + 1).into_par_iter().map(|mby| {
+                    let mbx = d - 2 * mby;
+                    let (ref mb, blocks) = macroblocks[mby * mbw + mbx];
+
+                    reconstruct_macroblock(mbx, mby, mbw, mb, blocks, top, &left_borders[mby])
+                }).collect()
+            };
+
+            for (mby, p) in (first..last + 1).zip(pixels.iter()) {
+                left_borders[mby].update(p);
+                self.store_macroblock(d - 2 * mby, mby, p);
+            }
+        }
+
+        self.finish_frame();
+        timer.add_to(&mut self.stats.reconstruction);
+
+        Ok(&self.frame)
+    }
+
+    /// Consumes the decoder and returns the frame decoded by ```decode_frame```
+    pub fn into_frame(self) -> Frame {
+        self.frame
+    }
+}
+
+/// VP8 Encoder
+///
+/// Only encodes keyframes, each macroblock is predicted as a whole
+pub struct VP8Encoder<W> {
+    w: W,
+
+    // The first partition holds the frame and macroblock headers, the second the tokens
+    b: BoolWriter,
+    p: BoolWriter,
+
+    mbwidth: usize,
+    mbheight: usize,
+
+    segment: Segment,
+
+    top: Vec<MacroBlock>,
+    left: MacroBlock,
+
+    // The frame as the decoder reconstructs it, padded to whole macroblocks
+    ybuf: Vec<u8>,
+    ubuf: Vec<u8>,
+    vbuf: Vec<u8>,
+}
+
+impl<W: Write> VP8Encoder<W> {
+    /// Create a new encoder.
+    /// The raw vp8 bitstream is written to ```w```
+    pub fn new(w: W) -> VP8Encoder<W> {
+        VP8Encoder {
+            w: w,
+
+            b: BoolWriter::new(),
+            p: BoolWriter::new(),
+
+            mbwidth: 0,
+            mbheight: 0,
+
+            segment: Default::default(),
+
+            top: Vec::new(),
+            left: MacroBlock::new(),
+
+            ybuf: Vec::new(),
+            ubuf: Vec::new(),
+            vbuf: Vec::new(),
+        }
+    }
+
+    /// Encodes ```frame``` as a keyframe using the quantizer index ```quantizer```,
+    /// between 0 for the finest and 127 for the coarsest quantization.
+    pub fn encode_frame(&mut self, frame: &Frame, quantizer: u8) -> io::Result<()> {
+        if frame.width == 0 || frame.height == 0 || frame.width > 0x3FFF || frame.height > 0x3FFF {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                &format!("Invalid VP8 frame dimensions {}x{}", frame.width, frame.height)[..],
+            ))
+        }
+
+        let q = cmp::min(quantizer, 127);
+        self.set_quantizers(q as usize);
+
+        self.mbwidth  = (frame.width as usize + 15) / 16;
+        self.mbheight = (frame.height as usize + 15) / 16;
+
+        let ysrc = pad_plane(&frame.ybuf, frame.width as usize, frame.height as usize,
+                             self.mbwidth * 16, self.mbheight * 16);
+        let usrc = pad_plane(&frame.ubuf, frame.chroma_width() as usize, frame.chroma_height() as usize,
+                             self.mbwidth * 8, self.mbheight * 8);
+        let vsrc = pad_plane(&frame.vbuf, frame.chroma_width() as usize, frame.chroma_height() as usize,
+                             self.mbwidth * 8, self.mbheight * 8);
+
+        self.ybuf = vec![0u8; ysrc.len()];
+        self.ubuf = vec![0u8; usrc.len()];
+        self.vbuf = vec![0u8; vsrc.len()];
+
+        self.b = BoolWriter::new();
+        self.p = BoolWriter::new();
+        self.top = (0..self.mbwidth).map(|_| MacroBlock::new()).collect();
+
+        self.write_frame_header(q);
+
+        for mby in 0..self.mbheight {
+            self.left = MacroBlock::new();
+
+            for mbx in 0..self.mbwidth {
+                self.encode_macroblock(mbx, mby, &ysrc, &usrc, &vsrc);
+            }
+        }
+
+        let first_partition = mem::replace(&mut self.b, BoolWriter::new()).finish();
+        let tokens = mem::replace(&mut self.p, BoolWriter::new()).finish();
+
+        // The size of the first partition is stored in 19 bits
+        if first_partition.len() >= 1 << 19 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Frame is too large for a single VP8 keyframe",
+            ))
+        }
+
+        // A keyframe of version 0 intended for display
+        let tag = (first_partition.len() as u32) << 5 | 1 << 4;
+
+        try!(self.w.write_all(&[tag as u8, (tag >> 8) as u8, (tag >> 16) as u8]));
+        try!(self.w.write_all(&[0x9d, 0x01, 0x2a]));
+        try!(self.w.write_u16::<LittleEndian>(frame.width));
+        try!(self.w.write_u16::<LittleEndian>(frame.height));
+        try!(self.w.write_all(&first_partition));
+        try!(self.w.write_all(&tokens));
+
+        Ok(())
+    }
+
+    // The same quantizer steps the decoder derives from the index when there are no deltas
+    fn set_quantizers(&mut self, q: usize) {
+        self.segment.ydc  = DC_QUANT[q];
+        self.segment.yac  = AC_QUANT[q];
+
+        self.segment.y2dc = DC_QUANT[q] * 2;
+        self.segment.y2ac = cmp::max((AC_QUANT[q] as i32 * 155 / 100) as i16, 8);
+
+        self.segment.uvdc = cmp::min(DC_QUANT[q], 132);
+        self.segment.uvac = AC_QUANT[q];
+    }
+
+    fn write_frame_header(&mut self, q: u8) {
+        // Color space and clamping type
+        self.b.write_literal(1, 0);
+        self.b.write_literal(1, 0);
+
+        // No segmentation
+        self.b.write_flag(false);
+
+        // Filter type, level and sharpness, the loop filter is disabled
+        self.b.write_literal(1, 0);
+        self.b.write_literal(6, 0);
+        self.b.write_literal(3, 0);
+
+        // No loop filter adjustments
+        self.b.write_flag(false);
+
+        // A single DCT partition
+        self.b.write_literal(2, 0);
+
+        // The quantizer index without any deltas
+        self.b.write_literal(7, q);
+        for _ in 0usize..5 {
+            self.b.write_flag(false);
+        }
+
+        // Refresh entropy probs
+        self.b.write_literal(1, 0);
+
+        // Keep the default token probabilities
+        for i in 0usize..4 {
+            for j in 0usize..8 {
+                for k in 0usize..3 {
+                    for t in 0usize..NUM_DCT_TOKENS - 1 {
+                        self.b.write_bool(false, COEFF_UPDATE_PROBS[i][j][k][t]);
+                    }
+                }
+            }
+        }
+
+        // mb_no_skip_coeff, the coefficients of every macroblock are coded
+        self.b.write_literal(1, 0);
+    }
+
+    fn encode_macroblock(&mut self, mbx: usize, mby: usize, ysrc: &[u8], usrc: &[u8], vsrc: &[u8]) {
+        let ystride = self.mbwidth * 16;
+        let cstride = self.mbwidth * 8;
+
+        let y = read_block(ysrc, ystride, mbx * 16, mby * 16, 16);
+        let u = read_block(usrc, cstride, mbx * 8, mby * 8, 8);
+        let v = read_block(vsrc, cstride, mbx * 8, mby * 8, 8);
+
+        // Choose the prediction modes closest to the source
+        let yedges = block_edges(&self.ybuf, ystride, mbx, mby, 16);
+        let (luma_mode, ypred) = best_prediction(&[&y], &[&yedges], 16, mbx, mby);
+        let ypred = &ypred[0];
+
+        let uedges = block_edges(&self.ubuf, cstride, mbx, mby, 8);
+        let vedges = block_edges(&self.vbuf, cstride, mbx, mby, 8);
+        let (chroma_mode, cpred) = best_prediction(&[&u, &v], &[&uedges, &vedges], 8, mbx, mby);
+
+        self.b.write_with_tree(&KEYFRAME_YMODE_TREE, &KEYFRAME_YMODE_PROBS, 0, luma_mode);
+        self.b.write_with_tree(&KEYFRAME_UV_MODE_TREE, &KEYFRAME_UV_MODE_PROBS, 0, chroma_mode);
+
+        let yrec = self.encode_luma(mbx, &y, ypred);
+        write_block(&mut self.ybuf, ystride, mbx * 16, mby * 16, 16, &yrec);
+
+        let urec = self.encode_chroma(mbx, 5, &u, &cpred[0]);
+        write_block(&mut self.ubuf, cstride, mbx * 8, mby * 8, 8, &urec);
+
+        let vrec = self.encode_chroma(mbx, 7, &v, &cpred[1]);
+        write_block(&mut self.vbuf, cstride, mbx * 8, mby * 8, 8, &vrec);
+    }
+
+    // Codes the residue of the luma plane through the Y2 block and returns the reconstruction
+    fn encode_luma(&mut self, mbx: usize, src: &[u8], pred: &[u8]) -> Vec<u8> {
+        let mut blocks = [[0i32; 16]; 16];
+        let mut y2 = [0i32; 16];
+
+        for (i, block) in blocks.iter_mut().enumerate() {
+            residue(block, src, pred, 16, i % 4 * 4, i / 4 * 4);
+            transform::fdct4x4(block);
+            y2[i] = block[0];
+        }
+
+        // The DC coefficients of all blocks are coded in the Y2 block
+        transform::fwht4x4(&mut y2);
+        quantize(&mut y2, 0, self.segment.y2dc, self.segment.y2ac);
+
+        let complexity = self.top[mbx].complexity[0] + self.left.complexity[0];
+        let n = self.write_coefficients(&y2, 1, complexity as usize);
+        self.left.complexity[0] = n as u8;
+        self.top[mbx].complexity[0] = n as u8;
+
+        dequantize(&mut y2, 0, self.segment.y2dc, self.segment.y2ac);
+        transform::iwht4x4(&mut y2);
+
+        let mut rec = pred.to_vec();
+
+        for y in 0usize..4 {
+            for x in 0usize..4 {
+                let i = x + y * 4;
+                let block = &mut blocks[i];
+
+                quantize(block, 1, self.segment.ydc, self.segment.yac);
+
+                let complexity = self.top[mbx].complexity[x + 1] + self.left.complexity[y + 1];
+                let n = self.write_coefficients(block, 0, complexity as usize);
+                self.left.complexity[y + 1] = n as u8;
+                self.top[mbx].complexity[x + 1] = n as u8;
+
+                dequantize(block, 1, self.segment.ydc, self.segment.yac);
+                block[0] = y2[i];
+                transform::idct4x4(block);
+
+                add_residue(&mut rec, block, y * 4, x * 4, 16);
+            }
+        }
+
+        rec
+    }
+
+    // Codes the residue of a chroma plane, j is the first complexity index of the plane
+    fn encode_chroma(&mut self, mbx: usize, j: usize, src: &[u8], pred: &[u8]) -> Vec<u8> {
+        let mut rec = pred.to_vec();
+
+        for y in 0usize..2 {
+            for x in 0usize..2 {
+                let mut block = [0i32; 16];
+
+                residue(&mut block, src, pred, 8, x * 4, y * 4);
+                transform::fdct4x4(&mut block);
+                quantize(&mut block, 0, self.segment.uvdc, self.segment.uvac);
+
+                let complexity = self.top[mbx].complexity[x + j] + self.left.complexity[y + j];
+                let n = self.write_coefficients(&block, 2, complexity as usize);
+                self.left.complexity[y + j] = n as u8;
+                self.top[mbx].complexity[x + j] = n as u8;
+
+                dequantize(&mut block, 0, self.segment.uvdc, self.segment.uvac);
+                transform::idct4x4(&mut block);
+
+                add_residue(&mut rec, &block, y * 4, x * 4, 8);
+            }
+        }
+
+        rec
+    }
+
+    // The reverse of read_coefficients, returns whether any coefficient was coded
+    fn write_coefficients(&mut self, block: &[i32], plane: usize, complexity: usize) -> bool {
+        let first = if plane == 0 { 1usize } else { 0usize };
+        let probs = &COEFF_PROBS[plane];
+        let tree  = &DCT_TOKEN_TREE;
+
+        // The index following the last non zero coefficient
+        let end = (first..16usize).rev()
+                                  .find(|&i| block[ZIGZAG[i] as usize] != 0)
+                                  .map_or(first, |i| i + 1);
+
+        let mut complexity = complexity;
+        let mut skip = false;
+
+        for i in first..16usize {
+            let table = &probs[COEFF_BANDS[i] as usize][complexity];
+            let start = if skip { 2 } else { 0 };
+
+            if i == end {
+                self.p.write_with_tree(tree, table, start, DCT_EOB);
+                break
+            }
+
+            let value = block[ZIGZAG[i] as usize];
+            let abs_value = value.abs();
 
-                literal  @ DCT_1 ... DCT_4 => literal as i16,
+            if abs_value == 0 {
+                self.p.write_with_tree(tree, table, start, DCT_0);
+                skip = true;
+                complexity = 0;
+                continue
+            }
 
-                category @ DCT_CAT1 ... DCT_CAT6 => {
-                    let t = PROB_DCT_CAT[(category - DCT_CAT1) as usize];
+            if abs_value <= 4 {
+                self.p.write_with_tree(tree, table, start, abs_value as i8);
+            } else {
+                let category = (0usize..6).rev()
+                                          .find(|&c| abs_value >= DCT_CAT_BASE[c] as i32)
+                                          .unwrap();
 
-                    let mut extra = 0i16;
-                    let mut j = 0;
+                self.p.write_with_tree(tree, table, start, DCT_CAT1 + category as i8);
 
-                    while t[j] > 0 {
-                        extra += extra + self.partitions[p].read_bool(t[j]) as i16;
-                        j += 1;
-                    }
+                let t = PROB_DCT_CAT[category];
+                let extra = abs_value - DCT_CAT_BASE[category] as i32;
+                let bits = t.iter().take_while(|&&p| p > 0).count();
 
-                    DCT_CAT_BASE[(category - DCT_CAT1) as usize] as i16 + extra
+                for j in 0..bits {
+                    self.p.write_bool((extra >> (bits - 1 - j)) & 1 != 0, t[j]);
                 }
-
-                c => panic!(format!("unknown token: {}", c))
-            } as i32;
+            }
 
             skip = false;
+            complexity = if abs_value == 1 { 1 } else { 2 };
 
-            complexity = if abs_value == 0 { 0 }
-                         else if abs_value == 1 { 1 }
-                         else { 2 };
+            self.p.write_bool(value < 0, 128);
+        }
 
-            if self.partitions[p].read_bool(128) == 1 {
-                abs_value = -abs_value;
-            }
+        end > first
+    }
+}
 
-            block[ZIGZAG[i] as usize] = abs_value * if ZIGZAG[i] > 0 { acq }
-                                                   else { dcq } as i32;
+// Extends a width by height plane to padded_width by padded_height by repeating its edges
+fn pad_plane(plane: &[u8], width: usize, height: usize, padded_width: usize, padded_height: usize) -> Vec<u8> {
+    let mut padded = Vec::with_capacity(padded_width * padded_height);
 
-            has_coefficients = true;
+    for y in 0..padded_height {
+        let row = &plane[cmp::min(y, height - 1) * width..][..width];
+
+        for x in 0..padded_width {
+            padded.push(row[cmp::min(x, width - 1)]);
         }
+    }
 
-        has_coefficients
+    padded
+}
+
+fn read_block(plane: &[u8], stride: usize, x0: usize, y0: usize, size: usize) -> Vec<u8> {
+    let mut block = Vec::with_capacity(size * size);
+
+    for y in 0..size {
+        block.extend_from_slice(&plane[(y0 + y) * stride + x0..][..size]);
     }
 
-    fn read_residual_data(&mut self, mb: &MacroBlock, mbx: usize, p: usize) -> [i32; 384] {
-        let sindex     = mb.segmentid as usize;
-        let mut blocks = [0i32; 384];
-        let mut plane  = if mb.luma_mode == B_PRED { 3 }
-                         else { 1 };
+    block
+}
 
-        if plane == 1 {
-            let complexity = self.top[mbx].complexity[0] + self.left.complexity[0];
-            let mut block = [0i32; 16];
-            let dcq = self.segment[sindex].y2dc;
-            let acq = self.segment[sindex].y2ac;
-            let n   = self.read_coefficients(&mut block, p, plane, complexity as usize, dcq, acq);
+fn write_block(plane: &mut [u8], stride: usize, x0: usize, y0: usize, size: usize, block: &[u8]) {
+    for y in 0..size {
+        plane[(y0 + y) * stride + x0..][..size].copy_from_slice(&block[y * size..(y + 1) * size]);
+    }
+}
 
-            self.left.complexity[0] = if n { 1 } else { 0 };
-            self.top[mbx].complexity[0] = if n { 1 } else { 0 };
+// The pixels above, to the left and above left of a block, in the same way create_border
+// and create_border_chroma set them up for the decoder
+struct Edges {
+    above: Vec<u8>,
+    left: Vec<u8>,
+    corner: u8,
+}
 
-            transform::iwht4x4(&mut block);
+fn block_edges(plane: &[u8], stride: usize, mbx: usize, mby: usize, size: usize) -> Edges {
+    let x0 = mbx * size;
+    let y0 = mby * size;
 
-            for k in 0usize..16 {
-                blocks[16 * k] = block[k];
-            }
+    let above = if mby == 0 {
+        vec![127u8; size]
+    } else {
+        plane[(y0 - 1) * stride + x0..][..size].to_vec()
+    };
 
-            plane = 0;
-        }
+    let left = if mbx == 0 {
+        vec![129u8; size]
+    } else {
+        (0..size).map(|y| plane[(y0 + y) * stride + x0 - 1]).collect()
+    };
 
-        for y in 0usize..4 {
-            let mut left = self.left.complexity[y + 1];
-            for x in 0usize..4 {
-                let i = x + y * 4;
-                let block = &mut blocks[i * 16..i * 16 + 16];
+    let corner = if mby == 0 {
+        127
+    } else if mbx == 0 {
+        129
+    } else {
+        plane[(y0 - 1) * stride + x0 - 1]
+    };
 
-                let complexity = self.top[mbx].complexity[x + 1] + left;
-                let dcq = self.segment[sindex].ydc;
-                let acq = self.segment[sindex].yac;
+    Edges {above: above, left: left, corner: corner}
+}
 
-                let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
+fn predict_block(mode: i8, edges: &Edges, size: usize, mbx: usize, mby: usize) -> Vec<u8> {
+    let mut pred = vec![0u8; size * size];
 
-                if block[0] != 0 || n {
-                    transform::idct4x4(block);
-                }
+    for y in 0..size {
+        for x in 0..size {
+            pred[y * size + x] = match mode {
+                V_PRED  => edges.above[x],
+                H_PRED  => edges.left[y],
+                TM_PRED => clamp(edges.left[y] as i32 + edges.above[x] as i32 - edges.corner as i32, 0, 255) as u8,
+                _       => 0,
+            };
+        }
+    }
 
-                left = if n { 1 } else { 0 };
-                self.top[mbx].complexity[x + 1] = if n { 1 } else { 0 };
-            }
+    if mode == DC_PRED {
+        let mut sum = 0u32;
+        let mut shf = if size == 8 { 2 } else { 3 };
 
-            self.left.complexity[y + 1] = left;
+        if mbx != 0 {
+            sum += edges.left.iter().map(|&v| v as u32).sum::<u32>();
+            shf += 1;
         }
 
-        plane = 2;
+        if mby != 0 {
+            sum += edges.above.iter().map(|&v| v as u32).sum::<u32>();
+            shf += 1;
+        }
 
-        for &j in [5usize, 7usize].iter() {
-            for y in 0usize..2 {
-                let mut left = self.left.complexity[y + j];
+        let dcval = if mbx == 0 && mby == 0 { 128 } else { (sum + (1 << (shf - 1))) >> shf };
 
-                for x in 0usize..2 {
-                    let i = x + y * 2 + if j == 5 { 16 } else { 20 };
-                    let block = &mut blocks[i * 16..i * 16 + 16];
+        for p in pred.iter_mut() {
+            *p = dcval as u8;
+        }
+    }
 
-                    let complexity = self.top[mbx].complexity[x + j] + left;
-                    let dcq   = self.segment[sindex].uvdc;
-                    let acq   = self.segment[sindex].uvac;
+    pred
+}
 
-                    let n = self.read_coefficients(block, p, plane, complexity as usize, dcq, acq);
-                    if block[0] != 0 || n {
-                        transform::idct4x4(block);
-                    }
+// Returns the whole block prediction mode with the least squared error over all planes,
+// along with the prediction of each plane
+fn best_prediction(src: &[&[u8]], edges: &[&Edges], size: usize, mbx: usize, mby: usize) -> (i8, Vec<Vec<u8>>) {
+    let mut best = (DC_PRED, Vec::new());
+    let mut best_error = u64::max_value();
+
+    for &mode in [DC_PRED, V_PRED, H_PRED, TM_PRED].iter() {
+        let preds: Vec<_> = edges.iter().map(|e| predict_block(mode, e, size, mbx, mby)).collect();
+
+        let error = src.iter().zip(preds.iter()).map(|(s, p)| {
+            s.iter().zip(p.iter()).map(|(&a, &b)| {
+                let d = a as i64 - b as i64;
+                (d * d) as u64
+            }).sum::<u64>()
+        }).sum::<u64>();
+
+        if error < best_error {
+            best_error = error;
+            best = (mode, preds);
+        }
+    }
 
-                    left = if n { 1 } else { 0 };
-                    self.top[mbx].complexity[x + j] = if n { 1 } else { 0 };
-                }
+    best
+}
 
-                self.left.complexity[y + j] = left;
-            }
+// Writes the difference between the 4x4 blocks of src and pred at (x0, y0) into block
+fn residue(block: &mut [i32], src: &[u8], pred: &[u8], stride: usize, x0: usize, y0: usize) {
+    for y in 0usize..4 {
+        for x in 0usize..4 {
+            let i = (y0 + y) * stride + x0 + x;
+            block[y * 4 + x] = src[i] as i32 - pred[i] as i32;
         }
+    }
+}
 
-        blocks
+// The largest magnitude a coefficient token can represent
+const MAX_COEFF: i32 = 67 + 2047;
+
+fn quantize(block: &mut [i32], first: usize, dcq: i16, acq: i16) {
+    for i in first..16 {
+        let q = if i > 0 { acq } else { dcq } as i32;
+        let v = cmp::min((block[i].abs() + q / 2) / q, MAX_COEFF);
+
+        block[i] = if block[i] < 0 { -v } else { v };
     }
+}
 
-    /// Decodes the current frame and returns a reference to it
-    pub fn decode_frame(&mut self) -> io::Result<&Frame> {
-        let _ = try!(self.read_frame_header());
+fn dequantize(block: &mut [i32], first: usize, dcq: i16, acq: i16) {
+    for i in first..16 {
+        block[i] *= if i > 0 { acq } else { dcq } as i32;
+    }
+}
 
-        for mby in 0..self.mbheight as usize {
-            let p = mby % self.num_partitions as usize;
-            self.left = MacroBlock::new();
+// The reconstructed pixels of a macroblock, surrounded by the borders it was
+// predicted from, in the layout of create_border and create_border_chroma
+struct MacroBlockPixels {
+    y: [u8; 357],
+    u: [u8; 81],
+    v: [u8; 81],
+}
 
-            for mbx in 0..self.mbwidth as usize {
-                let (skip, mb) = self.read_macroblock_header(mbx);
-                let mut blocks = [0i32; 384];
+// The pixels left of the next macroblock of a row, each plane starts with
+// the pixel above them
+#[derive(Clone)]
+struct LeftBorder {
+    y: [u8; 1 + 16],
+    u: [u8; 1 + 8],
+    v: [u8; 1 + 8],
+}
 
-                if !skip {
-                    blocks = self.read_residual_data(&mb, mbx, p);
-                } else {
-                    if mb.luma_mode != B_PRED {
-                        self.left.complexity[0] = 0;
-                        self.top[mbx].complexity[0] = 0;
-                    }
+impl LeftBorder {
+    fn new() -> LeftBorder {
+        LeftBorder {
+            y: [129u8; 1 + 16],
+            u: [129u8; 1 + 8],
+            v: [129u8; 1 + 8],
+        }
+    }
 
-                    for i in 1usize..9 {
-                        self.left.complexity[i] = 0;
-                        self.top[mbx].complexity[i] = 0;
-                    }
-                }
+    // Takes the right column of the macroblock to the left
+    fn update(&mut self, pixels: &MacroBlockPixels) {
+        let stride = 1usize + 16 + 4;
+        self.y[0] = pixels.y[16];
+        for i in 0usize..16 {
+            self.y[i + 1] = pixels.y[(i + 1) * stride + 16];
+        }
+
+        let stride = 1usize + 8;
+        self.u[0] = pixels.u[8];
+        self.v[0] = pixels.v[8];
+        for i in 0usize..8 {
+            self.u[i + 1] = pixels.u[(i + 1) * stride + 8];
+            self.v[i + 1] = pixels.v[(i + 1) * stride + 8];
+        }
+    }
+}
+
+// Predicts a macroblock from the pixels around it and adds the residue in resdata.
+// top holds the bottom rows of the macroblocks above for each plane.
+fn reconstruct_macroblock(mbx: usize,
+                          mby: usize,
+                          mbw: usize,
+                          mb: &MacroBlock,
+                          mut resdata: [i32; 384],
+                          top: [&[u8]; 3],
+                          left: &LeftBorder) -> MacroBlockPixels {
+
+    for block in resdata.chunks_mut(16) {
+        if block.iter().any(|&c| c != 0) {
+            transform::idct4x4(block);
+        }
+    }
+
+    let stride = 1usize + 16 + 4;
+    let mut ws = create_border(mbx, mby, mbw, top[0], &left.y);
+
+    match mb.luma_mode {
+        V_PRED  => predict_vpred(&mut ws, 16, 1, 1, stride),
+        H_PRED  => predict_hpred(&mut ws, 16, 1, 1, stride),
+        TM_PRED => predict_tmpred(&mut ws, 16, 1, 1, stride),
+        DC_PRED => predict_dcpred(&mut ws, 16, stride, mby != 0, mbx != 0),
+        B_PRED  => predict_4x4(&mut ws, stride, &mb.bpred, &resdata),
+        _       => panic!("unknown luma intra prediction mode")
+    }
 
-                self.intra_predict(mbx, mby, &mb, &blocks);
+    if mb.luma_mode != B_PRED {
+        for y in 0usize..4 {
+            for x in 0usize..4 {
+                let i  = x + y * 4;
+                let rb = &resdata[i * 16..i * 16 + 16];
+                let y0 = 1 + y * 4;
+                let x0 = 1 + x * 4;
+
+                add_residue(&mut ws, rb, y0, x0, stride);
             }
+        }
+    }
+
+    let stride = 1usize + 8;
+    let mut uws = create_border_chroma(mbx, mby, top[1], &left.u);
+    let mut vws = create_border_chroma(mbx, mby, top[2], &left.v);
 
-            self.left_border = vec![129u8; 1 + 16];
+    match mb.chroma_mode {
+        DC_PRED => {
+            predict_dcpred(&mut uws, 8, stride, mby != 0, mbx != 0);
+            predict_dcpred(&mut vws, 8, stride, mby != 0, mbx != 0);
+        }
+        V_PRED => {
+            predict_vpred(&mut uws, 8, 1, 1, stride);
+            predict_vpred(&mut vws, 8, 1, 1, stride);
+        }
+        H_PRED => {
+            predict_hpred(&mut uws, 8, 1, 1, stride);
+            predict_hpred(&mut vws, 8, 1, 1, stride);
         }
+        TM_PRED => {
+            predict_tmpred(&mut uws, 8, 1, 1, stride);
+            predict_tmpred(&mut vws, 8, 1, 1, stride);
+        }
+        _ => panic!("unknown chroma intra prediction mode")
+    }
 
-        Ok(&self.frame)
+    // Blocks 16 to 19 hold the U residue, 20 to 23 the V residue
+    for y in 0usize..2 {
+        for x in 0usize..2 {
+            let i  = x + y * 2;
+            let ub = &resdata[(16 + i) * 16..(16 + i) * 16 + 16];
+            let vb = &resdata[(20 + i) * 16..(20 + i) * 16 + 16];
+            let y0 = 1 + y * 4;
+            let x0 = 1 + x * 4;
+
+            add_residue(&mut uws, ub, y0, x0, stride);
+            add_residue(&mut vws, vb, y0, x0, stride);
+        }
     }
+
+    MacroBlockPixels {
+        y: ws,
+        u: uws,
+        v: vws,
+    }
+}
+
+// Copies rows of a padded plane to out, cropped to width
+fn copy_rows(plane: &[u8], stride: usize, width: usize, rows: Range<usize>, out: &mut Vec<u8>) {
+    out.clear();
+    for row in plane.chunks(stride).skip(rows.start).take(rows.end - rows.start) {
+        out.extend_from_slice(&row[..width]);
+    }
+}
+
+// Moves the last rows of a plane to its top
+fn keep_last_rows(plane: &mut [u8], stride: usize, rows: usize) {
+    let len = plane.len();
+    let (top, bottom) = plane.split_at_mut(len - rows * stride);
+    top[..rows * stride].copy_from_slice(bottom);
 }
 
 fn init_top_macroblocks(width: usize) -> Vec<MacroBlock> {
@@ -1498,6 +3301,47 @@ fn create_border(mbx: usize, mby: usize, mbw: usize, top: &[u8], left: &[u8]) ->
     ws
 }
 
+fn create_border_chroma(mbx: usize, mby: usize, top: &[u8], left: &[u8]) -> [u8; 81] {
+    let stride = 1usize + 8;
+    let mut ws = [0u8; (1 + 8) * (1 + 8)];
+
+    // A
+    {
+        let above = &mut ws[1..stride];
+        if mby == 0 {
+            for i in 0usize..above.len() {
+                above[i] = 127;
+            }
+        } else {
+            for i in 0usize..8 {
+                above[i] = top[mbx * 8 + i];
+            }
+        }
+    }
+
+    // L
+    if mbx == 0 {
+        for i in 0usize..8 {
+            ws[(i + 1) * stride] = 129;
+        }
+    } else {
+        for i in 0usize..8 {
+            ws[(i + 1) * stride] = left[i + 1];
+        }
+    }
+
+    // P
+    ws[0] = if mby == 0 {
+        127
+    } else if mbx == 0 {
+        129
+    } else {
+        left[0]
+    };
+
+    ws
+}
+
 fn avg3(left: u8, this: u8, right: u8) -> u8 {
     let avg = (left as u16 + 2 * this as u16 + right as u16 + 2) >> 2;
     avg as u8
@@ -1841,3 +3685,298 @@ fn predict_bhupred(a: &mut [u8], x0: usize, y0: usize, stride: usize) {
     a[(y0 + 3) * stride + x0 + 2] = l3;
     a[(y0 + 3) * stride + x0 + 3] = l3;
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    #[cfg(benchmarks)]
+    use test;
+
+    use image::ImageError;
+    use super::{BoolReader, BoolWriter, VP8Decoder, DCT_TOKEN_TREE, COEFF_PROBS};
+
+    #[test]
+    fn bool_coder_round_trip() {
+        let mut w = BoolWriter::new();
+        let probs = &COEFF_PROBS[1][0][0];
+
+        for i in 0usize..1000 {
+            w.write_bool(i % 3 == 0, (i * 37 % 255) as u8 + 1);
+            w.write_literal(7, (i % 128) as u8);
+            w.write_with_tree(&DCT_TOKEN_TREE, probs, 0, (i % 12) as i8);
+        }
+
+        let mut r = BoolReader::new();
+        r.init(w.finish());
+
+        for i in 0usize..1000 {
+            assert_eq!(r.read_bool((i * 37 % 255) as u8 + 1), (i % 3 == 0) as u8);
+            assert_eq!(r.read_literal(7), (i % 128) as u8);
+            assert_eq!(r.read_with_tree(&DCT_TOKEN_TREE, probs, 0), (i % 12) as i8);
+        }
+    }
+
+    #[test]
+    fn bool_reader_resumes() {
+        let mut w = BoolWriter::new();
+        let probs = &COEFF_PROBS[1][0][0];
+
+        for i in 0usize..1000 {
+            w.write_bool(i % 3 == 0, (i * 37 % 255) as u8 + 1);
+            w.write_with_tree(&DCT_TOKEN_TREE, probs, 0, (i % 12) as i8);
+        }
+        let data = w.finish();
+
+        // The partition arrives a few bytes at a time, starting with less than
+        // the reader needs to start
+        let mut parts = data[1..].chunks(7);
+        let mut r = BoolReader::new();
+        r.init_partial(data[..1].to_vec());
+        assert!(r.starved());
+
+        let mut i = 0;
+        let mut retries = 0;
+        while i < 1000 {
+            let state = r.state();
+            let values = (r.read_bool((i * 37 % 255) as u8 + 1), r.read_with_tree(&DCT_TOKEN_TREE, probs, 0));
+
+            if r.starved() {
+                r.restore(state);
+                match parts.next() {
+                    Some(part) => r.extend(part),
+                    None => r.finish(),
+                }
+                retries += 1;
+                continue
+            }
+
+            assert_eq!(values, ((i % 3 == 0) as u8, (i % 12) as i8), "value {}", i);
+            i += 1;
+        }
+
+        assert!(retries >= data.len() / 7);
+        assert!(r.check_eof().is_ok());
+        assert_eq!(r.state().index, {
+            let mut whole = BoolReader::new();
+            whole.init(data.clone());
+            for i in 0usize..1000 {
+                whole.read_bool((i * 37 % 255) as u8 + 1);
+                whole.read_with_tree(&DCT_TOKEN_TREE, probs, 0);
+            }
+            whole.state().index
+        });
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let vp8 = &data[start..];
+
+        assert!(VP8Decoder::new(Cursor::new(vp8)).decode_frame().is_ok());
+
+        for len in (0..vp8.len() * 9 / 10).filter(|len| len % 97 == 0) {
+            assert!(VP8Decoder::new(Cursor::new(&vp8[..len])).decode_frame().is_err());
+        }
+    }
+
+    #[test]
+    fn empty_frame() {
+        let data = include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+
+        // A width or height of zero, keeping the scale hints in the top bits
+        for &(offset, field) in [(6, [0x00, 0x00]), (8, [0x00, 0x40]), (6, [0x00, 0xc0])].iter() {
+            let mut vp8 = data[start..].to_vec();
+            vp8[offset..offset + 2].copy_from_slice(&field);
+
+            let mut decoder = VP8Decoder::new(Cursor::new(&vp8[..]));
+            match decoder.decode_frame() {
+                Err(ImageError::FormatError(msg)) => assert_eq!(msg, "VP8: frame is empty"),
+                _ => panic!("expected a format error"),
+            }
+        }
+    }
+
+    #[test]
+    fn plane_layout() {
+        // 400x301 and 386x395, not a whole number of macroblocks
+        let images: [&[u8]; 2] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp"),
+        ];
+
+        for data in images.iter() {
+            let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+            let mut decoder = VP8Decoder::new(Cursor::new(&data[start..]));
+            let frame = decoder.decode_frame().unwrap();
+
+            let (width, height) = (frame.width as usize, frame.height as usize);
+            assert!(width % 16 != 0 || height % 16 != 0);
+            assert_eq!(frame.luma_stride(), width);
+            assert_eq!(frame.chroma_stride(), (width + 1) / 2);
+            assert_eq!(frame.ybuf.len(), width * height);
+            assert_eq!(frame.ubuf.len(), frame.chroma_stride() * (height + 1) / 2);
+            assert_eq!(frame.vbuf.len(), frame.ubuf.len());
+
+            let (last, chroma_last) = (height - 1, frame.chroma_height() as usize - 1);
+            assert!(frame.y_row(last) == &frame.ybuf[last * width..]);
+            assert!(frame.u_row(chroma_last) == &frame.ubuf[chroma_last * frame.chroma_stride()..]);
+            assert!(frame.v_row(0) == &frame.vbuf[..frame.chroma_stride()]);
+        }
+    }
+
+    #[test]
+    fn decode_by_rows() {
+        let images: [&[u8]; 3] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/3_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp"),
+        ];
+
+        for data in images.iter() {
+            let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+            let vp8 = &data[start..];
+
+            let frame = VP8Decoder::new(Cursor::new(vp8)).decode_frame().unwrap().clone();
+            let (width, cw) = (frame.width as usize, frame.chroma_width() as usize);
+
+            let mut decoder = VP8Decoder::new(Cursor::new(vp8));
+            let (mut y, mut u, mut v) = (Vec::new(), Vec::new(), Vec::new());
+            decoder.decode_frame_rows(|row, ys, us, vs| {
+                // The bands follow each other and cover the same rows of each plane
+                assert_eq!(row * width, y.len());
+                assert_eq!(row / 2 * cw, u.len());
+                assert_eq!((ys.len() / width + 1) / 2, us.len() / cw);
+
+                y.extend_from_slice(ys);
+                u.extend_from_slice(us);
+                v.extend_from_slice(vs);
+            }).unwrap();
+
+            assert!(y == frame.ybuf);
+            assert!(u == frame.ubuf);
+            assert!(v == frame.vbuf);
+
+            // Only a few rows are held
+            assert_eq!(decoder.ybuf.len(), (width + 15) / 16 * 16 * 24);
+            assert!(decoder.frame.ybuf.is_empty());
+        }
+    }
+
+    #[test]
+    fn decode_top_rows() {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let vp8 = &data[start..];
+
+        let frame = VP8Decoder::new(Cursor::new(vp8)).decode_frame().unwrap().clone();
+        let width = frame.width as usize;
+
+        for &max_rows in [1, 8, 9, 100, 300, 301, 1000].iter() {
+            let mut y = Vec::new();
+            VP8Decoder::new(Cursor::new(vp8)).decode_frame_rows_up_to(max_rows, |_, ys, _, _| {
+                y.extend_from_slice(ys);
+            }).unwrap();
+
+            // The band reaching the row ends it, the rows so far are final
+            let rows = y.len() / width;
+            assert!(rows >= max_rows.min(301) && rows < max_rows.min(301) + 16);
+            assert!(y[..] == frame.ybuf[..y.len()]);
+        }
+
+        // The rest of the frame is not read
+        let mut y = Vec::new();
+        VP8Decoder::new(Cursor::new(&vp8[..vp8.len() / 2])).decode_frame_rows_up_to(8, |_, ys, _, _| {
+            y.extend_from_slice(ys);
+        }).unwrap();
+        assert_eq!(y.len(), 8 * width);
+    }
+
+    #[test]
+    fn decode_partial_frame() {
+        let images: [&[u8]; 2] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/odd_size.webp"),
+        ];
+
+        for data in images.iter() {
+            let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+            let vp8 = &data[start..];
+            let first_partition_size = (vp8[0] as usize | (vp8[1] as usize) << 8 | (vp8[2] as usize) << 16) >> 5;
+            let header = 10 + first_partition_size;
+
+            let frame = VP8Decoder::new(Cursor::new(vp8)).decode_frame().unwrap().clone();
+
+            // The first partition is needed to start
+            let mut decoder = VP8Decoder::new(Cursor::new(&vp8[..header - 1]));
+            match decoder.start_frame_partial() {
+                Err(ImageError::NotEnoughData) => (),
+                _ => panic!("started without the first partition"),
+            }
+
+            for &size in [3, 100].iter() {
+                let mut decoder = VP8Decoder::new(Cursor::new(&vp8[..header]));
+                let mut rows = decoder.start_frame_partial().unwrap();
+
+                for part in vp8[header..].chunks(size) {
+                    let more = decoder.extend_frame(part).unwrap();
+                    assert!(more >= rows);
+                    rows = more;
+                }
+
+                // All but the last row may be decoded before the partitions end
+                assert!(rows + 1 >= decoder.mbheight as usize);
+
+                let partial = decoder.finish_frame_partial().unwrap();
+                assert!(partial.ybuf == frame.ybuf);
+                assert!(partial.ubuf == frame.ubuf);
+                assert!(partial.vbuf == frame.vbuf);
+            }
+
+            // A frame cut short only fails once it is finished
+            let mut decoder = VP8Decoder::new(Cursor::new(&vp8[..header]));
+            let _ = decoder.start_frame_partial().unwrap();
+            let rows = decoder.extend_frame(&vp8[header..(header + vp8.len()) / 2]).unwrap();
+            assert!(rows < decoder.mbheight as usize);
+            assert!(decoder.finish_frame_partial().is_err());
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_reconstruction() {
+        let images: [&[u8]; 3] = [
+            include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/2_webp_a.webp"),
+            include_bytes!("../../tests/images/webp/lossless/4_webp_a.webp"),
+        ];
+
+        for data in images.iter() {
+            let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+            let vp8 = &data[start..];
+
+            let serial = VP8Decoder::new(Cursor::new(vp8)).decode_frame().unwrap().clone();
+            let mut decoder = VP8Decoder::new(Cursor::new(vp8));
+            let parallel = decoder.decode_frame_parallel().unwrap();
+
+            assert!(serial.ybuf == parallel.ybuf);
+            assert!(serial.ubuf == parallel.ubuf);
+            assert!(serial.vbuf == parallel.vbuf);
+        }
+    }
+
+    #[bench]
+    #[cfg(benchmarks)]
+    fn bench_decode_frame(b: &mut test::Bencher) {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_a.webp");
+        let start = data.windows(4).position(|w| w == b"VP8 ").unwrap() + 8;
+        let vp8 = &data[start..];
+
+        b.iter(|| {
+            let mut decoder = VP8Decoder::new(Cursor::new(vp8));
+            test::black_box(decoder.decode_frame().unwrap().ybuf.len());
+        });
+        b.bytes = vp8.len() as u64;
+    }
+}
@@ -0,0 +1,1207 @@
+//! An implementation of the WebP lossless (VP8L) image format
+//!
+//! VP8L compresses ARGB images with a combination of LZ77 backward references,
+//! a cache of recently used colors and canonical prefix (Huffman) codes. Before
+//! compression the encoder may apply up to four reversible transforms to the
+//! pixels, which the decoder inverts in reverse order.
+//!
+//! # Related Links
+//! * [webp-lossless-bitstream-spec](https://developers.google.com/speed/webp/docs/webp_lossless_bitstream_specification) - The WebP Lossless Bitstream Specification
+//!
+
+use std::io::Read;
+use std::default::Default;
+
+use image::{ImageError, ImageResult};
+
+const VP8L_SIGNATURE: u8 = 0x2f;
+
+const NUM_LITERAL_CODES: u16 = 256;
+const NUM_LENGTH_CODES: u16 = 24;
+const NUM_DISTANCE_CODES: u16 = 40;
+const MAX_COLOR_CACHE_BITS: u32 = 11;
+
+// Indices of the prefix codes within a group
+const GREEN: usize = 0;
+const RED: usize = 1;
+const BLUE: usize = 2;
+const ALPHA: usize = 3;
+const DIST: usize = 4;
+
+static ALPHABET_SIZE: [u16; 5] = [
+    NUM_LITERAL_CODES + NUM_LENGTH_CODES,
+    NUM_LITERAL_CODES,
+    NUM_LITERAL_CODES,
+    NUM_LITERAL_CODES,
+    NUM_DISTANCE_CODES
+];
+
+// The order in which the code lengths of the code length code are stored
+static CODE_LENGTH_CODE_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+// Code lengths used by the repeat codes 16, 17 and 18 respectively
+static CODE_LENGTH_EXTRA_BITS: [u8; 3] = [2, 3, 7];
+static CODE_LENGTH_REPEAT_OFFSETS: [usize; 3] = [3, 3, 11];
+
+// Section 4.2.2: (xi, yi) offsets of the 120 short distance codes,
+// the resulting distance is xi + yi * xsize.
+static DISTANCE_MAP: [(i8, i8); 120] = [
+    (0, 1),  (1, 0),  (1, 1),  (-1, 1), (0, 2),  (2, 0),  (1, 2),  (-1, 2),
+    (2, 1),  (-2, 1), (2, 2),  (-2, 2), (0, 3),  (3, 0),  (1, 3),  (-1, 3),
+    (3, 1),  (-3, 1), (2, 3),  (-2, 3), (3, 2),  (-3, 2), (0, 4),  (4, 0),
+    (1, 4),  (-1, 4), (4, 1),  (-4, 1), (3, 3),  (-3, 3), (2, 4),  (-2, 4),
+    (4, 2),  (-4, 2), (0, 5),  (3, 4),  (-3, 4), (4, 3),  (-4, 3), (5, 0),
+    (1, 5),  (-1, 5), (5, 1),  (-5, 1), (2, 5),  (-2, 5), (5, 2),  (-5, 2),
+    (4, 4),  (-4, 4), (3, 5),  (-3, 5), (5, 3),  (-5, 3), (0, 6),  (6, 0),
+    (1, 6),  (-1, 6), (6, 1),  (-6, 1), (2, 6),  (-2, 6), (6, 2),  (-6, 2),
+    (4, 5),  (-4, 5), (5, 4),  (-5, 4), (3, 6),  (-3, 6), (6, 3),  (-6, 3),
+    (0, 7),  (7, 0),  (1, 7),  (-1, 7), (5, 5),  (-5, 5), (7, 1),  (-7, 1),
+    (4, 6),  (-4, 6), (6, 4),  (-6, 4), (2, 7),  (-2, 7), (7, 2),  (-7, 2),
+    (3, 7),  (-3, 7), (7, 3),  (-7, 3), (5, 6),  (-5, 6), (6, 5),  (-6, 5),
+    (8, 0),  (4, 7),  (-4, 7), (7, 4),  (-7, 4), (8, 1),  (8, 2),  (6, 6),
+    (-6, 6), (8, 3),  (5, 7),  (-5, 7), (7, 5),  (-7, 5), (8, 4),  (6, 7),
+    (-6, 7), (7, 6),  (-7, 6), (8, 5),  (7, 7),  (-7, 7), (8, 6),  (8, 7)
+];
+
+// Number of bits resolved by a single lookup when decoding prefix codes
+const TABLE_BITS: u8 = 8;
+const MAX_CODE_LENGTH: usize = 15;
+
+fn format_error(msg: &str) -> ImageError {
+    ImageError::FormatError(format!("VP8L: {}", msg))
+}
+
+// Reads bits least significant bit first as required by the specification
+struct BitReader {
+    buf: Vec<u8>,
+    index: usize,
+    value: u64,
+    nbits: u8,
+}
+
+impl BitReader {
+    fn new() -> BitReader {
+        BitReader {buf: Vec::new(), index: 0, value: 0, nbits: 0}
+    }
+
+    fn init(&mut self, buf: Vec<u8>) {
+        self.buf = buf;
+        self.index = 0;
+        self.value = 0;
+        self.nbits = 0;
+    }
+
+    fn fill(&mut self) {
+        while self.nbits <= 56 && self.index < self.buf.len() {
+            self.value |= (self.buf[self.index] as u64) << self.nbits;
+            self.index += 1;
+            self.nbits += 8;
+        }
+    }
+
+    // Returns the next n bits without consuming them.
+    // Past the end of the data the missing bits read as zero.
+    fn peek(&mut self, n: u8) -> u32 {
+        if self.nbits < n {
+            self.fill();
+        }
+
+        (self.value & ((1u64 << n) - 1)) as u32
+    }
+
+    fn consume(&mut self, n: u8) -> ImageResult<()> {
+        if self.nbits < n {
+            return Err(format_error("unexpected end of data"))
+        }
+
+        self.value >>= n;
+        self.nbits -= n;
+
+        Ok(())
+    }
+
+    fn read_bits(&mut self, n: u8) -> ImageResult<u32> {
+        let v = self.peek(n);
+        try!(self.consume(n));
+
+        Ok(v)
+    }
+}
+
+// A canonical prefix code
+struct HuffmanTree {
+    // Number of codes of each length
+    counts: [u16; MAX_CODE_LENGTH + 1],
+
+    // Symbols ordered by their code
+    symbols: Vec<u16>,
+
+    // (symbol, code length) for every combination of the next TABLE_BITS bits.
+    // A length of zero marks codes longer than TABLE_BITS.
+    table: Vec<(u16, u8)>,
+}
+
+impl HuffmanTree {
+    fn from_code_lengths(code_lengths: &[u8]) -> ImageResult<HuffmanTree> {
+        let mut counts = [0u16; MAX_CODE_LENGTH + 1];
+        for &len in code_lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let num_symbols: u16 = counts.iter().sum();
+        if num_symbols == 0 {
+            return Err(format_error("prefix code without symbols"))
+        }
+
+        let mut symbols = Vec::with_capacity(num_symbols as usize);
+        for len in 1..MAX_CODE_LENGTH + 1 {
+            for (symbol, &l) in code_lengths.iter().enumerate() {
+                if l as usize == len {
+                    symbols.push(symbol as u16);
+                }
+            }
+        }
+
+        // A single symbol is decoded without reading any bits
+        if num_symbols == 1 {
+            return Ok(HuffmanTree {
+                counts: counts,
+                symbols: symbols,
+                table: Vec::new(),
+            })
+        }
+
+        // Apart from the single symbol case the code must be complete
+        let mut left = 1i32;
+        for len in 1..MAX_CODE_LENGTH + 1 {
+            left <<= 1;
+            left -= counts[len] as i32;
+            if left < 0 {
+                return Err(format_error("over-subscribed prefix code"))
+            }
+        }
+
+        if left != 0 {
+            return Err(format_error("incomplete prefix code"))
+        }
+
+        let mut table = vec![(0u16, 0u8); 1 << TABLE_BITS];
+        let mut code = 0usize;
+        let mut index = 0usize;
+
+        for len in 1..TABLE_BITS as usize + 1 {
+            for _ in 0..counts[len] {
+                // Codes are stored most significant bit first
+                let mut reversed = 0;
+                for i in 0..len {
+                    reversed |= ((code >> i) & 1) << (len - 1 - i);
+                }
+
+                let mut j = reversed;
+                while j < table.len() {
+                    table[j] = (symbols[index], len as u8);
+                    j += 1 << len;
+                }
+
+                code += 1;
+                index += 1;
+            }
+
+            code <<= 1;
+        }
+
+        Ok(HuffmanTree {
+            counts: counts,
+            symbols: symbols,
+            table: table,
+        })
+    }
+
+    fn read_symbol(&self, br: &mut BitReader) -> ImageResult<u16> {
+        if self.symbols.len() == 1 {
+            return Ok(self.symbols[0])
+        }
+
+        let (symbol, len) = self.table[br.peek(TABLE_BITS) as usize];
+        if len > 0 {
+            try!(br.consume(len));
+            return Ok(symbol)
+        }
+
+        // Walk the code one bit at a time
+        let mut code = 0i32;
+        let mut first = 0i32;
+        let mut index = 0i32;
+
+        for len in 1..MAX_CODE_LENGTH + 1 {
+            code |= try!(br.read_bits(1)) as i32;
+
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + code - first) as usize])
+            }
+
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+
+        Err(format_error("invalid prefix code"))
+    }
+}
+
+// The five prefix codes used to decode a region of the image
+type HuffmanGroup = Vec<HuffmanTree>;
+
+struct ColorCache {
+    bits: u32,
+    colors: Vec<u32>,
+}
+
+impl ColorCache {
+    fn new(bits: u32) -> ColorCache {
+        ColorCache {
+            bits: bits,
+            colors: vec![0u32; 1 << bits],
+        }
+    }
+
+    fn insert(&mut self, argb: u32) {
+        let key = 0x1e35a7bdu32.wrapping_mul(argb) >> (32 - self.bits);
+        self.colors[key as usize] = argb;
+    }
+
+    fn lookup(&self, key: usize) -> u32 {
+        self.colors[key]
+    }
+}
+
+enum Transform {
+    Predictor {
+        bits: u8,
+        data: Vec<u32>,
+    },
+
+    CrossColor {
+        bits: u8,
+        data: Vec<u32>,
+    },
+
+    SubtractGreen,
+
+    ColorIndexing {
+        bits: u8,
+        palette: Vec<u32>,
+    },
+}
+
+/// A decoded lossless image
+#[derive(Default, Debug, Clone)]
+pub struct LosslessFrame {
+    /// The width of the image
+    pub width: u16,
+
+    /// The height of the image
+    pub height: u16,
+
+    /// The pixels of the image as ARGB values, one ```u32``` per pixel
+    pub buf: Vec<u32>,
+}
+
+/// VP8L Decoder
+pub struct VP8LDecoder<R> {
+    r: R,
+    br: BitReader,
+
+    frame: LosslessFrame,
+
+    // The transforms along with the width of the image they apply to,
+    // in the order they appear in the bitstream.
+    transforms: Vec<(Transform, usize)>,
+}
+
+impl<R: Read> VP8LDecoder<R> {
+    /// Create a new decoder.
+    /// The reader must present the contents of a VP8L chunk to the decoder
+    pub fn new(r: R) -> VP8LDecoder<R> {
+        VP8LDecoder {
+            r: r,
+            br: BitReader::new(),
+
+            frame: Default::default(),
+
+            transforms: Vec::new(),
+        }
+    }
+
+    fn read_data(&mut self) -> ImageResult<()> {
+        let mut buf = Vec::new();
+        try!(self.r.read_to_end(&mut buf));
+        self.br.init(buf);
+
+        Ok(())
+    }
+
+    fn read_header(&mut self) -> ImageResult<()> {
+        let signature = try!(self.br.read_bits(8)) as u8;
+        if signature != VP8L_SIGNATURE {
+            return Err(format_error("invalid signature"))
+        }
+
+        self.frame.width = try!(self.br.read_bits(14)) as u16 + 1;
+        self.frame.height = try!(self.br.read_bits(14)) as u16 + 1;
+
+        let _alpha_is_used = try!(self.br.read_bits(1));
+
+        let version = try!(self.br.read_bits(3));
+        if version != 0 {
+            return Err(format_error("unknown version"))
+        }
+
+        Ok(())
+    }
+
+    fn read_transform(&mut self, xsize: &mut usize, ysize: usize) -> ImageResult<()> {
+        let kind = try!(self.br.read_bits(2));
+
+        // Each transform may only be used once
+        for t in self.transforms.iter().map(|t| &t.0) {
+            let seen = match *t {
+                Transform::Predictor { .. } => 0,
+                Transform::CrossColor { .. } => 1,
+                Transform::SubtractGreen => 2,
+                Transform::ColorIndexing { .. } => 3,
+            };
+
+            if seen == kind {
+                return Err(format_error("transform used more than once"))
+            }
+        }
+
+        let width = *xsize;
+        let transform = match kind {
+            0 | 1 => {
+                let bits = try!(self.br.read_bits(3)) as u8 + 2;
+                let data = try!(self.decode_image_stream(
+                    subsample_size(width, bits), subsample_size(ysize, bits), false));
+
+                if kind == 0 {
+                    Transform::Predictor { bits: bits, data: data }
+                } else {
+                    Transform::CrossColor { bits: bits, data: data }
+                }
+            }
+
+            2 => Transform::SubtractGreen,
+
+            _ => {
+                let num_colors = try!(self.br.read_bits(8)) as usize + 1;
+                let bits = if num_colors > 16 { 0 }
+                           else if num_colors > 4 { 1 }
+                           else if num_colors > 2 { 2 }
+                           else { 3 };
+
+                *xsize = subsample_size(width, bits);
+
+                let colors = try!(self.decode_image_stream(num_colors, 1, false));
+
+                // The palette is delta coded, entries past its end are transparent black
+                let mut palette = vec![0u32; 1 << (8 >> bits)];
+                palette[0] = colors[0];
+                for i in 1..num_colors {
+                    palette[i] = add_pixels(colors[i], palette[i - 1]);
+                }
+
+                Transform::ColorIndexing { bits: bits, palette: palette }
+            }
+        };
+
+        self.transforms.push((transform, width));
+
+        Ok(())
+    }
+
+    fn read_code_lengths(&mut self, code_lengths: &mut [u8]) -> ImageResult<()> {
+        let num_symbols = code_lengths.len();
+
+        let mut code_length_code_lengths = [0u8; 19];
+        let num_code_lengths = 4 + try!(self.br.read_bits(4)) as usize;
+        for i in 0..num_code_lengths {
+            code_length_code_lengths[CODE_LENGTH_CODE_ORDER[i]] = try!(self.br.read_bits(3)) as u8;
+        }
+
+        let tree = try!(HuffmanTree::from_code_lengths(&code_length_code_lengths));
+
+        let mut max_symbol = if try!(self.br.read_bits(1)) == 1 {
+            let length_nbits = 2 + 2 * try!(self.br.read_bits(3)) as u8;
+            let max_symbol = 2 + try!(self.br.read_bits(length_nbits)) as usize;
+
+            if max_symbol > num_symbols {
+                return Err(format_error("invalid code length count"))
+            }
+
+            max_symbol
+        } else {
+            num_symbols
+        };
+
+        let mut prev_code_len = 8u8;
+        let mut symbol = 0;
+
+        while symbol < num_symbols {
+            if max_symbol == 0 {
+                break
+            }
+            max_symbol -= 1;
+
+            let code_len = try!(tree.read_symbol(&mut self.br)) as u8;
+
+            if code_len < 16 {
+                code_lengths[symbol] = code_len;
+                symbol += 1;
+
+                if code_len != 0 {
+                    prev_code_len = code_len;
+                }
+            } else {
+                let slot = code_len as usize - 16;
+                let extra = try!(self.br.read_bits(CODE_LENGTH_EXTRA_BITS[slot])) as usize;
+                let repeat = extra + CODE_LENGTH_REPEAT_OFFSETS[slot];
+
+                if symbol + repeat > num_symbols {
+                    return Err(format_error("code length repeat past the end of the alphabet"))
+                }
+
+                let len = if code_len == 16 { prev_code_len } else { 0 };
+                for _ in 0..repeat {
+                    code_lengths[symbol] = len;
+                    symbol += 1;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn read_huffman_code(&mut self, alphabet_size: usize) -> ImageResult<HuffmanTree> {
+        let mut code_lengths = vec![0u8; alphabet_size];
+
+        let simple = try!(self.br.read_bits(1)) == 1;
+        if simple {
+            let num_symbols = try!(self.br.read_bits(1)) + 1;
+            let first_symbol_bits = 1 + 7 * try!(self.br.read_bits(1)) as u8;
+
+            let mut symbols = vec![try!(self.br.read_bits(first_symbol_bits)) as usize];
+            if num_symbols == 2 {
+                symbols.push(try!(self.br.read_bits(8)) as usize);
+            }
+
+            for &s in symbols.iter() {
+                if s >= alphabet_size {
+                    return Err(format_error("symbol outside of the alphabet"))
+                }
+
+                code_lengths[s] = 1;
+            }
+        } else {
+            try!(self.read_code_lengths(&mut code_lengths));
+        }
+
+        HuffmanTree::from_code_lengths(&code_lengths)
+    }
+
+    fn read_huffman_group(&mut self, color_cache_size: usize) -> ImageResult<HuffmanGroup> {
+        let mut group = Vec::with_capacity(5);
+
+        for (i, &size) in ALPHABET_SIZE.iter().enumerate() {
+            let alphabet_size = if i == GREEN { size as usize + color_cache_size }
+                                else { size as usize };
+
+            group.push(try!(self.read_huffman_code(alphabet_size)));
+        }
+
+        Ok(group)
+    }
+
+    fn decode_image_stream(&mut self, xsize: usize, ysize: usize, is_level0: bool) -> ImageResult<Vec<u32>> {
+        let mut xsize = xsize;
+
+        if is_level0 {
+            while try!(self.br.read_bits(1)) == 1 {
+                try!(self.read_transform(&mut xsize, ysize));
+            }
+        }
+
+        let color_cache_bits = if try!(self.br.read_bits(1)) == 1 {
+            let bits = try!(self.br.read_bits(4));
+            if bits < 1 || bits > MAX_COLOR_CACHE_BITS {
+                return Err(format_error("invalid color cache size"))
+            }
+
+            Some(bits)
+        } else {
+            None
+        };
+
+        // The entropy image selects the prefix code group for each block of the image,
+        // only the main image may use one.
+        let mut huffman_bits = 0u8;
+        let mut huffman_image = Vec::new();
+        let mut num_groups = 1;
+
+        if is_level0 && try!(self.br.read_bits(1)) == 1 {
+            huffman_bits = try!(self.br.read_bits(3)) as u8 + 2;
+            huffman_image = try!(self.decode_image_stream(
+                subsample_size(xsize, huffman_bits), subsample_size(ysize, huffman_bits), false));
+
+            for p in huffman_image.iter_mut() {
+                // The group index is stored in the red and green channels
+                *p = (*p >> 8) & 0xffff;
+                if *p as usize >= num_groups {
+                    num_groups = *p as usize + 1;
+                }
+            }
+        }
+
+        let color_cache_size = color_cache_bits.map(|b| 1usize << b).unwrap_or(0);
+
+        let mut groups = Vec::with_capacity(num_groups);
+        for _ in 0..num_groups {
+            groups.push(try!(self.read_huffman_group(color_cache_size)));
+        }
+
+        let mut data = vec![0u32; xsize * ysize];
+        let mut cache = color_cache_bits.map(ColorCache::new);
+
+        let huffman_xsize = subsample_size(xsize, huffman_bits);
+        let mut pos = 0;
+
+        while pos < data.len() {
+            let group = if huffman_bits == 0 {
+                &groups[0]
+            } else {
+                let x = pos % xsize;
+                let y = pos / xsize;
+                let i = (y >> huffman_bits) * huffman_xsize + (x >> huffman_bits);
+
+                &groups[huffman_image[i] as usize]
+            };
+
+            let code = try!(group[GREEN].read_symbol(&mut self.br));
+
+            if code < NUM_LITERAL_CODES {
+                let red = try!(group[RED].read_symbol(&mut self.br)) as u32;
+                let blue = try!(group[BLUE].read_symbol(&mut self.br)) as u32;
+                let alpha = try!(group[ALPHA].read_symbol(&mut self.br)) as u32;
+
+                data[pos] = (alpha << 24) | (red << 16) | ((code as u32) << 8) | blue;
+                if let Some(ref mut c) = cache {
+                    c.insert(data[pos]);
+                }
+
+                pos += 1;
+            } else if code < NUM_LITERAL_CODES + NUM_LENGTH_CODES {
+                let length = try!(self.read_copy_distance(code - NUM_LITERAL_CODES));
+
+                let dist_symbol = try!(group[DIST].read_symbol(&mut self.br));
+                let dist_code = try!(self.read_copy_distance(dist_symbol));
+                let dist = plane_code_to_distance(xsize, dist_code);
+
+                if dist > pos || length > data.len() - pos {
+                    return Err(format_error("backward reference outside of the image"))
+                }
+
+                for _ in 0..length {
+                    data[pos] = data[pos - dist];
+                    if let Some(ref mut c) = cache {
+                        c.insert(data[pos]);
+                    }
+
+                    pos += 1;
+                }
+            } else {
+                let key = (code - NUM_LITERAL_CODES - NUM_LENGTH_CODES) as usize;
+
+                data[pos] = match cache {
+                    Some(ref mut c) => {
+                        let argb = c.lookup(key);
+                        c.insert(argb);
+                        argb
+                    }
+                    None => return Err(format_error("color cache index without a color cache"))
+                };
+
+                pos += 1;
+            }
+        }
+
+        if is_level0 {
+            while let Some((transform, width)) = self.transforms.pop() {
+                data = apply_inverse_transform(&transform, data, width, ysize);
+            }
+        }
+
+        Ok(data)
+    }
+
+    // Section 4.2.2: length and distance prefix codes
+    fn read_copy_distance(&mut self, symbol: u16) -> ImageResult<usize> {
+        if symbol < 4 {
+            return Ok(symbol as usize + 1)
+        }
+
+        let extra_bits = (symbol as u8 - 2) >> 1;
+        let offset = (2 + (symbol as usize & 1)) << extra_bits;
+
+        Ok(offset + try!(self.br.read_bits(extra_bits)) as usize + 1)
+    }
+
+    /// Decodes the image and returns a reference to it
+    pub fn decode_frame(&mut self) -> ImageResult<&LosslessFrame> {
+        try!(self.read_data());
+        try!(self.read_header());
+
+        let width = self.frame.width as usize;
+        let height = self.frame.height as usize;
+
+        self.frame.buf = try!(self.decode_image_stream(width, height, true));
+
+        Ok(&self.frame)
+    }
+
+    /// Decodes an image stream that is not preceded by a VP8L header,
+    /// such as the compressed alpha plane of a lossy image.
+    /// The dimensions of the image must be supplied by the caller.
+    pub fn decode_headerless_frame(&mut self, width: u16, height: u16) -> ImageResult<&LosslessFrame> {
+        try!(self.read_data());
+
+        self.frame.width = width;
+        self.frame.height = height;
+        self.frame.buf = try!(self.decode_image_stream(width as usize, height as usize, true));
+
+        Ok(&self.frame)
+    }
+
+    /// Consumes the decoder and returns the frame decoded by ```decode_frame```
+    pub fn into_frame(self) -> LosslessFrame {
+        self.frame
+    }
+}
+
+fn subsample_size(size: usize, bits: u8) -> usize {
+    (size + (1 << bits) - 1) >> bits
+}
+
+fn plane_code_to_distance(xsize: usize, plane_code: usize) -> usize {
+    if plane_code > 120 {
+        plane_code - 120
+    } else {
+        let (xi, yi) = DISTANCE_MAP[plane_code - 1];
+        let dist = xi as isize + yi as isize * xsize as isize;
+
+        if dist < 1 { 1 } else { dist as usize }
+    }
+}
+
+fn apply_inverse_transform(transform: &Transform, data: Vec<u32>, width: usize, height: usize) -> Vec<u32> {
+    let mut data = data;
+
+    match *transform {
+        Transform::Predictor { bits, data: ref modes } => {
+            inverse_predictor(&mut data, width, height, bits, modes);
+        }
+
+        Transform::CrossColor { bits, data: ref elements } => {
+            inverse_cross_color(&mut data, width, height, bits, elements);
+        }
+
+        Transform::SubtractGreen => {
+            for p in data.iter_mut() {
+                let green = (*p >> 8) & 0xff;
+                let red = ((*p >> 16) + green) & 0xff;
+                let blue = ((*p & 0xff) + green) & 0xff;
+
+                *p = (*p & 0xff00ff00) | (red << 16) | blue;
+            }
+        }
+
+        Transform::ColorIndexing { bits, ref palette } => {
+            return inverse_color_indexing(&data, width, height, bits, palette)
+        }
+    }
+
+    data
+}
+
+// Adds each of the four channels separately, modulo 256
+fn add_pixels(a: u32, b: u32) -> u32 {
+    let alpha_green = (a & 0xff00ff00).wrapping_add(b & 0xff00ff00);
+    let red_blue = (a & 0x00ff00ff).wrapping_add(b & 0x00ff00ff);
+
+    (alpha_green & 0xff00ff00) | (red_blue & 0x00ff00ff)
+}
+
+fn channel(p: u32, shift: u32) -> i32 {
+    ((p >> shift) & 0xff) as i32
+}
+
+fn average2(a: u32, b: u32) -> u32 {
+    (((a ^ b) & 0xfefefefe) >> 1) + (a & b)
+}
+
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let mut pl = 0;
+    let mut pt = 0;
+
+    for &shift in [24, 16, 8, 0].iter() {
+        pl += (channel(top, shift) - channel(top_left, shift)).abs();
+        pt += (channel(left, shift) - channel(top_left, shift)).abs();
+    }
+
+    if pl < pt { left } else { top }
+}
+
+fn clamp_add_subtract_full(a: u32, b: u32, c: u32) -> u32 {
+    let mut p = 0;
+
+    for &shift in [24, 16, 8, 0].iter() {
+        let v = channel(a, shift) + channel(b, shift) - channel(c, shift);
+        p |= (clamp_channel(v) as u32) << shift;
+    }
+
+    p
+}
+
+fn clamp_add_subtract_half(a: u32, b: u32) -> u32 {
+    let mut p = 0;
+
+    for &shift in [24, 16, 8, 0].iter() {
+        let a = channel(a, shift);
+        let v = a + (a - channel(b, shift)) / 2;
+        p |= (clamp_channel(v) as u32) << shift;
+    }
+
+    p
+}
+
+fn clamp_channel(v: i32) -> u8 {
+    if v < 0 { 0 } else if v > 255 { 255 } else { v as u8 }
+}
+
+fn predict(mode: u32, left: u32, top: u32, top_left: u32, top_right: u32) -> u32 {
+    match mode {
+        1  => left,
+        2  => top,
+        3  => top_right,
+        4  => top_left,
+        5  => average2(average2(left, top_right), top),
+        6  => average2(left, top_left),
+        7  => average2(left, top),
+        8  => average2(top_left, top),
+        9  => average2(top, top_right),
+        10 => average2(average2(left, top_left), average2(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => clamp_add_subtract_full(left, top, top_left),
+        13 => clamp_add_subtract_half(average2(left, top), top_left),
+        // Mode 0 and the unused modes 14 and 15 predict opaque black
+        _  => 0xff000000,
+    }
+}
+
+fn inverse_predictor(data: &mut [u32], width: usize, height: usize, bits: u8, modes: &[u32]) {
+    let tiles_per_row = subsample_size(width, bits);
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = y * width + x;
+
+            let pred = if y == 0 && x == 0 {
+                0xff000000
+            } else if y == 0 {
+                data[i - 1]
+            } else if x == 0 {
+                data[i - width]
+            } else {
+                let mode = (modes[(y >> bits) * tiles_per_row + (x >> bits)] >> 8) & 0xf;
+
+                // The top right pixel of the rightmost column is the leftmost pixel
+                // of the current row, which is exactly what the memory layout gives.
+                predict(mode, data[i - 1], data[i - width], data[i - width - 1], data[i - width + 1])
+            };
+
+            data[i] = add_pixels(data[i], pred);
+        }
+    }
+}
+
+fn color_transform_delta(t: u8, c: u8) -> i32 {
+    (t as i8 as i32 * c as i8 as i32) >> 5
+}
+
+fn inverse_cross_color(data: &mut [u32], width: usize, height: usize, bits: u8, elements: &[u32]) {
+    let tiles_per_row = subsample_size(width, bits);
+
+    for y in 0..height {
+        for x in 0..width {
+            let element = elements[(y >> bits) * tiles_per_row + (x >> bits)];
+            let green_to_red = element as u8;
+            let green_to_blue = (element >> 8) as u8;
+            let red_to_blue = (element >> 16) as u8;
+
+            let p = data[y * width + x];
+            let green = (p >> 8) as u8;
+
+            let red = ((p >> 16) as i32 + color_transform_delta(green_to_red, green)) as u8;
+            let blue = (p as i32 + color_transform_delta(green_to_blue, green) +
+                        color_transform_delta(red_to_blue, red)) as u8;
+
+            data[y * width + x] = (p & 0xff00ff00) | ((red as u32) << 16) | blue as u32;
+        }
+    }
+}
+
+fn inverse_color_indexing(data: &[u32], width: usize, height: usize, bits: u8, palette: &[u32]) -> Vec<u32> {
+    let packed_width = subsample_size(width, bits);
+    let bits_per_pixel = 8 >> bits;
+    let mask = (1 << bits_per_pixel) - 1;
+
+    let mut out = vec![0u32; width * height];
+
+    for y in 0..height {
+        for x in 0..width {
+            let packed = (data[y * packed_width + (x >> bits)] >> 8) & 0xff;
+            let shift = (x & ((1 << bits) - 1)) * bits_per_pixel;
+            let index = (packed >> shift) & mask;
+
+            out[y * width + x] = palette[index as usize];
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitReader, ColorCache, HuffmanTree, Transform, VP8LDecoder, apply_inverse_transform,
+                plane_code_to_distance, predict};
+
+    #[test]
+    fn huffman_tree_canonical_codes() {
+        // Lengths 2, 1, 3, 3 give the codes 10, 0, 110 and 111
+        let tree = HuffmanTree::from_code_lengths(&[2, 1, 3, 3]).unwrap();
+
+        // The codes packed least significant bit first: 0, 10, 111, 110
+        let mut br = BitReader::new();
+        br.init(vec![0b1111_1010, 0b0000_0000]);
+
+        let symbols: Vec<u16> = (0..4).map(|_| tree.read_symbol(&mut br).unwrap()).collect();
+        assert_eq!(symbols, vec![1, 0, 3, 2]);
+    }
+
+    #[test]
+    fn huffman_tree_rejects_invalid_codes() {
+        assert!(HuffmanTree::from_code_lengths(&[0, 0, 0]).is_err());
+        assert!(HuffmanTree::from_code_lengths(&[1, 1, 1]).is_err());
+        assert!(HuffmanTree::from_code_lengths(&[1, 2]).is_err());
+
+        // A single symbol is valid and consumes no bits
+        let tree = HuffmanTree::from_code_lengths(&[0, 4, 0]).unwrap();
+        let mut br = BitReader::new();
+        br.init(Vec::new());
+        assert_eq!(tree.read_symbol(&mut br).unwrap(), 1);
+    }
+
+    #[test]
+    fn distance_codes() {
+        assert_eq!(plane_code_to_distance(100, 1), 100);
+        assert_eq!(plane_code_to_distance(100, 2), 1);
+        assert_eq!(plane_code_to_distance(100, 4), 99);
+        assert_eq!(plane_code_to_distance(1, 4), 1);
+        assert_eq!(plane_code_to_distance(100, 121), 1);
+    }
+
+    #[test]
+    fn predictor_modes() {
+        let (left, top, top_left, top_right) = (0xff102030, 0xff403020, 0xff402010, 0x80808080);
+
+        assert_eq!(predict(0, left, top, top_left, top_right), 0xff000000);
+        assert_eq!(predict(5, left, top, top_left, top_right), 0xdf44403c);
+        assert_eq!(predict(10, left, top, top_left, top_right), 0xdf443c38);
+
+        // Select picks the neighbour in the direction of the smaller gradient
+        assert_eq!(predict(11, left, top, top_left, top_right), left);
+        assert_eq!(predict(11, left, top, 0xff102031, top_right), top);
+
+        // Each channel is clamped separately
+        assert_eq!(predict(12, left, top, top_left, top_right), 0xff103040);
+
+        // The halved difference is rounded towards zero, 0x0a + (0x0a - 0x0d) / 2 = 0x09
+        assert_eq!(predict(13, 0xff0afa0a, 0xff0afa0a, 0xff0d1001, top_right), 0xff09ff0e);
+
+        // The unused modes predict opaque black
+        assert_eq!(predict(14, left, top, top_left, top_right), 0xff000000);
+    }
+
+    #[test]
+    fn predictor_transform() {
+        // A crop of 1_webp_ll.webp made opaque, encoded by libwebp using most predictor modes
+        let data = include_bytes!("../../tests/images/webp/lossless/predictor.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let crop = decoder.decode_frame().unwrap().clone();
+        assert_eq!((crop.width, crop.height), (96, 64));
+
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+
+        for y in 0..64 {
+            for x in 0..96 {
+                assert_eq!(crop.buf[y * 96 + x], frame.buf[y * 400 + x] | 0xff000000);
+            }
+        }
+    }
+
+    #[test]
+    fn subtract_green() {
+        let data = vec![0x80f0c010, 0xff000000, 0x00ff01fe];
+        let data = apply_inverse_transform(&Transform::SubtractGreen, data, 3, 1);
+
+        // Green is added to red and blue modulo 256, alpha and green stay as they are
+        assert_eq!(data, vec![0x80b0c0d0, 0xff000000, 0x000001ff]);
+    }
+
+    #[test]
+    fn subtract_green_transform() {
+        // A green dominated gradient encoded by libwebp with the subtract green,
+        // predictor and cross color transforms, in that order. They are only
+        // inverted correctly the other way round.
+        let data = include_bytes!("../../tests/images/webp/lossless/subtract_green.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (32, 32));
+
+        for y in 0..32 {
+            for x in 0..32 {
+                let n = (x * 37 + y * 101) * 53 % 23;
+                let green = 100 + 2 * x + 2 * y + n;
+                let argb = 0xff000000 | (green - 60 + n % 3) << 16 | green << 8 | (green - 90 + n % 5);
+
+                assert_eq!(frame.buf[(y * 32 + x) as usize], argb);
+            }
+        }
+    }
+
+    #[test]
+    fn cross_color() {
+        // A row of two 2x2 blocks, the first predicts red as green and blue as
+        // red / 2 - green, the second is left as it is
+        let elements = vec![0xff10e020, 0xff000000];
+        let data = vec![0xff109020, 0x00007f00, 0x80123456];
+        let data = apply_inverse_transform(&Transform::CrossColor { bits: 1, data: elements }, data, 3, 1);
+
+        // Green 0x90 is -112 as a signed value, red is 0x10 - 112 = 0xa0 modulo 256,
+        // which gives a delta of -96 * 16 >> 5 = -48 for blue: 0x20 + 112 - 48 = 0x60.
+        // Green 0x7f gives red 0x7f and blue -127 + (127 * 16 >> 5) = -64, or 0xc0.
+        assert_eq!(data, vec![0xffa09060, 0x007f7fc0, 0x80123456]);
+    }
+
+    #[test]
+    fn cross_color_transform() {
+        // Red and blue follow green, in the left half with a positive slope and in
+        // the right half with a negative one. Encoded by libwebp with the cross
+        // color multipliers of each 8x8 block ranging over both signs.
+        let data = include_bytes!("../../tests/images/webp/lossless/cross_color.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (64, 64));
+
+        for y in 0..64 {
+            for x in 0..64 {
+                let n = (x * 37 + y * 101) * 53 % 29;
+                let green = (x * 3 + y * 2 + n * 5) % 256;
+                let (red, blue) = if x < 32 {
+                    let red = 40 + green * 3 / 4 + n % 3;
+                    (red, 20 + (green + red) / 3 + n % 2)
+                } else {
+                    (230 - green * 3 / 4 + n % 3, 250 - green / 2 + n % 2)
+                };
+
+                assert_eq!(frame.buf[(y * 64 + x) as usize], 0xff000000 | red << 16 | green << 8 | blue);
+            }
+        }
+    }
+
+    // Writes bits least significant bit first, as they are read
+    struct BitWriter {
+        buf: Vec<u8>,
+        nbits: usize,
+    }
+
+    impl BitWriter {
+        fn write(&mut self, value: u32, n: usize) {
+            for i in 0..n {
+                if self.nbits % 8 == 0 {
+                    self.buf.push(0);
+                }
+
+                let last = self.buf.len() - 1;
+                self.buf[last] |= (((value >> i) & 1) as u8) << (self.nbits % 8);
+                self.nbits += 1;
+            }
+        }
+
+        // The prefix codes of an image of a single ARGB value, which takes no bits per pixel
+        fn write_solid_codes(&mut self, argb: u32) {
+            for &symbol in [(argb >> 8) & 0xff, (argb >> 16) & 0xff, argb & 0xff, argb >> 24, 0].iter() {
+                self.write(1, 1);
+                self.write(0, 1);
+                self.write(1, 1);
+                self.write(symbol, 8);
+            }
+        }
+    }
+
+    #[test]
+    fn transform_order() {
+        // An 8x4 image of a single residual with the predictor, cross color and
+        // subtract green transforms in each order. The FNV-1a hashes of the RGBA
+        // pixels libwebp decodes, which differ for each order.
+        let orders: [([u32; 3], u64); 6] = [
+            ([0, 1, 2], 0x2b72da5d6f892821),
+            ([0, 2, 1], 0xf9551a59d34b77e4),
+            ([1, 0, 2], 0x43303bb638c1c3b2),
+            ([1, 2, 0], 0xb2c87fddceeabe4a),
+            ([2, 0, 1], 0xefc297fd68d3833b),
+            ([2, 1, 0], 0xc8b13cfae1f29624),
+        ];
+
+        for &(kinds, expected) in orders.iter() {
+            let mut w = BitWriter { buf: Vec::new(), nbits: 0 };
+            w.write(0x2f, 8);
+            w.write(8 - 1, 14);
+            w.write(4 - 1, 14);
+            w.write(1, 1);
+            w.write(0, 3);
+
+            for &kind in kinds.iter() {
+                w.write(1, 1);
+                w.write(kind, 2);
+
+                // The clamped gradient predictor and the cross color multipliers
+                // for 4x4 blocks, in sub-images without a color cache
+                if kind != 2 {
+                    w.write(0, 3);
+                    w.write(0, 1);
+                    w.write_solid_codes(if kind == 0 { 0xff000c00 } else { 0xff40c023 });
+                }
+            }
+
+            // The end of the transforms, and no color cache or entropy image
+            w.write(0, 3);
+            w.write_solid_codes(0xc05a3c7e);
+
+            let mut decoder = VP8LDecoder::new(&w.buf[..]);
+            let frame = decoder.decode_frame().unwrap();
+            assert_eq!((frame.width, frame.height), (8, 4));
+
+            let mut hash = 0xcbf29ce484222325u64;
+            for &argb in frame.buf.iter() {
+                for &shift in [16, 8, 0, 24].iter() {
+                    hash = (hash ^ (argb >> shift) as u8 as u64).wrapping_mul(0x100000001b3);
+                }
+            }
+
+            assert_eq!(hash, expected, "transforms {:?}", kinds);
+        }
+    }
+
+    #[test]
+    fn entropy_image() {
+        // Encoded by libwebp with 32 prefix code groups, chosen for each 16x16 block.
+        // The FNV-1a hash of the RGBA pixels libwebp decodes.
+        let data = include_bytes!("../../tests/images/webp/lossless/3_webp_ll.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+        assert_eq!((frame.width, frame.height), (800, 600));
+
+        let mut hash = 0xcbf29ce484222325u64;
+        for &argb in frame.buf.iter() {
+            for &shift in [16, 8, 0, 24].iter() {
+                hash = (hash ^ (argb >> shift) as u8 as u64).wrapping_mul(0x100000001b3);
+            }
+        }
+
+        assert_eq!(hash, 0xefb0575cc9f0efa9);
+    }
+
+    #[test]
+    fn color_cache_hash() {
+        let mut cache = ColorCache::new(4);
+        cache.insert(0xff336699);
+        cache.insert(0xff000000);
+
+        // (0x1e35a7bd * argb) >> (32 - bits)
+        assert_eq!(cache.lookup(0x1), 0xff336699);
+        assert_eq!(cache.lookup(0x4), 0xff000000);
+    }
+
+    #[test]
+    fn color_cache() {
+        // A 64x64 crop of 2_webp_ll.webp at (200, 150) made opaque, encoded by libwebp with
+        // a 64 color cache that more than half of the pixels are read from
+        let data = include_bytes!("../../tests/images/webp/lossless/color_cache.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let crop = decoder.decode_frame().unwrap().clone();
+        assert_eq!((crop.width, crop.height), (64, 64));
+
+        let data = include_bytes!("../../tests/images/webp/lossless/2_webp_ll.webp");
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+
+        for y in 0..64 {
+            for x in 0..64 {
+                assert_eq!(crop.buf[y * 64 + x], frame.buf[(y + 150) * 386 + x + 200] | 0xff000000);
+            }
+        }
+    }
+
+    #[test]
+    fn packed_palette_indices() {
+        // 13x5 images of 2, 4 and 16 colors, packing 8, 4 and 2 indices per pixel
+        let images: [(u32, &[u8]); 3] = [
+            (2, include_bytes!("../../tests/images/webp/lossless/palette_2.webp")),
+            (4, include_bytes!("../../tests/images/webp/lossless/palette_4.webp")),
+            (16, include_bytes!("../../tests/images/webp/lossless/palette_16.webp")),
+        ];
+
+        for &(colors, data) in images.iter() {
+            let mut decoder = VP8LDecoder::new(&data[20..]);
+            let frame = decoder.decode_frame().unwrap();
+            assert_eq!((frame.width, frame.height), (13, 5));
+
+            for (i, &argb) in frame.buf.iter().enumerate() {
+                let (x, y) = (i as u32 % 13, i as u32 / 13);
+                let index = (x * 7 + y * 3) % colors;
+                let expected = 0xff000000 | (index * 17) << 16 | (255 - index * 17) << 8 | (index * 40) & 0xff;
+
+                assert_eq!(argb, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_lossless_image() {
+        let data = include_bytes!("../../tests/images/webp/lossless/1_webp_ll.webp");
+
+        // Skip the RIFF header and the VP8L chunk header
+        let mut decoder = VP8LDecoder::new(&data[20..]);
+        let frame = decoder.decode_frame().unwrap();
+
+        assert_eq!((frame.width, frame.height), (400, 301));
+        assert_eq!(frame.buf.len(), 400 * 301);
+    }
+}
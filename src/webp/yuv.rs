@@ -0,0 +1,611 @@
+//! Conversion of VP8 frames between YUV 4:2:0 and RGB
+//!
+//! The conversion uses the BT.601 coefficients, in the same fixed point
+//! precision as libwebp, so that the output matches the reference codec.
+//! The chroma planes are upsampled the same way as well. The BT.709
+//! coefficients and full range samples are available for images that were
+//! encoded that way, which libwebp does not support.
+
+use std::cmp;
+use std::default::Default;
+
+use super::vp8::Frame;
+
+/// How the chroma planes are scaled up to the size of the luma plane
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Upsampling {
+    /// Each chroma value is interpolated from the four nearest chroma samples,
+    /// as libwebp does by default
+    Fancy,
+
+    /// Each chroma sample is repeated over the 2x2 pixels it covers, which is
+    /// faster but leaves color fringes along sharp diagonal edges
+    Nearest,
+}
+
+impl Default for Upsampling {
+    fn default() -> Upsampling {
+        Upsampling::Fancy
+    }
+}
+
+/// The order of the color channels in the pixels of the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelOrder {
+    /// Red, green and blue, followed by alpha in images that have it
+    Rgba,
+
+    /// Blue, green and red, followed by alpha in images that have it, as many
+    /// framebuffers expect
+    Bgra,
+}
+
+impl Default for PixelOrder {
+    fn default() -> PixelOrder {
+        PixelOrder::Rgba
+    }
+}
+
+impl PixelOrder {
+    /// The positions of the red and the blue value in a pixel
+    pub fn red_blue(self) -> (usize, usize) {
+        match self {
+            PixelOrder::Rgba => (0, 2),
+            PixelOrder::Bgra => (2, 0),
+        }
+    }
+}
+
+/// The coefficients of a conversion between YUV and RGB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMatrix {
+    /// ITU-R BT.601, which is what WebP is specified with
+    Bt601,
+
+    /// ITU-R BT.709, as used for HD video
+    Bt709,
+}
+
+/// How the YUV samples of lossy images are converted to RGB
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ColorConversion {
+    /// The coefficients of the conversion
+    pub matrix: ColorMatrix,
+
+    /// Whether the samples use the full range of 0 to 255. Otherwise luma is
+    /// in the studio range of 16 to 235 and chroma in 16 to 240, which values
+    /// outside of are clamped to black or white.
+    pub full_range: bool,
+}
+
+impl Default for ColorConversion {
+    fn default() -> ColorConversion {
+        ColorConversion {
+            matrix: ColorMatrix::Bt601,
+            full_range: false,
+        }
+    }
+}
+
+// The factors of the conversion with 14 fractional bits and the offsets of
+// R, G and B with 6 fractional bits, which include a half for rounding
+#[derive(Debug, Clone, Copy)]
+pub struct Coefficients {
+    y: i32,
+    v_to_r: i32,
+    u_to_g: i32,
+    v_to_g: i32,
+    u_to_b: i32,
+    r: i32,
+    g: i32,
+    b: i32,
+}
+
+// The studio range BT.601 conversion of libwebp
+const LIBWEBP: Coefficients = Coefficients {
+    y: 19077,
+    v_to_r: 26149,
+    u_to_g: 6419,
+    v_to_g: 13320,
+    u_to_b: 33050,
+    r: -14234,
+    g: 8708,
+    b: -17685,
+};
+
+impl ColorConversion {
+    fn coefficients(&self) -> Coefficients {
+        if *self == Default::default() {
+            return LIBWEBP
+        }
+
+        let (kr, kb) = match self.matrix {
+            ColorMatrix::Bt601 => (0.299, 0.114),
+            ColorMatrix::Bt709 => (0.2126, 0.0722),
+        };
+        let kg = 1.0 - kr - kb;
+
+        // The factors that scale luma to 0..1 and chroma to -0.5..0.5
+        let (y_scale, y_offset, c_scale) = if self.full_range {
+            (1.0, 0.0, 1.0)
+        } else {
+            (255.0 / 219.0, 16.0, 255.0 / 224.0)
+        };
+
+        let v_to_r = c_scale * 2.0 * (1.0 - kr);
+        let u_to_g = c_scale * 2.0 * kb * (1.0 - kb) / kg;
+        let v_to_g = c_scale * 2.0 * kr * (1.0 - kr) / kg;
+        let u_to_b = c_scale * 2.0 * (1.0 - kb);
+
+        let factor = |c: f64| (c * 16384.0).round() as i32;
+        let offset = |c: f64| ((c - y_scale * y_offset) * 64.0 + 32.0).round() as i32;
+
+        Coefficients {
+            y: factor(y_scale),
+            v_to_r: factor(v_to_r),
+            u_to_g: factor(u_to_g),
+            v_to_g: factor(v_to_g),
+            u_to_b: factor(u_to_b),
+            r: offset(-128.0 * v_to_r),
+            g: offset(128.0 * (u_to_g + v_to_g)),
+            b: offset(-128.0 * u_to_b),
+        }
+    }
+}
+
+// (v * coeff) >> 8, leaving 6 bits of fractional precision
+fn mult_hi(v: i32, coeff: i32) -> i32 {
+    (v * coeff) >> 8
+}
+
+// Drops the fractional bits and clamps to 0..255
+fn clip8(v: i32) -> u8 {
+    if v & !16383 == 0 {
+        (v >> 6) as u8
+    } else if v < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+/// A channel value of the RGB output
+pub trait Sample: Copy {
+    /// Converts a value of the color conversion, which has 6 fractional bits
+    fn from_fixed(v: i32) -> Self;
+
+    /// Converts an 8 bit value
+    fn from_u8(v: u8) -> Self;
+
+    /// Multiplies the value by ```alpha``` as a fraction of the largest value, rounded
+    fn premultiply(self, alpha: Self) -> Self;
+
+    /// Converts a row of YUV samples with a chroma value for each pixel into
+    /// pixels of ```bpp``` values in ```order```, leaving any after the first
+    /// three as they are
+    fn convert_row(c: &Coefficients, y: &[u8], u: &[u8], v: &[u8], buf: &mut [Self], bpp: usize, order: PixelOrder) {
+        for (x, pixel) in buf.chunks_mut(bpp).enumerate() {
+            yuv_to_rgb(c, y[x], u[x], v[x], pixel, order);
+        }
+    }
+}
+
+impl Sample for u8 {
+    fn from_fixed(v: i32) -> u8 {
+        clip8(v)
+    }
+
+    fn from_u8(v: u8) -> u8 {
+        v
+    }
+
+    fn premultiply(self, alpha: u8) -> u8 {
+        // Exact rounding of self * alpha / 255
+        let t = self as u32 * alpha as u32 + 128;
+        ((t + (t >> 8)) >> 8) as u8
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    fn convert_row(c: &Coefficients, y: &[u8], u: &[u8], v: &[u8], buf: &mut [u8], bpp: usize, order: PixelOrder) {
+        sse2::convert_row(c, y, u, v, buf, bpp, order)
+    }
+}
+
+/// 16 bit values keep the fractional bits of the color conversion,
+/// each 8 bit value ```n``` is scaled to ```257 * n```.
+impl Sample for u16 {
+    fn from_fixed(v: i32) -> u16 {
+        let v = cmp::max(0, cmp::min(v, 255 << 6));
+        ((v * 257 + 32) >> 6) as u16
+    }
+
+    fn from_u8(v: u8) -> u16 {
+        257 * v as u16
+    }
+
+    fn premultiply(self, alpha: u16) -> u16 {
+        ((self as u32 * alpha as u32 + 32767) / 65535) as u16
+    }
+}
+
+/// Floating point values are normalized to the range of 0 to 1 and keep the
+/// fractional bits of the color conversion, each 8 bit value ```n``` is scaled
+/// to ```n / 255```.
+impl Sample for f32 {
+    fn from_fixed(v: i32) -> f32 {
+        cmp::max(0, cmp::min(v, 255 << 6)) as f32 / (255 << 6) as f32
+    }
+
+    fn from_u8(v: u8) -> f32 {
+        v as f32 / 255.0
+    }
+
+    fn premultiply(self, alpha: f32) -> f32 {
+        self * alpha
+    }
+}
+
+// Converts a single YUV sample to RGB in order
+fn yuv_to_rgb<T: Sample>(c: &Coefficients, y: u8, u: u8, v: u8, rgb: &mut [T], order: PixelOrder) {
+    let (y, u, v) = (y as i32, u as i32, v as i32);
+    let luma = mult_hi(y, c.y);
+    let (r, b) = order.red_blue();
+
+    rgb[r] = T::from_fixed(luma + mult_hi(v, c.v_to_r) + c.r);
+    rgb[1] = T::from_fixed(luma - mult_hi(u, c.u_to_g) - mult_hi(v, c.v_to_g) + c.g);
+    rgb[b] = T::from_fixed(luma + mult_hi(u, c.u_to_b) + c.b);
+}
+
+/// Converts row ```y``` of ```frame``` into interleaved RGB and writes it to ```buf```.
+///
+/// Each chroma sample covers a 2x2 block of luma samples. On odd widths or heights the
+/// last chroma column or row only covers a single luma column or row.
+pub fn fill_rgb_row<T: Sample>(frame: &Frame,
+                               upsampling: Upsampling,
+                               conversion: ColorConversion,
+                               order: PixelOrder,
+                               y: usize,
+                               buf: &mut [T]) {
+
+    fill_row(frame, upsampling, conversion, order, y, buf, 3);
+}
+
+/// Converts row ```y``` of ```frame``` into interleaved RGBA and writes it to ```buf```,
+/// taking the alpha values from the plane ```alpha```.
+pub fn fill_rgba_row<T: Sample>(frame: &Frame,
+                                upsampling: Upsampling,
+                                conversion: ColorConversion,
+                                order: PixelOrder,
+                                alpha: &[u8],
+                                y: usize,
+                                buf: &mut [T]) {
+
+    let width = frame.width as usize;
+
+    fill_row(frame, upsampling, conversion, order, y, buf, 4);
+
+    for (a, rgba) in alpha[y * width..y * width + width].iter().zip(buf.chunks_mut(4)) {
+        rgba[3] = T::from_u8(*a);
+    }
+}
+
+fn fill_row<T: Sample>(frame: &Frame,
+                       upsampling: Upsampling,
+                       conversion: ColorConversion,
+                       order: PixelOrder,
+                       y: usize,
+                       buf: &mut [T],
+                       bpp: usize) {
+
+    let coefficients = conversion.coefficients();
+    let width = frame.width as usize;
+    let cw = frame.chroma_width() as usize;
+    let yrow = &frame.ybuf[y * width..y * width + width];
+
+    // The chroma row covering y, and for fancy upsampling the one on the
+    // other side of y. The first and last rows only use a single chroma row.
+    let near = y / 2;
+    let far = if y % 2 == 1 {
+        cmp::min(near + 1, frame.chroma_height() as usize - 1)
+    } else {
+        near.saturating_sub(1)
+    };
+
+    let urow = &frame.ubuf[near * cw..near * cw + cw];
+    let vrow = &frame.vbuf[near * cw..near * cw + cw];
+
+    if upsampling == Upsampling::Nearest {
+        for (x, pixel) in buf[..width * bpp].chunks_mut(bpp).enumerate() {
+            yuv_to_rgb(&coefficients, yrow[x], urow[x / 2], vrow[x / 2], pixel, order);
+        }
+
+        return
+    }
+
+    let mut u = vec![0u8; width];
+    let mut v = vec![0u8; width];
+    upsample_row(urow, &frame.ubuf[far * cw..far * cw + cw], &mut u);
+    upsample_row(vrow, &frame.vbuf[far * cw..far * cw + cw], &mut v);
+
+    T::convert_row(&coefficients, yrow, &u, &v, &mut buf[..width * bpp], bpp, order);
+}
+
+// Interpolates a full row of chroma values from the nearest chroma row and the
+// one beyond it. Each value weighs the nearest sample by 9/16, the two next to it
+// by 3/16 and the diagonal one by 1/16, with libwebp's rounding.
+fn upsample_row(near: &[u8], far: &[u8], out: &mut [u8]) {
+    let width = out.len();
+    let edge = |x: usize| ((3 * near[x] as u32 + far[x] as u32 + 2) >> 2) as u8;
+
+    out[0] = edge(0);
+
+    for x in 1..(width + 1) / 2 {
+        let (tl, t) = (near[x - 1] as u32, near[x] as u32);
+        let (l, c) = (far[x - 1] as u32, far[x] as u32);
+
+        let avg = tl + t + l + c + 8;
+        let diag_12 = (avg + 2 * (t + l)) >> 3;
+        let diag_03 = (avg + 2 * (tl + c)) >> 3;
+
+        out[2 * x - 1] = ((diag_12 + tl) >> 1) as u8;
+        out[2 * x] = ((diag_03 + t) >> 1) as u8;
+    }
+
+    if width % 2 == 0 {
+        out[width - 1] = edge(width / 2 - 1);
+    }
+}
+
+/// Converts the interleaved pixels in ```buf``` with ```bpp``` bytes each into a frame.
+///
+/// Pixels of one or two bytes are gray, otherwise the first three bytes are RGB.
+/// Any further bytes, such as alpha, are ignored.
+pub fn rgb_to_frame(buf: &[u8], width: u16, height: u16, bpp: usize) -> Frame {
+    let width = width as usize;
+    let height = height as usize;
+
+    let rgb = |x: usize, y: usize| {
+        let p = &buf[(y * width + x) * bpp..];
+
+        if bpp < 3 {
+            (p[0] as i32, p[0] as i32, p[0] as i32)
+        } else {
+            (p[0] as i32, p[1] as i32, p[2] as i32)
+        }
+    };
+
+    let mut frame: Frame = Default::default();
+    frame.width = width as u16;
+    frame.height = height as u16;
+    frame.keyframe = true;
+    frame.for_display = true;
+
+    for y in 0..height {
+        for x in 0..width {
+            let (r, g, b) = rgb(x, y);
+            frame.ybuf.push(((16839 * r + 33059 * g + 6420 * b + (1 << 15) + (16 << 16)) >> 16) as u8);
+        }
+    }
+
+    // Each chroma sample is computed from the sum of a 2x2 block of pixels,
+    // the last row and column are repeated on odd dimensions.
+    for y in 0..frame.chroma_height() as usize {
+        for x in 0..frame.chroma_width() as usize {
+            let (mut r, mut g, mut b) = (0, 0, 0);
+
+            for &(dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter() {
+                let (pr, pg, pb) = rgb(cmp::min(2 * x + dx, width - 1), cmp::min(2 * y + dy, height - 1));
+                r += pr;
+                g += pg;
+                b += pb;
+            }
+
+            frame.ubuf.push(clip_uv(-9719 * r - 19081 * g + 28800 * b));
+            frame.vbuf.push(clip_uv(28800 * r - 24116 * g - 4684 * b));
+        }
+    }
+
+    frame
+}
+
+// Rounds and clamps the chroma value of a 2x2 block of pixels
+fn clip_uv(v: i32) -> u8 {
+    let v = (v + (1 << 17) + (128 << 18)) >> 18;
+
+    if v & !255 == 0 {
+        v as u8
+    } else if v < 0 {
+        0
+    } else {
+        255
+    }
+}
+
+// The conversion of eight pixels at once, with the same fixed point arithmetic
+// as yuv_to_rgb. SSE2 is part of every x86_64 processor.
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+mod sse2 {
+    use std::arch::x86_64::*;
+
+    use super::{Coefficients, PixelOrder, yuv_to_rgb};
+
+    // (v * c) >> 8 of each lane, where all products fit into 31 bits
+    #[inline]
+    unsafe fn mult_hi(v: __m128i, c: i32) -> __m128i {
+        let c = _mm_set1_epi32(c);
+        let even = _mm_mul_epu32(v, c);
+        let odd = _mm_mul_epu32(_mm_srli_si128(v, 4), c);
+        let product = _mm_unpacklo_epi32(_mm_shuffle_epi32(even, 0b00_00_10_00), _mm_shuffle_epi32(odd, 0b00_00_10_00));
+
+        _mm_srai_epi32(product, 8)
+    }
+
+    // Drops the fractional bits of eight values in two vectors, the saturation of
+    // the packing clamps them to 0..255 like clip8
+    #[inline]
+    unsafe fn clip8(lo: __m128i, hi: __m128i) -> __m128i {
+        let words = _mm_packs_epi32(_mm_srai_epi32(lo, 6), _mm_srai_epi32(hi, 6));
+        _mm_packus_epi16(words, words)
+    }
+
+    pub fn convert_row(c: &Coefficients, y: &[u8], u: &[u8], v: &[u8], buf: &mut [u8], bpp: usize, order: PixelOrder) {
+        let width = buf.len() / bpp;
+        let (r, b) = order.red_blue();
+        let blocks = width / 8;
+
+        unsafe {
+            let zero = _mm_setzero_si128();
+            let widen = |s: &[u8]| {
+                let bytes = _mm_unpacklo_epi8(_mm_loadl_epi64(s.as_ptr() as *const __m128i), zero);
+                (_mm_unpacklo_epi16(bytes, zero), _mm_unpackhi_epi16(bytes, zero))
+            };
+
+            for block in 0..blocks {
+                let x = 8 * block;
+                let ((y0, y1), (u0, u1), (v0, v1)) = (widen(&y[x..x + 8]), widen(&u[x..x + 8]), widen(&v[x..x + 8]));
+
+                let red = |y, v| _mm_add_epi32(_mm_add_epi32(mult_hi(y, c.y), mult_hi(v, c.v_to_r)), _mm_set1_epi32(c.r));
+                let green = |y, u, v| _mm_add_epi32(
+                    _mm_sub_epi32(_mm_sub_epi32(mult_hi(y, c.y), mult_hi(u, c.u_to_g)), mult_hi(v, c.v_to_g)),
+                    _mm_set1_epi32(c.g));
+                let blue = |y, u| _mm_add_epi32(_mm_add_epi32(mult_hi(y, c.y), mult_hi(u, c.u_to_b)), _mm_set1_epi32(c.b));
+
+                let mut rgb = [[0u8; 16]; 3];
+                _mm_storeu_si128(rgb[0].as_mut_ptr() as *mut __m128i, clip8(red(y0, v0), red(y1, v1)));
+                _mm_storeu_si128(rgb[1].as_mut_ptr() as *mut __m128i, clip8(green(y0, u0, v0), green(y1, u1, v1)));
+                _mm_storeu_si128(rgb[2].as_mut_ptr() as *mut __m128i, clip8(blue(y0, u0), blue(y1, u1)));
+
+                for (i, pixel) in buf[x * bpp..(x + 8) * bpp].chunks_mut(bpp).enumerate() {
+                    pixel[r] = rgb[0][i];
+                    pixel[1] = rgb[1][i];
+                    pixel[b] = rgb[2][i];
+                }
+            }
+        }
+
+        for x in 8 * blocks..width {
+            yuv_to_rgb(c, y[x], u[x], v[x], &mut buf[x * bpp..x * bpp + bpp], order);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    #[cfg(benchmarks)]
+    use test;
+
+    use super::super::vp8::Frame;
+    use super::{ColorConversion, ColorMatrix, PixelOrder, Upsampling, fill_rgb_row, yuv_to_rgb};
+
+    fn rgb(matrix: ColorMatrix, full_range: bool, y: u8, u: u8, v: u8) -> [u8; 3] {
+        let conversion = ColorConversion { matrix: matrix, full_range: full_range };
+        let mut rgb = [0u8; 3];
+        yuv_to_rgb(&conversion.coefficients(), y, u, v, &mut rgb, PixelOrder::Rgba);
+
+        let mut bgr = [0u8; 3];
+        yuv_to_rgb(&conversion.coefficients(), y, u, v, &mut bgr, PixelOrder::Bgra);
+        assert_eq!(bgr, [rgb[2], rgb[1], rgb[0]]);
+
+        rgb
+    }
+
+    #[test]
+    fn color_conversions() {
+        for &matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709].iter() {
+            // Studio range black and white are clamped, full range uses every value
+            assert_eq!(rgb(matrix, false, 16, 128, 128), [0, 0, 0]);
+            assert_eq!(rgb(matrix, false, 235, 128, 128), [255, 255, 255]);
+            assert_eq!(rgb(matrix, false, 8, 128, 128), [0, 0, 0]);
+            assert_eq!(rgb(matrix, true, 0, 128, 128), [0, 0, 0]);
+            assert_eq!(rgb(matrix, true, 100, 128, 128), [100, 100, 100]);
+            assert_eq!(rgb(matrix, true, 255, 128, 128), [255, 255, 255]);
+        }
+
+        // Pure red has a lower luma in BT.709, decoding it as BT.601 gives a darker red
+        assert_eq!(rgb(ColorMatrix::Bt601, false, 82, 90, 240), [255, 1, 0]);
+        assert_eq!(rgb(ColorMatrix::Bt709, false, 63, 102, 240), [255, 1, 0]);
+        assert_eq!(rgb(ColorMatrix::Bt709, true, 54, 99, 255), [254, 0, 0]);
+        assert_eq!(rgb(ColorMatrix::Bt601, false, 63, 102, 240), [233, 0, 2]);
+    }
+
+    #[test]
+    fn yuv_to_rgb_sample() {
+        // The values of libwebp's VP8YuvToRgb
+        let c = ColorConversion::default().coefficients();
+        let mut rgb = [0u8; 3];
+        yuv_to_rgb(&c, 150, 100, 160, &mut rgb, PixelOrder::Rgba);
+        assert_eq!(rgb, [207, 141, 100]);
+    }
+
+    #[test]
+    fn odd_sized_frame() {
+        // A 3x3 frame has 2x2 chroma samples, the last of which cover the right
+        // column and the bottom row on their own
+        let mut frame: Frame = Default::default();
+        frame.width = 3;
+        frame.height = 3;
+        frame.ybuf = vec![150, 151, 152, 153, 154, 155, 156, 157, 158];
+        frame.ubuf = vec![100, 90, 80, 70];
+        frame.vbuf = vec![160, 170, 180, 190];
+
+        let c = ColorConversion::default().coefficients();
+        for y in 0..3 {
+            let mut row = vec![0u8; 3 * 3];
+            fill_rgb_row(&frame, Upsampling::Nearest, Default::default(), PixelOrder::Rgba, y, &mut row);
+
+            for x in 0..3 {
+                let chroma = y / 2 * 2 + x / 2;
+                let mut expected = [0u8; 3];
+                yuv_to_rgb(&c, frame.ybuf[y * 3 + x], frame.ubuf[chroma], frame.vbuf[chroma], &mut expected, PixelOrder::Rgba);
+                assert_eq!(&row[x * 3..x * 3 + 3], &expected);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "simd", target_arch = "x86_64"))]
+    fn simd_conversion() {
+        use super::sse2;
+
+        // Every luma value against a spread of chroma values, on a row that
+        // does not end on a block of eight pixels
+        let y: Vec<u8> = (0..259).map(|i| i as u8).collect();
+        let u: Vec<u8> = (0..259).map(|i| (i * 37 % 256) as u8).collect();
+        let v: Vec<u8> = (0..259).map(|i| (i * 101 % 256) as u8).collect();
+
+        for &full_range in [false, true].iter() {
+            for &matrix in [ColorMatrix::Bt601, ColorMatrix::Bt709].iter() {
+                let c = ColorConversion { matrix: matrix, full_range: full_range }.coefficients();
+
+                for &(bpp, order) in [(3, PixelOrder::Rgba), (4, PixelOrder::Rgba), (4, PixelOrder::Bgra)].iter() {
+                    let mut expected = vec![7u8; 259 * bpp];
+                    for (x, pixel) in expected.chunks_mut(bpp).enumerate() {
+                        yuv_to_rgb(&c, y[x], u[x], v[x], pixel, order);
+                    }
+
+                    let mut buf = vec![7u8; 259 * bpp];
+                    sse2::convert_row(&c, &y, &u, &v, &mut buf, bpp, order);
+                    assert_eq!(buf, expected);
+                }
+            }
+        }
+    }
+
+    #[bench]
+    #[cfg(benchmarks)]
+    fn bench_fill_rgb_row(b: &mut test::Bencher) {
+        use super::{fill_rgb_row, rgb_to_frame, Upsampling};
+
+        let image: Vec<u8> = (0..3 * 512 * 512).map(|i| (i * 37 % 251) as u8).collect();
+        let frame = rgb_to_frame(&image, 512, 512, 3);
+        let mut row = vec![0u8; 3 * 512];
+
+        b.iter(|| {
+            for y in 0..512 {
+                fill_rgb_row(&frame, Upsampling::Fancy, Default::default(), Default::default(), y, &mut row);
+            }
+            test::black_box(row[0]);
+        });
+        b.bytes = 3 * 512 * 512;
+    }
+}
@@ -0,0 +1,50 @@
+//! Run the WebP fuzz target over the test images, their truncations and
+//! deterministic mutations of them, as a quick check without cargo-fuzz.
+#![cfg(feature = "fuzzing")]
+
+use std::fs::File;
+use std::io::Read;
+
+extern crate image;
+extern crate glob;
+
+// A xorshift generator, so that failures can be reproduced
+struct Rng(u32);
+
+impl Rng {
+    fn next(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
+
+#[test]
+fn fuzz_webp_chunks() {
+    let mut rng = Rng(0x9e3779b9);
+
+    for path in glob::glob("tests/images/webp/*/*.webp").unwrap().filter_map(Result::ok) {
+        println!("{:?}", path);
+        let mut data = Vec::new();
+        File::open(&path).unwrap().read_to_end(&mut data).unwrap();
+
+        for len in 0..std::cmp::min(data.len(), 64) {
+            image::webp::fuzz_chunk_parsers(&data[..len]);
+        }
+
+        // A few bytes are overwritten, every other time within the chunk headers
+        // at the start of the file
+        for n in 0..256 {
+            let mut mutated = data.clone();
+            let range = if n % 2 == 0 { std::cmp::min(mutated.len(), 64) } else { mutated.len() };
+            for _ in 0..1 + rng.next() % 4 {
+                let i = rng.next() as usize % range;
+                mutated[i] = rng.next() as u8;
+            }
+
+            let len = mutated.len() - rng.next() as usize % 16;
+            image::webp::fuzz_chunk_parsers(&mutated[..len]);
+        }
+    }
+}
@@ -13,7 +13,7 @@ const IMAGE_DIR: &'static str = "images";
 fn process_images<F>(dir: &str, input_decoder: Option<&str>, func: F)
 where F: Fn(PathBuf) {
 	let base: PathBuf = BASE_PATH.iter().collect();
-	let decoders = &["tga", "tiff", "png", "gif", "bmp", "ico", "jpg", "hdr"];
+	let decoders = &["tga", "tiff", "png", "gif", "bmp", "ico", "jpg", "hdr", "webp"];
 	for decoder in decoders {
 		let mut path = base.clone();
 		path.push(dir);